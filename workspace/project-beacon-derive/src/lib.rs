@@ -0,0 +1,185 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, Ident, LitInt, Result,
+};
+
+/// Derives `project_beacon::vulkan::Vertex` from the field types of a struct.
+///
+/// Each field must implement `project_beacon::vulkan::VertexAttribute`, which provides its
+/// `vk::Format`. Offsets and the stride are computed by the compiler, and locations are
+/// assigned in declaration order.
+///
+/// Struct attributes:
+/// - `#[vertex(binding = N)]`: vertex buffer binding (default `0`)
+/// - `#[vertex(instance)]`: advance the attributes per instance instead of per vertex
+/// - `#[vertex(location = N)]`: location of the first attribute (default `0`)
+///
+/// Field attributes:
+/// - `#[vertex(location = N)]`: explicit location, following fields continue from it
+/// - `#[vertex(format = R8G8B8A8_SNORM)]`: override the format of the field
+/// - `#[vertex(skip)]`: padding or CPU only data that is not a shader input
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand_vertex(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct StructAttributes {
+    binding: Option<u32>,
+    instance: bool,
+    location: Option<u32>,
+}
+
+#[derive(Default)]
+struct FieldAttributes {
+    location: Option<u32>,
+    format: Option<Ident>,
+    skip: bool,
+}
+
+fn expand_vertex(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect::<Vec<_>>(),
+            _ => {
+                return Err(Error::new(
+                    input.span(),
+                    "Vertex can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "Vertex can only be derived for structs",
+            ))
+        }
+    };
+
+    let struct_attributes = parse_struct_attributes(&input.attrs)?;
+    let binding = struct_attributes.binding.unwrap_or(0);
+    let input_rate = if struct_attributes.instance {
+        quote!(::project_beacon::vulkan::ash::vk::VertexInputRate::INSTANCE)
+    } else {
+        quote!(::project_beacon::vulkan::ash::vk::VertexInputRate::VERTEX)
+    };
+    let first_location = struct_attributes.location.unwrap_or(0);
+
+    let mut attributes = vec![];
+    for field in fields {
+        let field_attributes = parse_field_attributes(&field.attrs)?;
+        if field_attributes.skip {
+            continue;
+        }
+
+        let field_name = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+
+        let set_location = field_attributes
+            .location
+            .map(|location| quote!(location = #location;));
+
+        let format = match &field_attributes.format {
+            Some(format) => quote!(::project_beacon::vulkan::ash::vk::Format::#format),
+            None => quote_spanned! {ty.span()=>
+                <#ty as ::project_beacon::vulkan::VertexAttribute>::FORMAT
+            },
+        };
+
+        attributes.push(quote_spanned! {field.span()=>
+            {
+                #set_location
+                let location_count =
+                    <#ty as ::project_beacon::vulkan::VertexAttribute>::LOCATION_COUNT;
+                let location_size =
+                    ::std::mem::size_of::<#ty>() as u32 / location_count;
+                let offset = ::std::mem::offset_of!(Self, #field_name) as u32;
+
+                for i in 0..location_count {
+                    attributes.push(::project_beacon::vulkan::ash::vk::VertexInputAttributeDescription {
+                        binding: #binding,
+                        location: location + i,
+                        format: #format,
+                        offset: offset + i * location_size,
+                    });
+                }
+                location += location_count;
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::project_beacon::vulkan::Vertex for #name #ty_generics #where_clause {
+            fn bindings() -> Vec<::project_beacon::vulkan::ash::vk::VertexInputBindingDescription> {
+                vec![::project_beacon::vulkan::ash::vk::VertexInputBindingDescription {
+                    binding: #binding,
+                    stride: ::std::mem::size_of::<Self>() as u32,
+                    input_rate: #input_rate,
+                }]
+            }
+
+            #[allow(unused_assignments)]
+            fn attributes() -> Vec<::project_beacon::vulkan::ash::vk::VertexInputAttributeDescription> {
+                let mut attributes = vec![];
+                let mut location: u32 = #first_location;
+
+                #(#attributes)*
+
+                attributes
+            }
+        }
+    })
+}
+
+fn parse_struct_attributes(attrs: &[syn::Attribute]) -> Result<StructAttributes> {
+    let mut result = StructAttributes::default();
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("vertex")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("binding") {
+                result.binding = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("instance") {
+                result.instance = true;
+            } else if meta.path.is_ident("location") {
+                result.location = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else {
+                return Err(meta.error("expected `binding`, `instance` or `location`"));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(result)
+}
+
+fn parse_field_attributes(attrs: &[syn::Attribute]) -> Result<FieldAttributes> {
+    let mut result = FieldAttributes::default();
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("vertex")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("location") {
+                result.location = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("format") {
+                result.format = Some(meta.value()?.parse::<Ident>()?);
+            } else if meta.path.is_ident("skip") {
+                result.skip = true;
+            } else {
+                return Err(meta.error("expected `location`, `format` or `skip`"));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(result)
+}
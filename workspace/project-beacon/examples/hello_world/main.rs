@@ -2,6 +2,7 @@ use std::time::Duration;
 
 use anyhow::Result;
 use ash::vk;
+use project_beacon::renderer::HDR_FORMAT;
 use project_beacon::vulkan::utils::create_gpu_only_buffer_from_data;
 use project_beacon::vulkan::{
     Buffer, CommandBuffer, Context, GraphicsPipeline, GraphicsPipelineCreateInfo,
@@ -30,7 +31,7 @@ impl App for Triangle {
 
         let pipeline_layout = context.create_pipeline_layout(&[])?;
 
-        let pipeline = create_pipeline(context, &pipeline_layout, base.swapchain.format)?;
+        let pipeline = create_pipeline(context, &pipeline_layout, HDR_FORMAT)?;
 
         Ok(Self {
             vertex_buffer,
@@ -56,10 +57,10 @@ impl App for Triangle {
         &self,
         base: &BaseApp<Self>,
         buffer: &CommandBuffer,
-        image_index: usize,
+        _: usize,
     ) -> Result<()> {
         buffer.begin_rendering(
-            &base.swapchain.views[image_index],
+            &base.storage_images[base.frame_slot()].view,
             base.swapchain.extent,
             vk::AttachmentLoadOp::CLEAR,
             None,
@@ -75,40 +76,14 @@ impl App for Triangle {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, project_beacon::vulkan::Vertex)]
+#[repr(C)]
 #[allow(dead_code)]
 struct Vertex {
     position: [f32; 2],
     color: [f32; 3],
 }
 
-impl project_beacon::vulkan::Vertex for Vertex {
-    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
-        vec![vk::VertexInputBindingDescription {
-            binding: 0,
-            stride: 20,
-            input_rate: vk::VertexInputRate::VERTEX,
-        }]
-    }
-
-    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
-        vec![
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                format: vk::Format::R32G32_SFLOAT,
-                offset: 0,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 8,
-            },
-        ]
-    }
-}
-
 fn create_vertex_buffer(context: &Context) -> Result<Buffer> {
     let vertices: [Vertex; 3] = [
         Vertex {
@@ -151,8 +126,12 @@ fn create_pipeline(
             ],
             primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             extent: None,
-            color_attachment_format,
+            color_attachment_formats: &[color_attachment_format],
             color_attachment_blend: None,
+            depth_attachment_format: None,
+            stencil_attachment_format: None,
+            depth_stencil_state: None,
+            samples: None,
             dynamic_states: Some(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
         },
     )
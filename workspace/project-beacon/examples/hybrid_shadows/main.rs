@@ -0,0 +1,355 @@
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use ash::vk;
+use glam::Mat4;
+use project_beacon::app::{App, BaseApp, FrameImages};
+use project_beacon::render_graph::{Access, ImageDesc, RenderGraph};
+use project_beacon::renderer::{
+    Denoiser, DenoiserSettings, TonemapSettings, DENOISER_ALBEDO_FORMAT, DENOISER_COLOR_FORMAT,
+    DENOISER_MOTION_FORMAT, DENOISER_NORMAL_DEPTH_FORMAT,
+};
+use project_beacon::scene::{GpuScene, MeshVertex, Scene};
+use project_beacon::vulkan::utils::create_gpu_only_buffer_from_data;
+use project_beacon::vulkan::{
+    Buffer, Context, DescriptorPool, DescriptorSet, DescriptorSetLayout, GraphicsPipeline,
+    GraphicsPipelineCreateInfo, GraphicsShaderCreateInfo, PipelineLayout, Pod, RenderingAttachment,
+    RenderingInfo, WriteDescriptorSet, WriteDescriptorSetKind,
+};
+
+const WIDTH: u32 = 1024;
+const HEIGHT: u32 = 576;
+const APP_NAME: &str = "Hybrid shadows";
+const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+fn main() -> Result<()> {
+    project_beacon::app::run::<HybridShadows>(APP_NAME, WIDTH, HEIGHT, true)
+}
+
+/// Rasterizes the Cornell box and traces shadow and ambient occlusion rays from the fragment
+/// shader with ray queries. The noisy result is denoised into the storage image the base app
+/// tonemaps to the swapchain.
+struct HybridShadows {
+    scene: Scene,
+    gpu_scene: GpuScene,
+    _transform_buffer: Buffer,
+    _descriptor_pool: DescriptorPool,
+    _descriptor_set_layout: DescriptorSetLayout,
+    descriptor_set: DescriptorSet,
+    pipeline_layout: PipelineLayout,
+    pipeline: GraphicsPipeline,
+    denoiser: Denoiser,
+    view_projection: Mat4,
+    frame: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct PushConstants {
+    view_projection: Mat4,
+    camera_position: [f32; 4],
+    instance: u32,
+    frame: u32,
+    light_count: u32,
+    _padding: u32,
+}
+
+// 16 byte aligned fields followed by four u32, no padding
+unsafe impl Pod for PushConstants {}
+
+impl App for HybridShadows {
+    fn new(base: &mut BaseApp<Self>) -> Result<Self> {
+        ensure!(
+            base.context.device_features().ray_query,
+            "{APP_NAME} needs a device supporting VK_KHR_ray_query"
+        );
+
+        let extent = base.swapchain.extent;
+        base.camera = Scene::cornell_box_camera(extent.width as f32 / extent.height as f32);
+
+        let context = &mut base.context;
+
+        let scene = Scene::cornell_box();
+        let gpu_scene = context.create_gpu_scene(&scene)?;
+
+        let transforms = scene
+            .instances
+            .iter()
+            .map(|instance| instance.transform)
+            .collect::<Vec<_>>();
+        let transform_buffer = create_gpu_only_buffer_from_data(
+            context,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &transforms,
+        )?;
+
+        let binding = |binding, ty, stage_flags| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(ty)
+                .stage_flags(stage_flags)
+                .build()
+        };
+        let descriptor_set_layout = context.create_descriptor_set_layout(&[
+            binding(
+                0,
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                1,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::VERTEX,
+            ),
+            binding(
+                2,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                3,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                4,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::VERTEX,
+            ),
+        ])?;
+
+        let descriptor_pool = context.create_descriptor_pool(
+            1,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 4,
+                },
+            ],
+        )?;
+        let descriptor_set = descriptor_pool.allocate_set(&descriptor_set_layout)?;
+        descriptor_set.update(&[
+            WriteDescriptorSet {
+                binding: 0,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::AccelerationStructure {
+                    acceleration_structure: &gpu_scene.tlas.acceleration_structure,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 1,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: &gpu_scene.instance_buffer,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 2,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: &gpu_scene.material_buffer,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 3,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: &gpu_scene.light_buffer,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 4,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: &transform_buffer,
+                },
+            },
+        ]);
+
+        let pipeline_layout = context.create_pipeline_layout_with_push_constants(
+            &[&descriptor_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as _,
+            }],
+        )?;
+
+        let pipeline = create_pipeline(context, &pipeline_layout)?;
+
+        let denoiser = context.create_denoiser(extent, DenoiserSettings::default())?;
+        base.tonemapper.settings = TonemapSettings::default();
+
+        Ok(Self {
+            scene,
+            gpu_scene,
+            _transform_buffer: transform_buffer,
+            _descriptor_pool: descriptor_pool,
+            _descriptor_set_layout: descriptor_set_layout,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+            denoiser,
+            view_projection: Mat4::IDENTITY,
+            frame: 0,
+        })
+    }
+
+    fn update(&mut self, base: &BaseApp<Self>, _: usize, _: Duration) -> Result<()> {
+        // Motion vectors are zero, the history is only valid while the camera is still
+        let camera = &base.camera;
+        let view_projection = camera.projection_matrix() * camera.view_matrix();
+        if view_projection != self.view_projection {
+            self.view_projection = view_projection;
+            self.denoiser.reset();
+        }
+
+        self.frame = self.frame.wrapping_add(1);
+
+        Ok(())
+    }
+
+    fn record_render_graph<'a>(
+        &'a self,
+        base: &'a BaseApp<Self>,
+        graph: &mut RenderGraph<'a>,
+        images: FrameImages,
+        _: usize,
+    ) -> Result<()> {
+        let extent = base.swapchain.extent;
+        let depth = graph.create_image("depth", ImageDesc::new_2d(DEPTH_FORMAT, extent));
+        let tlas = graph.import_acceleration_structure(
+            "tlas",
+            &self.gpu_scene.tlas.acceleration_structure,
+            Access::RayQueryAccelerationStructureRead,
+        );
+        let denoiser = self.denoiser.import_images(graph);
+
+        graph
+            .add_pass("hybrid shadows")
+            .read(tlas, Access::RayQueryAccelerationStructureRead)
+            .write(denoiser.color, Access::ColorAttachmentWrite)
+            .write(denoiser.albedo, Access::ColorAttachmentWrite)
+            .write(denoiser.normal_depth, Access::ColorAttachmentWrite)
+            .write(denoiser.motion, Access::ColorAttachmentWrite)
+            .write(depth, Access::DepthStencilAttachmentWrite)
+            .execute(move |buffer, resources| {
+                let attachment = |image, clear_color| {
+                    RenderingAttachment::color(resources.image_view(image)).clear_color(clear_color)
+                };
+                buffer.begin_rendering_with_info(
+                    &RenderingInfo::new(extent)
+                        .color_attachment(attachment(denoiser.color, [0.0, 0.0, 0.0, 1.0]))
+                        .color_attachment(attachment(denoiser.albedo, [1.0, 1.0, 1.0, 1.0]))
+                        .color_attachment(attachment(denoiser.normal_depth, [0.0; 4]))
+                        .color_attachment(attachment(denoiser.motion, [0.0; 4]))
+                        .depth_attachment(
+                            RenderingAttachment::depth(resources.image_view(depth))
+                                .layout(resources.layout(depth)),
+                        ),
+                );
+                buffer.bind_graphics_pipeline(&self.pipeline);
+                buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    &self.pipeline_layout,
+                    0,
+                    &[&self.descriptor_set],
+                );
+                buffer.set_viewport(extent);
+                buffer.set_scissor(extent);
+
+                let camera = &base.camera;
+                for (index, instance) in self.scene.instances.iter().enumerate() {
+                    buffer.push_constants(
+                        &self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        &PushConstants {
+                            view_projection: self.view_projection,
+                            camera_position: camera.position.extend(1.0).to_array(),
+                            instance: index as _,
+                            frame: self.frame,
+                            light_count: self.gpu_scene.light_count,
+                            _padding: 0,
+                        },
+                    );
+                    buffer.bind_vertex_buffer(&self.gpu_scene.vertex_buffers[instance.mesh]);
+                    buffer.bind_index_buffer(
+                        &self.gpu_scene.index_buffers[instance.mesh],
+                        vk::IndexType::UINT32,
+                    );
+                    buffer.draw_indexed(self.scene.meshes[instance.mesh].indices.len() as _);
+                }
+
+                buffer.end_rendering();
+
+                Ok(())
+            });
+
+        self.denoiser.add_pass(graph, denoiser);
+
+        graph
+            .add_pass("denoiser output")
+            .read(denoiser.output, Access::TransferRead)
+            .write(images.storage, Access::TransferWrite)
+            .execute(move |buffer, resources| {
+                buffer.blit_image(
+                    resources.image(denoiser.output),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    resources.image(images.storage),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::Filter::NEAREST,
+                );
+                Ok(())
+            });
+
+        Ok(())
+    }
+
+    fn on_recreate_swapchain(&mut self, base: &BaseApp<Self>) -> Result<()> {
+        self.denoiser.resize(&base.context, base.swapchain.extent)
+    }
+}
+
+fn create_pipeline(context: &Context, layout: &PipelineLayout) -> Result<GraphicsPipeline> {
+    context.create_graphics_pipeline::<MeshVertex>(
+        layout,
+        GraphicsPipelineCreateInfo {
+            shaders: &[
+                GraphicsShaderCreateInfo {
+                    source: &include_bytes!("./shaders/scene.vert.spv")[..],
+                    stage: vk::ShaderStageFlags::VERTEX,
+                },
+                GraphicsShaderCreateInfo {
+                    source: &include_bytes!("./shaders/scene.frag.spv")[..],
+                    stage: vk::ShaderStageFlags::FRAGMENT,
+                },
+            ],
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            extent: None,
+            color_attachment_formats: &[
+                DENOISER_COLOR_FORMAT,
+                DENOISER_ALBEDO_FORMAT,
+                DENOISER_NORMAL_DEPTH_FORMAT,
+                DENOISER_MOTION_FORMAT,
+            ],
+            color_attachment_blend: None,
+            depth_attachment_format: Some(DEPTH_FORMAT),
+            stencil_attachment_format: None,
+            depth_stencil_state: Some(
+                vk::PipelineDepthStencilStateCreateInfo::builder()
+                    .depth_test_enable(true)
+                    .depth_write_enable(true)
+                    .depth_compare_op(vk::CompareOp::LESS)
+                    .build(),
+            ),
+            samples: None,
+            dynamic_states: Some(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
+        },
+    )
+}
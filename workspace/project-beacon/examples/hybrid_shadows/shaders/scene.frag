@@ -0,0 +1,138 @@
+#version 460
+#extension GL_EXT_ray_query : require
+
+const float PI = 3.14159265359;
+const uint SHADOW_SAMPLES = 4;
+const uint AO_SAMPLES = 4;
+const float AO_RADIUS = 0.4;
+const vec3 AMBIENT = vec3(0.3);
+const float RAY_EPSILON = 1e-3;
+
+struct Material {
+    vec4 base_color_metallic;
+    vec4 emission_roughness;
+};
+
+struct Light {
+    vec4 positions[3];
+    // w is the area
+    vec4 emission;
+};
+
+layout(location = 0) in vec3 oPosition;
+layout(location = 1) in vec3 oNormal;
+layout(location = 2) flat in uint oMaterial;
+
+layout(set = 0, binding = 0) uniform accelerationStructureEXT tlas;
+layout(set = 0, binding = 2, std430) readonly buffer Materials { Material materials[]; };
+layout(set = 0, binding = 3, std430) readonly buffer Lights { Light lights[]; };
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_projection;
+    vec4 camera_position;
+    uint instance;
+    uint frame;
+    uint light_count;
+} pc;
+
+// Denoiser inputs
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec4 outAlbedo;
+layout(location = 2) out vec4 outNormalDepth;
+layout(location = 3) out vec4 outMotion;
+
+uint pcg_hash(uint value) {
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float random(inout uint state) {
+    state = pcg_hash(state);
+    return float(state >> 8) / 16777216.0;
+}
+
+bool is_visible(vec3 origin, vec3 direction, float distance) {
+    rayQueryEXT query;
+    rayQueryInitializeEXT(
+        query, tlas, gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT, 0xFF,
+        origin, 0.0, direction, distance);
+    while (rayQueryProceedEXT(query)) {}
+
+    return rayQueryGetIntersectionTypeEXT(query, true) == gl_RayQueryCommittedIntersectionNoneEXT;
+}
+
+// Stratified samples over each emissive triangle, traced with ray queries
+vec3 direct_light(vec3 position, vec3 n, inout uint rng) {
+    vec3 light = vec3(0.0);
+
+    for (uint i = 0; i < pc.light_count; i++) {
+        vec3 p0 = lights[i].positions[0].xyz;
+        vec3 p1 = lights[i].positions[1].xyz;
+        vec3 p2 = lights[i].positions[2].xyz;
+        vec3 light_normal = normalize(cross(p1 - p0, p2 - p0));
+
+        for (uint s = 0; s < SHADOW_SAMPLES; s++) {
+            float u0 = sqrt((float(s) + random(rng)) / float(SHADOW_SAMPLES));
+            float u1 = random(rng);
+            vec3 point = p0 * (1.0 - u0) + p1 * (u0 * (1.0 - u1)) + p2 * (u0 * u1);
+
+            vec3 to_light = point - position;
+            float distance = length(to_light);
+            vec3 l = to_light / distance;
+            float cos_surface = dot(n, l);
+            float cos_light = dot(light_normal, -l);
+            if (cos_surface <= 0.0 || cos_light <= 0.0) {
+                continue;
+            }
+
+            if (is_visible(position + n * RAY_EPSILON, l, distance * (1.0 - 1e-3))) {
+                light += lights[i].emission.rgb * lights[i].emission.w * cos_surface * cos_light
+                    / (distance * distance * float(SHADOW_SAMPLES));
+            }
+        }
+    }
+
+    return light;
+}
+
+// Fraction of short cosine weighted rays escaping the surface
+float ambient_occlusion(vec3 position, vec3 n, inout uint rng) {
+    vec3 t = normalize(abs(n.x) > 0.9 ? cross(n, vec3(0.0, 1.0, 0.0)) : cross(n, vec3(1.0, 0.0, 0.0)));
+    vec3 b = cross(n, t);
+
+    float visible = 0.0;
+    for (uint s = 0; s < AO_SAMPLES; s++) {
+        float u0 = random(rng);
+        float phi = 2.0 * PI * random(rng);
+        float r = sqrt(u0);
+        vec3 direction = t * (r * cos(phi)) + b * (r * sin(phi)) + n * sqrt(1.0 - u0);
+
+        visible += is_visible(position + n * RAY_EPSILON, direction, AO_RADIUS) ? 1.0 : 0.0;
+    }
+
+    return visible / float(AO_SAMPLES);
+}
+
+void main() {
+    Material material = materials[oMaterial];
+    vec3 base_color = material.base_color_metallic.rgb;
+
+    vec3 n = normalize(oNormal);
+    if (dot(n, pc.camera_position.xyz - oPosition) < 0.0) {
+        n = -n;
+    }
+
+    uvec2 pixel = uvec2(gl_FragCoord.xy);
+    uint rng = pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(pc.frame)));
+
+    vec3 diffuse = base_color / PI * direct_light(oPosition, n, rng);
+    vec3 ambient = base_color * AMBIENT * ambient_occlusion(oPosition, n, rng);
+    vec3 color = material.emission_roughness.rgb + diffuse + ambient;
+
+    outColor = vec4(color, 1.0);
+    outAlbedo = vec4(base_color, 1.0);
+    outNormalDepth = vec4(n, distance(pc.camera_position.xyz, oPosition));
+    // The scene is static, the history is dropped when the camera moves
+    outMotion = vec4(0.0);
+}
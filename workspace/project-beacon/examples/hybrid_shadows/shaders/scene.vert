@@ -0,0 +1,37 @@
+#version 460
+
+struct Instance {
+    uvec2 vertices;
+    uvec2 indices;
+    uint material;
+    uint padding[3];
+};
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 1) in vec3 vNormal;
+
+layout(set = 0, binding = 1, std430) readonly buffer Instances { Instance instances[]; };
+layout(set = 0, binding = 4, std430) readonly buffer Transforms { mat4 transforms[]; };
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_projection;
+    vec4 camera_position;
+    uint instance;
+    uint frame;
+    uint light_count;
+} pc;
+
+layout(location = 0) out vec3 oPosition;
+layout(location = 1) out vec3 oNormal;
+layout(location = 2) flat out uint oMaterial;
+
+void main() {
+    mat4 transform = transforms[pc.instance];
+    vec4 position = transform * vec4(vPosition, 1.0);
+
+    oPosition = position.xyz;
+    oNormal = mat3(transpose(inverse(transform))) * vNormal;
+    oMaterial = instances[pc.instance].material;
+
+    gl_Position = pc.view_projection * position;
+}
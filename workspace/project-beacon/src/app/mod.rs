@@ -1,38 +1,85 @@
 pub mod camera;
+mod overlay;
+mod stats;
 
-use anyhow::Result;
+pub use stats::*;
+
+use anyhow::{ensure, Result};
 use ash::vk::{self};
 // use camera::{Camera, Controls};
 use glam::vec3;
 use gpu_allocator::MemoryLocation;
 use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
     marker::PhantomData,
     time::{Duration, Instant},
 };
+use crate::render_graph::{Access, ImageHandle, RenderGraph, TransientResourcePool};
+use crate::renderer::{TonemapSettings, Tonemapper, HDR_FORMAT};
+use overlay::StatsOverlay;
 use crate::vulkan::*;
 use winit::{
     dpi::PhysicalSize,
     event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
-const IN_FLIGHT_FRAMES: u32 = 2;
+/// Name of the profiler scope covering a whole frame.
+const FRAME_SCOPE: &str = "frame";
+const MAX_PROFILER_SCOPES: u32 = 256;
 
 pub struct BaseApp<B: App> {
     phantom: PhantomData<B>,
     raytracing_enabled: bool,
     pub swapchain: Swapchain,
     pub command_pool: CommandPool,
+    /// HDR images ray traced or rasterized into, one per frame in flight, see
+    /// [`BaseApp::frame_slot`].
     pub storage_images: Vec<ImageAndView>,
+    /// Maps the HDR storage images to the swapchain, on both the ray tracing and raster paths.
+    /// Without ray tracing the settings are [`TonemapSettings::passthrough`].
+    pub tonemapper: Tonemapper,
+    /// One per frame in flight, like the other per-frame resources.
     command_buffers: Vec<CommandBuffer>,
     in_flight_frames: InFlightFrames,
-    pub context: Context,
+    gpu_profiler: GpuProfiler,
+    /// Profiler frame indices of the frames whose GPU time is still to be read back, with
+    /// their number in the frame stats.
+    gpu_timed_frames: VecDeque<(u64, u64)>,
+    transient_resources: RefCell<TransientResourcePool>,
+    pending_swapchain_config: RefCell<Option<SwapchainConfig>>,
+    fullscreen_mode: FullscreenMode,
+    pending_fullscreen_mode: Cell<Option<FullscreenMode>>,
     pub camera: camera::Camera,
-    stats_display_mode: StatsDisplayMode,
+    frame_stats: FrameStats,
+    stats_overlay: StatsOverlay,
+    stats_display_mode: Cell<StatsDisplayMode>,
+    /// Dropped last, once all resources are freed, so that only leaks are reported.
+    pub context: Context,
 }
 
 pub trait App: Sized {
+    /// Swapchain formats, present modes and frame pacing to request, called before the app is
+    /// created. See [`BaseApp::request_swapchain_config`] to change them at runtime.
+    fn swapchain_config() -> SwapchainConfig {
+        SwapchainConfig::default()
+    }
+
+    /// Device features enabled if the device supports them, on top of the ones the base app
+    /// requires. Check [`Context::device_features`] before relying on them.
+    fn optional_device_features() -> DeviceFeatures {
+        DeviceFeatures {
+            runtime_descriptor_array: true,
+            descriptor_indexing: true,
+            occlusion_query_precise: true,
+            pipeline_statistics_query: true,
+            sampler_anisotropy: true,
+            ..Default::default()
+        }
+    }
+
     fn new(base: &mut BaseApp<Self>) -> Result<Self>;
 
     fn update(
@@ -56,6 +103,8 @@ pub trait App: Sized {
         Ok(())
     }
 
+    /// Renders to the storage image of the frame slot, [`BaseApp::storage_images`] at
+    /// [`BaseApp::frame_slot`], in the `COLOR_ATTACHMENT_OPTIMAL` layout.
     fn record_raster_commands(
         &self,
         base: &BaseApp<Self>,
@@ -70,24 +119,52 @@ pub trait App: Sized {
         Ok(())
     }
 
+    /// Adds passes to the frame graph. They run after the ray tracing passes and before the
+    /// raster commands.
+    fn record_render_graph<'a>(
+        &'a self,
+        base: &'a BaseApp<Self>,
+        graph: &mut RenderGraph<'a>,
+        images: FrameImages,
+        image_index: usize,
+    ) -> Result<()> {
+        // prevents reports of unused parameters without needing to use #[allow]
+        let _ = base;
+        let _ = graph;
+        let _ = images;
+        let _ = image_index;
+
+        Ok(())
+    }
+
     fn on_recreate_swapchain(&mut self, base: &BaseApp<Self>) -> Result<()>;
+
+    /// Called after the device was lost, e.g. on a GPU reset, once `base` was recreated on a
+    /// new context. GPU resources created from the lost context must be recreated. By default
+    /// the app is created again.
+    fn on_device_lost(&mut self, base: &mut BaseApp<Self>) -> Result<()> {
+        *self = Self::new(base)?;
+
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum StatsDisplayMode {
-    None,
-    Basic,
-    Full,
+/// Images of the current frame, as imported in the frame graph.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameImages {
+    pub swapchain: ImageHandle,
+    /// HDR output of the ray tracing and raster passes, tonemapped into the swapchain.
+    pub storage: ImageHandle,
 }
 
-impl StatsDisplayMode {
-    fn next(self) -> Self {
-        match self {
-            Self::None => Self::Basic,
-            Self::Basic => Self::Full,
-            Self::Full => Self::None,
-        }
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FullscreenMode {
+    #[default]
+    Windowed,
+    /// Borderless window covering the current monitor.
+    Borderless,
+    /// Exclusive video mode of the current monitor, the largest with the highest refresh rate.
+    Exclusive,
 }
 
 pub fn run<A: App + 'static>(
@@ -97,31 +174,30 @@ pub fn run<A: App + 'static>(
     enable_raytracing: bool,
 ) -> Result<()> {
     let (window, event_loop) = create_window(app_name, width, height);
-    let mut base_app = BaseApp::new(&window, app_name, enable_raytracing)?;
+    let mut base_app = BaseApp::new(
+        &window,
+        app_name,
+        enable_raytracing,
+        &A::swapchain_config(),
+    )?;
     let mut app = A::new(&mut base_app)?;
 
     let mut controls = camera::Controls::default();
     let mut is_swapchain_dirty = false;
+    let mut is_occluded = false;
+    let mut was_minimized = false;
     let mut last_frame = Instant::now();
-    let mut frame_stats = FrameStats::default();
+    let mut frame_pacer = FramePacer::new(base_app.swapchain.config().frame_rate_limit);
+    let app_name = app_name.to_owned();
 
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+        *control_flow = frame_pacer.control_flow();
 
         let app = &mut app; // Make sure it is dropped before base_app
 
         controls = controls.handle_event(&event);
 
         match event {
-            Event::NewEvents(_) => {
-                let now = Instant::now();
-                let frame_time = now - last_frame;
-                last_frame = now;
-
-                frame_stats.set_frame_time(frame_time);
-
-                controls = controls.reset();
-            }
             // On resize
             Event::WindowEvent {
                 event: WindowEvent::Resized(..),
@@ -130,26 +206,84 @@ pub fn run<A: App + 'static>(
                 println!("Window has been resized");
                 is_swapchain_dirty = true;
             }
+            Event::WindowEvent {
+                event: WindowEvent::Occluded(occluded),
+                ..
+            } => is_occluded = occluded,
             // Draw
             Event::MainEventsCleared => {
+                if let Some(mode) = base_app.pending_fullscreen_mode.take() {
+                    base_app.fullscreen_mode = set_fullscreen_mode(&window, mode);
+                    is_swapchain_dirty = true;
+                }
+
+                // Sleep until the window is visible again
+                let size = window.inner_size();
+                if size.width == 0 || size.height == 0 || is_occluded {
+                    was_minimized = true;
+                    frame_pacer.pause();
+                    *control_flow = frame_pacer.control_flow();
+                    return;
+                }
+                if was_minimized {
+                    // Don't count the time spent minimized as a frame
+                    last_frame = Instant::now();
+                    was_minimized = false;
+                }
+
+                let pending_swapchain_config = base_app.pending_swapchain_config.get_mut().take();
+                if let Some(config) = pending_swapchain_config {
+                    match base_app.apply_swapchain_config(&config) {
+                        Ok(()) => app
+                            .on_recreate_swapchain(&base_app)
+                            .expect("Error on recreate swapchain callback"),
+                        Err(err) if is_device_lost(&err) => {
+                            recover_from_device_lost(&err, &mut base_app, app, &window, &app_name);
+                            // The config may not have been applied before the loss
+                            base_app.request_swapchain_config(config.clone());
+                        }
+                        Err(err) => panic!("Failed to apply swapchain config. Cause: {err:#}"),
+                    }
+                    frame_pacer.set_frame_rate_limit(config.frame_rate_limit);
+                }
+
+                let now = Instant::now();
+                if !frame_pacer.is_frame_due(now) {
+                    return;
+                }
+                frame_pacer.frame_started(now);
+
+                let frame_time = now - last_frame;
+                last_frame = now;
+
+                base_app.frame_stats.set_frame_time(frame_time);
+
                 if is_swapchain_dirty {
-                    let dim = window.inner_size();
-                    if dim.width > 0 && dim.height > 0 {
-                        base_app
-                            .recreate_swapchain(dim.width, dim.height)
-                            .expect("Failed to recreate swapchain");
-                        app.on_recreate_swapchain(&base_app)
-                            .expect("Error on recreate swapchain callback");
-                    } else {
-                        return;
+                    match base_app.recreate_swapchain(size.width, size.height) {
+                        Ok(()) => app
+                            .on_recreate_swapchain(&base_app)
+                            .expect("Error on recreate swapchain callback"),
+                        // The new swapchain has the current window size
+                        Err(err) if is_device_lost(&err) => {
+                            recover_from_device_lost(&err, &mut base_app, app, &window, &app_name)
+                        }
+                        Err(err) => panic!("Failed to recreate swapchain. Cause: {err:#}"),
                     }
                 }
 
-                base_app.camera = base_app.camera.update(&controls, frame_stats.frame_time);
+                base_app.camera = base_app.camera.update(&controls, frame_time);
+                controls = controls.reset();
 
-                is_swapchain_dirty = base_app
-                    .draw(&window, app, &mut frame_stats)
-                    .expect("Failed to tick");
+                is_swapchain_dirty = match base_app.draw(&window, app) {
+                    Ok(is_swapchain_dirty) => is_swapchain_dirty,
+                    Err(err) if is_device_lost(&err) => {
+                        recover_from_device_lost(&err, &mut base_app, app, &window, &app_name);
+                        false
+                    }
+                    Err(err) => panic!("Failed to tick. Cause: {err:#}"),
+                };
+
+                *control_flow = frame_pacer.control_flow();
             }
             // Keyboard
             Event::WindowEvent {
@@ -168,6 +302,15 @@ pub fn run<A: App + 'static>(
                 if key_code == VirtualKeyCode::R && state == ElementState::Pressed {
                     base_app.toggle_stats();
                 }
+                if key_code == VirtualKeyCode::V && state == ElementState::Pressed {
+                    base_app.toggle_vsync();
+                }
+                if key_code == VirtualKeyCode::F11 && state == ElementState::Pressed {
+                    base_app.toggle_fullscreen_mode(FullscreenMode::Borderless);
+                }
+                if key_code == VirtualKeyCode::F10 && state == ElementState::Pressed {
+                    base_app.toggle_fullscreen_mode(FullscreenMode::Exclusive);
+                }
             }
             // Mouse
             Event::WindowEvent {
@@ -188,14 +331,64 @@ pub fn run<A: App + 'static>(
                 ..
             } => *control_flow = ControlFlow::Exit,
             // Wait for gpu to finish pending work before closing app
-            Event::LoopDestroyed => base_app
-                .wait_for_gpu()
-                .expect("Failed to wait for gpu to finish work"),
+            Event::LoopDestroyed => match base_app.wait_for_gpu() {
+                // Objects of the lost device can still be destroyed, there is nothing to recreate
+                Err(err) if is_device_lost(&err) => report_device_lost(&err, &base_app.context),
+                result => result.expect("Failed to wait for gpu to finish work"),
+            },
             _ => (),
         }
     });
 }
 
+fn report_device_lost(err: &anyhow::Error, context: &Context) {
+    println!("Device lost: {err:#}");
+    print!("{}", context.device_lost_report());
+}
+
+/// Recreates the context of `base_app` and lets `app` recreate its resources.
+fn recover_from_device_lost<A: App>(
+    err: &anyhow::Error,
+    base_app: &mut BaseApp<A>,
+    app: &mut A,
+    window: &Window,
+    app_name: &str,
+) {
+    report_device_lost(err, &base_app.context);
+
+    base_app
+        .recreate_after_device_lost(window, app_name)
+        .expect("Failed to recreate the context after device loss");
+    app.on_device_lost(base_app)
+        .expect("Error on device lost callback");
+}
+
+/// Returns the applied mode, borderless if no exclusive video mode is available.
+fn set_fullscreen_mode(window: &Window, mode: FullscreenMode) -> FullscreenMode {
+    println!("Setting fullscreen mode to {mode:?}");
+
+    let video_mode = || {
+        window.current_monitor()?.video_modes().max_by_key(|video_mode| {
+            let size = video_mode.size();
+            (size.width * size.height, video_mode.refresh_rate_millihertz())
+        })
+    };
+
+    let (fullscreen, mode) = match (mode, video_mode()) {
+        (FullscreenMode::Windowed, _) => (None, mode),
+        (FullscreenMode::Exclusive, Some(video_mode)) => {
+            (Some(Fullscreen::Exclusive(video_mode)), mode)
+        }
+        (FullscreenMode::Borderless | FullscreenMode::Exclusive, _) => (
+            Some(Fullscreen::Borderless(None)),
+            FullscreenMode::Borderless,
+        ),
+    };
+    window.set_fullscreen(fullscreen);
+
+    mode
+}
+
 fn create_window(app_name: &str, width: u32, height: u32) -> (Window, EventLoop<()>) {
     println!("Creating window and event loop");
     let events_loop = EventLoop::new();
@@ -210,29 +403,54 @@ fn create_window(app_name: &str, width: u32, height: u32) -> (Window, EventLoop<
 }
 
 impl<B: App> BaseApp<B> {
-    fn new(window: &Window, app_name: &str, enable_raytracing: bool) -> Result<Self> {
+    fn new(
+        window: &Window,
+        app_name: &str,
+        enable_raytracing: bool,
+        swapchain_config: &SwapchainConfig,
+    ) -> Result<Self> {
         println!("Create application");
 
         // Vulkan context
         let mut required_extensions = vec!["VK_KHR_swapchain"];
+        let mut optional_extensions = vec![
+            "VK_EXT_hdr_metadata",
+            "VK_EXT_memory_budget",
+            "VK_EXT_device_fault",
+            "VK_NV_device_diagnostic_checkpoints",
+        ];
         if enable_raytracing {
             required_extensions.push("VK_KHR_ray_tracing_pipeline");
             required_extensions.push("VK_KHR_acceleration_structure");
             required_extensions.push("VK_KHR_deferred_host_operations");
+            // Only needed by apps tracing rays from rasterization or compute shaders
+            optional_extensions.push("VK_KHR_ray_query");
         }
 
+        let optional_device_features = B::optional_device_features().union(&DeviceFeatures {
+            ray_query: enable_raytracing,
+            ..Default::default()
+        });
+
         let mut context = ContextBuilder::new(window, window)
             .vulkan_version(VERSION_1_3)
             .app_name(app_name)
             .required_extensions(&required_extensions)
+            .optional_extensions(&optional_extensions)
             .required_device_features(DeviceFeatures {
                 ray_tracing_pipeline: enable_raytracing,
                 acceleration_structure: enable_raytracing,
+                ray_query: false,
                 runtime_descriptor_array: enable_raytracing,
+                descriptor_indexing: false,
                 buffer_device_address: enable_raytracing,
                 dynamic_rendering: true,
                 synchronization2: true,
+                occlusion_query_precise: false,
+                pipeline_statistics_query: false,
+                sampler_anisotropy: false,
             })
+            .optional_device_features(optional_device_features)
             .with_raytracing_context(enable_raytracing)
             .build()?;
 
@@ -241,26 +459,43 @@ impl<B: App> BaseApp<B> {
             Some(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
         )?;
 
-        let swapchain = Swapchain::new(
+        let swapchain = Swapchain::new_with_config(
             &context,
             window.inner_size().width,
             window.inner_size().height,
+            swapchain_config,
         )?;
 
-        let storage_images = if enable_raytracing {
-            create_storage_images(
-                &mut context,
-                swapchain.format,
-                swapchain.extent,
-                swapchain.images.len(),
-            )?
+        let storage_images = create_storage_images(
+            &mut context,
+            HDR_FORMAT,
+            swapchain.extent,
+            swapchain_config.frames_in_flight as _,
+        )?;
+
+        let tonemap_settings = if enable_raytracing {
+            TonemapSettings::default()
         } else {
-            vec![]
+            TonemapSettings::passthrough()
         };
+        let tonemapper = context.create_tonemapper(
+            &storage_images,
+            swapchain.format,
+            swapchain.encoding,
+            tonemap_settings,
+        )?;
+
+        let command_buffers =
+            create_command_buffers(&command_pool, swapchain_config.frames_in_flight)?;
 
-        let command_buffers = create_command_buffers(&command_pool, &swapchain)?;
+        let stats_overlay =
+            StatsOverlay::new(&context, &swapchain, swapchain_config.frames_in_flight)?;
 
-        let in_flight_frames = InFlightFrames::new(&context, IN_FLIGHT_FRAMES)?;
+        let in_flight_frames = InFlightFrames::new(&context, swapchain_config.frames_in_flight)?;
+        let gpu_profiler = create_gpu_profiler(&context, swapchain_config.frames_in_flight)?;
+
+        let transient_resources =
+            context.create_transient_resource_pool(swapchain_config.frames_in_flight);
 
         let camera = camera::Camera::new(
             vec3(0.0, 0.0, 1.0),
@@ -278,33 +513,116 @@ impl<B: App> BaseApp<B> {
             command_pool,
             swapchain,
             storage_images,
+            tonemapper,
             command_buffers,
             in_flight_frames,
+            gpu_profiler,
+            gpu_timed_frames: VecDeque::new(),
+            transient_resources: RefCell::new(transient_resources),
+            pending_swapchain_config: RefCell::new(None),
+            fullscreen_mode: FullscreenMode::Windowed,
+            pending_fullscreen_mode: Cell::new(None),
             camera,
-            stats_display_mode: StatsDisplayMode::Basic,
+            frame_stats: FrameStats::default(),
+            stats_overlay,
+            stats_display_mode: Cell::new(StatsDisplayMode::Basic),
         })
     }
 
+    /// Recreates the context and everything created from it, keeping the camera, swapchain
+    /// config, tonemapping and display settings.
+    fn recreate_after_device_lost(&mut self, window: &Window, app_name: &str) -> Result<()> {
+        println!("Recreating the context after device loss");
+
+        // The window can only have one swapchain, the lost one must go first
+        self.swapchain.destroy();
+
+        let config = self.swapchain.config().clone();
+        let mut base_app = Self::new(window, app_name, self.raytracing_enabled, &config)?;
+        base_app.camera = self.camera;
+        base_app.fullscreen_mode = self.fullscreen_mode;
+        base_app.stats_display_mode = self.stats_display_mode.clone();
+        base_app.frame_stats = std::mem::take(&mut self.frame_stats);
+        base_app.tonemapper.settings = self.tonemapper.settings;
+
+        *self = base_app;
+
+        Ok(())
+    }
+
     fn recreate_swapchain(&mut self, width: u32, height: u32) -> Result<()> {
         println!("Recreating the swapchain");
 
-        self.wait_for_gpu()?;
+        // The old swapchain is passed to the new one, only the frames using it need to finish
+        self.in_flight_frames.wait_all()?;
 
-        // Swapchain and dependent resources
         self.swapchain.resize(&self.context, width, height)?;
 
-        // Recreate storage image for RT and update descriptor set
-        let storage_images = create_storage_images(
-            &mut self.context,
-            self.swapchain.format,
-            self.swapchain.extent,
-            self.swapchain.images.len(),
-        )?;
+        self.recreate_swapchain_resources()
+    }
+
+    /// Applies `config` before the next frame, recreating the swapchain.
+    pub fn request_swapchain_config(&self, config: SwapchainConfig) {
+        *self.pending_swapchain_config.borrow_mut() = Some(config);
+    }
+
+    fn apply_swapchain_config(&mut self, config: &SwapchainConfig) -> Result<()> {
+        println!("Applying swapchain config {config:?}");
 
-        let _ = std::mem::replace(&mut self.storage_images, storage_images);
+        self.wait_for_gpu()?;
+
+        if config.frames_in_flight != self.in_flight_frames.per_frames.len() as u32 {
+            self.in_flight_frames = InFlightFrames::new(&self.context, config.frames_in_flight)?;
+            self.gpu_profiler = create_gpu_profiler(&self.context, config.frames_in_flight)?;
+            self.gpu_timed_frames.clear();
+            *self.transient_resources.get_mut() =
+                self.context.create_transient_resource_pool(config.frames_in_flight);
+        }
+
+        self.swapchain.set_config(&self.context, config)?;
+
+        self.recreate_swapchain_resources()
+    }
+
+    /// Recreates what depends on the swapchain extent or the frames in flight, if they changed.
+    fn recreate_swapchain_resources(&mut self) -> Result<()> {
+        let extent = self.swapchain.extent;
+        let frame_count = self.in_flight_frames.per_frames.len() as u32;
+        let extent_changed = self.storage_images.first().is_none_or(|storage_image| {
+            storage_image.image.extent.width != extent.width
+                || storage_image.image.extent.height != extent.height
+        });
+
+        if extent_changed {
+            self.transient_resources.get_mut().clear();
+        }
+
+        // Recreate storage images and update the tonemapper descriptor sets
+        if extent_changed || self.storage_images.len() != frame_count as usize {
+            let storage_images =
+                create_storage_images(&mut self.context, HDR_FORMAT, extent, frame_count as _)?;
+
+            let _ = std::mem::replace(&mut self.storage_images, storage_images);
+
+            self.tonemapper
+                .resize(&self.context, &self.storage_images)?;
+        }
+        self.tonemapper
+            .set_target(self.swapchain.format, self.swapchain.encoding);
+
+        if self.command_buffers.len() != frame_count as usize {
+            self.command_buffers = create_command_buffers(&self.command_pool, frame_count)?;
+        }
+
+        if !self
+            .stats_overlay
+            .is_compatible(&self.swapchain, frame_count)
+        {
+            self.stats_overlay = StatsOverlay::new(&self.context, &self.swapchain, frame_count)?;
+        }
 
         // Update camera aspect ration
-        self.camera.aspect_ratio = width as f32 / height as f32;
+        self.camera.aspect_ratio = extent.width as f32 / extent.height as f32;
 
         Ok(())
     }
@@ -313,47 +631,41 @@ impl<B: App> BaseApp<B> {
         self.context.device_wait_idle()
     }
 
-    fn draw(
-        &mut self,
-        _: &Window,
-        base_app: &mut B,
-        frame_stats: &mut FrameStats,
-    ) -> Result<bool> {
+    fn draw(&mut self, _: &Window, base_app: &mut B) -> Result<bool> {
         // Drawing the frame
         self.in_flight_frames.next();
         self.in_flight_frames.fence().wait(None)?;
+        self.in_flight_frames.begin_frame(&self.context);
+        self.transient_resources.get_mut().begin_frame();
 
-        // Can't get for gpu time on the first frames or vkGetQueryPoolResults gets stuck
-        // due to VK_QUERY_RESULT_WAIT_BIT
-        let gpu_time = (frame_stats.total_frame_count >= IN_FLIGHT_FRAMES)
-            .then(|| self.in_flight_frames.gpu_frame_time_ms())
-            .transpose()?
-            .unwrap_or_default();
-        frame_stats.set_gpu_time_time(gpu_time);
-        frame_stats.tick();
-
+        // Waiting on the GPU or the presentation engine is not CPU time
+        let cpu_start = Instant::now();
         let next_image_result = self.swapchain.acquire_next_image(
             std::u64::MAX,
             self.in_flight_frames.image_available_semaphore(),
         );
-        let image_index = match next_image_result {
-            Ok(AcquiredImage { index, .. }) => index as usize,
+        let acquire_time = cpu_start.elapsed();
+        // A suboptimal image can still be presented, the swapchain is recreated after
+        let (image_index, is_suboptimal) = match next_image_result {
+            Ok(AcquiredImage {
+                index,
+                is_suboptimal,
+            }) => (index as usize, is_suboptimal),
             Err(err) => match err.downcast_ref::<vk::Result>() {
                 Some(&vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(true),
-                _ => panic!("Error while acquiring next image. Cause: {}", err),
+                _ => return Err(err.context("Error while acquiring next image")),
             },
         };
         self.in_flight_frames.fence().reset()?;
 
-        base_app.update(self, image_index, frame_stats.frame_time)?;
+        let frame_time = self.frame_stats.frame_time();
+        base_app.update(self, image_index, frame_time)?;
 
-        let command_buffer = &self.command_buffers[image_index];
+        // Per-frame resources are indexed by frame slot, the fence of the slot guarantees the
+        // GPU is done with them. The image index may repeat with a different slot.
+        let command_buffer = &self.command_buffers[self.frame_slot()];
 
-        self.record_command_buffer(
-            command_buffer,
-            image_index,
-            base_app,
-        )?;
+        self.record_command_buffer(command_buffer, image_index, base_app, frame_time)?;
 
         self.context.graphics_queue.submit(
             command_buffer,
@@ -368,6 +680,13 @@ impl<B: App> BaseApp<B> {
             self.in_flight_frames.fence(),
         )?;
 
+        let frame = self
+            .frame_stats
+            .tick(cpu_start.elapsed().saturating_sub(acquire_time));
+        self.gpu_timed_frames
+            .push_back((self.gpu_profiler.frame_index(), frame));
+        self.read_gpu_times();
+
         let signal_semaphores = [self.in_flight_frames.render_finished_semaphore()];
         let present_result = self.swapchain.queue_present(
             image_index as _,
@@ -378,12 +697,12 @@ impl<B: App> BaseApp<B> {
             Ok(true) => return Ok(true),
             Err(err) => match err.downcast_ref::<vk::Result>() {
                 Some(&vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(true),
-                _ => panic!("Failed to present queue. Cause: {}", err),
+                _ => return Err(err.context("Failed to present queue")),
             },
             _ => {}
         }
 
-        Ok(false)
+        Ok(is_suboptimal)
     }
 
     fn record_command_buffer(
@@ -391,125 +710,173 @@ impl<B: App> BaseApp<B> {
         buffer: &CommandBuffer,
         image_index: usize,
         base_app: &B,
+        delta_time: Duration,
     ) -> Result<()> {
         let swapchain_image = &self.swapchain.images[image_index];
         let swapchain_image_view = &self.swapchain.views[image_index];
+        let frame_slot = self.frame_slot();
 
         buffer.reset()?;
 
         buffer.begin(None)?;
 
-        buffer.reset_all_timestamp_queries_from_pool(self.in_flight_frames.timing_query_pool());
+        self.gpu_profiler.begin_frame(buffer)?;
+        let frame_scope = buffer.profile_scope(&self.gpu_profiler, FRAME_SCOPE);
+
+        let mut graph = RenderGraph::new();
+        graph.set_profiler(&self.gpu_profiler);
 
-        buffer.write_timestamp(
-            vk::PipelineStageFlags2::NONE,
-            self.in_flight_frames.timing_query_pool(),
-            0,
+        let swapchain = graph.import_image(
+            "swapchain",
+            swapchain_image,
+            Some(swapchain_image_view),
+            Access::Undefined,
         );
+        graph.set_final_access(swapchain, Access::Present);
+
+        let storage_image = &self.storage_images[frame_slot];
+        let storage = graph.import_image(
+            "storage image",
+            &storage_image.image,
+            Some(&storage_image.view),
+            Access::General,
+        );
+        graph.set_final_access(storage, Access::General);
 
         if self.raytracing_enabled {
-            base_app.record_raytracing_commands(self, buffer, image_index)?;
-
-            let storage_image = &self.storage_images[image_index].image;
-            // Copy ray tracing result into swapchain
-            buffer.pipeline_image_barriers(&[
-                ImageBarrier {
-                    image: swapchain_image,
-                    old_layout: vk::ImageLayout::UNDEFINED,
-                    new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    src_access_mask: vk::AccessFlags2::NONE,
-                    dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
-                    src_stage_mask: vk::PipelineStageFlags2::NONE,
-                    dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                },
-                ImageBarrier {
-                    image: storage_image,
-                    old_layout: vk::ImageLayout::GENERAL,
-                    new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                    src_access_mask: vk::AccessFlags2::SHADER_WRITE,
-                    dst_access_mask: vk::AccessFlags2::TRANSFER_READ,
-                    src_stage_mask: vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR,
-                    dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                },
-            ]);
-
-            buffer.copy_image(
-                storage_image,
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                swapchain_image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
-
-            buffer.pipeline_image_barriers(&[
-                ImageBarrier {
-                    image: swapchain_image,
-                    old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                    src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
-                    dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                    src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                    dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                },
-                ImageBarrier {
-                    image: storage_image,
-                    old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                    new_layout: vk::ImageLayout::GENERAL,
-                    src_access_mask: vk::AccessFlags2::TRANSFER_READ,
-                    dst_access_mask: vk::AccessFlags2::NONE,
-                    src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                    dst_stage_mask: vk::PipelineStageFlags2::ALL_COMMANDS,
-                },
-            ]);
+            graph
+                .add_pass("ray tracing")
+                .write(storage, Access::RayTracingShaderStorageWrite)
+                .execute(|buffer, _| {
+                    base_app.record_raytracing_commands(self, buffer, image_index)
+                });
         }
 
-        if !self.raytracing_enabled {
-            buffer.pipeline_image_barriers(&[ImageBarrier {
-                image: swapchain_image,
-                old_layout: vk::ImageLayout::UNDEFINED,
-                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                src_access_mask: vk::AccessFlags2::NONE,
-                dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                src_stage_mask: vk::PipelineStageFlags2::NONE,
-                dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-            }]);
-        }
+        base_app.record_render_graph(
+            self,
+            &mut graph,
+            FrameImages { swapchain, storage },
+            image_index,
+        )?;
 
         // Rasterization
-        base_app.record_raster_commands(self, buffer, image_index)?;
+        graph
+            .add_pass("raster")
+            .write(storage, Access::ColorAttachmentReadWrite)
+            .execute(|buffer, _| base_app.record_raster_commands(self, buffer, image_index));
 
-        // UI
-        buffer.begin_rendering(
-            swapchain_image_view,
-            self.swapchain.extent,
-            vk::AttachmentLoadOp::DONT_CARE,
-            None,
-        );
+        // Tonemap the ray traced or rasterized result into the swapchain
+        self.tonemapper
+            .add_passes(&mut graph, storage, frame_slot, swapchain, delta_time);
 
-        buffer.end_rendering();
-
-        buffer.pipeline_image_barriers(&[ImageBarrier {
-            image: swapchain_image,
-            old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-            src_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-            dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_READ,
-            src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-            dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-        }]);
-
-        buffer.write_timestamp(
-            vk::PipelineStageFlags2::ALL_COMMANDS,
-            self.in_flight_frames.timing_query_pool(),
-            1,
-        );
+        // UI
+        graph
+            .add_pass("ui")
+            .write(swapchain, Access::ColorAttachmentReadWrite)
+            .execute(move |buffer, resources| {
+                buffer.begin_rendering(
+                    resources.image_view(swapchain),
+                    self.swapchain.extent,
+                    vk::AttachmentLoadOp::LOAD,
+                    None,
+                );
+
+                self.stats_overlay.record(
+                    buffer,
+                    frame_slot,
+                    self.swapchain.extent,
+                    &self.frame_stats,
+                    self.stats_display_mode.get(),
+                    self.tonemapper.settings.paper_white,
+                )?;
+
+                buffer.end_rendering();
+
+                Ok(())
+            });
+
+        graph.execute(buffer, &mut self.transient_resources.borrow_mut())?;
+
+        drop(frame_scope);
 
         buffer.end()?;
 
         Ok(())
     }
 
-    fn toggle_stats(&mut self) {
-        self.stats_display_mode = self.stats_display_mode.next();
+    /// Sets the GPU times of the frames the profiler read back.
+    fn read_gpu_times(&mut self) {
+        let history = self.gpu_profiler.history();
+        let Some(latest) = history.back() else {
+            return;
+        };
+
+        while let Some(&(profiler_frame, frame)) = self.gpu_timed_frames.front() {
+            if profiler_frame > latest.frame_index {
+                break;
+            }
+
+            let timings = history
+                .iter()
+                .find(|timings| timings.frame_index == profiler_frame);
+            if let Some(scope) = timings.and_then(|timings| timings.scope(FRAME_SCOPE)) {
+                self.frame_stats.set_gpu_time(frame, scope.duration());
+            }
+            self.gpu_timed_frames.pop_front();
+        }
+    }
+
+    /// Index of the frame in flight being recorded, for per-frame resources such as
+    /// [`BaseApp::storage_images`]. Unlike the swapchain image index, the GPU is done with the
+    /// resources of this slot.
+    pub fn frame_slot(&self) -> usize {
+        self.in_flight_frames.current_frame
+    }
+
+    /// GPU timings of the frame graph passes, in a root scope named "frame".
+    pub fn gpu_profiler(&self) -> &GpuProfiler {
+        &self.gpu_profiler
+    }
+
+    /// Frame, CPU and GPU times of the last frames, e.g. to export them with
+    /// [`FrameStats::write_csv`] or [`FrameStats::write_json`].
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    pub fn stats_display_mode(&self) -> StatsDisplayMode {
+        self.stats_display_mode.get()
+    }
+
+    pub fn set_stats_display_mode(&self, mode: StatsDisplayMode) {
+        self.stats_display_mode.set(mode);
+    }
+
+    fn toggle_stats(&self) {
+        self.set_stats_display_mode(self.stats_display_mode().next());
+    }
+
+    pub fn fullscreen_mode(&self) -> FullscreenMode {
+        self.fullscreen_mode
+    }
+
+    /// Applies `mode` before the next frame. The swapchain is recreated on the following resize.
+    pub fn request_fullscreen_mode(&self, mode: FullscreenMode) {
+        self.pending_fullscreen_mode.set(Some(mode));
+    }
+
+    fn toggle_fullscreen_mode(&self, mode: FullscreenMode) {
+        if self.fullscreen_mode == mode {
+            self.request_fullscreen_mode(FullscreenMode::Windowed);
+        } else {
+            self.request_fullscreen_mode(mode);
+        }
+    }
+
+    fn toggle_vsync(&self) {
+        let config = self.swapchain.config().clone();
+        let vsync = !config.is_vsync();
+        self.request_swapchain_config(config.vsync(vsync));
     }
 }
 
@@ -523,7 +890,10 @@ fn create_storage_images(
 
     for _ in 0..count {
         let image = context.create_image(
-            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::STORAGE,
+            vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::COLOR_ATTACHMENT,
             MemoryLocation::GpuOnly,
             format,
             extent.width,
@@ -550,8 +920,13 @@ fn create_storage_images(
     Ok(images)
 }
 
-fn create_command_buffers(pool: &CommandPool, swapchain: &Swapchain) -> Result<Vec<CommandBuffer>> {
-    pool.allocate_command_buffers(vk::CommandBufferLevel::PRIMARY, swapchain.images.len() as _)
+fn create_gpu_profiler(context: &Context, frames_in_flight: u32) -> Result<GpuProfiler> {
+    // One more frame than in flight so that results are read back without waiting
+    context.create_gpu_profiler(frames_in_flight + 1, MAX_PROFILER_SCOPES)
+}
+
+fn create_command_buffers(pool: &CommandPool, frame_count: u32) -> Result<Vec<CommandBuffer>> {
+    pool.allocate_command_buffers(vk::CommandBufferLevel::PRIMARY, frame_count)
 }
 
 pub struct ImageAndView {
@@ -568,24 +943,25 @@ struct PerFrame {
     image_available_semaphore: Semaphore,
     render_finished_semaphore: Semaphore,
     fence: Fence,
-    timing_query_pool: TimestampQueryPool<2>,
+    /// Deletion frame last recorded with these sync objects, see [`Context::begin_frame`].
+    frame_index: u64,
 }
 
 impl InFlightFrames {
     fn new(context: &Context, frame_count: u32) -> Result<Self> {
+        ensure!(frame_count > 0, "At least one frame must be in flight");
+
         let sync_objects = (0..frame_count)
             .map(|_i| {
                 let image_available_semaphore = context.create_semaphore()?;
                 let render_finished_semaphore = context.create_semaphore()?;
                 let fence = context.create_fence(Some(vk::FenceCreateFlags::SIGNALED))?;
 
-                let timing_query_pool = context.create_timestamp_query_pool()?;
-
                 Ok(PerFrame {
                     image_available_semaphore,
                     render_finished_semaphore,
                     fence,
-                    timing_query_pool,
+                    frame_index: 0,
                 })
             })
             .collect::<Result<Vec<_>>>()?;
@@ -612,103 +988,116 @@ impl InFlightFrames {
         &self.per_frames[self.current_frame].fence
     }
 
-    fn timing_query_pool(&self) -> &TimestampQueryPool<2> {
-        &self.per_frames[self.current_frame].timing_query_pool
+    /// Frees what the current frame's previous use dropped and starts a new deletion frame,
+    /// once its fence is signaled.
+    fn begin_frame(&mut self, context: &Context) {
+        let frame = &mut self.per_frames[self.current_frame];
+        context.complete_frame(frame.frame_index);
+        frame.frame_index = context.begin_frame();
     }
 
-    fn gpu_frame_time_ms(&self) -> Result<Duration> {
-        let result = self.timing_query_pool().wait_for_all_results()?;
-        let time = Duration::from_nanos(result[1].saturating_sub(result[0]));
-
-        Ok(time)
+    fn wait_all(&self) -> Result<()> {
+        self.per_frames
+            .iter()
+            .try_for_each(|frame| frame.fence.wait(None))
     }
 }
 
-#[derive(Debug)]
-struct FrameStats {
-    // we collect gpu timings the frame after it was computed
-    // so we keep frame times for the two last frames
-    previous_frame_time: Duration,
-    frame_time: Duration,
-    cpu_time: Duration,
-    gpu_time: Duration,
-    frame_time_ms_log: Queue<f32>,
-    cpu_time_ms_log: Queue<f32>,
-    gpu_time_ms_log: Queue<f32>,
-    total_frame_count: u32,
-    frame_count: u32,
-    fps_counter: u32,
-    timer: Duration,
+/// Paces frames to a maximum frame rate, waiting in the event loop instead of spinning.
+struct FramePacer {
+    frame_duration: Option<Duration>,
+    next_frame: Instant,
+    /// No frame is rendered until the next event, e.g. while minimized.
+    paused: bool,
 }
 
-impl Default for FrameStats {
-    fn default() -> Self {
-        Self {
-            previous_frame_time: Default::default(),
-            frame_time: Default::default(),
-            cpu_time: Default::default(),
-            gpu_time: Default::default(),
-            frame_time_ms_log: Queue::new(FrameStats::MAX_LOG_SIZE),
-            cpu_time_ms_log: Queue::new(FrameStats::MAX_LOG_SIZE),
-            gpu_time_ms_log: Queue::new(FrameStats::MAX_LOG_SIZE),
-            total_frame_count: Default::default(),
-            frame_count: Default::default(),
-            fps_counter: Default::default(),
-            timer: Default::default(),
-        }
+impl FramePacer {
+    fn new(frame_rate_limit: Option<u32>) -> Self {
+        let mut pacer = Self {
+            frame_duration: None,
+            next_frame: Instant::now(),
+            paused: false,
+        };
+        pacer.set_frame_rate_limit(frame_rate_limit);
+        pacer
     }
-}
 
-impl FrameStats {
-    const ONE_SEC: Duration = Duration::from_secs(1);
-    const MAX_LOG_SIZE: usize = 1000;
-
-    fn tick(&mut self) {
-        // compute cpu time
-        self.cpu_time = self.previous_frame_time.saturating_sub(self.gpu_time);
-
-        // push log
-        self.frame_time_ms_log
-            .push(self.previous_frame_time.as_millis() as _);
-        self.cpu_time_ms_log.push(self.cpu_time.as_millis() as _);
-        self.gpu_time_ms_log.push(self.gpu_time.as_millis() as _);
-
-        // increment counter
-        self.total_frame_count += 1;
-        self.frame_count += 1;
-        self.timer += self.frame_time;
-
-        // reset counter if a sec has passed
-        if self.timer > FrameStats::ONE_SEC {
-            self.fps_counter = self.frame_count;
-            self.frame_count = 0;
-            self.timer -= FrameStats::ONE_SEC;
-        }
+    fn set_frame_rate_limit(&mut self, frame_rate_limit: Option<u32>) {
+        self.frame_duration = frame_rate_limit
+            .filter(|&fps| fps > 0)
+            .map(|fps| Duration::from_secs(1) / fps);
     }
 
-    fn set_frame_time(&mut self, frame_time: Duration) {
-        self.previous_frame_time = self.frame_time;
-        self.frame_time = frame_time;
+    fn is_frame_due(&self, now: Instant) -> bool {
+        self.frame_duration.is_none() || now >= self.next_frame
     }
 
-    fn set_gpu_time_time(&mut self, gpu_time: Duration) {
-        self.gpu_time = gpu_time;
+    fn frame_started(&mut self, now: Instant) {
+        self.paused = false;
+        if let Some(frame_duration) = self.frame_duration {
+            // Don't try to catch up on missed frames
+            self.next_frame = (self.next_frame + frame_duration).max(now);
+        }
     }
-}
 
-#[derive(Debug)]
-struct Queue<T>(Vec<T>, usize);
-
-impl<T> Queue<T> {
-    fn new(max_size: usize) -> Self {
-        Self(Vec::with_capacity(max_size), max_size)
+    fn pause(&mut self) {
+        self.paused = true;
     }
 
-    fn push(&mut self, value: T) {
-        if self.0.len() == self.1 {
-            self.0.remove(0);
+    /// Only polls while rendering without a limit, otherwise waits for the next frame or event.
+    fn control_flow(&self) -> ControlFlow {
+        match self.frame_duration {
+            _ if self.paused => ControlFlow::Wait,
+            Some(_) => ControlFlow::WaitUntil(self.next_frame),
+            None => ControlFlow::Poll,
         }
-        self.0.push(value);
     }
 }
 
+#[test]
+fn test_frame_pacer() {
+    let start = Instant::now();
+    let ms = Duration::from_millis;
+
+    let mut pacer = FramePacer::new(Some(100));
+    pacer.next_frame = start;
+    assert!(pacer.is_frame_due(start));
+    assert_eq!(pacer.control_flow(), ControlFlow::WaitUntil(start));
+
+    pacer.frame_started(start);
+    assert!(!pacer.is_frame_due(start + ms(9)));
+    assert!(pacer.is_frame_due(start + ms(10)));
+    assert_eq!(pacer.control_flow(), ControlFlow::WaitUntil(start + ms(10)));
+
+    // Frames started a bit late keep the schedule
+    pacer.frame_started(start + ms(12));
+    assert_eq!(pacer.next_frame, start + ms(20));
+
+    // Missed frames are not caught up on, pacing resumes after a single due frame
+    pacer.frame_started(start + ms(55));
+    assert!(pacer.is_frame_due(start + ms(55)));
+    pacer.frame_started(start + ms(56));
+    assert_eq!(pacer.next_frame, start + ms(65));
+
+    // Without a limit every frame is due
+    pacer.set_frame_rate_limit(None);
+    assert!(pacer.is_frame_due(start));
+    assert_eq!(pacer.control_flow(), ControlFlow::Poll);
+    pacer.frame_started(start + ms(100));
+    assert_eq!(pacer.next_frame, start + ms(65));
+
+    pacer.set_frame_rate_limit(Some(0));
+    assert_eq!(pacer.control_flow(), ControlFlow::Poll);
+
+    pacer.set_frame_rate_limit(Some(50));
+    assert_eq!(pacer.frame_duration, Some(ms(20)));
+    assert!(!pacer.is_frame_due(start + ms(64)));
+
+    // Paused, e.g. minimized, waits for events with or without a limit
+    pacer.pause();
+    assert_eq!(pacer.control_flow(), ControlFlow::Wait);
+    pacer.set_frame_rate_limit(None);
+    assert_eq!(pacer.control_flow(), ControlFlow::Wait);
+    pacer.frame_started(start + ms(200));
+    assert_eq!(pacer.control_flow(), ControlFlow::Poll);
+}
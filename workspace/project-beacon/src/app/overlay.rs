@@ -0,0 +1,316 @@
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+
+use crate::app::{FrameStats, StatsDisplayMode};
+use crate::renderer::OutputTransform;
+use crate::vulkan::{
+    Buffer, CommandBuffer, Context, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+    GraphicsPipeline, GraphicsPipelineCreateInfo, GraphicsShaderCreateInfo, PipelineLayout, Pod,
+    Swapchain, SwapchainEncoding, WriteDescriptorSet, WriteDescriptorSetKind,
+};
+
+// Must match overlay.glsl
+const MAX_GLYPHS: usize = 256;
+const MAX_GRAPH_SAMPLES: usize = 256;
+
+/// Size of a font pixel, in pixels.
+const FONT_SCALE: f32 = 2.0;
+const CELL_WIDTH: f32 = 4.0;
+const CELL_HEIGHT: f32 = 6.0;
+const GRAPH_HEIGHT: f32 = 64.0;
+
+/// Frame stats drawn on top of the swapchain, in the top left corner.
+pub(crate) struct StatsOverlay {
+    format: vk::Format,
+    encoding: SwapchainEncoding,
+    output_transform: OutputTransform,
+    /// One per swapchain image, written by the CPU when recording.
+    buffers: Vec<Buffer>,
+    descriptor_sets: Vec<DescriptorSet>,
+    _descriptor_pool: DescriptorPool,
+    _descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: GraphicsPipeline,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct PushConstants {
+    screen_size: [f32; 2],
+    panel_size: [f32; 2],
+    scale: f32,
+    column_count: u32,
+    line_count: u32,
+    graph_sample_count: u32,
+    graph_height: f32,
+    graph_max_ms: f32,
+    output_transform: u32,
+    paper_white: f32,
+}
+
+// Only 4 byte fields, no padding
+unsafe impl Pod for PushConstants {}
+
+impl StatsOverlay {
+    /// Creates one glyph buffer per frame in flight.
+    pub(crate) fn new(context: &Context, swapchain: &Swapchain, frame_count: u32) -> Result<Self> {
+        let descriptor_set_layout =
+            context.create_descriptor_set_layout(&[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()])?;
+
+        let pipeline_layout = context.create_pipeline_layout_with_push_constants(
+            &[&descriptor_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as _,
+            }],
+        )?;
+
+        let pipeline = context.create_graphics_pipeline::<()>(
+            &pipeline_layout,
+            GraphicsPipelineCreateInfo {
+                shaders: &[
+                    GraphicsShaderCreateInfo {
+                        source: &include_bytes!("./shaders/overlay.vert.spv")[..],
+                        stage: vk::ShaderStageFlags::VERTEX,
+                    },
+                    GraphicsShaderCreateInfo {
+                        source: &include_bytes!("./shaders/overlay.frag.spv")[..],
+                        stage: vk::ShaderStageFlags::FRAGMENT,
+                    },
+                ],
+                primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                extent: None,
+                color_attachment_formats: &[swapchain.format],
+                color_attachment_blend: Some(vk::PipelineColorBlendAttachmentState {
+                    blend_enable: vk::TRUE,
+                    src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                    dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                    color_blend_op: vk::BlendOp::ADD,
+                    src_alpha_blend_factor: vk::BlendFactor::ZERO,
+                    dst_alpha_blend_factor: vk::BlendFactor::ONE,
+                    alpha_blend_op: vk::BlendOp::ADD,
+                    color_write_mask: vk::ColorComponentFlags::RGBA,
+                }),
+                depth_attachment_format: None,
+                stencil_attachment_format: None,
+                depth_stencil_state: None,
+                samples: None,
+                dynamic_states: Some(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]),
+            },
+        )?;
+
+        let buffers = (0..frame_count)
+            .map(|_| {
+                context.create_buffer(
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    MemoryLocation::CpuToGpu,
+                    ((MAX_GLYPHS + MAX_GRAPH_SAMPLES) * std::mem::size_of::<u32>()) as _,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let descriptor_pool = context.create_descriptor_pool(
+            frame_count,
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: frame_count,
+            }],
+        )?;
+        let descriptor_sets = descriptor_pool.allocate_sets(&descriptor_set_layout, frame_count)?;
+        for (set, buffer) in descriptor_sets.iter().zip(&buffers) {
+            set.update(&[WriteDescriptorSet {
+                binding: 0,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer { buffer },
+            }]);
+        }
+
+        Ok(Self {
+            format: swapchain.format,
+            encoding: swapchain.encoding,
+            output_transform: OutputTransform::new(swapchain.format, swapchain.encoding),
+            buffers,
+            descriptor_sets,
+            _descriptor_pool: descriptor_pool,
+            _descriptor_set_layout: descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    /// Whether the overlay can still draw to `swapchain`, or must be recreated.
+    pub(crate) fn is_compatible(&self, swapchain: &Swapchain, frame_count: u32) -> bool {
+        self.format == swapchain.format
+            && self.encoding == swapchain.encoding
+            && self.buffers.len() == frame_count as usize
+    }
+
+    /// Draws the overlay, inside a rendering to the swapchain image. `frame_slot` picks the
+    /// glyph buffer, which must not be in use by the GPU.
+    pub(crate) fn record(
+        &self,
+        cmd_buffer: &CommandBuffer,
+        frame_slot: usize,
+        extent: vk::Extent2D,
+        stats: &FrameStats,
+        mode: StatsDisplayMode,
+        paper_white: f32,
+    ) -> Result<()> {
+        if mode == StatsDisplayMode::None {
+            return Ok(());
+        }
+
+        let lines = stats_lines(stats, mode);
+        let line_count = lines.len();
+        let column_count = lines
+            .iter()
+            .map(|line| line.len())
+            .max()
+            .unwrap_or_default()
+            .min(MAX_GLYPHS / line_count);
+
+        let mut data = vec![0u32; MAX_GLYPHS + MAX_GRAPH_SAMPLES];
+        for (line_index, line) in lines.iter().enumerate() {
+            for (column, c) in line.chars().take(column_count).enumerate() {
+                data[line_index * column_count + column] = glyph(c);
+            }
+        }
+
+        let graph = match mode {
+            StatsDisplayMode::Full => {
+                let samples = stats.samples();
+                let skipped = samples.len().saturating_sub(MAX_GRAPH_SAMPLES);
+                samples
+                    .skip(skipped)
+                    .map(|sample| sample.frame_time_ms)
+                    .collect::<Vec<_>>()
+            }
+            _ => vec![],
+        };
+        for (value, frame_time) in data[MAX_GLYPHS..].iter_mut().zip(&graph) {
+            *value = frame_time.to_bits();
+        }
+        self.buffers[frame_slot].copy_data_to_buffer(&data)?;
+
+        let padding = 2.0 * FONT_SCALE;
+        let text_width = column_count as f32 * CELL_WIDTH * FONT_SCALE;
+        let text_height = line_count as f32 * CELL_HEIGHT * FONT_SCALE;
+        let (graph_width, graph_height) = if graph.is_empty() {
+            (0.0, 0.0)
+        } else {
+            (graph.len().max(128) as f32, GRAPH_HEIGHT + padding)
+        };
+        let graph_max_ms = graph.iter().copied().fold(1.0, f32::max);
+
+        cmd_buffer.bind_graphics_pipeline(&self.pipeline);
+        cmd_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            &self.pipeline_layout,
+            0,
+            &[&self.descriptor_sets[frame_slot]],
+        );
+        cmd_buffer.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &PushConstants {
+                screen_size: [extent.width as _, extent.height as _],
+                panel_size: [
+                    text_width.max(graph_width) + 2.0 * padding,
+                    text_height + graph_height + 2.0 * padding,
+                ],
+                scale: FONT_SCALE,
+                column_count: column_count as _,
+                line_count: line_count as _,
+                graph_sample_count: graph.len() as _,
+                graph_height: GRAPH_HEIGHT,
+                graph_max_ms,
+                output_transform: self.output_transform as _,
+                paper_white,
+            },
+        );
+        cmd_buffer.set_viewport(extent);
+        cmd_buffer.set_scissor(extent);
+        cmd_buffer.draw(6);
+
+        Ok(())
+    }
+}
+
+fn stats_lines(stats: &FrameStats, mode: StatsDisplayMode) -> Vec<String> {
+    let mut lines = vec![
+        format!("{} FPS", stats.fps()),
+        format!("FRAME {:.2} MS", stats.frame_time().as_secs_f32() * 1000.0),
+    ];
+
+    if mode == StatsDisplayMode::Full {
+        let summary = stats.summary();
+        let frame_time = &summary.frame_time;
+        lines.extend([
+            format!("CPU {:.2} MS", stats.cpu_time().as_secs_f32() * 1000.0),
+            format!("GPU {:.2} MS", stats.gpu_time().as_secs_f32() * 1000.0),
+            format!(
+                "P50 {:.2} P95 {:.2} P99 {:.2}",
+                frame_time.p50, frame_time.p95, frame_time.p99
+            ),
+            format!("1% LOW {:.0} FPS", summary.one_percent_low_fps),
+        ]);
+    }
+
+    lines
+}
+
+/// 3x5 bitmap of `c`, rows first with the top left pixel in bit 14. Blank if not supported.
+fn glyph(c: char) -> u32 {
+    match c.to_ascii_uppercase() {
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b111_001_111_100_111,
+        '3' => 0b111_001_111_001_111,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_111_001_111,
+        '6' => 0b111_100_111_101_111,
+        '7' => 0b111_001_001_001_001,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_111,
+        'A' => 0b010_101_111_101_101,
+        'B' => 0b110_101_110_101_110,
+        'C' => 0b011_100_100_100_011,
+        'D' => 0b110_101_101_101_110,
+        'E' => 0b111_100_110_100_111,
+        'F' => 0b111_100_110_100_100,
+        'G' => 0b011_100_101_101_011,
+        'H' => 0b101_101_111_101_101,
+        'I' => 0b111_010_010_010_111,
+        'J' => 0b001_001_001_101_010,
+        'K' => 0b101_101_110_101_101,
+        'L' => 0b100_100_100_100_111,
+        'M' => 0b101_111_111_101_101,
+        'N' => 0b110_101_101_101_101,
+        'O' => 0b010_101_101_101_010,
+        'P' => 0b110_101_110_100_100,
+        'Q' => 0b010_101_101_110_011,
+        'R' => 0b110_101_110_101_101,
+        'S' => 0b011_100_010_001_110,
+        'T' => 0b111_010_010_010_010,
+        'U' => 0b101_101_101_101_111,
+        'V' => 0b101_101_101_101_010,
+        'W' => 0b101_101_111_111_101,
+        'X' => 0b101_101_010_101_101,
+        'Y' => 0b101_101_010_010_010,
+        'Z' => 0b111_001_010_100_111,
+        '.' => 0b000_000_000_000_010,
+        ':' => 0b000_010_000_010_000,
+        '%' => 0b101_001_010_100_101,
+        '/' => 0b001_001_010_100_100,
+        '-' => 0b000_000_111_000_000,
+        _ => 0,
+    }
+}
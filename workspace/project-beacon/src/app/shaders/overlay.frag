@@ -0,0 +1,52 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+
+#include "overlay.glsl"
+#include "../../renderer/tonemap/shaders/output.glsl"
+
+// 3x5 glyphs in 4x6 cells
+#define GLYPH_SIZE vec2(3.0, 5.0)
+#define CELL_SIZE vec2(4.0, 6.0)
+
+layout(set = 0, binding = 0) readonly buffer Overlay {
+    // Bit 14 is the top left pixel, rows first
+    uint glyphs[MAX_GLYPHS];
+    // Frame times in milliseconds, oldest first
+    float graph[MAX_GRAPH_SAMPLES];
+};
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    float padding = 2.0 * scale;
+    vec2 p = gl_FragCoord.xy - MARGIN - padding;
+    float inner_width = panel_size.x - 2.0 * padding;
+    float text_height = float(line_count) * CELL_SIZE.y * scale;
+
+    vec4 color = vec4(0.0, 0.0, 0.0, 0.6);
+
+    if (p.x >= 0.0 && p.y >= 0.0 && p.y < text_height) {
+        uvec2 cell = uvec2(p / (CELL_SIZE * scale));
+        uvec2 texel = uvec2(mod(p / scale, CELL_SIZE));
+        if (cell.x < column_count && texel.x < uint(GLYPH_SIZE.x) && texel.y < uint(GLYPH_SIZE.y)) {
+            uint glyph = glyphs[cell.y * column_count + cell.x];
+            uint bit = 14 - (texel.y * 3 + texel.x);
+            if (((glyph >> bit) & 1) != 0) {
+                color = vec4(1.0);
+            }
+        }
+    } else if (graph_sample_count > 0 && p.x >= 0.0 && p.x < inner_width) {
+        float y = p.y - text_height - padding;
+        if (y >= 0.0 && y < graph_height) {
+            uint index = min(uint(p.x / inner_width * float(graph_sample_count)), graph_sample_count - 1);
+            float t = clamp(graph[index] / graph_max_ms, 0.0, 1.0);
+            if (graph_height - y <= t * graph_height) {
+                color = vec4(mix(vec3(0.2, 0.9, 0.2), vec3(0.9, 0.2, 0.2), t), 1.0);
+            } else {
+                color.a = 0.8;
+            }
+        }
+    }
+
+    out_color = vec4(encode_output(color.rgb, output_transform, paper_white), color.a);
+}
@@ -0,0 +1,19 @@
+// Panel offset from the top left corner, in pixels
+#define MARGIN 8.0
+
+#define MAX_GLYPHS 256
+#define MAX_GRAPH_SAMPLES 256
+
+layout(push_constant) uniform PushConstants {
+    vec2 screen_size;
+    vec2 panel_size;
+    // Size of a font pixel, in pixels
+    float scale;
+    uint column_count;
+    uint line_count;
+    uint graph_sample_count;
+    float graph_height;
+    float graph_max_ms;
+    uint output_transform;
+    float paper_white;
+};
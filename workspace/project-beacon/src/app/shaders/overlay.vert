@@ -0,0 +1,15 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+
+#include "overlay.glsl"
+
+// Counter clockwise in framebuffer space
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(1.0, 0.0),
+    vec2(0.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 1.0)
+);
+
+void main() {
+    vec2 position = MARGIN + CORNERS[gl_VertexIndex] * panel_size;
+    gl_Position = vec4(position / screen_size * 2.0 - 1.0, 0.0, 1.0);
+}
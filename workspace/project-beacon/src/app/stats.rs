@@ -0,0 +1,420 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::Result;
+
+/// What the stats overlay shows, cycled with R.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatsDisplayMode {
+    None,
+    /// FPS and frame time.
+    #[default]
+    Basic,
+    /// Also CPU and GPU times, percentiles and a frame time graph.
+    Full,
+}
+
+impl StatsDisplayMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Basic,
+            Self::Basic => Self::Full,
+            Self::Full => Self::None,
+        }
+    }
+}
+
+/// Timings of a frame, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameSample {
+    /// Number of the frame since the start, counting from 0.
+    pub frame: u64,
+    pub frame_time_ms: f32,
+    /// From the start of the frame to its submission, without waiting on the GPU.
+    pub cpu_time_ms: f32,
+    /// `None` until read back, a few frames later.
+    pub gpu_time_ms: Option<f32>,
+}
+
+/// Distribution of a timing, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimingSummary {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    /// Mean of the slowest 1% of the samples.
+    pub one_percent_low: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameStatsSummary {
+    pub sample_count: usize,
+    pub frame_time: TimingSummary,
+    pub cpu_time: TimingSummary,
+    /// Of the frames whose GPU time was read back.
+    pub gpu_time: TimingSummary,
+    /// Frames per second over the whole log.
+    pub average_fps: f32,
+    /// Frames per second of the slowest 1% of the frames.
+    pub one_percent_low_fps: f32,
+}
+
+/// Sample counts of equally wide buckets between `min` and `max`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Histogram {
+    pub min: f32,
+    pub max: f32,
+    pub counts: Vec<u32>,
+}
+
+/// Frame, CPU and GPU times of the last frames.
+#[derive(Debug)]
+pub struct FrameStats {
+    frame_time: Duration,
+    cpu_time: Duration,
+    /// Of the latest frame read back, a few frames ago.
+    gpu_time: Duration,
+    samples: VecDeque<FrameSample>,
+    max_sample_count: usize,
+    total_frame_count: u64,
+    frame_count: u32,
+    fps_counter: u32,
+    timer: Duration,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self::new(FrameStats::DEFAULT_MAX_SAMPLE_COUNT)
+    }
+}
+
+impl FrameStats {
+    const ONE_SEC: Duration = Duration::from_secs(1);
+    pub const DEFAULT_MAX_SAMPLE_COUNT: usize = 1000;
+
+    /// Keeps the last `max_sample_count` frames.
+    pub fn new(max_sample_count: usize) -> Self {
+        Self {
+            frame_time: Default::default(),
+            cpu_time: Default::default(),
+            gpu_time: Default::default(),
+            samples: VecDeque::with_capacity(max_sample_count),
+            max_sample_count,
+            total_frame_count: Default::default(),
+            frame_count: Default::default(),
+            fps_counter: Default::default(),
+            timer: Default::default(),
+        }
+    }
+
+    /// Logs a submitted frame, returns its number for [`FrameStats::set_gpu_time`].
+    pub(crate) fn tick(&mut self, cpu_time: Duration) -> u64 {
+        let frame = self.total_frame_count;
+        self.cpu_time = cpu_time;
+
+        // push log
+        self.push_sample(FrameSample {
+            frame,
+            frame_time_ms: milliseconds(self.frame_time),
+            cpu_time_ms: milliseconds(cpu_time),
+            gpu_time_ms: None,
+        });
+
+        // increment counter
+        self.total_frame_count += 1;
+        self.frame_count += 1;
+        self.timer += self.frame_time;
+
+        // reset counter if a sec has passed
+        if self.timer > FrameStats::ONE_SEC {
+            self.fps_counter = self.frame_count;
+            self.frame_count = 0;
+            self.timer -= FrameStats::ONE_SEC;
+        }
+
+        frame
+    }
+
+    pub(crate) fn set_frame_time(&mut self, frame_time: Duration) {
+        self.frame_time = frame_time;
+    }
+
+    /// Sets the GPU time of `frame` once read back, if it is still logged.
+    pub(crate) fn set_gpu_time(&mut self, frame: u64, gpu_time: Duration) {
+        self.gpu_time = gpu_time;
+        if let Some(sample) = self.samples.iter_mut().rev().find(|s| s.frame == frame) {
+            sample.gpu_time_ms = Some(milliseconds(gpu_time));
+        }
+    }
+
+    fn push_sample(&mut self, sample: FrameSample) {
+        if self.samples.len() == self.max_sample_count {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    pub fn gpu_time(&self) -> Duration {
+        self.gpu_time
+    }
+
+    /// Frames rendered during the last second.
+    pub fn fps(&self) -> u32 {
+        self.fps_counter
+    }
+
+    pub fn total_frame_count(&self) -> u64 {
+        self.total_frame_count
+    }
+
+    /// Logged frames, oldest first.
+    pub fn samples(&self) -> impl ExactSizeIterator<Item = &FrameSample> + '_ {
+        self.samples.iter()
+    }
+
+    pub fn summary(&self) -> FrameStatsSummary {
+        let summarize = |timing: fn(&FrameSample) -> Option<f32>| {
+            TimingSummary::new(&self.samples.iter().filter_map(timing).collect::<Vec<_>>())
+        };
+        let frame_time = summarize(|s| Some(s.frame_time_ms));
+
+        FrameStatsSummary {
+            sample_count: self.samples.len(),
+            frame_time,
+            cpu_time: summarize(|s| Some(s.cpu_time_ms)),
+            gpu_time: summarize(|s| s.gpu_time_ms),
+            average_fps: fps(frame_time.mean),
+            one_percent_low_fps: fps(frame_time.one_percent_low),
+        }
+    }
+
+    pub fn frame_time_histogram(&self, bucket_count: usize) -> Histogram {
+        Histogram::new(
+            &self
+                .samples
+                .iter()
+                .map(|s| s.frame_time_ms)
+                .collect::<Vec<_>>(),
+            bucket_count,
+        )
+    }
+
+    /// One line per logged frame, with a header. GPU times not read back yet are empty.
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "frame,frame_time_ms,cpu_time_ms,gpu_time_ms")?;
+        for sample in &self.samples {
+            let gpu_time_ms = sample
+                .gpu_time_ms
+                .map_or(String::new(), |time| time.to_string());
+            writeln!(
+                writer,
+                "{},{},{},{gpu_time_ms}",
+                sample.frame, sample.frame_time_ms, sample.cpu_time_ms
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The summary and the logged frames. GPU times not read back yet are `null`.
+    pub fn write_json(&self, mut writer: impl Write) -> Result<()> {
+        fn timing_json(summary: &TimingSummary) -> String {
+            format!(
+                r#"{{"min":{},"max":{},"mean":{},"p50":{},"p95":{},"p99":{},"one_percent_low":{}}}"#,
+                json_number(summary.min),
+                json_number(summary.max),
+                json_number(summary.mean),
+                json_number(summary.p50),
+                json_number(summary.p95),
+                json_number(summary.p99),
+                json_number(summary.one_percent_low),
+            )
+        }
+
+        let summary = self.summary();
+        write!(
+            writer,
+            r#"{{"summary":{{"sample_count":{},"average_fps":{},"one_percent_low_fps":{},"frame_time_ms":{},"cpu_time_ms":{},"gpu_time_ms":{}}},"samples":["#,
+            summary.sample_count,
+            json_number(summary.average_fps),
+            json_number(summary.one_percent_low_fps),
+            timing_json(&summary.frame_time),
+            timing_json(&summary.cpu_time),
+            timing_json(&summary.gpu_time),
+        )?;
+        for (index, sample) in self.samples.iter().enumerate() {
+            if index > 0 {
+                write!(writer, ",")?;
+            }
+            let gpu_time_ms = sample
+                .gpu_time_ms
+                .map_or("null".to_owned(), |time| json_number(time).to_string());
+            write!(
+                writer,
+                r#"{{"frame":{},"frame_time_ms":{},"cpu_time_ms":{},"gpu_time_ms":{gpu_time_ms}}}"#,
+                sample.frame,
+                json_number(sample.frame_time_ms),
+                json_number(sample.cpu_time_ms),
+            )?;
+        }
+        writeln!(writer, "]}}")?;
+
+        Ok(())
+    }
+}
+
+impl TimingSummary {
+    /// All zeros if `values` is empty.
+    pub fn new(values: &[f32]) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f32::total_cmp);
+
+        let slowest = sorted.len().div_ceil(100);
+        let mean = |values: &[f32]| values.iter().sum::<f32>() / values.len() as f32;
+
+        Self {
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: mean(&sorted),
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            one_percent_low: mean(&sorted[sorted.len() - slowest..]),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn new(values: &[f32], bucket_count: usize) -> Self {
+        let min = values.iter().copied().reduce(f32::min).unwrap_or(0.0);
+        let max = values.iter().copied().reduce(f32::max).unwrap_or(0.0);
+
+        let mut counts = vec![0; bucket_count];
+        if bucket_count > 0 {
+            let width = (max - min) / bucket_count as f32;
+            for value in values {
+                let bucket = if width > 0.0 {
+                    ((value - min) / width) as usize
+                } else {
+                    0
+                };
+                counts[bucket.min(bucket_count - 1)] += 1;
+            }
+        }
+
+        Self { min, max, counts }
+    }
+
+    pub fn bucket_width(&self) -> f32 {
+        (self.max - self.min) / self.counts.len().max(1) as f32
+    }
+}
+
+/// Nearest rank percentile of sorted values.
+fn percentile(sorted: &[f32], percentile: f32) -> f32 {
+    let rank = (percentile * sorted.len() as f32 / 100.0).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn milliseconds(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+fn fps(frame_time_ms: f32) -> f32 {
+    if frame_time_ms > 0.0 {
+        1000.0 / frame_time_ms
+    } else {
+        0.0
+    }
+}
+
+/// JSON has no NaN or infinity.
+fn json_number(value: f32) -> f32 {
+    if value.is_finite() {
+        value
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn test_timing_summary() {
+    let values = (1..=200).map(|v| v as f32).collect::<Vec<_>>();
+    let summary = TimingSummary::new(&values);
+
+    assert_eq!(summary.min, 1.0);
+    assert_eq!(summary.max, 200.0);
+    assert_eq!(summary.mean, 100.5);
+    assert_eq!(summary.p50, 100.0);
+    assert_eq!(summary.p95, 190.0);
+    assert_eq!(summary.p99, 198.0);
+    assert_eq!(summary.one_percent_low, 199.5);
+
+    let histogram = Histogram::new(&values, 4);
+    assert_eq!(histogram.counts, vec![50, 50, 50, 50]);
+}
+
+#[test]
+fn test_write_csv() {
+    let mut stats = FrameStats::new(2);
+    for (frame_time, cpu_time) in [(10, 4), (20, 5), (16, 6)] {
+        stats.set_frame_time(Duration::from_millis(frame_time));
+        stats.tick(Duration::from_millis(cpu_time));
+    }
+    // Frame 0 was dropped from the log
+    stats.set_gpu_time(0, Duration::from_millis(9));
+    stats.set_gpu_time(1, Duration::from_millis(8));
+
+    let mut csv = vec![];
+    stats.write_csv(&mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        concat!(
+            "frame,frame_time_ms,cpu_time_ms,gpu_time_ms\n",
+            "1,20,5,8\n",
+            "2,16,6,\n",
+        )
+    );
+    assert_eq!(stats.gpu_time(), Duration::from_millis(8));
+}
+
+#[test]
+fn test_write_json() {
+    let mut stats = FrameStats::new(10);
+    for frame_time in [20, 30] {
+        stats.set_frame_time(Duration::from_millis(frame_time));
+        stats.tick(Duration::from_millis(2));
+    }
+    stats.set_gpu_time(0, Duration::from_millis(5));
+
+    let mut json = vec![];
+    stats.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+
+    // Only read back GPU times are summarized
+    let gpu_summary =
+        r#""gpu_time_ms":{"min":5,"max":5,"mean":5,"p50":5,"p95":5,"p99":5,"one_percent_low":5}"#;
+    assert!(json.starts_with(r#"{"summary":{"sample_count":2,"average_fps":40,"#));
+    assert!(json.contains(gpu_summary), "{json}");
+    assert!(json.ends_with(concat!(
+        r#""samples":[{"frame":0,"frame_time_ms":20,"cpu_time_ms":2,"gpu_time_ms":5},"#,
+        r#"{"frame":1,"frame_time_ms":30,"cpu_time_ms":2,"gpu_time_ms":null}]}"#,
+        "\n",
+    )));
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+
+use crate::compute::{
+    check_count, check_max_count, create_scratch_buffer, Kernels, PrefixScan, PushConstants,
+};
+use crate::vulkan::{
+    Buffer, CommandBuffer, ComputePipeline, Context, DescriptorPool, DescriptorSet,
+};
+
+/// Copies the `u32` elements with a flag of 1 to the start of the output, keeping their order,
+/// and writes their number to the first element of the count buffer, e.g. the instances that
+/// pass GPU culling. Flags must be 0 or 1.
+///
+/// The flags are scanned into output offsets, then flagged elements are scattered.
+pub struct StreamCompaction {
+    max_count: u32,
+    descriptor_set: DescriptorSet,
+    _descriptor_pool: DescriptorPool,
+    _offsets: Buffer,
+    flag_scan: PrefixScan,
+    pipeline: ComputePipeline,
+    kernels: Kernels,
+}
+
+impl StreamCompaction {
+    pub(crate) fn new(
+        context: &Context,
+        input: &Buffer,
+        flags: &Buffer,
+        output: &Buffer,
+        count: &Buffer,
+        max_count: u32,
+    ) -> Result<Self> {
+        check_max_count(max_count)?;
+
+        let kernels = Kernels::new(context)?;
+        let pipeline =
+            kernels.create_pipeline(context, &include_bytes!("./shaders/compact.comp.spv")[..])?;
+
+        let offsets = create_scratch_buffer(context, "stream compaction offsets", max_count)?;
+        let flag_scan = PrefixScan::new(context, flags, &offsets, max_count)?;
+        let (descriptor_pool, mut descriptor_sets) =
+            kernels.create_descriptor_sets(context, &[[input, flags, &offsets, output, count]])?;
+
+        Ok(Self {
+            max_count,
+            descriptor_set: descriptor_sets.remove(0),
+            _descriptor_pool: descriptor_pool,
+            _offsets: offsets,
+            flag_scan,
+            pipeline,
+            kernels,
+        })
+    }
+
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+
+    /// Compacts the first `count` elements of the input.
+    pub fn record(&self, cmd_buffer: &CommandBuffer, count: u32) -> Result<()> {
+        check_count(count, self.max_count)?;
+
+        self.flag_scan.record(cmd_buffer, count)?;
+        // Also writes a count of 0 without elements
+        self.kernels.dispatch(
+            cmd_buffer,
+            &self.pipeline,
+            &self.descriptor_set,
+            PushConstants {
+                count,
+                ..Default::default()
+            },
+        );
+
+        Ok(())
+    }
+}
+
+impl Context {
+    /// See [`StreamCompaction`], up to `max_count` elements. `count` holds a single `u32`.
+    pub fn create_stream_compaction(
+        &self,
+        input: &Buffer,
+        flags: &Buffer,
+        output: &Buffer,
+        count: &Buffer,
+        max_count: u32,
+    ) -> Result<StreamCompaction> {
+        StreamCompaction::new(self, input, flags, output, count, max_count)
+    }
+}
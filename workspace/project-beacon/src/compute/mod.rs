@@ -0,0 +1,223 @@
+//! GPU primitives for compute passes: reduction, exclusive prefix scan, radix sort and stream
+//! compaction of `u32` buffers.
+//!
+//! Primitives bind the buffers they are created with, which need the `STORAGE_BUFFER` usage and
+//! must outlive them. Each primitive is recorded in a command buffer with a barrier after it, so
+//! its results are visible to later compute shaders. Other consumers, e.g. transfers or indirect
+//! draws, need their own barrier.
+
+mod compact;
+mod reduce;
+mod scan;
+mod sort;
+
+pub use compact::*;
+pub use reduce::*;
+pub use scan::*;
+pub use sort::*;
+
+use anyhow::{ensure, Result};
+use ash::vk;
+use glam::UVec3;
+use gpu_allocator::MemoryLocation;
+
+use crate::vulkan::{
+    Buffer, CommandBuffer, ComputePipeline, ComputePipelineCreateInfo, Context, DescriptorPool,
+    DescriptorSet, DescriptorSetLayout, MemoryBarrier, PipelineLayout, Pod, WriteDescriptorSet,
+    WriteDescriptorSetKind,
+};
+
+/// Workgroup size of the primitives, see shaders/common.glsl.
+pub const WORKGROUP_SIZE: u32 = 256;
+/// Limit on the number of elements of a primitive, so that it fits in one dispatch on every
+/// device.
+pub const MAX_ELEMENT_COUNT: u32 = WORKGROUP_SIZE * 65535;
+
+const BINDING_COUNT: usize = 5;
+
+/// Number of workgroups of `workgroup_size` needed to cover `extent`.
+pub fn dispatch_for(extent: UVec3, workgroup_size: UVec3) -> UVec3 {
+    UVec3::new(
+        extent.x.div_ceil(workgroup_size.x),
+        extent.y.div_ceil(workgroup_size.y),
+        extent.z.div_ceil(workgroup_size.z),
+    )
+}
+
+impl CommandBuffer {
+    /// Dispatches enough workgroups of `workgroup_size` to cover `extent`, see [`dispatch_for`].
+    pub fn dispatch_for(&self, extent: UVec3, workgroup_size: UVec3) {
+        let group_count = dispatch_for(extent, workgroup_size);
+        self.dispatch(group_count.x, group_count.y, group_count.z);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct PushConstants {
+    count: u32,
+    param: u32,
+    block_count: u32,
+}
+
+// Only 4 byte fields, no padding
+unsafe impl Pod for PushConstants {}
+
+/// Layouts shared by the shaders of the primitives: five storage buffers and push constants.
+struct Kernels {
+    descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+}
+
+impl Kernels {
+    fn new(context: &Context) -> Result<Self> {
+        let bindings = (0..BINDING_COUNT as u32)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_count(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let descriptor_set_layout = context.create_descriptor_set_layout(&bindings)?;
+
+        let pipeline_layout = context.create_pipeline_layout_with_push_constants(
+            &[&descriptor_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as _,
+            }],
+        )?;
+
+        Ok(Self {
+            descriptor_set_layout,
+            pipeline_layout,
+        })
+    }
+
+    fn create_pipeline(&self, context: &Context, shader_source: &[u8]) -> Result<ComputePipeline> {
+        context.create_compute_pipeline(
+            &self.pipeline_layout,
+            ComputePipelineCreateInfo { shader_source },
+        )
+    }
+
+    /// One set per entry of `sets`, with the buffers bound in order.
+    fn create_descriptor_sets(
+        &self,
+        context: &Context,
+        sets: &[[&Buffer; BINDING_COUNT]],
+    ) -> Result<(DescriptorPool, Vec<DescriptorSet>)> {
+        let count = sets.len() as u32;
+        let descriptor_pool = context.create_descriptor_pool(
+            count,
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: BINDING_COUNT as u32 * count,
+            }],
+        )?;
+        let descriptor_sets = descriptor_pool.allocate_sets(&self.descriptor_set_layout, count)?;
+
+        for (set, buffers) in descriptor_sets.iter().zip(sets) {
+            let writes = buffers
+                .iter()
+                .enumerate()
+                .map(|(binding, &buffer)| WriteDescriptorSet {
+                    binding: binding as _,
+                    array_element: 0,
+                    kind: WriteDescriptorSetKind::StorageBuffer { buffer },
+                })
+                .collect::<Vec<_>>();
+            set.update(&writes);
+        }
+
+        Ok((descriptor_pool, descriptor_sets))
+    }
+
+    /// Dispatches one invocation per element, at least one workgroup, then waits for the
+    /// writes of the shader.
+    fn dispatch(
+        &self,
+        cmd_buffer: &CommandBuffer,
+        pipeline: &ComputePipeline,
+        set: &DescriptorSet,
+        push_constants: PushConstants,
+    ) {
+        cmd_buffer.bind_compute_pipeline(pipeline);
+        cmd_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[set],
+        );
+        cmd_buffer.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &push_constants,
+        );
+        cmd_buffer.dispatch(block_count(push_constants.count).max(1), 1, 1);
+        cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+        }]);
+    }
+}
+
+/// Number of workgroups processing `count` elements.
+fn block_count(count: u32) -> u32 {
+    count.div_ceil(WORKGROUP_SIZE)
+}
+
+fn check_max_count(max_count: u32) -> Result<()> {
+    ensure!(
+        (1..=MAX_ELEMENT_COUNT).contains(&max_count),
+        "Compute primitives support 1 to {MAX_ELEMENT_COUNT} elements, not {max_count}"
+    );
+    Ok(())
+}
+
+fn check_count(count: u32, max_count: u32) -> Result<()> {
+    ensure!(
+        count <= max_count,
+        "{count} elements is more than the maximum of {max_count}"
+    );
+    Ok(())
+}
+
+fn create_scratch_buffer(context: &Context, name: &str, len: u32) -> Result<Buffer> {
+    let mut buffer = context.create_buffer(
+        vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::TRANSFER_DST,
+        MemoryLocation::GpuOnly,
+        len as u64 * std::mem::size_of::<u32>() as u64,
+    )?;
+    buffer.set_name(name)?;
+
+    Ok(buffer)
+}
+
+#[test]
+fn test_dispatch_for() {
+    assert_eq!(
+        dispatch_for(UVec3::new(1920, 1080, 1), UVec3::new(8, 8, 1)),
+        UVec3::new(240, 135, 1)
+    );
+    assert_eq!(
+        dispatch_for(UVec3::new(1000, 1, 1), UVec3::new(256, 1, 1)),
+        UVec3::new(4, 1, 1)
+    );
+    assert_eq!(
+        dispatch_for(UVec3::new(0, 1, 1), UVec3::new(64, 1, 1)),
+        UVec3::new(0, 1, 1)
+    );
+    assert_eq!(block_count(256), 1);
+    assert_eq!(block_count(257), 2);
+}
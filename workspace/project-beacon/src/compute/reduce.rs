@@ -0,0 +1,107 @@
+use anyhow::Result;
+use ash::vk;
+
+use crate::compute::{check_count, check_max_count, create_scratch_buffer, Kernels, PushConstants};
+use crate::vulkan::{
+    Buffer, CommandBuffer, ComputePipeline, Context, DescriptorPool, DescriptorSet, MemoryBarrier,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReduceOp {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl ReduceOp {
+    /// Result of reducing no elements.
+    pub fn identity(self) -> u32 {
+        match self {
+            Self::Sum | Self::Max => 0,
+            Self::Min => u32::MAX,
+        }
+    }
+}
+
+/// Reduces `u32` elements to their sum, minimum or maximum, written to the first element of
+/// [`Reduction::output`]. Sums wrap on overflow.
+///
+/// Workgroups reduce their elements in shared memory, then combine their result atomically.
+pub struct Reduction {
+    max_count: u32,
+    output: Buffer,
+    descriptor_set: DescriptorSet,
+    _descriptor_pool: DescriptorPool,
+    pipeline: ComputePipeline,
+    kernels: Kernels,
+}
+
+impl Reduction {
+    pub(crate) fn new(context: &Context, input: &Buffer, max_count: u32) -> Result<Self> {
+        check_max_count(max_count)?;
+
+        let kernels = Kernels::new(context)?;
+        let pipeline =
+            kernels.create_pipeline(context, &include_bytes!("./shaders/reduce.comp.spv")[..])?;
+
+        let output = create_scratch_buffer(context, "reduction output", 1)?;
+        let (descriptor_pool, mut descriptor_sets) = kernels
+            .create_descriptor_sets(context, &[[input, &output, &output, &output, &output]])?;
+
+        Ok(Self {
+            max_count,
+            output,
+            descriptor_set: descriptor_sets.remove(0),
+            _descriptor_pool: descriptor_pool,
+            pipeline,
+            kernels,
+        })
+    }
+
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+
+    /// Single `u32` holding the result of the last reduction.
+    pub fn output(&self) -> &Buffer {
+        &self.output
+    }
+
+    /// Reduces the first `count` elements of the input with `op`.
+    pub fn record(&self, cmd_buffer: &CommandBuffer, count: u32, op: ReduceOp) -> Result<()> {
+        check_count(count, self.max_count)?;
+
+        cmd_buffer.fill_buffer(&self.output, 0, vk::WHOLE_SIZE, op.identity());
+        cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            src_stage_mask: vk::PipelineStageFlags2::ALL_TRANSFER,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+        }]);
+        if count == 0 {
+            return Ok(());
+        }
+
+        self.kernels.dispatch(
+            cmd_buffer,
+            &self.pipeline,
+            &self.descriptor_set,
+            PushConstants {
+                count,
+                param: op as _,
+                ..Default::default()
+            },
+        );
+
+        Ok(())
+    }
+}
+
+impl Context {
+    /// See [`Reduction`], up to `max_count` elements.
+    pub fn create_reduction(&self, input: &Buffer, max_count: u32) -> Result<Reduction> {
+        Reduction::new(self, input, max_count)
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+
+use crate::compute::{
+    block_count, check_count, check_max_count, create_scratch_buffer, Kernels, PushConstants,
+    WORKGROUP_SIZE,
+};
+use crate::vulkan::{
+    Buffer, CommandBuffer, ComputePipeline, Context, DescriptorPool, DescriptorSet,
+};
+
+/// Exclusive prefix sum of `u32` elements: each output element is the sum of the input
+/// elements before it.
+///
+/// Workgroups scan blocks of [`WORKGROUP_SIZE`] elements and write their sums, which are
+/// scanned the same way and added back, with one level per factor of [`WORKGROUP_SIZE`].
+pub struct PrefixScan {
+    max_count: u32,
+    /// One per level, the first scans the input.
+    descriptor_sets: Vec<DescriptorSet>,
+    _descriptor_pool: DescriptorPool,
+    _block_sums: Vec<Buffer>,
+    scan_pipeline: ComputePipeline,
+    add_pipeline: ComputePipeline,
+    kernels: Kernels,
+}
+
+impl PrefixScan {
+    /// `input` and `output` can be the same buffer, for scanning in place.
+    pub(crate) fn new(
+        context: &Context,
+        input: &Buffer,
+        output: &Buffer,
+        max_count: u32,
+    ) -> Result<Self> {
+        check_max_count(max_count)?;
+
+        let kernels = Kernels::new(context)?;
+        let scan_pipeline =
+            kernels.create_pipeline(context, &include_bytes!("./shaders/scan.comp.spv")[..])?;
+        let add_pipeline =
+            kernels.create_pipeline(context, &include_bytes!("./shaders/scan_add.comp.spv")[..])?;
+
+        // Sums of the blocks of each level, the last one has a single block
+        let mut block_sums = vec![];
+        let mut level_count = max_count;
+        loop {
+            let blocks = block_count(level_count);
+            block_sums.push(create_scratch_buffer(context, "prefix scan sums", blocks)?);
+            if blocks == 1 {
+                break;
+            }
+            level_count = blocks;
+        }
+
+        let sets = block_sums
+            .iter()
+            .enumerate()
+            .map(|(level, sums)| {
+                let (input, output) = match level {
+                    0 => (input, output),
+                    _ => (&block_sums[level - 1], &block_sums[level - 1]),
+                };
+                [input, output, sums, sums, sums]
+            })
+            .collect::<Vec<_>>();
+        let (descriptor_pool, descriptor_sets) = kernels.create_descriptor_sets(context, &sets)?;
+
+        Ok(Self {
+            max_count,
+            descriptor_sets,
+            _descriptor_pool: descriptor_pool,
+            _block_sums: block_sums,
+            scan_pipeline,
+            add_pipeline,
+            kernels,
+        })
+    }
+
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+
+    /// Scans the first `count` elements of the input into the output.
+    pub fn record(&self, cmd_buffer: &CommandBuffer, count: u32) -> Result<()> {
+        check_count(count, self.max_count)?;
+        if count == 0 {
+            return Ok(());
+        }
+
+        // Elements scanned at each level
+        let mut counts = vec![count];
+        while let Some(&level_count) = counts.last().filter(|&&c| c > WORKGROUP_SIZE) {
+            counts.push(block_count(level_count));
+        }
+
+        let levels = self.descriptor_sets.iter().zip(&counts);
+        for (set, &count) in levels.clone() {
+            self.kernels.dispatch(
+                cmd_buffer,
+                &self.scan_pipeline,
+                set,
+                PushConstants {
+                    count,
+                    ..Default::default()
+                },
+            );
+        }
+        // The top level is a single block, complete after its scan
+        for (set, &count) in levels.rev().skip(1) {
+            self.kernels.dispatch(
+                cmd_buffer,
+                &self.add_pipeline,
+                set,
+                PushConstants {
+                    count,
+                    ..Default::default()
+                },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Context {
+    /// See [`PrefixScan`], up to `max_count` elements.
+    pub fn create_prefix_scan(
+        &self,
+        input: &Buffer,
+        output: &Buffer,
+        max_count: u32,
+    ) -> Result<PrefixScan> {
+        PrefixScan::new(self, input, output, max_count)
+    }
+}
@@ -0,0 +1,40 @@
+// Shared by the compute primitives, must match compute/mod.rs
+
+#define WORKGROUP_SIZE 256
+
+layout(local_size_x = WORKGROUP_SIZE) in;
+
+layout(set = 0, binding = 0) buffer Buffer0 { uint data0[]; };
+layout(set = 0, binding = 1) buffer Buffer1 { uint data1[]; };
+layout(set = 0, binding = 2) buffer Buffer2 { uint data2[]; };
+layout(set = 0, binding = 3) buffer Buffer3 { uint data3[]; };
+layout(set = 0, binding = 4) buffer Buffer4 { uint data4[]; };
+
+layout(push_constant) uniform PushConstants {
+    uint count;
+    uint param;
+    uint block_count;
+};
+
+shared uint scan_shared[WORKGROUP_SIZE];
+
+// Exclusive prefix sum of value over the workgroup, must be called by all invocations
+uint workgroup_exclusive_scan(uint value, out uint total) {
+    uint index = gl_LocalInvocationID.x;
+    scan_shared[index] = value;
+    barrier();
+
+    for (uint offset = 1; offset < WORKGROUP_SIZE; offset <<= 1) {
+        uint other = index >= offset ? scan_shared[index - offset] : 0;
+        barrier();
+        scan_shared[index] += other;
+        barrier();
+    }
+
+    uint inclusive = scan_shared[index];
+    total = scan_shared[WORKGROUP_SIZE - 1];
+    // scan_shared can be reused after
+    barrier();
+
+    return inclusive - value;
+}
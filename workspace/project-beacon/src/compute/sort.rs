@@ -0,0 +1,125 @@
+use anyhow::Result;
+
+use crate::compute::{
+    block_count, check_count, check_max_count, create_scratch_buffer, Kernels, PrefixScan,
+    PushConstants,
+};
+use crate::vulkan::{
+    Buffer, CommandBuffer, ComputePipeline, Context, DescriptorPool, DescriptorSet,
+};
+
+/// Bits of the key sorted by each pass, must match shaders/sort_*.comp.
+const RADIX_BITS: u32 = 4;
+const RADIX: u32 = 1 << RADIX_BITS;
+const PASS_COUNT: u32 = u32::BITS / RADIX_BITS;
+
+/// Stable sort of `u32` keys in ascending order, with a `u32` value moved along each key,
+/// e.g. the index of a particle sorted by depth.
+///
+/// Each pass sorts a digit of 4 bits: workgroups count their digits, the counts
+/// are scanned into offsets, and keys are scattered to their offset. Passes ping-pong between
+/// the keys and values and scratch buffers, the sorted keys and values end up in place.
+pub struct RadixSort {
+    max_count: u32,
+    /// Scatters from the keys to the scratch buffers, and back.
+    descriptor_sets: Vec<DescriptorSet>,
+    _descriptor_pool: DescriptorPool,
+    _scratch_keys: Buffer,
+    _scratch_values: Buffer,
+    /// Digit counts of each workgroup, digit major, scanned in place.
+    _counts: Buffer,
+    count_scan: PrefixScan,
+    count_pipeline: ComputePipeline,
+    scatter_pipeline: ComputePipeline,
+    kernels: Kernels,
+}
+
+impl RadixSort {
+    pub(crate) fn new(
+        context: &Context,
+        keys: &Buffer,
+        values: &Buffer,
+        max_count: u32,
+    ) -> Result<Self> {
+        check_max_count(max_count)?;
+
+        let kernels = Kernels::new(context)?;
+        let count_pipeline = kernels.create_pipeline(
+            context,
+            &include_bytes!("./shaders/sort_count.comp.spv")[..],
+        )?;
+        let scatter_pipeline = kernels.create_pipeline(
+            context,
+            &include_bytes!("./shaders/sort_scatter.comp.spv")[..],
+        )?;
+
+        let scratch_keys = create_scratch_buffer(context, "radix sort keys", max_count)?;
+        let scratch_values = create_scratch_buffer(context, "radix sort values", max_count)?;
+        let count_len = RADIX * block_count(max_count);
+        let counts = create_scratch_buffer(context, "radix sort counts", count_len)?;
+        let count_scan = PrefixScan::new(context, &counts, &counts, count_len)?;
+
+        let (descriptor_pool, descriptor_sets) = kernels.create_descriptor_sets(
+            context,
+            &[
+                [keys, values, &scratch_keys, &scratch_values, &counts],
+                [&scratch_keys, &scratch_values, keys, values, &counts],
+            ],
+        )?;
+
+        Ok(Self {
+            max_count,
+            descriptor_sets,
+            _descriptor_pool: descriptor_pool,
+            _scratch_keys: scratch_keys,
+            _scratch_values: scratch_values,
+            _counts: counts,
+            count_scan,
+            count_pipeline,
+            scatter_pipeline,
+            kernels,
+        })
+    }
+
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+
+    /// Sorts the first `count` keys and values.
+    pub fn record(&self, cmd_buffer: &CommandBuffer, count: u32) -> Result<()> {
+        check_count(count, self.max_count)?;
+        if count <= 1 {
+            return Ok(());
+        }
+
+        let block_count = block_count(count);
+        for pass in 0..PASS_COUNT {
+            let set = &self.descriptor_sets[pass as usize % 2];
+            let push_constants = PushConstants {
+                count,
+                param: pass * RADIX_BITS,
+                block_count,
+            };
+
+            self.kernels
+                .dispatch(cmd_buffer, &self.count_pipeline, set, push_constants);
+            self.count_scan.record(cmd_buffer, RADIX * block_count)?;
+            self.kernels
+                .dispatch(cmd_buffer, &self.scatter_pipeline, set, push_constants);
+        }
+
+        Ok(())
+    }
+}
+
+impl Context {
+    /// See [`RadixSort`], up to `max_count` keys and values.
+    pub fn create_radix_sort(
+        &self,
+        keys: &Buffer,
+        values: &Buffer,
+        max_count: u32,
+    ) -> Result<RadixSort> {
+        RadixSort::new(self, keys, values, max_count)
+    }
+}
@@ -1,2 +1,6 @@
 pub mod vulkan;
+pub mod compute;
 pub mod app;
+pub mod render_graph;
+pub mod renderer;
+pub mod scene;
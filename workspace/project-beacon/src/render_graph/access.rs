@@ -0,0 +1,233 @@
+use ash::vk;
+
+pub(crate) const WRITE_ACCESS_MASK: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::SHADER_WRITE.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::HOST_WRITE.as_raw()
+        | vk::AccessFlags2::MEMORY_WRITE.as_raw()
+        | vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR.as_raw(),
+);
+
+/// How a pass uses a resource.
+///
+/// Each access maps to the pipeline stages and memory accesses involved and, for images, to
+/// the layout the image has to be in. Buffers and acceleration structures ignore the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// Contents are undefined. Only meaningful as the initial access of an imported resource.
+    Undefined,
+    /// Any access from any stage, image in `GENERAL` layout.
+    General,
+    /// Image handed over to the presentation engine.
+    Present,
+    TransferRead,
+    TransferWrite,
+    VertexBufferRead,
+    IndexBufferRead,
+    IndirectBufferRead,
+    /// Uniform buffer read from vertex, fragment or compute shaders.
+    UniformRead,
+    ColorAttachmentWrite,
+    /// Color attachment loaded or blended into.
+    ColorAttachmentReadWrite,
+    DepthStencilAttachmentRead,
+    DepthStencilAttachmentWrite,
+    FragmentShaderSampledRead,
+    FragmentShaderStorageRead,
+    ComputeShaderSampledRead,
+    ComputeShaderStorageRead,
+    ComputeShaderStorageWrite,
+    /// Uniform buffer read from ray tracing shaders.
+    RayTracingShaderUniformRead,
+    RayTracingShaderSampledRead,
+    RayTracingShaderStorageRead,
+    RayTracingShaderStorageWrite,
+    AccelerationStructureBuildRead,
+    AccelerationStructureBuildWrite,
+    /// Acceleration structure traced against from ray tracing shaders.
+    RayTracingAccelerationStructureRead,
+    /// Acceleration structure queried from fragment or compute shaders (ray query).
+    RayQueryAccelerationStructureRead,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessInfo {
+    pub stage_mask: vk::PipelineStageFlags2,
+    pub access_mask: vk::AccessFlags2,
+    pub layout: vk::ImageLayout,
+}
+
+impl Access {
+    pub fn info(self) -> AccessInfo {
+        use vk::{AccessFlags2 as A, ImageLayout as L, PipelineStageFlags2 as S};
+
+        let (stage_mask, access_mask, layout) = match self {
+            Self::Undefined => (S::NONE, A::NONE, L::UNDEFINED),
+            Self::General => (
+                S::ALL_COMMANDS,
+                A::MEMORY_READ | A::MEMORY_WRITE,
+                L::GENERAL,
+            ),
+            Self::Present => (S::NONE, A::NONE, L::PRESENT_SRC_KHR),
+            Self::TransferRead => (S::TRANSFER, A::TRANSFER_READ, L::TRANSFER_SRC_OPTIMAL),
+            Self::TransferWrite => (S::TRANSFER, A::TRANSFER_WRITE, L::TRANSFER_DST_OPTIMAL),
+            Self::VertexBufferRead => (
+                S::VERTEX_ATTRIBUTE_INPUT,
+                A::VERTEX_ATTRIBUTE_READ,
+                L::UNDEFINED,
+            ),
+            Self::IndexBufferRead => (S::INDEX_INPUT, A::INDEX_READ, L::UNDEFINED),
+            Self::IndirectBufferRead => (S::DRAW_INDIRECT, A::INDIRECT_COMMAND_READ, L::UNDEFINED),
+            // The ray tracing stage is only valid with the ray tracing pipeline feature
+            Self::UniformRead => (
+                S::VERTEX_SHADER | S::FRAGMENT_SHADER | S::COMPUTE_SHADER,
+                A::UNIFORM_READ,
+                L::UNDEFINED,
+            ),
+            Self::ColorAttachmentWrite => (
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::COLOR_ATTACHMENT_WRITE,
+                L::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Self::ColorAttachmentReadWrite => (
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+                L::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Self::DepthStencilAttachmentRead => (
+                S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS,
+                A::DEPTH_STENCIL_ATTACHMENT_READ,
+                L::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ),
+            Self::DepthStencilAttachmentWrite => (
+                S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS,
+                A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            Self::FragmentShaderSampledRead => (
+                S::FRAGMENT_SHADER,
+                A::SHADER_SAMPLED_READ,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::FragmentShaderStorageRead => {
+                (S::FRAGMENT_SHADER, A::SHADER_STORAGE_READ, L::GENERAL)
+            }
+            Self::ComputeShaderSampledRead => (
+                S::COMPUTE_SHADER,
+                A::SHADER_SAMPLED_READ,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::ComputeShaderStorageRead => {
+                (S::COMPUTE_SHADER, A::SHADER_STORAGE_READ, L::GENERAL)
+            }
+            Self::ComputeShaderStorageWrite => (
+                S::COMPUTE_SHADER,
+                A::SHADER_STORAGE_READ | A::SHADER_STORAGE_WRITE,
+                L::GENERAL,
+            ),
+            Self::RayTracingShaderUniformRead => {
+                (S::RAY_TRACING_SHADER_KHR, A::UNIFORM_READ, L::UNDEFINED)
+            }
+            Self::RayTracingShaderSampledRead => (
+                S::RAY_TRACING_SHADER_KHR,
+                A::SHADER_SAMPLED_READ,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::RayTracingShaderStorageRead => (
+                S::RAY_TRACING_SHADER_KHR,
+                A::SHADER_STORAGE_READ,
+                L::GENERAL,
+            ),
+            Self::RayTracingShaderStorageWrite => (
+                S::RAY_TRACING_SHADER_KHR,
+                A::SHADER_STORAGE_READ | A::SHADER_STORAGE_WRITE,
+                L::GENERAL,
+            ),
+            Self::AccelerationStructureBuildRead => (
+                S::ACCELERATION_STRUCTURE_BUILD_KHR,
+                A::ACCELERATION_STRUCTURE_READ_KHR | A::SHADER_READ,
+                L::UNDEFINED,
+            ),
+            Self::AccelerationStructureBuildWrite => (
+                S::ACCELERATION_STRUCTURE_BUILD_KHR,
+                A::ACCELERATION_STRUCTURE_READ_KHR | A::ACCELERATION_STRUCTURE_WRITE_KHR,
+                L::UNDEFINED,
+            ),
+            Self::RayTracingAccelerationStructureRead => (
+                S::RAY_TRACING_SHADER_KHR,
+                A::ACCELERATION_STRUCTURE_READ_KHR,
+                L::UNDEFINED,
+            ),
+            Self::RayQueryAccelerationStructureRead => (
+                S::FRAGMENT_SHADER | S::COMPUTE_SHADER,
+                A::ACCELERATION_STRUCTURE_READ_KHR,
+                L::UNDEFINED,
+            ),
+        };
+
+        AccessInfo {
+            stage_mask,
+            access_mask,
+            layout,
+        }
+    }
+
+    pub fn is_write(self) -> bool {
+        self.info().access_mask.intersects(WRITE_ACCESS_MASK)
+    }
+
+    pub fn is_read(self) -> bool {
+        !(self.info().access_mask & !WRITE_ACCESS_MASK).is_empty()
+    }
+
+    /// Usage flags a transient image needs to support this access.
+    pub fn image_usage(self) -> vk::ImageUsageFlags {
+        use vk::ImageUsageFlags as U;
+
+        match self {
+            Self::TransferRead => U::TRANSFER_SRC,
+            Self::TransferWrite => U::TRANSFER_DST,
+            Self::ColorAttachmentWrite | Self::ColorAttachmentReadWrite => U::COLOR_ATTACHMENT,
+            Self::DepthStencilAttachmentRead | Self::DepthStencilAttachmentWrite => {
+                U::DEPTH_STENCIL_ATTACHMENT
+            }
+            Self::FragmentShaderSampledRead
+            | Self::ComputeShaderSampledRead
+            | Self::RayTracingShaderSampledRead => U::SAMPLED,
+            Self::General
+            | Self::FragmentShaderStorageRead
+            | Self::ComputeShaderStorageRead
+            | Self::ComputeShaderStorageWrite
+            | Self::RayTracingShaderStorageRead
+            | Self::RayTracingShaderStorageWrite => U::STORAGE,
+            _ => U::empty(),
+        }
+    }
+
+    /// Usage flags a transient buffer needs to support this access.
+    pub fn buffer_usage(self) -> vk::BufferUsageFlags {
+        use vk::BufferUsageFlags as U;
+
+        match self {
+            Self::TransferRead => U::TRANSFER_SRC,
+            Self::TransferWrite => U::TRANSFER_DST,
+            Self::VertexBufferRead => U::VERTEX_BUFFER,
+            Self::IndexBufferRead => U::INDEX_BUFFER,
+            Self::IndirectBufferRead => U::INDIRECT_BUFFER,
+            Self::UniformRead | Self::RayTracingShaderUniformRead => U::UNIFORM_BUFFER,
+            Self::AccelerationStructureBuildRead => {
+                U::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR | U::SHADER_DEVICE_ADDRESS
+            }
+            Self::General
+            | Self::FragmentShaderStorageRead
+            | Self::ComputeShaderStorageRead
+            | Self::ComputeShaderStorageWrite
+            | Self::RayTracingShaderStorageRead
+            | Self::RayTracingShaderStorageWrite => U::STORAGE_BUFFER,
+            _ => U::empty(),
+        }
+    }
+}
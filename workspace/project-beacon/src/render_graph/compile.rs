@@ -0,0 +1,412 @@
+use std::{collections::HashMap, ops::BitOrAssign};
+
+use ash::vk;
+
+use crate::render_graph::{
+    access::WRITE_ACCESS_MASK,
+    graph::{Resource, ResourceKind},
+    AccelerationStructureHandle, Access, BufferDesc, BufferHandle, ImageDesc, ImageHandle,
+    RenderGraph, ResourceHandle,
+};
+
+/// The result of compiling a [`RenderGraph`]: the passes to execute, in order, with the
+/// barriers to record before each of them, and the physical resources needed for the
+/// transient ones.
+#[derive(Debug, Clone)]
+pub struct CompiledGraph {
+    pub passes: Vec<CompiledPass>,
+    /// Indices of the passes that were culled.
+    pub culled_passes: Vec<usize>,
+    /// Barriers moving imported resources to their final access.
+    pub final_barriers: Vec<Barrier>,
+    pub(crate) image_slots: Vec<(ImageDesc, vk::ImageUsageFlags)>,
+    pub(crate) buffer_slots: Vec<(BufferDesc, vk::BufferUsageFlags)>,
+    pub(crate) resource_slots: Vec<Option<usize>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledPass {
+    /// Index of the pass in declaration order.
+    pub index: usize,
+    pub name: String,
+    pub barriers: Vec<Barrier>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Barrier {
+    pub resource: ResourceHandle,
+    pub src_stage_mask: vk::PipelineStageFlags2,
+    pub src_access_mask: vk::AccessFlags2,
+    pub dst_stage_mask: vk::PipelineStageFlags2,
+    pub dst_access_mask: vk::AccessFlags2,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+impl CompiledGraph {
+    /// Number of physical images backing the transient images of the graph.
+    pub fn physical_image_count(&self) -> usize {
+        self.image_slots.len()
+    }
+
+    pub fn physical_buffer_count(&self) -> usize {
+        self.buffer_slots.len()
+    }
+
+    /// Physical image backing a transient image, `None` if it is unused.
+    pub fn image_slot(&self, image: ImageHandle) -> Option<usize> {
+        self.resource_slots[image.0]
+    }
+
+    pub fn buffer_slot(&self, buffer: BufferHandle) -> Option<usize> {
+        self.resource_slots[buffer.0]
+    }
+
+    pub fn is_culled(&self, pass_index: usize) -> bool {
+        self.culled_passes.contains(&pass_index)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum StateKey {
+    Resource(usize),
+    ImageSlot(usize),
+    BufferSlot(usize),
+}
+
+/// What the GPU may still be doing with a resource.
+#[derive(Debug, Clone, Copy)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    write_stage_mask: vk::PipelineStageFlags2,
+    write_access_mask: vk::AccessFlags2,
+    /// Stages that have read the resource since the last write, and already see that write.
+    read_stage_mask: vk::PipelineStageFlags2,
+}
+
+impl ResourceState {
+    fn from_access(access: Access) -> Self {
+        let info = access.info();
+
+        if access.is_write() {
+            Self {
+                layout: info.layout,
+                write_stage_mask: info.stage_mask,
+                write_access_mask: info.access_mask & WRITE_ACCESS_MASK,
+                read_stage_mask: vk::PipelineStageFlags2::NONE,
+            }
+        } else {
+            Self {
+                layout: info.layout,
+                write_stage_mask: vk::PipelineStageFlags2::NONE,
+                write_access_mask: vk::AccessFlags2::NONE,
+                read_stage_mask: info.stage_mask,
+            }
+        }
+    }
+
+    /// State of a physical resource when the graph starts. It may still be in use by a
+    /// previous frame so the first barrier waits for all prior work.
+    fn transient() -> Self {
+        Self {
+            layout: vk::ImageLayout::UNDEFINED,
+            write_stage_mask: vk::PipelineStageFlags2::ALL_COMMANDS,
+            write_access_mask: vk::AccessFlags2::MEMORY_WRITE,
+            read_stage_mask: vk::PipelineStageFlags2::NONE,
+        }
+    }
+
+    /// Moves the resource to `access` and returns the barrier needed, if any. When
+    /// `discard` is set the previous contents are not preserved.
+    fn transition(
+        &mut self,
+        resource: ResourceHandle,
+        access: Access,
+        is_image: bool,
+        discard: bool,
+    ) -> Option<Barrier> {
+        let info = access.info();
+        let layout_changes = is_image && (discard || self.layout != info.layout);
+        let old_layout = if discard {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            self.layout
+        };
+
+        let barrier = |src_stage_mask, src_access_mask| Barrier {
+            resource,
+            src_stage_mask,
+            src_access_mask,
+            dst_stage_mask: info.stage_mask,
+            dst_access_mask: info.access_mask,
+            old_layout: if is_image {
+                old_layout
+            } else {
+                vk::ImageLayout::UNDEFINED
+            },
+            new_layout: if is_image {
+                info.layout
+            } else {
+                vk::ImageLayout::UNDEFINED
+            },
+        };
+
+        if access.is_write() || layout_changes {
+            let src_stage_mask = self.write_stage_mask | self.read_stage_mask;
+            let needs_barrier = layout_changes || !src_stage_mask.is_empty();
+            let result = needs_barrier.then(|| barrier(src_stage_mask, self.write_access_mask));
+
+            // A layout transition is a write, later reads from other stages must wait for it
+            *self = Self {
+                layout: if is_image { info.layout } else { self.layout },
+                write_stage_mask: info.stage_mask,
+                write_access_mask: if access.is_write() {
+                    info.access_mask & WRITE_ACCESS_MASK
+                } else {
+                    vk::AccessFlags2::NONE
+                },
+                read_stage_mask: if access.is_write() {
+                    vk::PipelineStageFlags2::NONE
+                } else {
+                    info.stage_mask
+                },
+            };
+
+            return result;
+        }
+
+        if self.write_stage_mask.is_empty() || self.read_stage_mask.contains(info.stage_mask) {
+            self.read_stage_mask |= info.stage_mask;
+            return None;
+        }
+
+        self.read_stage_mask |= info.stage_mask;
+        Some(barrier(self.write_stage_mask, self.write_access_mask))
+    }
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn compile(&self) -> CompiledGraph {
+        let kept = self.cull();
+        let order = (0..self.passes.len())
+            .filter(|&i| kept[i])
+            .collect::<Vec<_>>();
+        let culled_passes = (0..self.passes.len())
+            .filter(|&i| !kept[i])
+            .collect::<Vec<_>>();
+
+        // Lifetimes of the resources, as positions in the execution order
+        let mut lifetimes = vec![None::<(usize, usize)>; self.resources.len()];
+        for (position, &pass_index) in order.iter().enumerate() {
+            for access in &self.passes[pass_index].accesses {
+                let lifetime = &mut lifetimes[access.resource];
+                *lifetime = Some(match *lifetime {
+                    Some((first, _)) => (first, position),
+                    None => (position, position),
+                });
+            }
+        }
+
+        // Outputs must not be overwritten by an aliased resource
+        for (index, resource) in self.resources.iter().enumerate() {
+            if let (true, Some((_, last))) = (resource.is_output, &mut lifetimes[index]) {
+                *last = order.len();
+            }
+        }
+
+        let (resource_slots, image_slots, buffer_slots) = self.alias(&order, &lifetimes);
+
+        // Barriers
+        let state_key =
+            |resource: usize| match (&self.resources[resource].kind, resource_slots[resource]) {
+                (ResourceKind::TransientImage(_), Some(slot)) => StateKey::ImageSlot(slot),
+                (ResourceKind::TransientBuffer(_), Some(slot)) => StateKey::BufferSlot(slot),
+                _ => StateKey::Resource(resource),
+            };
+
+        let mut states = HashMap::new();
+        let mut first_use = vec![true; self.resources.len()];
+
+        let passes = order
+            .iter()
+            .map(|&pass_index| {
+                let pass = &self.passes[pass_index];
+
+                let barriers = pass
+                    .accesses
+                    .iter()
+                    .filter_map(|access| {
+                        let resource = &self.resources[access.resource];
+                        let state = states.entry(state_key(access.resource)).or_insert_with(|| {
+                            resource
+                                .initial_access()
+                                .map(ResourceState::from_access)
+                                .unwrap_or_else(ResourceState::transient)
+                        });
+
+                        let discard = !resource.is_imported() && first_use[access.resource];
+                        first_use[access.resource] = false;
+
+                        state.transition(
+                            handle(resource, access.resource),
+                            access.access,
+                            resource.is_image(),
+                            discard,
+                        )
+                    })
+                    .collect();
+
+                CompiledPass {
+                    index: pass_index,
+                    name: pass.name.clone(),
+                    barriers,
+                }
+            })
+            .collect();
+
+        let final_barriers = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter_map(|(index, resource)| {
+                let final_access = resource.final_access?;
+                let state = states.entry(StateKey::Resource(index)).or_insert_with(|| {
+                    ResourceState::from_access(resource.initial_access().unwrap())
+                });
+
+                state.transition(
+                    handle(resource, index),
+                    final_access,
+                    resource.is_image(),
+                    false,
+                )
+            })
+            .collect();
+
+        CompiledGraph {
+            passes,
+            culled_passes,
+            final_barriers,
+            image_slots,
+            buffer_slots,
+            resource_slots,
+        }
+    }
+
+    /// Walks the passes backwards, keeping the ones writing to a resource needed by an
+    /// already kept pass, an output or an imported resource.
+    fn cull(&self) -> Vec<bool> {
+        let mut needed = self
+            .resources
+            .iter()
+            .map(|r| r.is_output || r.is_imported())
+            .collect::<Vec<_>>();
+        let mut kept = vec![false; self.passes.len()];
+
+        for (index, pass) in self.passes.iter().enumerate().rev() {
+            let keep = pass.has_side_effects
+                || pass
+                    .accesses
+                    .iter()
+                    .any(|a| a.is_write && needed[a.resource]);
+            if !keep {
+                continue;
+            }
+
+            kept[index] = true;
+            for access in &pass.accesses {
+                if !access.is_write || access.access.is_read() {
+                    needed[access.resource] = true;
+                }
+            }
+        }
+
+        kept
+    }
+
+    /// Assigns a physical slot to each used transient resource, reusing slots with the same
+    /// description whose previous user is no longer alive. A slot supports the usage of all
+    /// the resources aliased to it.
+    #[allow(clippy::type_complexity)]
+    fn alias(
+        &self,
+        order: &[usize],
+        lifetimes: &[Option<(usize, usize)>],
+    ) -> (
+        Vec<Option<usize>>,
+        Vec<(ImageDesc, vk::ImageUsageFlags)>,
+        Vec<(BufferDesc, vk::BufferUsageFlags)>,
+    ) {
+        let mut image_usages = vec![vk::ImageUsageFlags::empty(); self.resources.len()];
+        let mut buffer_usages = vec![vk::BufferUsageFlags::empty(); self.resources.len()];
+        for &pass_index in order {
+            for access in &self.passes[pass_index].accesses {
+                image_usages[access.resource] |= access.access.image_usage();
+                buffer_usages[access.resource] |= access.access.buffer_usage();
+            }
+        }
+
+        let mut transients = (0..self.resources.len())
+            .filter(|&i| !self.resources[i].is_imported())
+            .filter_map(|i| lifetimes[i].map(|lifetime| (i, lifetime)))
+            .collect::<Vec<_>>();
+        transients.sort_by_key(|&(_, (first, _))| first);
+
+        let mut resource_slots = vec![None; self.resources.len()];
+        let mut image_slots = vec![];
+        let mut image_slot_ends = vec![];
+        let mut buffer_slots = vec![];
+        let mut buffer_slot_ends = vec![];
+
+        for (index, (first, last)) in transients {
+            resource_slots[index] = Some(match self.resources[index].kind {
+                ResourceKind::TransientImage(desc) => {
+                    let key = (desc, image_usages[index]);
+                    find_or_add_slot(&mut image_slots, &mut image_slot_ends, key, first, last)
+                }
+                ResourceKind::TransientBuffer(desc) => {
+                    let key = (desc, buffer_usages[index]);
+                    find_or_add_slot(&mut buffer_slots, &mut buffer_slot_ends, key, first, last)
+                }
+                _ => unreachable!(),
+            });
+        }
+
+        (resource_slots, image_slots, buffer_slots)
+    }
+}
+
+fn find_or_add_slot<D: PartialEq, U: BitOrAssign>(
+    slots: &mut Vec<(D, U)>,
+    slot_ends: &mut Vec<usize>,
+    (desc, usage): (D, U),
+    first: usize,
+    last: usize,
+) -> usize {
+    match (0..slots.len()).find(|&i| slots[i].0 == desc && slot_ends[i] < first) {
+        Some(slot) => {
+            slots[slot].1 |= usage;
+            slot_ends[slot] = last;
+            slot
+        }
+        None => {
+            slots.push((desc, usage));
+            slot_ends.push(last);
+            slots.len() - 1
+        }
+    }
+}
+
+fn handle(resource: &Resource, index: usize) -> ResourceHandle {
+    match resource.kind {
+        ResourceKind::ImportedImage { .. } | ResourceKind::TransientImage(_) => {
+            ImageHandle(index).into()
+        }
+        ResourceKind::ImportedBuffer { .. } | ResourceKind::TransientBuffer(_) => {
+            BufferHandle(index).into()
+        }
+        ResourceKind::ImportedAccelerationStructure { .. } => {
+            AccelerationStructureHandle(index).into()
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Write;
+
+use crate::render_graph::RenderGraph;
+
+impl<'a> RenderGraph<'a> {
+    /// Graphviz representation of the graph. Culled passes are dashed, imported resources
+    /// are filled and transient ones show the physical resource they are aliased to.
+    ///
+    /// Render it with `dot -Tsvg graph.dot -o graph.svg`.
+    pub fn to_dot(&self) -> String {
+        let compiled = self.compile();
+        let mut dot = String::new();
+
+        writeln!(dot, "digraph render_graph {{").unwrap();
+        writeln!(dot, "    rankdir=LR;").unwrap();
+        writeln!(dot, "    node [fontname=\"Helvetica\"];").unwrap();
+        writeln!(dot, "    edge [fontname=\"Helvetica\", fontsize=10];").unwrap();
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let style = if compiled.is_culled(index) {
+                ", style=dashed, color=gray, fontcolor=gray"
+            } else {
+                ""
+            };
+            writeln!(
+                dot,
+                "    pass_{index} [shape=box, label=\"{}\"{style}];",
+                escape(&pass.name)
+            )
+            .unwrap();
+        }
+
+        for (index, resource) in self.resources.iter().enumerate() {
+            let (label, style) = if resource.is_imported() {
+                (
+                    escape(&resource.name),
+                    ", style=filled, fillcolor=lightblue",
+                )
+            } else {
+                match compiled.resource_slots[index] {
+                    Some(slot) => (format!("{}\\nslot {slot}", escape(&resource.name)), ""),
+                    None => (escape(&resource.name), ", style=dashed, color=gray"),
+                }
+            };
+            writeln!(
+                dot,
+                "    resource_{index} [shape=ellipse, label=\"{label}\"{style}];"
+            )
+            .unwrap();
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for access in &pass.accesses {
+                let (from, to) = if access.is_write {
+                    (
+                        format!("pass_{index}"),
+                        format!("resource_{}", access.resource),
+                    )
+                } else {
+                    (
+                        format!("resource_{}", access.resource),
+                        format!("pass_{index}"),
+                    )
+                };
+                writeln!(dot, "    {from} -> {to} [label=\"{:?}\"];", access.access).unwrap();
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+
+        dot
+    }
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,362 @@
+use anyhow::Result;
+
+use crate::render_graph::{
+    AccelerationStructureHandle, Access, Barrier, BufferDesc, BufferHandle, ImageDesc, ImageHandle,
+    PassResources, PhysicalResource, ResourceHandle, TransientResourcePool,
+};
+use crate::vulkan::{
+    AccelerationStructure, Buffer, BufferBarrier, CommandBuffer, GpuProfiler, Image, ImageBarrier,
+    ImageView, MemoryBarrier,
+};
+
+type ExecuteFn<'a> = Box<dyn FnOnce(&CommandBuffer, &PassResources) -> Result<()> + 'a>;
+
+/// A frame's worth of passes and the resources they use.
+///
+/// Passes declare how they access each resource and the graph takes care of the pipeline
+/// barriers and layout transitions between them. Passes that don't contribute to an
+/// imported resource, an output or a side effect are culled. Transient resources are
+/// allocated from a [`TransientResourcePool`] and share physical resources when their
+/// lifetimes don't overlap.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    pub(crate) resources: Vec<Resource<'a>>,
+    pub(crate) passes: Vec<Pass<'a>>,
+    profiler: Option<&'a GpuProfiler>,
+}
+
+pub(crate) struct Resource<'a> {
+    pub(crate) name: String,
+    pub(crate) kind: ResourceKind<'a>,
+    pub(crate) final_access: Option<Access>,
+    pub(crate) is_output: bool,
+}
+
+pub(crate) enum ResourceKind<'a> {
+    ImportedImage {
+        image: &'a Image,
+        view: Option<&'a ImageView>,
+        initial_access: Access,
+    },
+    ImportedBuffer {
+        buffer: &'a Buffer,
+        initial_access: Access,
+    },
+    ImportedAccelerationStructure {
+        acceleration_structure: &'a AccelerationStructure,
+        initial_access: Access,
+    },
+    TransientImage(ImageDesc),
+    TransientBuffer(BufferDesc),
+}
+
+impl<'a> Resource<'a> {
+    pub(crate) fn initial_access(&self) -> Option<Access> {
+        match self.kind {
+            ResourceKind::ImportedImage { initial_access, .. }
+            | ResourceKind::ImportedBuffer { initial_access, .. }
+            | ResourceKind::ImportedAccelerationStructure { initial_access, .. } => {
+                Some(initial_access)
+            }
+            ResourceKind::TransientImage(_) | ResourceKind::TransientBuffer(_) => None,
+        }
+    }
+
+    pub(crate) fn is_imported(&self) -> bool {
+        self.initial_access().is_some()
+    }
+
+    pub(crate) fn is_image(&self) -> bool {
+        matches!(
+            self.kind,
+            ResourceKind::ImportedImage { .. } | ResourceKind::TransientImage(_)
+        )
+    }
+}
+
+pub(crate) struct Pass<'a> {
+    pub(crate) name: String,
+    pub(crate) accesses: Vec<PassAccess>,
+    pub(crate) has_side_effects: bool,
+    pub(crate) execute: Option<ExecuteFn<'a>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PassAccess {
+    pub(crate) resource: usize,
+    pub(crate) access: Access,
+    pub(crate) is_write: bool,
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    pass: Pass<'a>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Imports an image living outside of the graph. `initial_access` is how the image was
+    /// last used before the graph executes.
+    pub fn import_image(
+        &mut self,
+        name: &str,
+        image: &'a Image,
+        view: Option<&'a ImageView>,
+        initial_access: Access,
+    ) -> ImageHandle {
+        ImageHandle(self.add_resource(
+            name,
+            ResourceKind::ImportedImage {
+                image,
+                view,
+                initial_access,
+            },
+        ))
+    }
+
+    pub fn import_buffer(
+        &mut self,
+        name: &str,
+        buffer: &'a Buffer,
+        initial_access: Access,
+    ) -> BufferHandle {
+        BufferHandle(self.add_resource(
+            name,
+            ResourceKind::ImportedBuffer {
+                buffer,
+                initial_access,
+            },
+        ))
+    }
+
+    pub fn import_acceleration_structure(
+        &mut self,
+        name: &str,
+        acceleration_structure: &'a AccelerationStructure,
+        initial_access: Access,
+    ) -> AccelerationStructureHandle {
+        AccelerationStructureHandle(self.add_resource(
+            name,
+            ResourceKind::ImportedAccelerationStructure {
+                acceleration_structure,
+                initial_access,
+            },
+        ))
+    }
+
+    /// Creates an image that only lives for the duration of the graph.
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ImageHandle {
+        ImageHandle(self.add_resource(name, ResourceKind::TransientImage(desc)))
+    }
+
+    /// Creates a buffer that only lives for the duration of the graph.
+    pub fn create_buffer(&mut self, name: &str, desc: BufferDesc) -> BufferHandle {
+        BufferHandle(self.add_resource(name, ResourceKind::TransientBuffer(desc)))
+    }
+
+    /// Transitions an imported resource to `access` once all passes have executed,
+    /// e.g. `Access::Present` for the swapchain image.
+    pub fn set_final_access(&mut self, resource: impl Into<ResourceHandle>, access: Access) {
+        let resource = &mut self.resources[resource.into().index()];
+        assert!(
+            resource.is_imported(),
+            "Final access can only be set on imported resources"
+        );
+        resource.final_access = Some(access);
+    }
+
+    /// Keeps the passes writing to `resource` from being culled. Imported resources are
+    /// always considered outputs.
+    pub fn mark_output(&mut self, resource: impl Into<ResourceHandle>) {
+        self.resources[resource.into().index()].is_output = true;
+    }
+
+    /// Measures each pass in a profiler scope named after it.
+    pub fn set_profiler(&mut self, profiler: &'a GpuProfiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn add_pass<'g>(&'g mut self, name: &str) -> PassBuilder<'g, 'a> {
+        PassBuilder {
+            graph: self,
+            pass: Pass {
+                name: name.to_owned(),
+                accesses: vec![],
+                has_side_effects: false,
+                execute: None,
+            },
+        }
+    }
+
+    /// Compiles the graph, allocates transient resources from `pool` and records all passes
+    /// into `buffer`.
+    pub fn execute(self, buffer: &CommandBuffer, pool: &mut TransientResourcePool) -> Result<()> {
+        let compiled = self.compile();
+
+        let pool_images = pool.acquire_images(&compiled.image_slots)?;
+        let pool_buffers = pool.acquire_buffers(&compiled.buffer_slots)?;
+        let pool = &*pool;
+
+        let RenderGraph {
+            resources,
+            passes,
+            profiler,
+        } = self;
+
+        let physical_resources = resources
+            .iter()
+            .enumerate()
+            .map(|(index, resource)| match resource.kind {
+                ResourceKind::ImportedImage { image, view, .. } => {
+                    PhysicalResource::Image(image, view)
+                }
+                ResourceKind::ImportedBuffer { buffer, .. } => PhysicalResource::Buffer(buffer),
+                ResourceKind::ImportedAccelerationStructure {
+                    acceleration_structure,
+                    ..
+                } => PhysicalResource::AccelerationStructure(acceleration_structure),
+                ResourceKind::TransientImage(_) => match compiled.resource_slots[index] {
+                    Some(slot) => {
+                        let image = &pool.images[pool_images[slot]];
+                        PhysicalResource::Image(&image.image, Some(&image.view))
+                    }
+                    None => PhysicalResource::None,
+                },
+                ResourceKind::TransientBuffer(_) => match compiled.resource_slots[index] {
+                    Some(slot) => {
+                        PhysicalResource::Buffer(&pool.buffers[pool_buffers[slot]].buffer)
+                    }
+                    None => PhysicalResource::None,
+                },
+            })
+            .collect::<Vec<_>>();
+        let mut pass_resources = PassResources {
+            accesses: vec![None; physical_resources.len()],
+            resources: physical_resources,
+        };
+
+        let (mut passes, accesses): (Vec<_>, Vec<_>) = passes
+            .into_iter()
+            .map(|p| ((p.name, p.execute), p.accesses))
+            .unzip();
+
+        for compiled_pass in &compiled.passes {
+            let (name, execute) = &mut passes[compiled_pass.index];
+            let _scope = profiler.map(|profiler| buffer.profile_scope(profiler, name));
+            buffer.set_checkpoint(name);
+
+            record_barriers(buffer, &pass_resources, &compiled_pass.barriers);
+
+            pass_resources.accesses.fill(None);
+            for access in &accesses[compiled_pass.index] {
+                pass_resources.accesses[access.resource] = Some(access.access);
+            }
+            if let Some(execute) = execute.take() {
+                execute(buffer, &pass_resources)?;
+            }
+        }
+
+        record_barriers(buffer, &pass_resources, &compiled.final_barriers);
+
+        Ok(())
+    }
+
+    fn add_resource(&mut self, name: &str, kind: ResourceKind<'a>) -> usize {
+        self.resources.push(Resource {
+            name: name.to_owned(),
+            kind,
+            final_access: None,
+            is_output: false,
+        });
+
+        self.resources.len() - 1
+    }
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    pub fn read(self, resource: impl Into<ResourceHandle>, access: Access) -> Self {
+        self.access(resource.into(), access, false)
+    }
+
+    pub fn write(self, resource: impl Into<ResourceHandle>, access: Access) -> Self {
+        debug_assert!(
+            access.is_write(),
+            "{access:?} is not a write access, declare it with PassBuilder::read"
+        );
+        self.access(resource.into(), access, true)
+    }
+
+    /// The pass is never culled, e.g. because it writes to resources unknown to the graph.
+    pub fn side_effects(mut self) -> Self {
+        self.pass.has_side_effects = true;
+        self
+    }
+
+    pub fn execute(
+        mut self,
+        execute: impl FnOnce(&CommandBuffer, &PassResources) -> Result<()> + 'a,
+    ) {
+        self.pass.execute = Some(Box::new(execute));
+        self.graph.passes.push(self.pass);
+    }
+
+    fn access(mut self, resource: ResourceHandle, access: Access, is_write: bool) -> Self {
+        let resource = resource.index();
+        assert!(
+            self.pass.accesses.iter().all(|a| a.resource != resource),
+            "Resource {} is declared more than once by pass {}",
+            self.graph.resources[resource].name,
+            self.pass.name
+        );
+
+        self.pass.accesses.push(PassAccess {
+            resource,
+            access,
+            is_write,
+        });
+        self
+    }
+}
+
+fn record_barriers(buffer: &CommandBuffer, resources: &PassResources, barriers: &[Barrier]) {
+    if barriers.is_empty() {
+        return;
+    }
+
+    let mut memory_barriers = vec![];
+    let mut buffer_barriers = vec![];
+    let mut image_barriers = vec![];
+
+    for barrier in barriers {
+        match barrier.resource {
+            ResourceHandle::Image(handle) => image_barriers.push(ImageBarrier {
+                image: resources.image(handle),
+                old_layout: barrier.old_layout,
+                new_layout: barrier.new_layout,
+                src_access_mask: barrier.src_access_mask,
+                dst_access_mask: barrier.dst_access_mask,
+                src_stage_mask: barrier.src_stage_mask,
+                dst_stage_mask: barrier.dst_stage_mask,
+            }),
+            ResourceHandle::Buffer(handle) => buffer_barriers.push(BufferBarrier {
+                buffer: resources.buffer(handle),
+                src_access_mask: barrier.src_access_mask,
+                dst_access_mask: barrier.dst_access_mask,
+                src_stage_mask: barrier.src_stage_mask,
+                dst_stage_mask: barrier.dst_stage_mask,
+            }),
+            // Acceleration structures are synchronized with global memory barriers
+            ResourceHandle::AccelerationStructure(_) => memory_barriers.push(MemoryBarrier {
+                src_access_mask: barrier.src_access_mask,
+                dst_access_mask: barrier.dst_access_mask,
+                src_stage_mask: barrier.src_stage_mask,
+                dst_stage_mask: barrier.dst_stage_mask,
+            }),
+        }
+    }
+
+    buffer.pipeline_barriers(&memory_barriers, &buffer_barriers, &image_barriers);
+}
@@ -0,0 +1,10 @@
+mod access;
+mod compile;
+mod dot;
+mod graph;
+mod resources;
+
+pub use access::*;
+pub use compile::*;
+pub use graph::*;
+pub use resources::*;
@@ -0,0 +1,295 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::{vulkan::Allocator, MemoryLocation};
+
+use crate::render_graph::Access;
+use crate::vulkan::{AccelerationStructure, Buffer, Context, Device, Image, ImageView};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageHandle(pub(crate) usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferHandle(pub(crate) usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AccelerationStructureHandle(pub(crate) usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceHandle {
+    Image(ImageHandle),
+    Buffer(BufferHandle),
+    AccelerationStructure(AccelerationStructureHandle),
+}
+
+impl ResourceHandle {
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Self::Image(ImageHandle(i))
+            | Self::Buffer(BufferHandle(i))
+            | Self::AccelerationStructure(AccelerationStructureHandle(i)) => i,
+        }
+    }
+}
+
+impl From<ImageHandle> for ResourceHandle {
+    fn from(handle: ImageHandle) -> Self {
+        Self::Image(handle)
+    }
+}
+
+impl From<BufferHandle> for ResourceHandle {
+    fn from(handle: BufferHandle) -> Self {
+        Self::Buffer(handle)
+    }
+}
+
+impl From<AccelerationStructureHandle> for ResourceHandle {
+    fn from(handle: AccelerationStructureHandle) -> Self {
+        Self::AccelerationStructure(handle)
+    }
+}
+
+/// Description of a transient image. Usage flags are derived from the accesses declared by
+/// the passes using it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl ImageDesc {
+    pub fn new_2d(format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self {
+            format,
+            extent,
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+
+    pub fn samples(self, samples: vk::SampleCountFlags) -> Self {
+        Self { samples, ..self }
+    }
+}
+
+/// Description of a transient, GPU only buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferDesc {
+    pub size: vk::DeviceSize,
+}
+
+impl BufferDesc {
+    pub fn new(size: vk::DeviceSize) -> Self {
+        Self { size }
+    }
+}
+
+/// Physical resources a pass can access while recording.
+pub struct PassResources<'r> {
+    pub(crate) resources: Vec<PhysicalResource<'r>>,
+    /// Declared by the executing pass, by resource.
+    pub(crate) accesses: Vec<Option<Access>>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum PhysicalResource<'r> {
+    None,
+    Image(&'r Image, Option<&'r ImageView>),
+    Buffer(&'r Buffer),
+    AccelerationStructure(&'r AccelerationStructure),
+}
+
+impl<'r> PassResources<'r> {
+    pub fn image(&self, handle: ImageHandle) -> &'r Image {
+        match self.resources[handle.0] {
+            PhysicalResource::Image(image, _) => image,
+            _ => panic!("Image was not declared by any executed pass"),
+        }
+    }
+
+    pub fn image_view(&self, handle: ImageHandle) -> &'r ImageView {
+        match self.resources[handle.0] {
+            PhysicalResource::Image(_, Some(view)) => view,
+            PhysicalResource::Image(_, None) => panic!("Image was imported without a view"),
+            _ => panic!("Image was not declared by any executed pass"),
+        }
+    }
+
+    /// Layout of the image in the executing pass, from the access it declared. E.g. for the
+    /// [`RenderingAttachment::layout`](crate::vulkan::RenderingAttachment::layout) of
+    /// attachments.
+    pub fn layout(&self, handle: ImageHandle) -> vk::ImageLayout {
+        self.accesses[handle.0]
+            .expect("Image was not declared by the executing pass")
+            .info()
+            .layout
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> &'r Buffer {
+        match self.resources[handle.0] {
+            PhysicalResource::Buffer(buffer) => buffer,
+            _ => panic!("Buffer was not declared by any executed pass"),
+        }
+    }
+
+    pub fn acceleration_structure(
+        &self,
+        handle: AccelerationStructureHandle,
+    ) -> &'r AccelerationStructure {
+        match self.resources[handle.0] {
+            PhysicalResource::AccelerationStructure(acceleration_structure) => {
+                acceleration_structure
+            }
+            _ => panic!("Acceleration structure was not declared by any executed pass"),
+        }
+    }
+}
+
+/// Owns the physical images and buffers backing transient graph resources.
+///
+/// Resources are kept across frames and reused by any graph asking for the same description
+/// and usage. Entries not used for more than `frames_in_flight` frames are released.
+pub struct TransientResourcePool {
+    device: Arc<Device>,
+    allocator: Arc<Mutex<Allocator>>,
+    frames_in_flight: u64,
+    frame: u64,
+    pub(crate) images: Vec<TransientImage>,
+    pub(crate) buffers: Vec<TransientBuffer>,
+}
+
+pub(crate) struct TransientImage {
+    key: (ImageDesc, vk::ImageUsageFlags),
+    pub(crate) image: Image,
+    pub(crate) view: ImageView,
+    last_used: u64,
+}
+
+pub(crate) struct TransientBuffer {
+    key: (BufferDesc, vk::BufferUsageFlags),
+    pub(crate) buffer: Buffer,
+    last_used: u64,
+}
+
+impl TransientResourcePool {
+    pub(crate) fn new(context: &Context, frames_in_flight: u32) -> Self {
+        Self {
+            device: context.device.clone(),
+            allocator: context.allocator.clone(),
+            frames_in_flight: frames_in_flight as _,
+            frame: 0,
+            images: vec![],
+            buffers: vec![],
+        }
+    }
+
+    /// Must be called once per frame, after waiting for the fence of the frame being reused.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+
+        let frame = self.frame;
+        let frames_in_flight = self.frames_in_flight;
+        self.images
+            .retain(|i| i.last_used + frames_in_flight >= frame);
+        self.buffers
+            .retain(|b| b.last_used + frames_in_flight >= frame);
+    }
+
+    /// Releases all resources. The GPU must not be using any of them anymore.
+    pub fn clear(&mut self) {
+        self.images.clear();
+        self.buffers.clear();
+    }
+
+    pub(crate) fn acquire_images(
+        &mut self,
+        keys: &[(ImageDesc, vk::ImageUsageFlags)],
+    ) -> Result<Vec<usize>> {
+        let mut taken = vec![false; self.images.len()];
+        let mut indices = Vec::with_capacity(keys.len());
+
+        for key in keys {
+            let index =
+                match (0..self.images.len()).find(|&i| !taken[i] && self.images[i].key == *key) {
+                    Some(index) => index,
+                    None => {
+                        let (desc, usage) = *key;
+                        let image = Image::new_2d(
+                            self.device.clone(),
+                            self.allocator.clone(),
+                            usage,
+                            MemoryLocation::GpuOnly,
+                            desc.format,
+                            desc.extent,
+                            desc.samples,
+                        )?;
+                        let view = image.create_image_view()?;
+
+                        self.images.push(TransientImage {
+                            key: *key,
+                            image,
+                            view,
+                            last_used: self.frame,
+                        });
+                        taken.push(false);
+
+                        self.images.len() - 1
+                    }
+                };
+
+            taken[index] = true;
+            self.images[index].last_used = self.frame;
+            indices.push(index);
+        }
+
+        Ok(indices)
+    }
+
+    pub(crate) fn acquire_buffers(
+        &mut self,
+        keys: &[(BufferDesc, vk::BufferUsageFlags)],
+    ) -> Result<Vec<usize>> {
+        let mut taken = vec![false; self.buffers.len()];
+        let mut indices = Vec::with_capacity(keys.len());
+
+        for key in keys {
+            let index =
+                match (0..self.buffers.len()).find(|&i| !taken[i] && self.buffers[i].key == *key) {
+                    Some(index) => index,
+                    None => {
+                        let (desc, usage) = *key;
+                        let buffer = Buffer::new(
+                            self.device.clone(),
+                            self.allocator.clone(),
+                            usage,
+                            MemoryLocation::GpuOnly,
+                            desc.size,
+                        )?;
+
+                        self.buffers.push(TransientBuffer {
+                            key: *key,
+                            buffer,
+                            last_used: self.frame,
+                        });
+                        taken.push(false);
+
+                        self.buffers.len() - 1
+                    }
+                };
+
+            taken[index] = true;
+            self.buffers[index].last_used = self.frame;
+            indices.push(index);
+        }
+
+        Ok(indices)
+    }
+}
+
+impl Context {
+    pub fn create_transient_resource_pool(&self, frames_in_flight: u32) -> TransientResourcePool {
+        TransientResourcePool::new(self, frames_in_flight)
+    }
+}
@@ -0,0 +1,429 @@
+use std::cell::Cell;
+
+use anyhow::{ensure, Result};
+use ash::vk;
+
+use crate::app::ImageAndView;
+use crate::render_graph::{Access, ImageHandle, RenderGraph};
+use crate::renderer::create_storage_image;
+use crate::vulkan::{
+    CommandBuffer, ComputePipeline, ComputePipelineCreateInfo, Context, DescriptorPool,
+    DescriptorSet, DescriptorSetLayout, MemoryBarrier, PipelineLayout, Pod, WriteDescriptorSet,
+    WriteDescriptorSetKind,
+};
+
+pub const DENOISER_COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const DENOISER_ALBEDO_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const DENOISER_NORMAL_DEPTH_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+pub const DENOISER_MOTION_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const FILTER_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+const GROUP_SIZE: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiserSettings {
+    /// Number of à-trous passes, the filter footprint doubles with each one.
+    pub atrous_iterations: u32,
+    /// Minimum weight of the current frame in the color history.
+    pub temporal_alpha: f32,
+    /// Minimum weight of the current frame in the luminance moments history.
+    pub moments_alpha: f32,
+    pub max_history_length: f32,
+    /// Luminance edge stopping, higher values blur more across luminance changes.
+    pub phi_color: f32,
+    /// Normal edge stopping exponent.
+    pub phi_normal: f32,
+    /// Depth edge stopping, relative to the depth of the filtered pixel.
+    pub phi_depth: f32,
+}
+
+impl Default for DenoiserSettings {
+    fn default() -> Self {
+        Self {
+            atrous_iterations: 5,
+            temporal_alpha: 0.2,
+            moments_alpha: 0.2,
+            max_history_length: 32.0,
+            phi_color: 10.0,
+            phi_normal: 128.0,
+            phi_depth: 1.0,
+        }
+    }
+}
+
+/// SVGF denoiser: temporal reprojection of demodulated color and luminance moments, then
+/// variance guided à-trous filtering.
+///
+/// The renderer writes its noisy output and G-buffer to the input images, from shaders or as
+/// color attachments, and leaves them in the `GENERAL` layout:
+/// - color: radiance, demodulated by the albedo before filtering and remodulated after.
+/// - albedo: surface color, 1 for the sky.
+/// - normal and depth: world space normal in xyz and linear depth in w, 0 for the sky.
+/// - motion: xy is the motion in pixels from the previous frame to the current one.
+pub struct Denoiser {
+    pub settings: DenoiserSettings,
+    extent: vk::Extent2D,
+    images: DenoiserImages,
+    _descriptor_pool: DescriptorPool,
+    _descriptor_set_layout: DescriptorSetLayout,
+    descriptor_set: DescriptorSet,
+    pipeline_layout: PipelineLayout,
+    reproject_pipeline: ComputePipeline,
+    atrous_pipeline: ComputePipeline,
+    history_valid: Cell<bool>,
+}
+
+struct DenoiserImages {
+    color: ImageAndView,
+    albedo: ImageAndView,
+    normal_depth: ImageAndView,
+    motion: ImageAndView,
+    previous_normal_depth: ImageAndView,
+    history_color: ImageAndView,
+    previous_moments: ImageAndView,
+    moments: ImageAndView,
+    filter: [ImageAndView; 2],
+    output: ImageAndView,
+}
+
+/// The denoiser images, imported in a render graph.
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiserHandles {
+    pub color: ImageHandle,
+    pub albedo: ImageHandle,
+    pub normal_depth: ImageHandle,
+    pub motion: ImageHandle,
+    pub output: ImageHandle,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct PushConstants {
+    iteration: u32,
+    iteration_count: u32,
+    history_valid: u32,
+    temporal_alpha: f32,
+    moments_alpha: f32,
+    max_history_length: f32,
+    phi_color: f32,
+    phi_normal: f32,
+    phi_depth: f32,
+}
+
+// Only 4 byte fields, no padding
+unsafe impl Pod for PushConstants {}
+
+impl Denoiser {
+    pub(crate) fn new(
+        context: &Context,
+        extent: vk::Extent2D,
+        settings: DenoiserSettings,
+    ) -> Result<Self> {
+        let binding = |binding, descriptor_count| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(descriptor_count)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        };
+        let descriptor_set_layout = context.create_descriptor_set_layout(&[
+            binding(0, 1),
+            binding(1, 1),
+            binding(2, 1),
+            binding(3, 1),
+            binding(4, 1),
+            binding(5, 1),
+            binding(6, 1),
+            binding(7, 1),
+            binding(8, 2),
+            binding(9, 1),
+        ])?;
+
+        let descriptor_pool = context.create_descriptor_pool(
+            1,
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 11,
+            }],
+        )?;
+        let descriptor_set = descriptor_pool.allocate_set(&descriptor_set_layout)?;
+
+        let pipeline_layout = context.create_pipeline_layout_with_push_constants(
+            &[&descriptor_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as _,
+            }],
+        )?;
+
+        let reproject_pipeline = context.create_compute_pipeline(
+            &pipeline_layout,
+            ComputePipelineCreateInfo {
+                shader_source: &include_bytes!("./shaders/reproject.comp.spv")[..],
+            },
+        )?;
+        let atrous_pipeline = context.create_compute_pipeline(
+            &pipeline_layout,
+            ComputePipelineCreateInfo {
+                shader_source: &include_bytes!("./shaders/atrous.comp.spv")[..],
+            },
+        )?;
+
+        let images = DenoiserImages::new(context, extent)?;
+        images.write_descriptors(&descriptor_set);
+
+        Ok(Self {
+            settings,
+            extent,
+            images,
+            _descriptor_pool: descriptor_pool,
+            _descriptor_set_layout: descriptor_set_layout,
+            descriptor_set,
+            pipeline_layout,
+            reproject_pipeline,
+            atrous_pipeline,
+            history_valid: Cell::new(false),
+        })
+    }
+
+    /// Recreates all images, dropping the history. The GPU must not be using the denoiser.
+    pub fn resize(&mut self, context: &Context, extent: vk::Extent2D) -> Result<()> {
+        self.images = DenoiserImages::new(context, extent)?;
+        self.images.write_descriptors(&self.descriptor_set);
+        self.extent = extent;
+        self.reset();
+
+        Ok(())
+    }
+
+    /// Drops the history, e.g. on camera cuts.
+    pub fn reset(&self) {
+        self.history_valid.set(false);
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn color_input(&self) -> &ImageAndView {
+        &self.images.color
+    }
+
+    pub fn albedo_input(&self) -> &ImageAndView {
+        &self.images.albedo
+    }
+
+    pub fn normal_depth_input(&self) -> &ImageAndView {
+        &self.images.normal_depth
+    }
+
+    pub fn motion_input(&self) -> &ImageAndView {
+        &self.images.motion
+    }
+
+    pub fn output(&self) -> &ImageAndView {
+        &self.images.output
+    }
+
+    /// Denoises the inputs into the output image. Writes to the inputs must be visible to
+    /// compute shaders.
+    pub fn record(&self, cmd_buffer: &CommandBuffer) -> Result<()> {
+        ensure!(
+            self.settings.atrous_iterations > 0,
+            "The denoiser needs at least one à-trous iteration"
+        );
+
+        let compute_barrier = MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+        };
+        let group_count_x = self.extent.width.div_ceil(GROUP_SIZE);
+        let group_count_y = self.extent.height.div_ceil(GROUP_SIZE);
+
+        cmd_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[&self.descriptor_set],
+        );
+
+        cmd_buffer.bind_compute_pipeline(&self.reproject_pipeline);
+        self.push_constants(cmd_buffer, 0);
+        cmd_buffer.dispatch(group_count_x, group_count_y, 1);
+
+        cmd_buffer.bind_compute_pipeline(&self.atrous_pipeline);
+        for iteration in 0..self.settings.atrous_iterations {
+            cmd_buffer.pipeline_memory_barriers(&[compute_barrier]);
+            self.push_constants(cmd_buffer, iteration);
+            cmd_buffer.dispatch(group_count_x, group_count_y, 1);
+        }
+
+        // Current normals, depths and moments are the history of the next frame
+        cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            dst_access_mask: vk::AccessFlags2::TRANSFER_READ | vk::AccessFlags2::TRANSFER_WRITE,
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
+        }]);
+        for (src, dst) in [
+            (
+                &self.images.normal_depth,
+                &self.images.previous_normal_depth,
+            ),
+            (&self.images.moments, &self.images.previous_moments),
+        ] {
+            cmd_buffer.copy_image(
+                &src.image,
+                vk::ImageLayout::GENERAL,
+                &dst.image,
+                vk::ImageLayout::GENERAL,
+            );
+        }
+        cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ,
+            src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+        }]);
+
+        self.history_valid.set(true);
+
+        Ok(())
+    }
+
+    /// Imports the inputs and the output in `graph`. Passes rendering the noisy image write
+    /// to the input handles.
+    pub fn import_images<'a>(&'a self, graph: &mut RenderGraph<'a>) -> DenoiserHandles {
+        let mut import = |name, image: &'a ImageAndView| {
+            let handle = graph.import_image(name, &image.image, Some(&image.view), Access::General);
+            graph.set_final_access(handle, Access::General);
+            handle
+        };
+
+        DenoiserHandles {
+            color: import("denoiser color", &self.images.color),
+            albedo: import("denoiser albedo", &self.images.albedo),
+            normal_depth: import("denoiser normal depth", &self.images.normal_depth),
+            motion: import("denoiser motion", &self.images.motion),
+            output: import("denoiser output", &self.images.output),
+        }
+    }
+
+    pub fn add_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, handles: DenoiserHandles) {
+        graph
+            .add_pass("denoise")
+            .read(handles.color, Access::ComputeShaderStorageRead)
+            .read(handles.albedo, Access::ComputeShaderStorageRead)
+            .read(handles.normal_depth, Access::ComputeShaderStorageRead)
+            .read(handles.motion, Access::ComputeShaderStorageRead)
+            .write(handles.output, Access::ComputeShaderStorageWrite)
+            .execute(move |cmd_buffer, _| self.record(cmd_buffer));
+    }
+
+    fn push_constants(&self, cmd_buffer: &CommandBuffer, iteration: u32) {
+        let settings = &self.settings;
+        cmd_buffer.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &PushConstants {
+                iteration,
+                iteration_count: settings.atrous_iterations,
+                history_valid: self.history_valid.get() as _,
+                temporal_alpha: settings.temporal_alpha,
+                moments_alpha: settings.moments_alpha,
+                max_history_length: settings.max_history_length,
+                phi_color: settings.phi_color,
+                phi_normal: settings.phi_normal,
+                phi_depth: settings.phi_depth,
+            },
+        );
+    }
+}
+
+impl DenoiserImages {
+    fn new(context: &Context, extent: vk::Extent2D) -> Result<Self> {
+        let input = |format| {
+            create_storage_image(
+                context,
+                format,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                extent,
+            )
+        };
+        let history = |format| {
+            create_storage_image(context, format, vk::ImageUsageFlags::TRANSFER_DST, extent)
+        };
+
+        Ok(Self {
+            color: input(DENOISER_COLOR_FORMAT)?,
+            albedo: input(DENOISER_ALBEDO_FORMAT)?,
+            normal_depth: input(DENOISER_NORMAL_DEPTH_FORMAT)?,
+            motion: input(DENOISER_MOTION_FORMAT)?,
+            previous_normal_depth: history(DENOISER_NORMAL_DEPTH_FORMAT)?,
+            history_color: history(DENOISER_COLOR_FORMAT)?,
+            previous_moments: history(FILTER_FORMAT)?,
+            moments: input(FILTER_FORMAT)?,
+            filter: [history(FILTER_FORMAT)?, history(FILTER_FORMAT)?],
+            output: create_storage_image(
+                context,
+                DENOISER_COLOR_FORMAT,
+                vk::ImageUsageFlags::TRANSFER_SRC,
+                extent,
+            )?,
+        })
+    }
+
+    fn write_descriptors(&self, descriptor_set: &DescriptorSet) {
+        fn storage_image(binding: u32, image: &ImageAndView) -> WriteDescriptorSet<'_> {
+            WriteDescriptorSet {
+                binding,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageImage {
+                    view: &image.view,
+                    layout: vk::ImageLayout::GENERAL,
+                },
+            }
+        }
+
+        let filter_views = [&self.filter[0].view, &self.filter[1].view];
+
+        descriptor_set.update(&[
+            storage_image(0, &self.color),
+            storage_image(1, &self.albedo),
+            storage_image(2, &self.normal_depth),
+            storage_image(3, &self.motion),
+            storage_image(4, &self.previous_normal_depth),
+            storage_image(5, &self.history_color),
+            storage_image(6, &self.previous_moments),
+            storage_image(7, &self.moments),
+            WriteDescriptorSet {
+                binding: 8,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageImageArray {
+                    views: &filter_views,
+                    layout: vk::ImageLayout::GENERAL,
+                },
+            },
+            storage_image(9, &self.output),
+        ]);
+    }
+}
+
+impl Context {
+    pub fn create_denoiser(
+        &self,
+        extent: vk::Extent2D,
+        settings: DenoiserSettings,
+    ) -> Result<Denoiser> {
+        Denoiser::new(self, extent, settings)
+    }
+}
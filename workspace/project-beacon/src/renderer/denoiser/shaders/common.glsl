@@ -0,0 +1,44 @@
+layout(local_size_x = 8, local_size_y = 8) in;
+
+// Inputs written by the renderer
+layout(set = 0, binding = 0, rgba16f) uniform readonly image2D color_image;
+layout(set = 0, binding = 1, rgba16f) uniform readonly image2D albedo_image;
+// xyz is the world space normal, w the linear depth, 0 where nothing was hit
+layout(set = 0, binding = 2, rgba32f) uniform readonly image2D normal_depth_image;
+// Pixels from the previous frame to the current one
+layout(set = 0, binding = 3, rgba16f) uniform readonly image2D motion_image;
+
+// History
+layout(set = 0, binding = 4, rgba32f) uniform image2D previous_normal_depth_image;
+layout(set = 0, binding = 5, rgba16f) uniform image2D history_color_image;
+// First and second luminance moments and history length
+layout(set = 0, binding = 6, rgba32f) uniform image2D previous_moments_image;
+layout(set = 0, binding = 7, rgba32f) uniform image2D moments_image;
+
+// Demodulated color and variance, ping-ponged between filter iterations
+layout(set = 0, binding = 8, rgba32f) uniform image2D filter_images[2];
+layout(set = 0, binding = 9, rgba16f) uniform writeonly image2D output_image;
+
+layout(push_constant) uniform PushConstants {
+    uint iteration;
+    uint iteration_count;
+    uint history_valid;
+    float temporal_alpha;
+    float moments_alpha;
+    float max_history_length;
+    float phi_color;
+    float phi_normal;
+    float phi_depth;
+} pc;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+vec3 demodulation_albedo(ivec2 pixel) {
+    return max(imageLoad(albedo_image, pixel).rgb, vec3(1e-3));
+}
+
+bool in_bounds(ivec2 pixel, ivec2 size) {
+    return all(greaterThanEqual(pixel, ivec2(0))) && all(lessThan(pixel, size));
+}
@@ -0,0 +1,46 @@
+mod denoiser;
+mod path_tracer;
+pub mod reference;
+mod tonemap;
+
+pub use denoiser::*;
+pub use path_tracer::*;
+pub use tonemap::*;
+
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+
+use crate::app::ImageAndView;
+use crate::vulkan::{Context, ImageBarrier};
+
+/// Creates a storage image, in addition to `usage`, and transitions it to `GENERAL`.
+pub(crate) fn create_storage_image(
+    context: &Context,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    extent: vk::Extent2D,
+) -> Result<ImageAndView> {
+    let image = context.create_image(
+        vk::ImageUsageFlags::STORAGE | usage,
+        MemoryLocation::GpuOnly,
+        format,
+        extent.width,
+        extent.height,
+    )?;
+    let view = image.create_image_view()?;
+
+    context.execute_one_time_commands(|cmd_buffer| {
+        cmd_buffer.pipeline_image_barriers(&[ImageBarrier {
+            image: &image,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::GENERAL,
+            src_access_mask: vk::AccessFlags2::NONE,
+            dst_access_mask: vk::AccessFlags2::NONE,
+            src_stage_mask: vk::PipelineStageFlags2::NONE,
+            dst_stage_mask: vk::PipelineStageFlags2::ALL_COMMANDS,
+        }]);
+    })?;
+
+    Ok(ImageAndView { view, image })
+}
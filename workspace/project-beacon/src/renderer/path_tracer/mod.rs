@@ -0,0 +1,471 @@
+use std::cell::Cell;
+
+use anyhow::Result;
+use ash::vk;
+use glam::Vec3;
+
+use crate::app::{camera::Camera, ImageAndView};
+use crate::render_graph::{Access, ImageHandle, RenderGraph};
+use crate::renderer::create_storage_image;
+use crate::scene::GpuScene;
+use crate::vulkan::{
+    CommandBuffer, Context, DescriptorPool, DescriptorSet, DescriptorSetLayout, Image, ImageView,
+    PipelineLayout, Pod, RayTracingPipeline, RayTracingPipelineCreateInfo,
+    RayTracingShaderCreateInfo, RayTracingShaderGroup, ShaderBindingTable, WriteDescriptorSet,
+    WriteDescriptorSetKind,
+};
+
+pub const ACCUMULATION_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+const SHADER_STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(
+    vk::ShaderStageFlags::RAYGEN_KHR.as_raw()
+        | vk::ShaderStageFlags::CLOSEST_HIT_KHR.as_raw()
+        | vk::ShaderStageFlags::MISS_KHR.as_raw(),
+);
+
+#[derive(Debug, Clone, Copy)]
+pub struct PathTracerSettings {
+    pub max_bounces: u32,
+    pub samples_per_frame: u32,
+    /// Stops tracing once this many frames have been accumulated, 0 never stops.
+    pub max_frames: u32,
+    /// Bounce after which paths are randomly terminated based on their throughput.
+    pub russian_roulette_depth: u32,
+}
+
+impl Default for PathTracerSettings {
+    fn default() -> Self {
+        Self {
+            max_bounces: 8,
+            samples_per_frame: 1,
+            max_frames: 0,
+            russian_roulette_depth: 3,
+        }
+    }
+}
+
+/// Progressive path tracer with next event estimation, accumulating into an RGBA32F image.
+///
+/// Accumulation restarts when the camera, the scene generation or the settings change.
+pub struct PathTracer {
+    pub settings: PathTracerSettings,
+    accumulation: Image,
+    accumulation_view: ImageView,
+    _descriptor_pool: DescriptorPool,
+    _descriptor_set_layout: DescriptorSetLayout,
+    descriptor_set: DescriptorSet,
+    pipeline_layout: PipelineLayout,
+    pipeline: RayTracingPipeline,
+    shader_binding_table: ShaderBindingTable,
+    accumulation_state: Cell<AccumulationState>,
+    /// Of the TLAS the descriptor set references.
+    tlas_generation: Cell<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct PushConstants {
+    position_tan_half_fov: [f32; 4],
+    forward_aspect: [f32; 4],
+    right: [f32; 4],
+    up: [f32; 4],
+    sky_color: [f32; 4],
+    sample_index: u32,
+    samples_per_frame: u32,
+    max_bounces: u32,
+    light_count: u32,
+    russian_roulette_depth: u32,
+    _padding: [u32; 3],
+}
+
+// Only 4 byte fields, padded explicitly to a multiple of 16 bytes
+unsafe impl Pod for PushConstants {}
+
+/// What the accumulated frames were rendered with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AccumulationKey {
+    position: Vec3,
+    direction: Vec3,
+    fov: f32,
+    aspect_ratio: f32,
+    scene_generation: u64,
+    max_bounces: u32,
+    samples_per_frame: u32,
+    russian_roulette_depth: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct AccumulationState {
+    key: Option<AccumulationKey>,
+    frame_count: u32,
+}
+
+impl AccumulationState {
+    /// Returns the index of the frame to trace, or `None` once `max_frames` are accumulated.
+    fn next_frame(&mut self, key: AccumulationKey, max_frames: u32) -> Option<u32> {
+        if self.key != Some(key) {
+            *self = Self {
+                key: Some(key),
+                frame_count: 0,
+            };
+        }
+
+        if max_frames != 0 && self.frame_count >= max_frames {
+            return None;
+        }
+
+        self.frame_count += 1;
+        Some(self.frame_count - 1)
+    }
+}
+
+impl PathTracer {
+    pub(crate) fn new(
+        context: &Context,
+        scene: &GpuScene,
+        extent: vk::Extent2D,
+        settings: PathTracerSettings,
+    ) -> Result<Self> {
+        let descriptor_set_layout = context.create_descriptor_set_layout(&[
+            layout_binding(0, vk::DescriptorType::ACCELERATION_STRUCTURE_KHR),
+            layout_binding(1, vk::DescriptorType::STORAGE_IMAGE),
+            layout_binding(2, vk::DescriptorType::STORAGE_BUFFER),
+            layout_binding(3, vk::DescriptorType::STORAGE_BUFFER),
+            layout_binding(4, vk::DescriptorType::STORAGE_BUFFER),
+        ])?;
+
+        let descriptor_pool = context.create_descriptor_pool(
+            1,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 3,
+                },
+            ],
+        )?;
+        let descriptor_set = descriptor_pool.allocate_set(&descriptor_set_layout)?;
+
+        let pipeline_layout = context.create_pipeline_layout_with_push_constants(
+            &[&descriptor_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: SHADER_STAGES,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as _,
+            }],
+        )?;
+
+        let pipeline = context.create_ray_tracing_pipeline(
+            &pipeline_layout,
+            RayTracingPipelineCreateInfo {
+                shaders: &[
+                    RayTracingShaderCreateInfo {
+                        source: &include_bytes!("./shaders/raygen.rgen.spv")[..],
+                        stage: vk::ShaderStageFlags::RAYGEN_KHR,
+                        group: RayTracingShaderGroup::RayGen,
+                    },
+                    RayTracingShaderCreateInfo {
+                        source: &include_bytes!("./shaders/miss.rmiss.spv")[..],
+                        stage: vk::ShaderStageFlags::MISS_KHR,
+                        group: RayTracingShaderGroup::Miss,
+                    },
+                    RayTracingShaderCreateInfo {
+                        source: &include_bytes!("./shaders/shadow.rmiss.spv")[..],
+                        stage: vk::ShaderStageFlags::MISS_KHR,
+                        group: RayTracingShaderGroup::Miss,
+                    },
+                    RayTracingShaderCreateInfo {
+                        source: &include_bytes!("./shaders/closest_hit.rchit.spv")[..],
+                        stage: vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                        group: RayTracingShaderGroup::ClosestHit,
+                    },
+                ],
+                hit_groups: &[],
+                max_ray_recursion_depth: 1,
+            },
+        )?;
+        let shader_binding_table = context.create_shader_binding_table(&pipeline)?;
+
+        let (accumulation, accumulation_view) = create_accumulation_image(context, extent)?;
+
+        let path_tracer = Self {
+            settings,
+            accumulation,
+            accumulation_view,
+            _descriptor_pool: descriptor_pool,
+            _descriptor_set_layout: descriptor_set_layout,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+            shader_binding_table,
+            accumulation_state: Default::default(),
+            tlas_generation: Default::default(),
+        };
+        path_tracer.write_descriptors(scene);
+
+        Ok(path_tracer)
+    }
+
+    /// Recreates the accumulation image. The GPU must not be using the path tracer.
+    pub fn resize(
+        &mut self,
+        context: &Context,
+        scene: &GpuScene,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let (accumulation, accumulation_view) = create_accumulation_image(context, extent)?;
+        self.accumulation = accumulation;
+        self.accumulation_view = accumulation_view;
+
+        self.write_descriptors(scene);
+        self.reset();
+
+        Ok(())
+    }
+
+    /// Renders `scene` from now on. The GPU must not be using the path tracer.
+    pub fn set_scene(&mut self, scene: &GpuScene) {
+        self.write_descriptors(scene);
+        self.reset();
+    }
+
+    pub fn reset(&self) {
+        self.accumulation_state.set(Default::default());
+    }
+
+    /// Number of frames accumulated since the last reset.
+    pub fn frame_count(&self) -> u32 {
+        self.accumulation_state.get().frame_count
+    }
+
+    /// Average of all accumulated samples, in `GENERAL` layout outside of [`PathTracer::record`].
+    pub fn accumulation_image(&self) -> &Image {
+        &self.accumulation
+    }
+
+    pub fn accumulation_image_view(&self) -> &ImageView {
+        &self.accumulation_view
+    }
+
+    /// Traces one frame into the accumulation image, unless `max_frames` are accumulated.
+    /// The accumulation image must be in the `GENERAL` layout.
+    pub fn record(&self, cmd_buffer: &CommandBuffer, scene: &GpuScene, camera: &Camera) {
+        // The TLAS was recreated by a scene update, while the GPU was not using the scene
+        if scene.tlas.generation() != self.tlas_generation.get() {
+            self.write_descriptors(scene);
+        }
+
+        let key = AccumulationKey {
+            position: camera.position,
+            direction: camera.direction,
+            fov: camera.fov,
+            aspect_ratio: camera.aspect_ratio,
+            scene_generation: scene.generation(),
+            max_bounces: self.settings.max_bounces,
+            samples_per_frame: self.settings.samples_per_frame,
+            russian_roulette_depth: self.settings.russian_roulette_depth,
+        };
+
+        let mut state = self.accumulation_state.get();
+        let sample_index = state.next_frame(key, self.settings.max_frames);
+        self.accumulation_state.set(state);
+
+        let Some(sample_index) = sample_index else {
+            return;
+        };
+
+        let forward = camera.direction.normalize();
+        let right = forward.cross(Vec3::Y).normalize();
+        let up = right.cross(forward);
+
+        let push_constants = PushConstants {
+            position_tan_half_fov: camera
+                .position
+                .extend((camera.fov.to_radians() * 0.5).tan())
+                .to_array(),
+            forward_aspect: forward.extend(camera.aspect_ratio).to_array(),
+            right: right.extend(0.0).to_array(),
+            up: up.extend(0.0).to_array(),
+            sky_color: scene.sky_color.extend(0.0).to_array(),
+            sample_index,
+            samples_per_frame: self.settings.samples_per_frame.max(1),
+            max_bounces: self.settings.max_bounces,
+            light_count: scene.light_count,
+            russian_roulette_depth: self.settings.russian_roulette_depth,
+            _padding: [0; 3],
+        };
+
+        cmd_buffer.bind_rt_pipeline(&self.pipeline);
+        cmd_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::RAY_TRACING_KHR,
+            &self.pipeline_layout,
+            0,
+            &[&self.descriptor_set],
+        );
+        cmd_buffer.push_constants(&self.pipeline_layout, SHADER_STAGES, 0, &push_constants);
+        cmd_buffer.trace_rays(
+            &self.shader_binding_table,
+            self.accumulation.extent.width,
+            self.accumulation.extent.height,
+        );
+    }
+
+    /// Adds a pass tracing into the accumulation image and one blitting the result to
+    /// `output`, which must support being a blit destination.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        scene: &'a GpuScene,
+        camera: Camera,
+        output: ImageHandle,
+    ) -> ImageHandle {
+        let accumulation = graph.import_image(
+            "path tracer accumulation",
+            &self.accumulation,
+            Some(&self.accumulation_view),
+            Access::General,
+        );
+        graph.set_final_access(accumulation, Access::General);
+
+        graph
+            .add_pass("path tracing")
+            .write(accumulation, Access::RayTracingShaderStorageWrite)
+            .execute(move |cmd_buffer, _| {
+                self.record(cmd_buffer, scene, &camera);
+                Ok(())
+            });
+
+        graph
+            .add_pass("path tracer output")
+            .read(accumulation, Access::TransferRead)
+            .write(output, Access::TransferWrite)
+            .execute(move |cmd_buffer, resources| {
+                cmd_buffer.blit_image(
+                    resources.image(accumulation),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    resources.image(output),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::Filter::NEAREST,
+                );
+                Ok(())
+            });
+
+        accumulation
+    }
+
+    fn write_descriptors(&self, scene: &GpuScene) {
+        self.tlas_generation.set(scene.tlas.generation());
+        self.descriptor_set.update(&[
+            WriteDescriptorSet {
+                binding: 0,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::AccelerationStructure {
+                    acceleration_structure: &scene.tlas.acceleration_structure,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 1,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageImage {
+                    view: &self.accumulation_view,
+                    layout: vk::ImageLayout::GENERAL,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 2,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: &scene.instance_buffer,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 3,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: &scene.material_buffer,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 4,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: &scene.light_buffer,
+                },
+            },
+        ]);
+    }
+}
+
+fn layout_binding(binding: u32, ty: vk::DescriptorType) -> vk::DescriptorSetLayoutBinding {
+    vk::DescriptorSetLayoutBinding::builder()
+        .binding(binding)
+        .descriptor_count(1)
+        .descriptor_type(ty)
+        .stage_flags(SHADER_STAGES)
+        .build()
+}
+
+fn create_accumulation_image(
+    context: &Context,
+    extent: vk::Extent2D,
+) -> Result<(Image, ImageView)> {
+    let ImageAndView { image, view } = create_storage_image(
+        context,
+        ACCUMULATION_FORMAT,
+        vk::ImageUsageFlags::TRANSFER_SRC,
+        extent,
+    )?;
+
+    Ok((image, view))
+}
+
+impl Context {
+    pub fn create_path_tracer(
+        &self,
+        scene: &GpuScene,
+        extent: vk::Extent2D,
+        settings: PathTracerSettings,
+    ) -> Result<PathTracer> {
+        PathTracer::new(self, scene, extent, settings)
+    }
+}
+
+#[test]
+fn test_accumulation_resets_on_change() {
+    let key = AccumulationKey {
+        position: Vec3::ZERO,
+        direction: Vec3::NEG_Z,
+        fov: 60.0,
+        aspect_ratio: 1.0,
+        scene_generation: 0,
+        max_bounces: 8,
+        samples_per_frame: 1,
+        russian_roulette_depth: 3,
+    };
+    let mut state = AccumulationState::default();
+
+    assert_eq!(state.next_frame(key, 3), Some(0));
+    assert_eq!(state.next_frame(key, 3), Some(1));
+
+    let moved = AccumulationKey {
+        position: Vec3::X,
+        ..key
+    };
+    assert_eq!(state.next_frame(moved, 3), Some(0));
+
+    let updated = AccumulationKey {
+        scene_generation: 1,
+        ..moved
+    };
+    assert_eq!(state.next_frame(updated, 3), Some(0));
+    assert_eq!(state.next_frame(updated, 3), Some(1));
+    assert_eq!(state.next_frame(updated, 3), Some(2));
+    assert_eq!(state.next_frame(updated, 3), None);
+}
@@ -0,0 +1,153 @@
+const float PI = 3.14159265359;
+
+struct HitPayload {
+    vec3 position;
+    // Negative on miss
+    float t;
+    vec3 normal;
+    uint material;
+    vec3 geometric_normal;
+    float area;
+};
+
+struct Instance {
+    uvec2 vertices;
+    uvec2 indices;
+    uint material;
+    uint padding[3];
+};
+
+struct Material {
+    vec4 base_color_metallic;
+    vec4 emission_roughness;
+};
+
+struct Light {
+    vec4 positions[3];
+    // w is the area
+    vec4 emission;
+};
+
+layout(push_constant) uniform PushConstants {
+    vec4 position_tan_half_fov;
+    vec4 forward_aspect;
+    vec4 right;
+    vec4 up;
+    vec4 sky_color;
+    uint sample_index;
+    uint samples_per_frame;
+    uint max_bounces;
+    uint light_count;
+    uint russian_roulette_depth;
+} pc;
+
+// https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+uint pcg_hash(uint value) {
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float random(inout uint state) {
+    state = pcg_hash(state);
+    return float(state >> 8) / 16777216.0;
+}
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Building an Orthonormal Basis, Revisited (Duff et al.)
+mat3 tangent_frame(vec3 n) {
+    float s = n.z >= 0.0 ? 1.0 : -1.0;
+    float a = -1.0 / (s + n.z);
+    float b = n.x * n.y * a;
+    vec3 t = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    vec3 bt = vec3(b, s + n.y * n.y * a, -n.y);
+    return mat3(t, bt, n);
+}
+
+float ggx_alpha(Material material) {
+    return max(material.emission_roughness.w * material.emission_roughness.w, 1e-3);
+}
+
+vec3 specular_f0(Material material) {
+    return mix(vec3(0.04), material.base_color_metallic.rgb, material.base_color_metallic.w);
+}
+
+float specular_probability(Material material) {
+    return mix(0.25, 1.0, material.base_color_metallic.w);
+}
+
+float ggx_d(float n_dot_h, float alpha) {
+    float a2 = alpha * alpha;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float smith_g1(float n_dot_x, float alpha) {
+    float a2 = alpha * alpha;
+    return 2.0 * n_dot_x / (n_dot_x + sqrt(a2 + (1.0 - a2) * n_dot_x * n_dot_x));
+}
+
+vec3 fresnel_schlick(vec3 f0, float cos_theta) {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Lambert diffuse + GGX specular, without the cosine term
+vec3 eval_bsdf(Material material, vec3 n, vec3 v, vec3 l) {
+    float n_dot_l = dot(n, l);
+    float n_dot_v = dot(n, v);
+    if (n_dot_l <= 0.0 || n_dot_v <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 h = normalize(v + l);
+    float alpha = ggx_alpha(material);
+    vec3 f = fresnel_schlick(specular_f0(material), dot(v, h));
+
+    vec3 specular = f * ggx_d(dot(n, h), alpha) * smith_g1(n_dot_l, alpha)
+        * smith_g1(n_dot_v, alpha) / (4.0 * n_dot_l * n_dot_v);
+    vec3 diffuse = (1.0 - f) * (1.0 - material.base_color_metallic.w)
+        * material.base_color_metallic.rgb / PI;
+
+    return diffuse + specular;
+}
+
+float pdf_bsdf(Material material, vec3 n, vec3 v, vec3 l) {
+    float n_dot_l = dot(n, l);
+    if (n_dot_l <= 0.0) {
+        return 0.0;
+    }
+
+    vec3 h = normalize(v + l);
+    float p_specular = specular_probability(material);
+    float specular = ggx_d(max(dot(n, h), 0.0), ggx_alpha(material)) * max(dot(n, h), 0.0)
+        / (4.0 * max(dot(v, h), 1e-6));
+    float diffuse = n_dot_l / PI;
+
+    return p_specular * specular + (1.0 - p_specular) * diffuse;
+}
+
+vec3 sample_bsdf(Material material, vec3 n, vec3 v, inout uint rng) {
+    mat3 frame = tangent_frame(n);
+    float u0 = random(rng);
+    float u1 = random(rng);
+    float phi = 2.0 * PI * u1;
+
+    if (random(rng) < specular_probability(material)) {
+        float alpha = ggx_alpha(material);
+        float cos_theta = sqrt((1.0 - u0) / (1.0 + (alpha * alpha - 1.0) * u0));
+        float sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+        vec3 h = frame * vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+        return reflect(-v, h);
+    }
+
+    // Cosine weighted hemisphere
+    float r = sqrt(u0);
+    return frame * vec3(r * cos(phi), r * sin(phi), sqrt(max(1.0 - u0, 0.0)));
+}
+
+float power_heuristic(float pdf, float other_pdf) {
+    return pdf * pdf / (pdf * pdf + other_pdf * other_pdf);
+}
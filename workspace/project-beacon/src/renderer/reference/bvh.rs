@@ -0,0 +1,282 @@
+use glam::{Vec2, Vec3};
+
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// World space triangle with per vertex normals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Triangle {
+    pub positions: [Vec3; 3],
+    pub normals: [Vec3; 3],
+    pub material: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleHit {
+    pub triangle: usize,
+    pub t: f32,
+    /// Weights of the second and third vertices.
+    pub barycentrics: Vec2,
+}
+
+/// Bounding volume hierarchy over triangles, split at the centroid median of the largest axis.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    pub triangles: Vec<Triangle>,
+    nodes: Vec<BvhNode>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    /// First triangle of leaves, right child of interior nodes. The left child follows its parent.
+    offset: u32,
+    /// 0 for interior nodes.
+    triangle_count: u32,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Entry distance of the ray, if it enters before `t_max`.
+    pub fn intersect(&self, origin: Vec3, inv_direction: Vec3, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - origin) * inv_direction;
+        let t1 = (self.max - origin) * inv_direction;
+        let near = t0.min(t1).max_element().max(0.0);
+        let far = t0.max(t1).min_element().min(t_max);
+
+        (near <= far).then_some(near)
+    }
+}
+
+impl Triangle {
+    pub fn bounds(&self) -> Aabb {
+        self.positions.iter().fold(Aabb::EMPTY, |b, p| b.grow(*p))
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.positions[0] + self.positions[1] + self.positions[2]) / 3.0
+    }
+
+    /// Möller-Trumbore, both faces are hit.
+    pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<(f32, Vec2)> {
+        let [p0, p1, p2] = self.positions;
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let p = ray.direction.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = ray.origin - p0;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(e1);
+        let v = ray.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(q) * inv_det;
+        (t > 0.0 && t < t_max).then_some((t, Vec2::new(u, v)))
+    }
+}
+
+impl Bvh {
+    /// Builds the hierarchy, reordering `triangles`.
+    pub fn new(mut triangles: Vec<Triangle>) -> Self {
+        let mut nodes = Vec::with_capacity((2 * triangles.len()).max(1));
+        let count = triangles.len();
+        build_node(&mut nodes, &mut triangles, 0, count);
+
+        Self { triangles, nodes }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    /// Closest hit before `t_max`.
+    pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<TriangleHit> {
+        let mut closest = None;
+        let mut t_max = t_max;
+        self.traverse(ray, t_max, |index, triangle| {
+            if let Some((t, barycentrics)) = triangle.intersect(ray, t_max) {
+                t_max = t;
+                closest = Some(TriangleHit {
+                    triangle: index,
+                    t,
+                    barycentrics,
+                });
+            }
+            (false, t_max)
+        });
+
+        closest
+    }
+
+    /// Whether anything is hit before `t_max`.
+    pub fn occluded(&self, ray: &Ray, t_max: f32) -> bool {
+        let mut occluded = false;
+        self.traverse(ray, t_max, |_, triangle| {
+            occluded = triangle.intersect(ray, t_max).is_some();
+            (occluded, t_max)
+        });
+
+        occluded
+    }
+
+    /// Visits triangles of the leaves the ray enters. `visit` returns whether to stop and the
+    /// current maximum distance.
+    fn traverse(
+        &self,
+        ray: &Ray,
+        mut t_max: f32,
+        mut visit: impl FnMut(usize, &Triangle) -> (bool, f32),
+    ) {
+        if self.triangles.is_empty() {
+            return;
+        }
+
+        let inv_direction = ray.direction.recip();
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node
+                .bounds
+                .intersect(ray.origin, inv_direction, t_max)
+                .is_none()
+            {
+                continue;
+            }
+
+            if node.triangle_count == 0 {
+                stack.push(node.offset as usize);
+                stack.push(index + 1);
+                continue;
+            }
+
+            let first = node.offset as usize;
+            for (index, triangle) in self.triangles[first..first + node.triangle_count as usize]
+                .iter()
+                .enumerate()
+            {
+                let (stop, new_t_max) = visit(first + index, triangle);
+                if stop {
+                    return;
+                }
+                t_max = new_t_max;
+            }
+        }
+    }
+}
+
+fn build_node(nodes: &mut Vec<BvhNode>, triangles: &mut [Triangle], first: usize, count: usize) {
+    let range = &mut triangles[first..first + count];
+    let bounds = range.iter().fold(Aabb::EMPTY, |b, t| b.union(t.bounds()));
+
+    let index = nodes.len();
+    nodes.push(BvhNode {
+        bounds,
+        offset: first as _,
+        triangle_count: count as _,
+    });
+    if count <= MAX_LEAF_TRIANGLES {
+        return;
+    }
+
+    let centroids = range.iter().fold(Aabb::EMPTY, |b, t| b.grow(t.centroid()));
+    let extent = centroids.max - centroids.min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let half = count / 2;
+    range.select_nth_unstable_by(half, |a, b| {
+        a.centroid()[axis].total_cmp(&b.centroid()[axis])
+    });
+
+    build_node(nodes, triangles, first, half);
+    let right = nodes.len();
+    build_node(nodes, triangles, first + half, count - half);
+
+    nodes[index].offset = right as _;
+    nodes[index].triangle_count = 0;
+}
+
+#[test]
+fn test_bvh_matches_brute_force() {
+    let mut state = 1u32;
+    let mut random = move || {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (state >> 8) as f32 / 16_777_216.0 * 2.0 - 1.0
+    };
+
+    let triangles = (0..200)
+        .map(|_| {
+            let center = Vec3::new(random(), random(), random()) * 4.0;
+            Triangle {
+                positions: [0; 3].map(|_| center + Vec3::new(random(), random(), random()) * 0.3),
+                normals: [Vec3::Y; 3],
+                material: 0,
+            }
+        })
+        .collect::<Vec<_>>();
+    let bvh = Bvh::new(triangles.clone());
+
+    for _ in 0..500 {
+        let ray = Ray {
+            origin: Vec3::new(random(), random(), random()) * 6.0,
+            direction: Vec3::new(random(), random(), random()).normalize(),
+        };
+
+        let expected = triangles
+            .iter()
+            .filter_map(|t| t.intersect(&ray, f32::INFINITY))
+            .map(|(t, _)| t)
+            .min_by(f32::total_cmp);
+        let hit = bvh.intersect(&ray, f32::INFINITY).map(|hit| hit.t);
+
+        assert_eq!(expected, hit);
+        assert_eq!(expected.is_some(), bvh.occluded(&ray, f32::INFINITY));
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{ensure, Result};
+use glam::Vec3;
+
+/// Linear RGBA32F image, laid out like a read back [`crate::renderer::ACCUMULATION_FORMAT`] image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+/// Per channel statistics of the difference between two images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDifference {
+    pub mean_absolute_error: f32,
+    pub root_mean_squared_error: f32,
+    /// Mean luminance of the first image over the second one, 1 when both are equally bright.
+    pub mean_luminance_ratio: f32,
+}
+
+impl CpuImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; (width * height) as usize],
+        }
+    }
+
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<[f32; 4]>) -> Result<Self> {
+        ensure!(
+            pixels.len() == (width * height) as usize,
+            "Expected {} pixels, got {}",
+            width * height,
+            pixels.len()
+        );
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Vec3 {
+        let [r, g, b, _] = self.pixels[(y * self.width + x) as usize];
+        Vec3::new(r, g, b)
+    }
+
+    pub fn mean_luminance(&self) -> f32 {
+        let sum = self
+            .pixels
+            .iter()
+            .map(|&[r, g, b, _]| luminance(Vec3::new(r, g, b)) as f64)
+            .sum::<f64>();
+
+        (sum / self.pixels.len().max(1) as f64) as f32
+    }
+
+    /// Compares RGB channels. Noisy renders of the same scene should have a small mean absolute
+    /// error and a luminance ratio close to 1.
+    pub fn compare(&self, other: &Self) -> Result<ImageDifference> {
+        ensure!(
+            self.width == other.width && self.height == other.height,
+            "Cannot compare a {}x{} image to a {}x{} one",
+            self.width,
+            self.height,
+            other.width,
+            other.height
+        );
+
+        let (absolute, squared) = self.pixels.iter().zip(&other.pixels).fold(
+            (0.0f64, 0.0f64),
+            |(absolute, squared), (a, b)| {
+                (0..3).fold((absolute, squared), |(absolute, squared), c| {
+                    let difference = (a[c] - b[c]) as f64;
+                    (
+                        absolute + difference.abs(),
+                        squared + difference * difference,
+                    )
+                })
+            },
+        );
+        let count = (3 * self.pixels.len()).max(1) as f64;
+
+        Ok(ImageDifference {
+            mean_absolute_error: (absolute / count) as f32,
+            root_mean_squared_error: (squared / count).sqrt() as f32,
+            mean_luminance_ratio: self.mean_luminance() / other.mean_luminance(),
+        })
+    }
+}
+
+pub(super) fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
@@ -0,0 +1,432 @@
+mod bvh;
+mod image;
+
+pub use bvh::*;
+pub use image::*;
+
+use std::f32::consts::PI;
+
+use glam::{Mat3, Vec3};
+
+use crate::app::camera::Camera;
+use crate::scene::{LightTriangle, Material, Scene};
+
+const RAY_EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReferenceSettings {
+    pub samples_per_pixel: u32,
+    pub max_bounces: u32,
+    /// Bounce after which paths are randomly terminated based on their throughput.
+    pub russian_roulette_depth: u32,
+    /// Images rendered with the same seed are identical.
+    pub seed: u32,
+}
+
+impl Default for ReferenceSettings {
+    fn default() -> Self {
+        Self {
+            samples_per_pixel: 64,
+            max_bounces: 8,
+            russian_roulette_depth: 3,
+            seed: 0,
+        }
+    }
+}
+
+/// CPU path tracer shading like [`crate::renderer::PathTracer`], to validate its output on
+/// machines without ray tracing hardware or to bake images offline.
+pub struct ReferenceRenderer {
+    bvh: Bvh,
+    materials: Vec<Material>,
+    lights: Vec<LightTriangle>,
+    sky_color: Vec3,
+}
+
+impl ReferenceRenderer {
+    pub fn new(scene: &Scene) -> Self {
+        let triangles = scene
+            .instances
+            .iter()
+            .flat_map(|instance| {
+                let mesh = &scene.meshes[instance.mesh];
+                let normal_transform = instance.transform.inverse().transpose();
+
+                (0..mesh.triangle_count()).map(move |index| {
+                    let vertices = mesh.triangle(index);
+                    Triangle {
+                        positions: vertices
+                            .map(|v| instance.transform.transform_point3(v.position)),
+                        normals: vertices
+                            .map(|v| normal_transform.transform_vector3(v.normal).normalize()),
+                        material: instance.material as _,
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            bvh: Bvh::new(triangles),
+            materials: scene.materials.clone(),
+            lights: scene.emissive_triangles(),
+            sky_color: scene.sky_color,
+        }
+    }
+
+    pub fn bvh(&self) -> &Bvh {
+        &self.bvh
+    }
+
+    /// Renders the scene on all available cores.
+    pub fn render(
+        &self,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+        settings: &ReferenceSettings,
+    ) -> CpuImage {
+        let mut image = CpuImage::new(width, height);
+        if width == 0 || height == 0 {
+            return image;
+        }
+
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get()) as u32;
+        let rows_per_chunk = height.div_ceil(threads).max(1);
+
+        std::thread::scope(|scope| {
+            for (chunk_index, chunk) in image
+                .pixels
+                .chunks_mut((rows_per_chunk * width) as usize)
+                .enumerate()
+            {
+                scope.spawn(move || {
+                    for (index, pixel) in chunk.iter_mut().enumerate() {
+                        let index = index as u32 + chunk_index as u32 * rows_per_chunk * width;
+                        let color = self.render_pixel(
+                            camera,
+                            index % width,
+                            index / width,
+                            width,
+                            height,
+                            settings,
+                        );
+                        *pixel = color.extend(1.0).to_array();
+                    }
+                });
+            }
+        });
+
+        image
+    }
+
+    fn render_pixel(
+        &self,
+        camera: &Camera,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        settings: &ReferenceSettings,
+    ) -> Vec3 {
+        let mut rng = pcg_hash(x.wrapping_add(pcg_hash(y.wrapping_add(pcg_hash(settings.seed)))));
+
+        let forward = camera.direction.normalize();
+        let right = forward.cross(Vec3::Y).normalize();
+        let up = right.cross(forward);
+        let tan_half_fov = (camera.fov.to_radians() * 0.5).tan();
+
+        let samples = settings.samples_per_pixel.max(1);
+        let mut color = Vec3::ZERO;
+        for _ in 0..samples {
+            let u = (x as f32 + random(&mut rng)) / width as f32 * 2.0 - 1.0;
+            let v = (y as f32 + random(&mut rng)) / height as f32 * 2.0 - 1.0;
+            let direction = (forward + u * camera.aspect_ratio * tan_half_fov * right
+                - v * tan_half_fov * up)
+                .normalize();
+
+            let sample = self.trace_path(
+                Ray {
+                    origin: camera.position,
+                    direction,
+                },
+                settings,
+                &mut rng,
+            );
+            if sample.is_finite() {
+                color += sample;
+            }
+        }
+
+        color / samples as f32
+    }
+
+    /// Radiance arriving along `ray`, estimated with one path.
+    pub fn trace_path(&self, ray: Ray, settings: &ReferenceSettings, rng: &mut u32) -> Vec3 {
+        let mut ray = ray;
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut previous_pdf = 0.0;
+
+        for bounce in 0..=settings.max_bounces {
+            let Some(hit) = self.bvh.intersect(&ray, f32::INFINITY) else {
+                radiance += throughput * self.sky_color;
+                break;
+            };
+
+            let triangle = &self.bvh.triangles[hit.triangle];
+            let material = &self.materials[triangle.material as usize];
+            let [p0, p1, p2] = triangle.positions;
+            let edges = (p1 - p0).cross(p2 - p0);
+            let geometric_normal = edges.normalize();
+            let front_face = geometric_normal.dot(ray.direction) < 0.0;
+
+            if front_face && material.is_emissive() {
+                if bounce == 0 {
+                    radiance += throughput * material.emission;
+                } else {
+                    let cos_light = -geometric_normal.dot(ray.direction);
+                    let area = 0.5 * edges.length();
+                    let light_pdf = hit.t * hit.t / (cos_light * area * self.lights.len() as f32);
+                    radiance +=
+                        throughput * material.emission * power_heuristic(previous_pdf, light_pdf);
+                }
+            }
+
+            if bounce == settings.max_bounces {
+                break;
+            }
+
+            let [u, v] = hit.barycentrics.to_array();
+            let normal = (triangle.normals[0] * (1.0 - u - v)
+                + triangle.normals[1] * u
+                + triangle.normals[2] * v)
+                .normalize();
+
+            // Shade the side the ray comes from
+            let position = ray.origin + ray.direction * hit.t;
+            let gn = if front_face {
+                geometric_normal
+            } else {
+                -geometric_normal
+            };
+            let n = if normal.dot(gn) < 0.0 {
+                -normal
+            } else {
+                normal
+            };
+            let v = -ray.direction;
+
+            if !self.lights.is_empty() {
+                radiance += throughput * self.sample_light(material, position, n, gn, v, rng);
+            }
+
+            let l = sample_bsdf(material, n, v, rng);
+            let pdf = pdf_bsdf(material, n, v, l);
+            if pdf <= 0.0 || gn.dot(l) <= 0.0 {
+                break;
+            }
+
+            throughput *= eval_bsdf(material, n, v, l) * n.dot(l) / pdf;
+            previous_pdf = pdf;
+            ray = Ray {
+                origin: position + gn * RAY_EPSILON,
+                direction: l,
+            };
+
+            if bounce >= settings.russian_roulette_depth {
+                let survival = throughput.max_element().clamp(0.05, 0.95);
+                if random(rng) > survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        radiance
+    }
+
+    /// Direct light from one emissive triangle picked uniformly, weighted against BSDF sampling.
+    fn sample_light(
+        &self,
+        material: &Material,
+        position: Vec3,
+        n: Vec3,
+        gn: Vec3,
+        v: Vec3,
+        rng: &mut u32,
+    ) -> Vec3 {
+        let light_count = self.lights.len();
+        let index = ((random(rng) * light_count as f32) as usize).min(light_count - 1);
+        let light = &self.lights[index];
+
+        let u0 = random(rng).sqrt();
+        let u1 = random(rng);
+        let [p0, p1, p2] = light.positions;
+        let point = p0 * (1.0 - u0) + p1 * (u0 * (1.0 - u1)) + p2 * (u0 * u1);
+
+        let to_light = point - position;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let l = to_light / distance;
+
+        let cos_light = light.normal().dot(-l);
+        let cos_surface = n.dot(l);
+        if cos_light <= 0.0 || cos_surface <= 0.0 || gn.dot(l) <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        let shadow_ray = Ray {
+            origin: position + gn * RAY_EPSILON,
+            direction: l,
+        };
+        if self.bvh.occluded(&shadow_ray, distance * (1.0 - 1e-3)) {
+            return Vec3::ZERO;
+        }
+
+        let light_pdf = distance_squared / (cos_light * light.area() * light_count as f32);
+        let weight = power_heuristic(light_pdf, pdf_bsdf(material, n, v, l));
+
+        eval_bsdf(material, n, v, l) * cos_surface * light.emission * weight / light_pdf
+    }
+}
+
+// https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+fn pcg_hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+fn random(state: &mut u32) -> f32 {
+    *state = pcg_hash(*state);
+    (*state >> 8) as f32 / 16_777_216.0
+}
+
+// Building an Orthonormal Basis, Revisited (Duff et al.)
+fn tangent_frame(n: Vec3) -> Mat3 {
+    let s = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let t = Vec3::new(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let bt = Vec3::new(b, s + n.y * n.y * a, -n.y);
+    Mat3::from_cols(t, bt, n)
+}
+
+fn ggx_alpha(material: &Material) -> f32 {
+    (material.roughness * material.roughness).max(1e-3)
+}
+
+fn specular_f0(material: &Material) -> Vec3 {
+    Vec3::splat(0.04).lerp(material.base_color, material.metallic)
+}
+
+fn specular_probability(material: &Material) -> f32 {
+    0.25 + 0.75 * material.metallic
+}
+
+fn ggx_d(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+fn smith_g1(n_dot_x: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    2.0 * n_dot_x / (n_dot_x + (a2 + (1.0 - a2) * n_dot_x * n_dot_x).sqrt())
+}
+
+fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+// Lambert diffuse + GGX specular, without the cosine term
+fn eval_bsdf(material: &Material, n: Vec3, v: Vec3, l: Vec3) -> Vec3 {
+    let n_dot_l = n.dot(l);
+    let n_dot_v = n.dot(v);
+    if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let h = (v + l).normalize();
+    let alpha = ggx_alpha(material);
+    let f = fresnel_schlick(specular_f0(material), v.dot(h));
+
+    let specular = f * ggx_d(n.dot(h), alpha) * smith_g1(n_dot_l, alpha) * smith_g1(n_dot_v, alpha)
+        / (4.0 * n_dot_l * n_dot_v);
+    let diffuse = (Vec3::ONE - f) * (1.0 - material.metallic) * material.base_color / PI;
+
+    diffuse + specular
+}
+
+fn pdf_bsdf(material: &Material, n: Vec3, v: Vec3, l: Vec3) -> f32 {
+    let n_dot_l = n.dot(l);
+    if n_dot_l <= 0.0 {
+        return 0.0;
+    }
+
+    let h = (v + l).normalize();
+    let n_dot_h = n.dot(h).max(0.0);
+    let p_specular = specular_probability(material);
+    let specular = ggx_d(n_dot_h, ggx_alpha(material)) * n_dot_h / (4.0 * v.dot(h).max(1e-6));
+    let diffuse = n_dot_l / PI;
+
+    p_specular * specular + (1.0 - p_specular) * diffuse
+}
+
+fn sample_bsdf(material: &Material, n: Vec3, v: Vec3, rng: &mut u32) -> Vec3 {
+    let frame = tangent_frame(n);
+    let u0 = random(rng);
+    let u1 = random(rng);
+    let phi = 2.0 * PI * u1;
+
+    if random(rng) < specular_probability(material) {
+        let alpha = ggx_alpha(material);
+        let cos_theta = ((1.0 - u0) / (1.0 + (alpha * alpha - 1.0) * u0)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let h = frame * Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        return reflect(-v, h);
+    }
+
+    // Cosine weighted hemisphere
+    let r = u0.sqrt();
+    frame * Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u0).max(0.0).sqrt())
+}
+
+fn reflect(i: Vec3, n: Vec3) -> Vec3 {
+    i - 2.0 * n.dot(i) * n
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    pdf * pdf / (pdf * pdf + other_pdf * other_pdf)
+}
+
+#[test]
+fn test_cornell_box_is_deterministic() {
+    let scene = Scene::cornell_box();
+    let camera = Scene::cornell_box_camera(1.0);
+    let renderer = ReferenceRenderer::new(&scene);
+    let settings = ReferenceSettings {
+        samples_per_pixel: 4,
+        ..Default::default()
+    };
+
+    let image = renderer.render(&camera, 16, 16, &settings);
+    assert_eq!(image, renderer.render(&camera, 16, 16, &settings));
+
+    // The light is near the top center of the image
+    assert!(image.pixel(7, 1).min_element() > 1.0);
+    assert!(image.mean_luminance() > 0.05);
+    assert!(image.pixels.iter().flatten().all(|c| c.is_finite()));
+
+    let other_seed = renderer.render(
+        &camera,
+        16,
+        16,
+        &ReferenceSettings {
+            seed: 1,
+            ..settings
+        },
+    );
+    assert_ne!(image, other_seed);
+    assert!(image.compare(&other_seed).unwrap().mean_luminance_ratio > 0.5);
+}
@@ -0,0 +1,546 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use ash::vk;
+
+use crate::app::ImageAndView;
+use crate::render_graph::{Access, ImageHandle, RenderGraph};
+use crate::renderer::create_storage_image;
+use crate::vulkan::utils::create_gpu_only_buffer_from_data;
+use crate::vulkan::{
+    Buffer, CommandBuffer, ComputePipeline, ComputePipelineCreateInfo, Context, DescriptorPool,
+    DescriptorSet, DescriptorSetLayout, MemoryBarrier, PipelineLayout, Pod, SwapchainEncoding,
+    WriteDescriptorSet, WriteDescriptorSetKind,
+};
+
+/// Format of the HDR images the tonemapper reads.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const OUTPUT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const HISTOGRAM_BINS: usize = 256;
+const HISTOGRAM_GROUP_SIZE: u32 = 16;
+const TONEMAP_GROUP_SIZE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TonemapOperator {
+    #[default]
+    Aces,
+    AgX,
+    Reinhard,
+    /// Only clamps to the display range, for images already exposed for it, e.g. rasterized
+    /// with SDR colors.
+    None,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TonemapSettings {
+    pub operator: TonemapOperator,
+    /// Exposes for the average scene luminance, measured with a histogram, instead of using
+    /// only the compensation.
+    pub auto_exposure: bool,
+    /// In stops, applied on top of the automatic exposure.
+    pub exposure_compensation: f32,
+    /// Luminance range covered by the histogram, in log2 units.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    /// How fast the exposure adapts to luminance changes, higher is faster.
+    pub adaptation_speed: f32,
+    /// Luminance of an SDR white on HDR outputs, in nits.
+    pub paper_white: f32,
+    /// Luminance the tone curve rolls off to on HDR outputs, in nits.
+    pub peak_luminance: f32,
+}
+
+impl TonemapOperator {
+    /// Index of the operator in tonemap.comp.
+    fn shader_index(self) -> u32 {
+        match self {
+            Self::Aces => 0,
+            Self::AgX => 1,
+            Self::Reinhard => 2,
+            Self::None => 3,
+        }
+    }
+}
+
+impl TonemapSettings {
+    /// No exposure nor tone curve, values are only encoded for the target.
+    pub fn passthrough() -> Self {
+        Self {
+            operator: TonemapOperator::None,
+            auto_exposure: false,
+            ..Default::default()
+        }
+    }
+
+    fn log_luminance_range(&self) -> f32 {
+        self.max_log_luminance - self.min_log_luminance
+    }
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            operator: TonemapOperator::Aces,
+            auto_exposure: true,
+            exposure_compensation: 0.0,
+            min_log_luminance: -10.0,
+            max_log_luminance: 6.0,
+            adaptation_speed: 1.5,
+            paper_white: 203.0,
+            peak_luminance: 1000.0,
+        }
+    }
+}
+
+/// Maps HDR images to the display range: a luminance histogram drives the auto-exposure, then
+/// the tone curve is applied and the result is written to an output image ready to be blitted
+/// to the swapchain.
+///
+/// The tonemapper can read from several input images, e.g. one per frame in flight, all
+/// [`HDR_FORMAT`] storage images in the `GENERAL` layout.
+pub struct Tonemapper {
+    pub settings: TonemapSettings,
+    output_transform: OutputTransform,
+    output: ImageAndView,
+    histogram_buffer: Buffer,
+    exposure_buffer: Buffer,
+    descriptor_sets: Vec<DescriptorSet>,
+    _descriptor_pool: DescriptorPool,
+    descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    histogram_pipeline: ComputePipeline,
+    exposure_pipeline: ComputePipeline,
+    tonemap_pipeline: ComputePipeline,
+    history_valid: Cell<bool>,
+}
+
+/// Encoding applied to the tonemapped values, so that blitting them to the target is correct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputTransform {
+    /// The target format does the sRGB encoding.
+    Linear,
+    Srgb,
+    Pq,
+    ScRgb,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct PushConstants {
+    operator: u32,
+    auto_exposure: u32,
+    output_transform: u32,
+    reset: u32,
+    pixel_count: u32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation_rate: f32,
+    exposure_compensation: f32,
+    paper_white: f32,
+    peak_luminance: f32,
+}
+
+// Only 4 byte fields, no padding
+unsafe impl Pod for PushConstants {}
+
+impl Tonemapper {
+    pub(crate) fn new(
+        context: &Context,
+        inputs: &[ImageAndView],
+        target_format: vk::Format,
+        target_encoding: SwapchainEncoding,
+        settings: TonemapSettings,
+    ) -> Result<Self> {
+        ensure!(
+            !inputs.is_empty(),
+            "The tonemapper needs at least one input"
+        );
+
+        let binding = |binding, ty| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(ty)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        };
+        let descriptor_set_layout = context.create_descriptor_set_layout(&[
+            binding(0, vk::DescriptorType::STORAGE_IMAGE),
+            binding(1, vk::DescriptorType::STORAGE_BUFFER),
+            binding(2, vk::DescriptorType::STORAGE_BUFFER),
+            binding(3, vk::DescriptorType::STORAGE_IMAGE),
+        ])?;
+
+        let pipeline_layout = context.create_pipeline_layout_with_push_constants(
+            &[&descriptor_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as _,
+            }],
+        )?;
+
+        let create_pipeline = |shader_source| {
+            context.create_compute_pipeline(
+                &pipeline_layout,
+                ComputePipelineCreateInfo { shader_source },
+            )
+        };
+        let histogram_pipeline =
+            create_pipeline(&include_bytes!("./shaders/histogram.comp.spv")[..])?;
+        let exposure_pipeline =
+            create_pipeline(&include_bytes!("./shaders/exposure.comp.spv")[..])?;
+        let tonemap_pipeline = create_pipeline(&include_bytes!("./shaders/tonemap.comp.spv")[..])?;
+
+        let histogram_buffer = create_gpu_only_buffer_from_data(
+            context,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &[0u32; HISTOGRAM_BINS],
+        )?;
+        // Exposure and adapted luminance
+        let exposure_buffer = create_gpu_only_buffer_from_data(
+            context,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &[1.0f32, 0.18],
+        )?;
+
+        let output = create_output_image(context, inputs)?;
+        let (descriptor_pool, descriptor_sets) = create_descriptor_sets(
+            context,
+            &descriptor_set_layout,
+            inputs,
+            &output,
+            &histogram_buffer,
+            &exposure_buffer,
+        )?;
+
+        Ok(Self {
+            settings,
+            output_transform: OutputTransform::new(target_format, target_encoding),
+            output,
+            histogram_buffer,
+            exposure_buffer,
+            descriptor_sets,
+            _descriptor_pool: descriptor_pool,
+            descriptor_set_layout,
+            pipeline_layout,
+            histogram_pipeline,
+            exposure_pipeline,
+            tonemap_pipeline,
+            history_valid: Cell::new(false),
+        })
+    }
+
+    /// Recreates the output image for new inputs. The GPU must not be using the tonemapper.
+    pub fn resize(&mut self, context: &Context, inputs: &[ImageAndView]) -> Result<()> {
+        ensure!(
+            !inputs.is_empty(),
+            "The tonemapper needs at least one input"
+        );
+
+        self.output = create_output_image(context, inputs)?;
+        let (descriptor_pool, descriptor_sets) = create_descriptor_sets(
+            context,
+            &self.descriptor_set_layout,
+            inputs,
+            &self.output,
+            &self.histogram_buffer,
+            &self.exposure_buffer,
+        )?;
+        self.descriptor_sets = descriptor_sets;
+        self._descriptor_pool = descriptor_pool;
+
+        Ok(())
+    }
+
+    /// Encodes for a new target, e.g. when the swapchain format or encoding changed.
+    pub fn set_target(&mut self, target_format: vk::Format, target_encoding: SwapchainEncoding) {
+        self.output_transform = OutputTransform::new(target_format, target_encoding);
+    }
+
+    /// Snaps the exposure to the next frame instead of adapting, e.g. on scene changes.
+    pub fn reset(&self) {
+        self.history_valid.set(false);
+    }
+
+    /// Tonemapped image, in the `GENERAL` layout. Values are encoded for the target format and
+    /// encoding, so that blitting to the target does the right thing.
+    pub fn output(&self) -> &ImageAndView {
+        &self.output
+    }
+
+    /// Tonemaps the input at `input_index` into the output. Writes to the input must be visible
+    /// to compute shaders.
+    pub fn record(&self, cmd_buffer: &CommandBuffer, input_index: usize, delta_time: Duration) {
+        let extent = self.output.image.extent;
+        let settings = &self.settings;
+        let compute_barrier = MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+        };
+
+        cmd_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[&self.descriptor_sets[input_index]],
+        );
+        cmd_buffer.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &PushConstants {
+                operator: settings.operator.shader_index(),
+                auto_exposure: settings.auto_exposure as _,
+                output_transform: self.output_transform as _,
+                reset: !self.history_valid.get() as _,
+                pixel_count: extent.width * extent.height,
+                min_log_luminance: settings.min_log_luminance,
+                log_luminance_range: settings.log_luminance_range(),
+                adaptation_rate: adaptation_rate(settings.adaptation_speed, delta_time),
+                exposure_compensation: settings.exposure_compensation,
+                paper_white: settings.paper_white,
+                peak_luminance: settings.peak_luminance,
+            },
+        );
+
+        if settings.auto_exposure {
+            cmd_buffer.bind_compute_pipeline(&self.histogram_pipeline);
+            cmd_buffer.dispatch(
+                extent.width.div_ceil(HISTOGRAM_GROUP_SIZE),
+                extent.height.div_ceil(HISTOGRAM_GROUP_SIZE),
+                1,
+            );
+            cmd_buffer.pipeline_memory_barriers(&[compute_barrier]);
+
+            cmd_buffer.bind_compute_pipeline(&self.exposure_pipeline);
+            cmd_buffer.dispatch(1, 1, 1);
+            cmd_buffer.pipeline_memory_barriers(&[compute_barrier]);
+
+            self.history_valid.set(true);
+        }
+
+        cmd_buffer.bind_compute_pipeline(&self.tonemap_pipeline);
+        cmd_buffer.dispatch(
+            extent.width.div_ceil(TONEMAP_GROUP_SIZE),
+            extent.height.div_ceil(TONEMAP_GROUP_SIZE),
+            1,
+        );
+    }
+
+    /// Tonemaps `input`, the input at `input_index`, and blits the result to `target`.
+    ///
+    /// The histogram and exposure buffers are shared by all frames, they are imported so that
+    /// the graph orders this frame's accesses after the previous frame's.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        input: ImageHandle,
+        input_index: usize,
+        target: ImageHandle,
+        delta_time: Duration,
+    ) {
+        let output = graph.import_image(
+            "tonemap output",
+            &self.output.image,
+            Some(&self.output.view),
+            Access::General,
+        );
+        graph.set_final_access(output, Access::General);
+        let histogram = graph.import_buffer(
+            "tonemap histogram",
+            &self.histogram_buffer,
+            Access::ComputeShaderStorageWrite,
+        );
+        let exposure = graph.import_buffer(
+            "tonemap exposure",
+            &self.exposure_buffer,
+            Access::ComputeShaderStorageWrite,
+        );
+
+        graph
+            .add_pass("tonemap")
+            .read(input, Access::ComputeShaderStorageRead)
+            .write(histogram, Access::ComputeShaderStorageWrite)
+            .write(exposure, Access::ComputeShaderStorageWrite)
+            .write(output, Access::ComputeShaderStorageWrite)
+            .execute(move |cmd_buffer, _| {
+                self.record(cmd_buffer, input_index, delta_time);
+                Ok(())
+            });
+
+        graph
+            .add_pass("tonemap output")
+            .read(output, Access::TransferRead)
+            .write(target, Access::TransferWrite)
+            .execute(move |cmd_buffer, resources| {
+                cmd_buffer.blit_image(
+                    resources.image(output),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    resources.image(target),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::Filter::NEAREST,
+                );
+                Ok(())
+            });
+    }
+}
+
+fn create_output_image(context: &Context, inputs: &[ImageAndView]) -> Result<ImageAndView> {
+    let extent = inputs[0].image.extent;
+    ensure!(
+        inputs.iter().all(|input| input.image.extent == extent),
+        "All tonemapper inputs must have the same extent"
+    );
+
+    create_storage_image(
+        context,
+        OUTPUT_FORMAT,
+        vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::Extent2D {
+            width: extent.width,
+            height: extent.height,
+        },
+    )
+}
+
+fn create_descriptor_sets(
+    context: &Context,
+    layout: &DescriptorSetLayout,
+    inputs: &[ImageAndView],
+    output: &ImageAndView,
+    histogram_buffer: &Buffer,
+    exposure_buffer: &Buffer,
+) -> Result<(DescriptorPool, Vec<DescriptorSet>)> {
+    let count = inputs.len() as u32;
+    let descriptor_pool = context.create_descriptor_pool(
+        count,
+        &[
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 2 * count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 2 * count,
+            },
+        ],
+    )?;
+    let descriptor_sets = descriptor_pool.allocate_sets(layout, count)?;
+
+    for (set, input) in descriptor_sets.iter().zip(inputs) {
+        set.update(&[
+            WriteDescriptorSet {
+                binding: 0,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageImage {
+                    view: &input.view,
+                    layout: vk::ImageLayout::GENERAL,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 1,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: histogram_buffer,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 2,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBuffer {
+                    buffer: exposure_buffer,
+                },
+            },
+            WriteDescriptorSet {
+                binding: 3,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageImage {
+                    view: &output.view,
+                    layout: vk::ImageLayout::GENERAL,
+                },
+            },
+        ]);
+    }
+
+    Ok((descriptor_pool, descriptor_sets))
+}
+
+impl OutputTransform {
+    pub(crate) fn new(format: vk::Format, encoding: SwapchainEncoding) -> Self {
+        match encoding {
+            SwapchainEncoding::Srgb if is_srgb(format) => Self::Linear,
+            SwapchainEncoding::Srgb => Self::Srgb,
+            SwapchainEncoding::Hdr10 => Self::Pq,
+            SwapchainEncoding::ScRgb => Self::ScRgb,
+        }
+    }
+}
+
+/// Fraction of the way to the target luminance covered in `delta_time`, so that adaptation
+/// doesn't depend on the frame rate.
+fn adaptation_rate(adaptation_speed: f32, delta_time: Duration) -> f32 {
+    1.0 - (-delta_time.as_secs_f32() * adaptation_speed).exp()
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB
+    )
+}
+
+impl Context {
+    /// Creates a tonemapper for `inputs`, writing to images of `target_format` presented with
+    /// `target_encoding`.
+    pub fn create_tonemapper(
+        &self,
+        inputs: &[ImageAndView],
+        target_format: vk::Format,
+        target_encoding: SwapchainEncoding,
+        settings: TonemapSettings,
+    ) -> Result<Tonemapper> {
+        Tonemapper::new(self, inputs, target_format, target_encoding, settings)
+    }
+}
+
+#[test]
+fn test_adaptation_rate() {
+    assert_eq!(adaptation_rate(1.5, Duration::ZERO), 0.0);
+    let rate = adaptation_rate(1.5, Duration::from_secs(1));
+    assert!((rate - (1.0 - (-1.5f32).exp())).abs() < 1e-6);
+    assert!(adaptation_rate(1.5, Duration::from_secs(10)) > 0.99);
+}
+
+#[test]
+fn test_output_transform() {
+    use vk::Format as F;
+
+    assert_eq!(
+        OutputTransform::new(F::B8G8R8A8_SRGB, SwapchainEncoding::Srgb),
+        OutputTransform::Linear
+    );
+    assert_eq!(
+        OutputTransform::new(F::B8G8R8A8_UNORM, SwapchainEncoding::Srgb),
+        OutputTransform::Srgb
+    );
+    assert_eq!(
+        OutputTransform::new(F::A2B10G10R10_UNORM_PACK32, SwapchainEncoding::Hdr10),
+        OutputTransform::Pq
+    );
+    assert_eq!(
+        OutputTransform::new(F::R16G16B16A16_SFLOAT, SwapchainEncoding::ScRgb),
+        OutputTransform::ScRgb
+    );
+    assert_eq!(TonemapOperator::AgX.shader_index(), 1);
+    assert_eq!(TonemapOperator::None.shader_index(), 3);
+}
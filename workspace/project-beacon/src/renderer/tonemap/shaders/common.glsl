@@ -0,0 +1,32 @@
+#define HISTOGRAM_BINS 256
+
+layout(set = 0, binding = 0, rgba16f) uniform readonly image2D hdr_image;
+// Bin 0 counts pixels darker than the minimum luminance
+layout(set = 0, binding = 1) buffer Histogram {
+    uint bins[HISTOGRAM_BINS];
+};
+layout(set = 0, binding = 2) buffer Exposure {
+    float exposure;
+    float adapted_luminance;
+};
+layout(set = 0, binding = 3, rgba16f) uniform writeonly image2D output_image;
+
+layout(push_constant) uniform PushConstants {
+    uint tonemap_operator;
+    uint auto_exposure;
+    uint output_transform;
+    uint reset;
+    uint pixel_count;
+    float min_log_luminance;
+    float log_luminance_range;
+    // Fraction of the way to the target luminance covered this frame
+    float adaptation_rate;
+    float exposure_compensation;
+    // In nits, for HDR outputs
+    float paper_white;
+    float peak_luminance;
+};
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
@@ -0,0 +1,48 @@
+// Encodings of linear Rec.709 colors for the swapchain, see OutputTransform
+
+#define OUTPUT_LINEAR 0
+#define OUTPUT_SRGB 1
+#define OUTPUT_PQ 2
+#define OUTPUT_SCRGB 3
+
+vec3 srgb_encode(vec3 color) {
+    return mix(
+        12.92 * color,
+        1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055,
+        greaterThan(color, vec3(0.0031308))
+    );
+}
+
+// BT.709 to BT.2020 primaries, both linear
+vec3 rec2020_from_rec709(vec3 color) {
+    const mat3 m = mat3(
+        0.6274, 0.0691, 0.0164,
+        0.3293, 0.9195, 0.0880,
+        0.0433, 0.0114, 0.8956
+    );
+    return m * color;
+}
+
+// ST 2084 inverse EOTF, from nits
+vec3 pq_encode(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// 1 is paper white on HDR outputs
+vec3 encode_output(vec3 color, uint transform, float paper_white) {
+    if (transform == OUTPUT_SRGB) {
+        return srgb_encode(color);
+    } else if (transform == OUTPUT_PQ) {
+        return pq_encode(rec2020_from_rec709(color) * paper_white);
+    } else if (transform == OUTPUT_SCRGB) {
+        return color * paper_white / 80.0;
+    }
+    return color;
+}
@@ -0,0 +1,238 @@
+use anyhow::{ensure, Result};
+use ash::vk;
+use glam::Vec3;
+use gpu_allocator::MemoryLocation;
+
+use crate::scene::{LightTriangle, Material, MeshVertex, Scene};
+use crate::vulkan::{
+    utils::create_gpu_only_buffer_from_data, AccelerationStructure, BlasBuilder, Buffer, Context,
+    Tlas, TlasBuilder, TlasInstance,
+};
+
+/// A [`Scene`] uploaded to the GPU: one BLAS per mesh, a TLAS with one instance per mesh
+/// instance and the instance, material and light buffers read by ray tracing shaders.
+pub struct GpuScene {
+    pub vertex_buffers: Vec<Buffer>,
+    pub index_buffers: Vec<Buffer>,
+    pub blases: Vec<AccelerationStructure>,
+    pub tlas: Tlas,
+    pub instance_buffer: Buffer,
+    pub material_buffer: Buffer,
+    pub light_buffer: Buffer,
+    pub light_count: u32,
+    pub sky_color: Vec3,
+    instance_meshes: Vec<usize>,
+    material_count: usize,
+    generation: u64,
+}
+
+/// Per instance data indexed with `gl_InstanceCustomIndexEXT`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct GpuInstance {
+    vertex_address: u64,
+    index_address: u64,
+    material: u32,
+    _padding: [u32; 3],
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct GpuMaterial {
+    base_color_metallic: [f32; 4],
+    emission_roughness: [f32; 4],
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct GpuLight {
+    positions: [[f32; 4]; 3],
+    emission: [f32; 4],
+}
+
+impl GpuScene {
+    pub(crate) fn new(context: &Context, scene: &Scene) -> Result<Self> {
+        ensure!(!scene.instances.is_empty(), "Scene has no instances");
+
+        let geometry_usage = vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::VERTEX_BUFFER
+            | vk::BufferUsageFlags::INDEX_BUFFER;
+
+        let vertex_buffers = scene
+            .meshes
+            .iter()
+            .map(|mesh| create_gpu_only_buffer_from_data(context, geometry_usage, &mesh.vertices))
+            .collect::<Result<Vec<_>>>()?;
+        let index_buffers = scene
+            .meshes
+            .iter()
+            .map(|mesh| create_gpu_only_buffer_from_data(context, geometry_usage, &mesh.indices))
+            .collect::<Result<Vec<_>>>()?;
+
+        let blas_builders = vertex_buffers
+            .iter()
+            .zip(&index_buffers)
+            .map(|(vertex_buffer, index_buffer)| {
+                BlasBuilder::from_triangles(
+                    vertex_buffer,
+                    index_buffer,
+                    vk::Format::R32G32B32_SFLOAT,
+                    std::mem::size_of::<MeshVertex>() as _,
+                    true,
+                )
+            })
+            .collect::<Vec<_>>();
+        let blas_infos = blas_builders
+            .iter()
+            .map(BlasBuilder::create_info)
+            .collect::<Vec<_>>();
+        let blases = context.create_acceleration_structures(&blas_infos)?;
+
+        let tlas = TlasBuilder::new()
+            .instances(tlas_instances(scene, &blases))
+            .build(context)?;
+
+        let instances = gpu_instances(scene, &vertex_buffers, &index_buffers);
+        let instance_buffer = context.create_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+            (instances.len() * std::mem::size_of::<GpuInstance>()) as _,
+        )?;
+        instance_buffer.copy_data_to_buffer(&instances)?;
+
+        let material_buffer = context.create_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+            (scene.materials.len().max(1) * std::mem::size_of::<GpuMaterial>()) as _,
+        )?;
+        material_buffer.copy_data_to_buffer(&gpu_materials(&scene.materials))?;
+
+        let lights = gpu_lights(&scene.emissive_triangles());
+        let light_buffer = context.create_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+            (lights.len().max(1) * std::mem::size_of::<GpuLight>()) as _,
+        )?;
+        light_buffer.copy_data_to_buffer(&lights)?;
+
+        Ok(Self {
+            vertex_buffers,
+            index_buffers,
+            blases,
+            tlas,
+            instance_buffer,
+            material_buffer,
+            light_buffer,
+            light_count: lights.len() as _,
+            sky_color: scene.sky_color,
+            instance_meshes: scene.instances.iter().map(|i| i.mesh).collect(),
+            material_count: scene.materials.len(),
+            generation: 0,
+        })
+    }
+
+    /// Applies new instance transforms, materials and sky color, refitting the TLAS.
+    ///
+    /// Meshes, the mesh of each instance and the number of materials and emissive triangles
+    /// cannot change. The GPU must not be using the scene.
+    pub fn update(&mut self, context: &Context, scene: &Scene) -> Result<()> {
+        ensure!(
+            scene.meshes.len() == self.blases.len()
+                && scene
+                    .instances
+                    .iter()
+                    .map(|i| i.mesh)
+                    .eq(self.instance_meshes.iter().copied()),
+            "Scene meshes changed, a new GpuScene must be created"
+        );
+        ensure!(
+            scene.materials.len() == self.material_count,
+            "Material count changed, a new GpuScene must be created"
+        );
+        let lights = gpu_lights(&scene.emissive_triangles());
+        ensure!(
+            lights.len() == self.light_count as usize,
+            "Emissive triangle count changed, a new GpuScene must be created"
+        );
+
+        self.tlas
+            .update(context, &tlas_instances(scene, &self.blases))?;
+
+        self.instance_buffer.copy_data_to_buffer(&gpu_instances(
+            scene,
+            &self.vertex_buffers,
+            &self.index_buffers,
+        ))?;
+        self.material_buffer
+            .copy_data_to_buffer(&gpu_materials(&scene.materials))?;
+        self.light_buffer.copy_data_to_buffer(&lights)?;
+        self.sky_color = scene.sky_color;
+
+        self.generation += 1;
+
+        Ok(())
+    }
+
+    /// Incremented on each update, renderers use it to know their history is stale.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+fn tlas_instances(scene: &Scene, blases: &[AccelerationStructure]) -> Vec<TlasInstance> {
+    scene
+        .instances
+        .iter()
+        .enumerate()
+        .map(|(index, instance)| {
+            TlasInstance::new(&blases[instance.mesh], instance.transform)
+                .custom_index(index as _)
+                .flags(vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE)
+        })
+        .collect()
+}
+
+fn gpu_instances(
+    scene: &Scene,
+    vertex_buffers: &[Buffer],
+    index_buffers: &[Buffer],
+) -> Vec<GpuInstance> {
+    scene
+        .instances
+        .iter()
+        .map(|instance| GpuInstance {
+            vertex_address: vertex_buffers[instance.mesh].get_device_address(),
+            index_address: index_buffers[instance.mesh].get_device_address(),
+            material: instance.material as _,
+            _padding: [0; 3],
+        })
+        .collect()
+}
+
+fn gpu_materials(materials: &[Material]) -> Vec<GpuMaterial> {
+    materials
+        .iter()
+        .map(|material| GpuMaterial {
+            base_color_metallic: material.base_color.extend(material.metallic).to_array(),
+            emission_roughness: material.emission.extend(material.roughness).to_array(),
+        })
+        .collect()
+}
+
+fn gpu_lights(lights: &[LightTriangle]) -> Vec<GpuLight> {
+    lights
+        .iter()
+        .map(|light| GpuLight {
+            positions: light.positions.map(|p| p.extend(0.0).to_array()),
+            emission: light.emission.extend(light.area()).to_array(),
+        })
+        .collect()
+}
+
+impl Context {
+    pub fn create_gpu_scene(&self, scene: &Scene) -> Result<GpuScene> {
+        GpuScene::new(self, scene)
+    }
+}
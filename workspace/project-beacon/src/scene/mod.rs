@@ -0,0 +1,360 @@
+mod gpu;
+
+pub use gpu::*;
+
+use std::mem::{offset_of, size_of};
+
+use ash::vk;
+use glam::{vec3, Mat4, Quat, Vec3};
+
+use crate::app::camera::Camera;
+use crate::vulkan::Vertex;
+
+/// Meshes, materials and their instances, shared by the GPU and CPU renderers.
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub instances: Vec<MeshInstance>,
+    /// Radiance of rays escaping the scene.
+    pub sky_color: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct MeshVertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+}
+
+/// Indexed triangle list. Triangles are front facing when counter-clockwise, which is also
+/// the winding emissive triangles emit from.
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// Metallic/roughness material, lit with a Lambert diffuse and a GGX specular lobe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emission: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshInstance {
+    pub mesh: usize,
+    pub material: usize,
+    pub transform: Mat4,
+}
+
+/// World space triangle of an emissive instance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightTriangle {
+    pub positions: [Vec3; 3],
+    pub emission: Vec3,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Vec3::splat(0.8),
+            metallic: 0.0,
+            roughness: 1.0,
+            emission: Vec3::ZERO,
+        }
+    }
+}
+
+impl Material {
+    pub fn diffuse(base_color: Vec3) -> Self {
+        Self {
+            base_color,
+            ..Default::default()
+        }
+    }
+
+    pub fn metal(base_color: Vec3, roughness: f32) -> Self {
+        Self {
+            base_color,
+            metallic: 1.0,
+            roughness,
+            ..Default::default()
+        }
+    }
+
+    pub fn emissive(emission: Vec3) -> Self {
+        Self {
+            base_color: Vec3::ZERO,
+            emission,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.max_element() > 0.0
+    }
+}
+
+// Written by hand as the derive macro refers to the crate by name
+impl Vertex for MeshVertex {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<Self>() as _,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, position) as _,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, normal) as _,
+            },
+        ]
+    }
+}
+
+impl Mesh {
+    /// Unit quad in the XZ plane, facing +Y.
+    pub fn quad() -> Self {
+        let normal = Vec3::Y;
+        let vertices = [
+            vec3(-0.5, 0.0, -0.5),
+            vec3(-0.5, 0.0, 0.5),
+            vec3(0.5, 0.0, 0.5),
+            vec3(0.5, 0.0, -0.5),
+        ]
+        .map(|position| MeshVertex { position, normal })
+        .to_vec();
+
+        Self {
+            vertices,
+            indices: vec![0, 1, 2, 0, 2, 3],
+        }
+    }
+
+    /// Unit cube centered on the origin.
+    pub fn cube() -> Self {
+        let quad = Self::quad();
+        let mut cube = Self::default();
+
+        for normal in [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ] {
+            let face = Mat4::from_rotation_translation(
+                Quat::from_rotation_arc(Vec3::Y, normal),
+                normal * 0.5,
+            );
+            cube.append(&quad, face);
+        }
+
+        cube
+    }
+
+    pub fn append(&mut self, other: &Mesh, transform: Mat4) {
+        let normal_transform = transform.inverse().transpose();
+        let first_vertex = self.vertices.len() as u32;
+
+        self.vertices
+            .extend(other.vertices.iter().map(|v| MeshVertex {
+                position: transform.transform_point3(v.position),
+                normal: normal_transform.transform_vector3(v.normal).normalize(),
+            }));
+        self.indices
+            .extend(other.indices.iter().map(|i| first_vertex + i));
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangle(&self, index: usize) -> [MeshVertex; 3] {
+        let indices = &self.indices[index * 3..index * 3 + 3];
+        [0, 1, 2].map(|i| self.vertices[indices[i] as usize])
+    }
+}
+
+impl LightTriangle {
+    pub fn area(&self) -> f32 {
+        let [p0, p1, p2] = self.positions;
+        (p1 - p0).cross(p2 - p0).length() * 0.5
+    }
+
+    /// Normal of the emitting side.
+    pub fn normal(&self) -> Vec3 {
+        let [p0, p1, p2] = self.positions;
+        (p1 - p0).cross(p2 - p0).normalize()
+    }
+}
+
+impl Scene {
+    pub fn add_mesh(&mut self, mesh: Mesh) -> usize {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn add_instance(&mut self, mesh: usize, material: usize, transform: Mat4) -> usize {
+        self.instances.push(MeshInstance {
+            mesh,
+            material,
+            transform,
+        });
+        self.instances.len() - 1
+    }
+
+    /// Triangles of all emissive instances, used for next event estimation.
+    pub fn emissive_triangles(&self) -> Vec<LightTriangle> {
+        self.instances
+            .iter()
+            .filter(|instance| self.materials[instance.material].is_emissive())
+            .flat_map(|instance| {
+                let mesh = &self.meshes[instance.mesh];
+                let emission = self.materials[instance.material].emission;
+
+                (0..mesh.triangle_count()).map(move |index| LightTriangle {
+                    positions: mesh
+                        .triangle(index)
+                        .map(|v| instance.transform.transform_point3(v.position)),
+                    emission,
+                })
+            })
+            .collect()
+    }
+
+    /// The classic Cornell box, 2 units wide with the floor at y = 0, lit by an area light
+    /// below the ceiling. Use with [`Scene::cornell_box_camera`].
+    pub fn cornell_box() -> Self {
+        let mut scene = Self::default();
+
+        let quad = scene.add_mesh(Mesh::quad());
+        let cube = scene.add_mesh(Mesh::cube());
+
+        let white = scene.add_material(Material::diffuse(vec3(0.73, 0.73, 0.73)));
+        let red = scene.add_material(Material::diffuse(vec3(0.65, 0.05, 0.05)));
+        let green = scene.add_material(Material::diffuse(vec3(0.12, 0.45, 0.15)));
+        let metal = scene.add_material(Material::metal(vec3(0.9, 0.9, 0.9), 0.3));
+        let light = scene.add_material(Material::emissive(vec3(17.0, 12.0, 4.0)));
+
+        let wall = |rotation: Quat, translation: Vec3| {
+            Mat4::from_scale_rotation_translation(Vec3::splat(2.0), rotation, translation)
+        };
+        let half_turn = std::f32::consts::PI;
+        let quarter_turn = std::f32::consts::FRAC_PI_2;
+
+        // Floor, ceiling and back wall
+        scene.add_instance(quad, white, wall(Quat::IDENTITY, Vec3::ZERO));
+        scene.add_instance(
+            quad,
+            white,
+            wall(Quat::from_rotation_x(half_turn), vec3(0.0, 2.0, 0.0)),
+        );
+        scene.add_instance(
+            quad,
+            white,
+            wall(Quat::from_rotation_x(quarter_turn), vec3(0.0, 1.0, -1.0)),
+        );
+        // Left and right walls
+        scene.add_instance(
+            quad,
+            red,
+            wall(Quat::from_rotation_z(-quarter_turn), vec3(-1.0, 1.0, 0.0)),
+        );
+        scene.add_instance(
+            quad,
+            green,
+            wall(Quat::from_rotation_z(quarter_turn), vec3(1.0, 1.0, 0.0)),
+        );
+
+        scene.add_instance(
+            quad,
+            light,
+            Mat4::from_scale_rotation_translation(
+                Vec3::splat(0.5),
+                Quat::from_rotation_x(half_turn),
+                vec3(0.0, 1.99, 0.0),
+            ),
+        );
+
+        scene.add_instance(
+            cube,
+            metal,
+            Mat4::from_scale_rotation_translation(
+                vec3(0.6, 1.2, 0.6),
+                Quat::from_rotation_y(0.3),
+                vec3(-0.35, 0.6, -0.3),
+            ),
+        );
+        scene.add_instance(
+            cube,
+            white,
+            Mat4::from_scale_rotation_translation(
+                Vec3::splat(0.6),
+                Quat::from_rotation_y(-0.3),
+                vec3(0.35, 0.3, 0.3),
+            ),
+        );
+
+        scene
+    }
+
+    pub fn cornell_box_camera(aspect_ratio: f32) -> Camera {
+        Camera::new(
+            vec3(0.0, 1.0, 3.4),
+            vec3(0.0, 0.0, -1.0),
+            40.0,
+            aspect_ratio,
+            0.1,
+            100.0,
+        )
+    }
+}
+
+#[test]
+fn test_cube_faces_outwards() {
+    let cube = Mesh::cube();
+
+    assert_eq!(cube.triangle_count(), 12);
+    for index in 0..cube.triangle_count() {
+        let [v0, v1, v2] = cube.triangle(index);
+        let normal = (v1.position - v0.position).cross(v2.position - v0.position);
+        let center = (v0.position + v1.position + v2.position) / 3.0;
+
+        assert!(normal.dot(center) > 0.0);
+        assert!(normal.normalize().dot(v0.normal) > 0.99);
+    }
+}
+
+#[test]
+fn test_cornell_box_light() {
+    let lights = Scene::cornell_box().emissive_triangles();
+
+    assert_eq!(lights.len(), 2);
+    let area = lights.iter().map(LightTriangle::area).sum::<f32>();
+    assert!((area - 0.25).abs() < 1e-5);
+    for light in lights {
+        assert!(light.normal().dot(Vec3::NEG_Y) > 0.99);
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::{ensure, Result};
+use ash::vk;
+
+use crate::vulkan::{
+    Buffer, Context, DescriptorPool, DescriptorSet, DescriptorSetLayout, ImageView, Sampler,
+    WriteDescriptorSet, WriteDescriptorSetKind,
+};
+
+pub const BINDLESS_SAMPLED_IMAGE_BINDING: u32 = 0;
+pub const BINDLESS_STORAGE_BUFFER_BINDING: u32 = 1;
+pub const BINDLESS_SAMPLER_BINDING: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SampledImageHandle(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StorageBufferHandle(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerHandle(u32);
+
+impl SampledImageHandle {
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+impl StorageBufferHandle {
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+impl SamplerHandle {
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+/// The descriptor counts are clamped to the device's update-after-bind limits.
+#[derive(Debug, Clone, Copy)]
+pub struct BindlessDescriptorHeapCreateInfo {
+    pub max_sampled_images: u32,
+    pub max_storage_buffers: u32,
+    pub max_samplers: u32,
+    pub stages: vk::ShaderStageFlags,
+    /// Number of frames that can be in flight at once. A freed slot is only handed out
+    /// again once that many frames have begun since it was freed.
+    pub frames_in_flight: u32,
+}
+
+impl Default for BindlessDescriptorHeapCreateInfo {
+    fn default() -> Self {
+        Self {
+            max_sampled_images: 16 * 1024,
+            max_storage_buffers: 16 * 1024,
+            max_samplers: 256,
+            stages: vk::ShaderStageFlags::ALL,
+            frames_in_flight: 2,
+        }
+    }
+}
+
+/// One large, partially bound and update-after-bind descriptor set holding arrays of
+/// sampled images, storage buffers and samplers.
+///
+/// In GLSL (with `GL_EXT_nonuniform_qualifier`):
+///
+/// ```glsl
+/// layout(set = N, binding = 0) uniform texture2D textures[];
+/// layout(set = N, binding = 1) buffer Buffers { uint data[]; } buffers[];
+/// layout(set = N, binding = 2) uniform sampler samplers[];
+/// ```
+///
+/// Handles are stable for as long as the resource is registered and can be passed to
+/// shaders as plain `u32` indices.
+pub struct BindlessDescriptorHeap {
+    pub set: DescriptorSet,
+    pub layout: DescriptorSetLayout,
+    _pool: DescriptorPool,
+    sampled_images: SlotAllocator,
+    storage_buffers: SlotAllocator,
+    samplers: SlotAllocator,
+}
+
+impl BindlessDescriptorHeap {
+    pub(crate) fn new(
+        context: &Context,
+        create_info: BindlessDescriptorHeapCreateInfo,
+    ) -> Result<Self> {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(BINDLESS_SAMPLED_IMAGE_BINDING)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(create_info.max_sampled_images)
+                .stage_flags(create_info.stages)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(BINDLESS_STORAGE_BUFFER_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(create_info.max_storage_buffers)
+                .stage_flags(create_info.stages)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(BINDLESS_SAMPLER_BINDING)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(create_info.max_samplers)
+                .stage_flags(create_info.stages)
+                .build(),
+        ];
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+            3];
+
+        let layout = context.create_descriptor_set_layout_with_binding_flags(
+            &bindings,
+            &binding_flags,
+            vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+        )?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: create_info.max_sampled_images,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: create_info.max_storage_buffers,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: create_info.max_samplers,
+            },
+        ];
+        let pool = context.create_descriptor_pool_with_flags(
+            vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+            1,
+            &pool_sizes,
+        )?;
+        let set = pool.allocate_set(&layout)?;
+
+        Ok(Self {
+            set,
+            layout,
+            _pool: pool,
+            sampled_images: SlotAllocator::new(
+                create_info.max_sampled_images,
+                create_info.frames_in_flight,
+            ),
+            storage_buffers: SlotAllocator::new(
+                create_info.max_storage_buffers,
+                create_info.frames_in_flight,
+            ),
+            samplers: SlotAllocator::new(create_info.max_samplers, create_info.frames_in_flight),
+        })
+    }
+
+    /// Must be called once per frame, after waiting for the fence of the frame being reused.
+    /// Slots freed `frames_in_flight` frames ago become available again.
+    pub fn begin_frame(&mut self) {
+        self.sampled_images.begin_frame();
+        self.storage_buffers.begin_frame();
+        self.samplers.begin_frame();
+    }
+
+    pub fn add_sampled_image(
+        &mut self,
+        view: &ImageView,
+        layout: vk::ImageLayout,
+    ) -> Result<SampledImageHandle> {
+        let index = self
+            .sampled_images
+            .allocate()
+            .ok_or_else(|| anyhow::anyhow!("Bindless sampled image array is full"))?;
+
+        self.set.update(&[WriteDescriptorSet {
+            binding: BINDLESS_SAMPLED_IMAGE_BINDING,
+            array_element: index,
+            kind: WriteDescriptorSetKind::SampledImage { view, layout },
+        }]);
+
+        Ok(SampledImageHandle(index))
+    }
+
+    pub fn add_storage_buffer(&mut self, buffer: &Buffer) -> Result<StorageBufferHandle> {
+        let index = self
+            .storage_buffers
+            .allocate()
+            .ok_or_else(|| anyhow::anyhow!("Bindless storage buffer array is full"))?;
+
+        self.set.update(&[WriteDescriptorSet {
+            binding: BINDLESS_STORAGE_BUFFER_BINDING,
+            array_element: index,
+            kind: WriteDescriptorSetKind::StorageBuffer { buffer },
+        }]);
+
+        Ok(StorageBufferHandle(index))
+    }
+
+    pub fn add_sampler(&mut self, sampler: &Sampler) -> Result<SamplerHandle> {
+        let index = self
+            .samplers
+            .allocate()
+            .ok_or_else(|| anyhow::anyhow!("Bindless sampler array is full"))?;
+
+        self.set.update(&[WriteDescriptorSet {
+            binding: BINDLESS_SAMPLER_BINDING,
+            array_element: index,
+            kind: WriteDescriptorSetKind::Sampler { sampler },
+        }]);
+
+        Ok(SamplerHandle(index))
+    }
+
+    /// The slot is recycled once the GPU can no longer be reading it. The resource itself
+    /// must be kept alive by the caller for as long as in-flight frames may use it.
+    pub fn remove_sampled_image(&mut self, handle: SampledImageHandle) {
+        self.sampled_images.free(handle.0);
+    }
+
+    pub fn remove_storage_buffer(&mut self, handle: StorageBufferHandle) {
+        self.storage_buffers.free(handle.0);
+    }
+
+    pub fn remove_sampler(&mut self, handle: SamplerHandle) {
+        self.samplers.free(handle.0);
+    }
+}
+
+impl Context {
+    pub fn create_bindless_descriptor_heap(
+        &self,
+        create_info: BindlessDescriptorHeapCreateInfo,
+    ) -> Result<BindlessDescriptorHeap> {
+        ensure!(
+            self.device.features.descriptor_indexing,
+            "Cannot call Context::create_bindless_descriptor_heap when descriptor indexing is not enabled"
+        );
+
+        let create_info =
+            create_info.clamp_to_limits(&self.physical_device.descriptor_indexing_properties);
+
+        BindlessDescriptorHeap::new(self, create_info)
+    }
+}
+
+impl BindlessDescriptorHeapCreateInfo {
+    fn clamp_to_limits(self, limits: &vk::PhysicalDeviceDescriptorIndexingProperties) -> Self {
+        Self {
+            max_sampled_images: self
+                .max_sampled_images
+                .min(limits.max_descriptor_set_update_after_bind_sampled_images)
+                .min(limits.max_per_stage_descriptor_update_after_bind_sampled_images),
+            max_storage_buffers: self
+                .max_storage_buffers
+                .min(limits.max_descriptor_set_update_after_bind_storage_buffers)
+                .min(limits.max_per_stage_descriptor_update_after_bind_storage_buffers),
+            max_samplers: self
+                .max_samplers
+                .min(limits.max_descriptor_set_update_after_bind_samplers)
+                .min(limits.max_per_stage_descriptor_update_after_bind_samplers),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SlotAllocator {
+    capacity: u32,
+    frames_in_flight: u64,
+    next_unused: u32,
+    free: Vec<u32>,
+    pending: Vec<(u32, u64)>,
+    frame: u64,
+}
+
+impl SlotAllocator {
+    pub(crate) fn new(capacity: u32, frames_in_flight: u32) -> Self {
+        Self {
+            capacity,
+            frames_in_flight: frames_in_flight as _,
+            next_unused: 0,
+            free: vec![],
+            pending: vec![],
+            frame: 0,
+        }
+    }
+
+    pub(crate) fn allocate(&mut self) -> Option<u32> {
+        if let Some(index) = self.free.pop() {
+            return Some(index);
+        }
+
+        (self.next_unused < self.capacity).then(|| {
+            self.next_unused += 1;
+            self.next_unused - 1
+        })
+    }
+
+    pub(crate) fn free(&mut self, index: u32) {
+        debug_assert!(
+            index < self.next_unused,
+            "Freeing a slot that was never allocated"
+        );
+        self.pending.push((index, self.frame));
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.frame += 1;
+
+        let frame = self.frame;
+        let frames_in_flight = self.frames_in_flight;
+        let free = &mut self.free;
+        self.pending.retain(|&(index, freed_at)| {
+            let is_safe = freed_at + frames_in_flight <= frame;
+            if is_safe {
+                free.push(index);
+            }
+            !is_safe
+        });
+    }
+}
+
+#[test]
+fn test_slot_allocator_recycles_after_frames_in_flight() {
+    let mut slots = SlotAllocator::new(2, 2);
+
+    let a = slots.allocate().unwrap();
+    let b = slots.allocate().unwrap();
+    assert_ne!(a, b);
+    assert_eq!(slots.allocate(), None);
+
+    slots.free(a);
+    assert_eq!(slots.allocate(), None);
+
+    slots.begin_frame();
+    assert_eq!(slots.allocate(), None);
+
+    slots.begin_frame();
+    assert_eq!(slots.allocate(), Some(a));
+    assert_eq!(slots.allocate(), None);
+}
+
+#[test]
+fn test_create_info_clamped_to_limits() {
+    let limits = vk::PhysicalDeviceDescriptorIndexingProperties {
+        max_descriptor_set_update_after_bind_sampled_images: 8 * 1024,
+        max_per_stage_descriptor_update_after_bind_sampled_images: 4 * 1024,
+        max_descriptor_set_update_after_bind_storage_buffers: 1024,
+        max_per_stage_descriptor_update_after_bind_storage_buffers: 2048,
+        max_descriptor_set_update_after_bind_samplers: 1024,
+        max_per_stage_descriptor_update_after_bind_samplers: 1024,
+        ..Default::default()
+    };
+
+    let create_info = BindlessDescriptorHeapCreateInfo::default().clamp_to_limits(&limits);
+    assert_eq!(create_info.max_sampled_images, 4 * 1024);
+    assert_eq!(create_info.max_storage_buffers, 1024);
+    assert_eq!(create_info.max_samplers, 256);
+}
@@ -10,13 +10,15 @@ use gpu_allocator::{
     MemoryLocation,
 };
 
-use crate::vulkan::{device::Device, Context};
+use crate::vulkan::{device::Device, Context, Pod};
 
 pub struct Buffer {
     device: Arc<Device>,
     allocator: Arc<Mutex<Allocator>>,
     pub(crate) inner: vk::Buffer,
     allocation: Option<Allocation>,
+    /// Id of the allocation in the memory tracker of the device.
+    allocation_id: u64,
     pub size: vk::DeviceSize,
 }
 
@@ -44,12 +46,17 @@ impl Buffer {
                 .inner
                 .bind_buffer_memory(inner, allocation.memory(), allocation.offset())?
         };
+        let allocation_id =
+            device
+                .memory
+                .track("buffer", &allocation, requirements.memory_type_bits);
 
         Ok(Self {
             device,
             allocator,
             inner,
             allocation: Some(allocation),
+            allocation_id,
             size,
         })
     }
@@ -71,10 +78,77 @@ impl Buffer {
         Ok(())
     }
 
+    /// Reads the buffer back, which must be host visible. `T` is [`Pod`] as any bytes the GPU
+    /// wrote must be a valid value.
+    pub fn read_data_from_buffer<T: Pod>(&self) -> Result<Vec<T>> {
+        let allocation = self.allocation.as_ref().unwrap();
+        let data = allocation
+            .mapped_slice()
+            .ok_or_else(|| anyhow::anyhow!("Buffer memory is not host visible"))?;
+        let len = self.size as usize / std::mem::size_of::<T>();
+
+        Ok(
+            (0..len)
+                .map(|i| unsafe {
+                    std::ptr::read_unaligned(
+                        data.as_ptr().add(i * std::mem::size_of::<T>()) as *const T
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Names the allocation of the buffer, in memory reports and leak messages.
+    pub fn set_name(&mut self, name: &str) -> Result<()> {
+        self.allocator
+            .lock()
+            .unwrap()
+            .rename_allocation(self.allocation.as_mut().unwrap(), name)?;
+        self.device.memory.rename(self.allocation_id, name);
+
+        Ok(())
+    }
+
     pub fn get_device_address(&self) -> u64 {
         let addr_info = vk::BufferDeviceAddressInfo::builder().buffer(self.inner);
         unsafe { self.device.inner.get_buffer_device_address(&addr_info) }
     }
+
+    pub fn create_buffer_view(
+        &self,
+        format: vk::Format,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Result<BufferView> {
+        let view_info = vk::BufferViewCreateInfo::builder()
+            .buffer(self.inner)
+            .format(format)
+            .offset(offset)
+            .range(range);
+
+        let inner = unsafe { self.device.inner.create_buffer_view(&view_info, None)? };
+
+        Ok(BufferView {
+            device: self.device.clone(),
+            inner,
+        })
+    }
+}
+
+pub struct BufferView {
+    device: Arc<Device>,
+    pub(crate) inner: vk::BufferView,
+}
+
+impl Drop for BufferView {
+    fn drop(&mut self) {
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_buffer_view(inner, None)
+            });
+    }
 }
 
 impl Context {
@@ -96,11 +170,17 @@ impl Context {
 
 impl Drop for Buffer {
     fn drop(&mut self) {
-        unsafe { self.device.inner.destroy_buffer(self.inner, None) };
-        self.allocator
-            .lock()
-            .unwrap()
-            .free(self.allocation.take().unwrap())
-            .unwrap();
+        let inner = self.inner;
+        let allocator = self.allocator.clone();
+        let allocation = self.allocation.take().unwrap();
+        let allocation_id = self.allocation_id;
+
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| {
+                unsafe { device.inner.destroy_buffer(inner, None) };
+                allocator.lock().unwrap().free(allocation).unwrap();
+                device.memory.untrack(allocation_id);
+            });
     }
 }
@@ -4,9 +4,9 @@ use anyhow::Result;
 use ash::vk;
 
 use crate::vulkan::{
-    device::Device, Buffer, ComputePipeline, Context, DescriptorSet, GraphicsPipeline, Image,
-    ImageView, PipelineLayout, QueueFamily, RayTracingContext, RayTracingPipeline,
-    ShaderBindingTable, TimestampQueryPool,
+    bytes_of, device::Device, AccelerationStructure, Buffer, ComputePipeline, Context,
+    DescriptorSet, GraphicsPipeline, Image, ImageView, PipelineLayout, Pod, QueryPool, QueueFamily,
+    RayTracingContext, RayTracingPipeline, ScopedQueryPool, ShaderBindingTable, TimestampQueryPool,
 };
 
 pub struct CommandPool {
@@ -173,6 +173,22 @@ impl CommandBuffer {
         };
     }
 
+    pub fn bind_index_buffer(&self, index_buffer: &Buffer, index_type: vk::IndexType) {
+        unsafe {
+            self.device
+                .inner
+                .cmd_bind_index_buffer(self.inner, index_buffer.inner, 0, index_type)
+        };
+    }
+
+    pub fn draw_indexed(&self, index_count: u32) {
+        unsafe {
+            self.device
+                .inner
+                .cmd_draw_indexed(self.inner, index_count, 1, 0, 0, 0)
+        };
+    }
+
     pub fn draw(&self, vertex_count: u32) {
         unsafe {
             self.device
@@ -189,12 +205,39 @@ impl CommandBuffer {
         }
     }
 
+    pub fn push_constants<T: Pod>(
+        &self,
+        layout: &PipelineLayout,
+        stages: vk::ShaderStageFlags,
+        offset: u32,
+        data: &T,
+    ) {
+        let data = bytes_of(data);
+
+        unsafe {
+            self.device
+                .inner
+                .cmd_push_constants(self.inner, layout.inner, stages, offset, data)
+        };
+    }
+
     pub fn bind_descriptor_sets(
         &self,
         bind_point: vk::PipelineBindPoint,
         layout: &PipelineLayout,
         first_set: u32,
         sets: &[&DescriptorSet],
+    ) {
+        self.bind_descriptor_sets_with_dynamic_offsets(bind_point, layout, first_set, sets, &[]);
+    }
+
+    pub fn bind_descriptor_sets_with_dynamic_offsets(
+        &self,
+        bind_point: vk::PipelineBindPoint,
+        layout: &PipelineLayout,
+        first_set: u32,
+        sets: &[&DescriptorSet],
+        dynamic_offsets: &[u32],
     ) {
         let sets = sets.iter().map(|s| s.inner).collect::<Vec<_>>();
         unsafe {
@@ -204,13 +247,43 @@ impl CommandBuffer {
                 layout.inner,
                 first_set,
                 &sets,
-                &[],
+                dynamic_offsets,
             )
         }
     }
 
+    pub fn pipeline_memory_barriers(&self, barriers: &[MemoryBarrier]) {
+        self.pipeline_barriers(barriers, &[], &[]);
+    }
+
     pub fn pipeline_buffer_barriers(&self, barriers: &[BufferBarrier]) {
-        let barriers = barriers
+        self.pipeline_barriers(&[], barriers, &[]);
+    }
+
+    pub fn pipeline_image_barriers(&self, barriers: &[ImageBarrier]) {
+        self.pipeline_barriers(&[], &[], barriers);
+    }
+
+    /// Records all barriers with a single `vkCmdPipelineBarrier2`.
+    pub fn pipeline_barriers(
+        &self,
+        memory_barriers: &[MemoryBarrier],
+        buffer_barriers: &[BufferBarrier],
+        image_barriers: &[ImageBarrier],
+    ) {
+        let memory_barriers = memory_barriers
+            .iter()
+            .map(|b| {
+                vk::MemoryBarrier2::builder()
+                    .src_stage_mask(b.src_stage_mask)
+                    .src_access_mask(b.src_access_mask)
+                    .dst_stage_mask(b.dst_stage_mask)
+                    .dst_access_mask(b.dst_access_mask)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let buffer_barriers = buffer_barriers
             .iter()
             .map(|b| {
                 vk::BufferMemoryBarrier2::builder()
@@ -225,29 +298,7 @@ impl CommandBuffer {
             })
             .collect::<Vec<_>>();
 
-        let dependency_info = vk::DependencyInfo::builder().buffer_memory_barriers(&barriers);
-
-        unsafe {
-            self.device
-                .inner
-                .cmd_pipeline_barrier2(self.inner, &dependency_info)
-        };
-    }
-
-    pub fn copy_buffer(&self, src_buffer: &Buffer, dst_buffer: &Buffer) {
-        unsafe {
-            let region = vk::BufferCopy::builder().size(src_buffer.size);
-            self.device.inner.cmd_copy_buffer(
-                self.inner,
-                src_buffer.inner,
-                dst_buffer.inner,
-                std::slice::from_ref(&region),
-            )
-        };
-    }
-
-    pub fn pipeline_image_barriers(&self, barriers: &[ImageBarrier]) {
-        let barriers = barriers
+        let image_barriers = image_barriers
             .iter()
             .map(|b| {
                 vk::ImageMemoryBarrier2::builder()
@@ -259,7 +310,7 @@ impl CommandBuffer {
                     .new_layout(b.new_layout)
                     .image(b.image.inner)
                     .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        aspect_mask: b.image.aspect_mask(),
                         base_mip_level: 0,
                         level_count: 1,
                         base_array_layer: 0,
@@ -269,7 +320,10 @@ impl CommandBuffer {
             })
             .collect::<Vec<_>>();
 
-        let dependency_info = vk::DependencyInfo::builder().image_memory_barriers(&barriers);
+        let dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(&memory_barriers)
+            .buffer_memory_barriers(&buffer_barriers)
+            .image_memory_barriers(&image_barriers);
 
         unsafe {
             self.device
@@ -278,6 +332,33 @@ impl CommandBuffer {
         };
     }
 
+    pub fn copy_buffer(&self, src_buffer: &Buffer, dst_buffer: &Buffer) {
+        unsafe {
+            let region = vk::BufferCopy::builder().size(src_buffer.size);
+            self.device.inner.cmd_copy_buffer(
+                self.inner,
+                src_buffer.inner,
+                dst_buffer.inner,
+                std::slice::from_ref(&region),
+            )
+        };
+    }
+
+    /// Fills `size` bytes from `offset` with `data`, `vk::WHOLE_SIZE` for the rest of the buffer.
+    pub fn fill_buffer(
+        &self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        data: u32,
+    ) {
+        unsafe {
+            self.device
+                .inner
+                .cmd_fill_buffer(self.inner, buffer.inner, offset, size, data)
+        };
+    }
+
     pub fn copy_image(
         &self,
         src_image: &Image,
@@ -316,6 +397,46 @@ impl CommandBuffer {
         };
     }
 
+    /// Blits the whole `src_image` onto the whole `dst_image`, converting formats and scaling.
+    pub fn blit_image(
+        &self,
+        src_image: &Image,
+        src_layout: vk::ImageLayout,
+        dst_image: &Image,
+        dst_layout: vk::ImageLayout,
+        filter: vk::Filter,
+    ) {
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_array_layer: 0,
+            mip_level: 0,
+            layer_count: 1,
+        };
+        let corner = |image: &Image| vk::Offset3D {
+            x: image.extent.width as _,
+            y: image.extent.height as _,
+            z: 1,
+        };
+
+        let region = vk::ImageBlit::builder()
+            .src_subresource(subresource)
+            .src_offsets([vk::Offset3D::default(), corner(src_image)])
+            .dst_subresource(subresource)
+            .dst_offsets([vk::Offset3D::default(), corner(dst_image)]);
+
+        unsafe {
+            self.device.inner.cmd_blit_image(
+                self.inner,
+                src_image.inner,
+                src_layout,
+                dst_image.inner,
+                dst_layout,
+                std::slice::from_ref(&region),
+                filter,
+            )
+        };
+    }
+
     pub fn copy_buffer_to_image(&self, src: &Buffer, dst: &Image, layout: vk::ImageLayout) {
         let region = vk::BufferImageCopy::builder()
             .image_subresource(vk::ImageSubresourceLayers {
@@ -337,6 +458,27 @@ impl CommandBuffer {
         };
     }
 
+    pub fn copy_image_to_buffer(&self, src: &Image, layout: vk::ImageLayout, dst: &Buffer) {
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(src.extent);
+
+        unsafe {
+            self.device.inner.cmd_copy_image_to_buffer(
+                self.inner,
+                src.inner,
+                layout,
+                dst.inner,
+                std::slice::from_ref(&region),
+            );
+        };
+    }
+
     pub fn build_acceleration_structures(
         &self,
         as_build_geo_info: &vk::AccelerationStructureBuildGeometryInfoKHR,
@@ -357,7 +499,86 @@ impl CommandBuffer {
         };
     }
 
+    /// Builds or updates several acceleration structures with a single command. They must not
+    /// depend on each other nor share overlapping scratch memory.
+    pub fn build_multiple_acceleration_structures(
+        &self,
+        as_build_geo_infos: &[vk::AccelerationStructureBuildGeometryInfoKHR],
+        as_build_range_infos: &[&[vk::AccelerationStructureBuildRangeInfoKHR]],
+    ) {
+        let ray_tracing = self.ray_tracing.as_ref().expect(
+            "Cannot call CommandBuffer::build_multiple_acceleration_structures when ray tracing is not enabled",
+        );
+
+        unsafe {
+            ray_tracing
+                .acceleration_structure_fn
+                .cmd_build_acceleration_structures(
+                    self.inner,
+                    as_build_geo_infos,
+                    as_build_range_infos,
+                )
+        };
+    }
+
+    pub fn copy_acceleration_structure(
+        &self,
+        src: &AccelerationStructure,
+        dst: &AccelerationStructure,
+        mode: vk::CopyAccelerationStructureModeKHR,
+    ) {
+        let ray_tracing = self.ray_tracing.as_ref().expect(
+            "Cannot call CommandBuffer::copy_acceleration_structure when ray tracing is not enabled",
+        );
+
+        let copy_info = vk::CopyAccelerationStructureInfoKHR::builder()
+            .src(src.inner)
+            .dst(dst.inner)
+            .mode(mode);
+
+        unsafe {
+            ray_tracing
+                .acceleration_structure_fn
+                .cmd_copy_acceleration_structure(self.inner, &copy_info)
+        };
+    }
+
+    pub fn write_acceleration_structures_properties(
+        &self,
+        structures: &[&AccelerationStructure],
+        pool: &QueryPool,
+        first_query: u32,
+    ) {
+        let ray_tracing = self.ray_tracing.as_ref().expect(
+            "Cannot call CommandBuffer::write_acceleration_structures_properties when ray tracing is not enabled",
+        );
+
+        let structures = structures.iter().map(|s| s.inner).collect::<Vec<_>>();
+
+        unsafe {
+            ray_tracing
+                .acceleration_structure_fn
+                .cmd_write_acceleration_structures_properties(
+                    self.inner,
+                    &structures,
+                    pool.ty,
+                    pool.inner,
+                    first_query,
+                )
+        };
+    }
+
     pub fn trace_rays(&self, shader_binding_table: &ShaderBindingTable, width: u32, height: u32) {
+        self.trace_rays_3d(shader_binding_table, width, height, 1);
+    }
+
+    pub fn trace_rays_3d(
+        &self,
+        shader_binding_table: &ShaderBindingTable,
+        width: u32,
+        height: u32,
+        depth: u32,
+    ) {
         let ray_tracing = self
             .ray_tracing
             .as_ref()
@@ -369,10 +590,10 @@ impl CommandBuffer {
                 &shader_binding_table.raygen_region,
                 &shader_binding_table.miss_region,
                 &shader_binding_table.hit_region,
-                &vk::StridedDeviceAddressRegionKHR::builder(),
+                &shader_binding_table.callable_region,
                 width,
                 height,
-                1,
+                depth,
             )
         };
     }
@@ -384,29 +605,45 @@ impl CommandBuffer {
         load_op: vk::AttachmentLoadOp,
         clear_color: Option<[f32; 4]>,
     ) {
-        let color_attachment_info = vk::RenderingAttachmentInfo::builder()
-            .image_view(image_view.inner)
-            .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
+        let color_attachment = RenderingAttachment::color(image_view)
             .load_op(load_op)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .clear_value(vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: clear_color.unwrap_or([1.0; 4]),
-                },
-            });
+            .clear_color(clear_color.unwrap_or([0.0, 0.0, 0.0, 1.0]));
 
-        let rendering_info = vk::RenderingInfo::builder()
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent,
-            })
-            .layer_count(1)
-            .color_attachments(std::slice::from_ref(&color_attachment_info));
+        self.begin_rendering_with_info(
+            &RenderingInfo::new(extent).color_attachment(color_attachment),
+        );
+    }
+
+    pub fn begin_rendering_with_info(&self, rendering_info: &RenderingInfo) {
+        let color_attachment_infos = rendering_info
+            .color_attachments
+            .iter()
+            .map(|attachment| attachment.to_vk())
+            .collect::<Vec<_>>();
+        let depth_attachment_info = rendering_info
+            .depth_attachment
+            .as_ref()
+            .map(|attachment| attachment.to_vk());
+        let stencil_attachment_info = rendering_info
+            .stencil_attachment
+            .as_ref()
+            .map(|attachment| attachment.to_vk());
+
+        let mut vk_rendering_info = vk::RenderingInfo::builder()
+            .render_area(rendering_info.render_area)
+            .layer_count(rendering_info.layer_count)
+            .color_attachments(&color_attachment_infos);
+        if let Some(depth_attachment_info) = depth_attachment_info.as_ref() {
+            vk_rendering_info = vk_rendering_info.depth_attachment(depth_attachment_info);
+        }
+        if let Some(stencil_attachment_info) = stencil_attachment_info.as_ref() {
+            vk_rendering_info = vk_rendering_info.stencil_attachment(stencil_attachment_info);
+        }
 
         unsafe {
             self.device
                 .inner
-                .cmd_begin_rendering(self.inner, &rendering_info)
+                .cmd_begin_rendering(self.inner, &vk_rendering_info)
         };
     }
 
@@ -453,6 +690,14 @@ impl CommandBuffer {
         }
     }
 
+    pub fn reset_query_pool(&self, pool: &QueryPool) {
+        unsafe {
+            self.device
+                .inner
+                .cmd_reset_query_pool(self.inner, pool.inner, 0, pool.count);
+        }
+    }
+
     pub fn write_timestamp<const C: usize>(
         &self,
         stage: vk::PipelineStageFlags2,
@@ -467,6 +712,69 @@ impl CommandBuffer {
                 .cmd_write_timestamp2(self.inner, stage, pool.inner, query_index)
         }
     }
+
+    /// Starts query `query_index` of `pool`, which must have been reset.
+    pub fn begin_query(&self, pool: &impl ScopedQueryPool, query_index: u32) {
+        let query_pool = pool.query_pool();
+        assert!(
+            query_index < query_pool.count,
+            "Query index must be < {}",
+            query_pool.count
+        );
+
+        unsafe {
+            self.device.inner.cmd_begin_query(
+                self.inner,
+                query_pool.inner,
+                query_index,
+                pool.control_flags(),
+            )
+        }
+    }
+
+    pub fn end_query(&self, pool: &impl ScopedQueryPool, query_index: u32) {
+        unsafe {
+            self.device
+                .inner
+                .cmd_end_query(self.inner, pool.query_pool().inner, query_index)
+        }
+    }
+
+    /// Writes a checkpoint named `name`, reported by [`Context::device_lost_report`] if the
+    /// GPU hangs after it. Does nothing without the `VK_NV_device_diagnostic_checkpoints`
+    /// extension.
+    pub fn set_checkpoint(&self, name: &str) {
+        if let Some(checkpoints) = &self.device.checkpoints {
+            checkpoints.set_checkpoint(self.inner, name);
+        }
+    }
+
+    pub fn write_query_pool_timestamp(
+        &self,
+        stage: vk::PipelineStageFlags2,
+        pool: &QueryPool,
+        query_index: u32,
+    ) {
+        assert!(
+            query_index < pool.count,
+            "Query index must be < {}",
+            pool.count
+        );
+
+        unsafe {
+            self.device
+                .inner
+                .cmd_write_timestamp2(self.inner, stage, pool.inner, query_index)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MemoryBarrier {
+    pub src_access_mask: vk::AccessFlags2,
+    pub dst_access_mask: vk::AccessFlags2,
+    pub src_stage_mask: vk::PipelineStageFlags2,
+    pub dst_stage_mask: vk::PipelineStageFlags2,
 }
 
 #[derive(Clone, Copy)]
@@ -488,3 +796,182 @@ pub struct ImageBarrier<'a> {
     pub src_stage_mask: vk::PipelineStageFlags2,
     pub dst_stage_mask: vk::PipelineStageFlags2,
 }
+
+#[derive(Clone, Copy)]
+pub struct ResolveAttachment<'a> {
+    pub view: &'a ImageView,
+    /// `None` for the layout of the resolved attachment, when recorded.
+    pub layout: Option<vk::ImageLayout>,
+    pub mode: vk::ResolveModeFlags,
+}
+
+#[derive(Clone, Copy)]
+pub struct RenderingAttachment<'a> {
+    pub view: &'a ImageView,
+    pub layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+    pub resolve: Option<ResolveAttachment<'a>>,
+}
+
+impl<'a> RenderingAttachment<'a> {
+    pub fn color(view: &'a ImageView) -> Self {
+        Self {
+            view,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+            resolve: None,
+        }
+    }
+
+    /// In `DEPTH_ATTACHMENT_OPTIMAL`, which render graph passes must replace with the layout
+    /// of their access, see [`PassResources::layout`](crate::render_graph::PassResources::layout).
+    pub fn depth(view: &'a ImageView) -> Self {
+        Self {
+            view,
+            layout: vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+            resolve: None,
+        }
+    }
+
+    pub fn depth_stencil(view: &'a ImageView) -> Self {
+        Self {
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ..Self::depth(view)
+        }
+    }
+
+    pub fn layout(self, layout: vk::ImageLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    pub fn load_op(self, load_op: vk::AttachmentLoadOp) -> Self {
+        Self { load_op, ..self }
+    }
+
+    pub fn store_op(self, store_op: vk::AttachmentStoreOp) -> Self {
+        Self { store_op, ..self }
+    }
+
+    pub fn clear_color(self, color: [f32; 4]) -> Self {
+        Self {
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue { float32: color },
+            },
+            ..self
+        }
+    }
+
+    pub fn clear_depth_stencil(self, depth: f32, stencil: u32) -> Self {
+        Self {
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+            },
+            ..self
+        }
+    }
+
+    /// Resolves the (multisampled) attachment into `view` at the end of rendering. `view` is in
+    /// the same layout as the attachment.
+    pub fn resolve(self, view: &'a ImageView, mode: vk::ResolveModeFlags) -> Self {
+        Self {
+            resolve: Some(ResolveAttachment {
+                view,
+                layout: None,
+                mode,
+            }),
+            ..self
+        }
+    }
+
+    fn to_vk(self) -> vk::RenderingAttachmentInfo {
+        let mut info = vk::RenderingAttachmentInfo::builder()
+            .image_view(self.view.inner)
+            .image_layout(self.layout)
+            .load_op(self.load_op)
+            .store_op(self.store_op)
+            .clear_value(self.clear_value);
+
+        if let Some(resolve) = self.resolve {
+            info = info
+                .resolve_mode(resolve.mode)
+                .resolve_image_view(resolve.view.inner)
+                .resolve_image_layout(resolve.layout.unwrap_or(self.layout));
+        }
+
+        info.build()
+    }
+}
+
+#[derive(Clone)]
+pub struct RenderingInfo<'a> {
+    render_area: vk::Rect2D,
+    layer_count: u32,
+    color_attachments: Vec<RenderingAttachment<'a>>,
+    depth_attachment: Option<RenderingAttachment<'a>>,
+    stencil_attachment: Option<RenderingAttachment<'a>>,
+}
+
+impl<'a> RenderingInfo<'a> {
+    pub fn new(extent: vk::Extent2D) -> Self {
+        Self {
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            layer_count: 1,
+            color_attachments: vec![],
+            depth_attachment: None,
+            stencil_attachment: None,
+        }
+    }
+
+    pub fn render_area(self, render_area: vk::Rect2D) -> Self {
+        Self {
+            render_area,
+            ..self
+        }
+    }
+
+    pub fn layer_count(self, layer_count: u32) -> Self {
+        Self {
+            layer_count,
+            ..self
+        }
+    }
+
+    /// Appends a color attachment, bound to the next `layout(location = N)` fragment output.
+    pub fn color_attachment(mut self, attachment: RenderingAttachment<'a>) -> Self {
+        self.color_attachments.push(attachment);
+        self
+    }
+
+    pub fn depth_attachment(self, attachment: RenderingAttachment<'a>) -> Self {
+        Self {
+            depth_attachment: Some(attachment),
+            ..self
+        }
+    }
+
+    pub fn stencil_attachment(self, attachment: RenderingAttachment<'a>) -> Self {
+        Self {
+            stencil_attachment: Some(attachment),
+            ..self
+        }
+    }
+}
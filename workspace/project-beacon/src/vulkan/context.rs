@@ -11,14 +11,16 @@ use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use crate::vulkan::{
     device::{Device, DeviceFeatures},
     instance::Instance,
+    is_device_lost,
     physical_device::PhysicalDevice,
     queue::{Queue, QueueFamily},
     surface::Surface,
-    CommandBuffer, CommandPool, RayTracingContext, Version, VERSION_1_0,
+    CommandBuffer, CommandPool, RayTracingContext, SamplerCache, Version, VERSION_1_0,
 };
 
 pub struct Context {
     pub allocator: Arc<Mutex<Allocator>>,
+    pub(crate) samplers: SamplerCache,
     pub command_pool: CommandPool,
     pub ray_tracing: Option<Arc<RayTracingContext>>,
     pub graphics_queue: Queue,
@@ -27,18 +29,20 @@ pub struct Context {
     pub present_queue_family: QueueFamily,
     pub graphics_queue_family: QueueFamily,
     pub physical_device: PhysicalDevice,
-    pub surface: Surface,
+    /// `None` for headless contexts.
+    pub surface: Option<Surface>,
     pub instance: Instance,
     _entry: Entry,
 }
 
 pub struct ContextBuilder<'a> {
-    window_handle: &'a dyn HasRawWindowHandle,
-    display_handle: &'a dyn HasRawDisplayHandle,
+    window: Option<(&'a dyn HasRawWindowHandle, &'a dyn HasRawDisplayHandle)>,
     vulkan_version: Version,
     app_name: &'a str,
     required_extensions: &'a [&'a str],
+    optional_extensions: &'a [&'a str],
     required_device_features: DeviceFeatures,
+    optional_device_features: DeviceFeatures,
     with_raytracing_context: bool,
 }
 
@@ -48,12 +52,22 @@ impl<'a> ContextBuilder<'a> {
         display_handle: &'a dyn HasRawDisplayHandle,
     ) -> Self {
         Self {
-            window_handle,
-            display_handle,
+            window: Some((window_handle, display_handle)),
+            ..Self::headless()
+        }
+    }
+
+    /// Context without a surface, for offscreen rendering and tests. Swapchains cannot be
+    /// created from it.
+    pub fn headless() -> Self {
+        Self {
+            window: None,
             vulkan_version: VERSION_1_0,
             app_name: "",
             required_extensions: &[],
+            optional_extensions: &[],
             required_device_features: Default::default(),
+            optional_device_features: Default::default(),
             with_raytracing_context: false,
         }
     }
@@ -76,6 +90,15 @@ impl<'a> ContextBuilder<'a> {
         }
     }
 
+    /// Device extensions enabled only if the selected device supports them. See
+    /// [`Context::is_extension_enabled`].
+    pub fn optional_extensions(self, optional_extensions: &'a [&str]) -> Self {
+        Self {
+            optional_extensions,
+            ..self
+        }
+    }
+
     pub fn required_device_features(self, required_device_features: DeviceFeatures) -> Self {
         Self {
             required_device_features,
@@ -83,6 +106,15 @@ impl<'a> ContextBuilder<'a> {
         }
     }
 
+    /// Device features enabled only if the selected device supports them, and the extensions
+    /// they need are enabled. See [`Context::device_features`].
+    pub fn optional_device_features(self, optional_device_features: DeviceFeatures) -> Self {
+        Self {
+            optional_device_features,
+            ..self
+        }
+    }
+
     pub fn with_raytracing_context(self, with_raytracing_context: bool) -> Self {
         Self {
             with_raytracing_context,
@@ -98,38 +130,65 @@ impl<'a> ContextBuilder<'a> {
 impl Context {
     fn new(
         ContextBuilder {
-            window_handle,
-            display_handle,
+            window,
             vulkan_version,
             app_name,
             required_extensions,
+            optional_extensions,
             required_device_features,
+            optional_device_features,
             with_raytracing_context,
         }: ContextBuilder,
     ) -> Result<Self> {
         // Vulkan instance
         let entry = Entry::linked();
-        let mut instance = Instance::new(&entry, display_handle, vulkan_version, app_name)?;
+        let mut instance = Instance::new(
+            &entry,
+            window.map(|(_, display_handle)| display_handle),
+            vulkan_version,
+            app_name,
+        )?;
 
         // Vulkan surface
-        let surface = Surface::new(&entry, &instance, window_handle, display_handle)?;
+        let surface = window
+            .map(|(window_handle, display_handle)| {
+                Surface::new(&entry, &instance, window_handle, display_handle)
+            })
+            .transpose()?;
 
-        let physical_devices = instance.enumerate_physical_devices(&surface)?;
+        let physical_devices = instance.enumerate_physical_devices(surface.as_ref())?;
         let (physical_device, graphics_queue_family, present_queue_family) =
             select_suitable_physical_device(
                 physical_devices,
                 required_extensions,
                 &required_device_features,
+                surface.is_some(),
             )?;
         println!("Selected physical device: {:?}", physical_device.name);
 
+        let extensions = required_extensions
+            .iter()
+            .chain(
+                optional_extensions
+                    .iter()
+                    .filter(|e| physical_device.supports_extensions(&[e])),
+            )
+            .copied()
+            .collect::<Vec<_>>();
+
+        let device_features = required_device_features.union(
+            &optional_device_features
+                .intersection(&physical_device.supported_device_features)
+                .with_extensions(&extensions),
+        );
+
         let queue_families = [graphics_queue_family, present_queue_family];
         let device = Arc::new(Device::new(
             &instance,
             &physical_device,
             &queue_families,
-            required_extensions,
-            &required_device_features,
+            &extensions,
+            &device_features,
         )?);
         let graphics_queue = device.get_queue(graphics_queue_family, 0);
         let present_queue = device.get_queue(present_queue_family, 0);
@@ -165,12 +224,13 @@ impl Context {
                 log_frees: true,
                 ..Default::default()
             },
-            buffer_device_address: required_device_features.buffer_device_address,
+            buffer_device_address: device_features.buffer_device_address,
             allocation_sizes: Default::default(),
         })?;
 
         Ok(Self {
             allocator: Arc::new(Mutex::new(allocator)),
+            samplers: SamplerCache::default(),
             command_pool,
             ray_tracing,
             present_queue,
@@ -190,6 +250,7 @@ fn select_suitable_physical_device(
     devices: &[PhysicalDevice],
     required_extensions: &[&str],
     required_device_features: &DeviceFeatures,
+    requires_present: bool,
 ) -> Result<(PhysicalDevice, QueueFamily, QueueFamily)> {
     println!("Choosing Vulkan physical device");
 
@@ -213,6 +274,11 @@ fn select_suitable_physical_device(
                     present = Some(*family);
                 }
 
+                // Headless contexts use the graphics queue in place of the present queue
+                if !requires_present && graphics.is_some() {
+                    present = graphics;
+                }
+
                 if graphics.is_some() && present.is_some() {
                     break;
                 }
@@ -224,8 +290,8 @@ fn select_suitable_physical_device(
             graphics.is_some()
                 && present.is_some()
                 && extention_support
-                && !device.supported_surface_formats.is_empty()
-                && !device.supported_present_modes.is_empty()
+                && (!requires_present || !device.supported_surface_formats.is_empty())
+                && (!requires_present || !device.supported_present_modes.is_empty())
                 && device
                     .supported_device_features
                     .is_compatible_with(required_device_features)
@@ -236,8 +302,20 @@ fn select_suitable_physical_device(
 }
 
 impl Context {
+    /// Whether a required or supported optional device extension was enabled.
+    pub fn is_extension_enabled(&self, name: &str) -> bool {
+        self.device.extensions.iter().any(|e| e == name)
+    }
+
+    /// Required device features, and the optional ones the device supports.
+    pub fn device_features(&self) -> DeviceFeatures {
+        self.device.features
+    }
+
+    /// Also destroys the resources dropped during frames in flight.
     pub fn device_wait_idle(&self) -> Result<()> {
         unsafe { self.device.inner.device_wait_idle()? };
+        self.device.deletion_queue.flush(&self.device);
 
         Ok(())
     }
@@ -271,3 +349,16 @@ impl Context {
         Ok(executor_result)
     }
 }
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        if let Err(err) = self.device_wait_idle() {
+            println!("Failed to wait for the device before dropping the context: {err}");
+            // Resources of a lost device are released after its context is recreated
+            if is_device_lost(&err) {
+                return;
+            }
+        }
+        self.report_memory_leaks();
+    }
+}
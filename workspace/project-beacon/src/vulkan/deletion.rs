@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::vulkan::{device::Device, Context};
+
+/// Resources dropped while frames that may use them are still executing on the GPU.
+///
+/// Each resource is tagged with the frame being recorded when it is dropped, and destroyed once
+/// that frame completes. Before the first frame starts, resources are destroyed immediately.
+pub(crate) struct DeletionQueue {
+    state: Mutex<DeletionState>,
+}
+
+struct DeletionState {
+    frame_index: u64,
+    completed_frame_index: u64,
+    pending: VecDeque<PendingDeletion>,
+}
+
+struct PendingDeletion {
+    frame_index: u64,
+    destroy: Box<dyn FnOnce(&Device) + Send>,
+}
+
+impl DeletionQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(DeletionState {
+                frame_index: 0,
+                completed_frame_index: 0,
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Calls `destroy` once the GPU is done with the frames in flight.
+    pub(crate) fn defer(&self, device: &Device, destroy: impl FnOnce(&Device) + Send + 'static) {
+        let mut state = self.state.lock().unwrap();
+        if state.completed_frame_index >= state.frame_index {
+            drop(state);
+            destroy(device);
+            return;
+        }
+
+        let frame_index = state.frame_index;
+        state.pending.push_back(PendingDeletion {
+            frame_index,
+            destroy: Box::new(destroy),
+        });
+    }
+
+    fn begin_frame(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.frame_index += 1;
+        state.frame_index
+    }
+
+    fn complete_frame(&self, device: &Device, frame_index: u64) {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.completed_frame_index = state.completed_frame_index.max(frame_index);
+
+            // Pending deletions are ordered by frame
+            let count = state
+                .pending
+                .iter()
+                .take_while(|deletion| deletion.frame_index <= frame_index)
+                .count();
+            state.pending.drain(..count).collect::<Vec<_>>()
+        };
+
+        // Destroying can drop other resources, which defer themselves
+        for deletion in ready {
+            (deletion.destroy)(device);
+        }
+    }
+
+    /// Destroys all pending resources, the GPU must be idle.
+    pub(crate) fn flush(&self, device: &Device) {
+        let ready = std::mem::take(&mut self.state.lock().unwrap().pending);
+        for deletion in ready {
+            (deletion.destroy)(device);
+        }
+    }
+
+    fn pending_count(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+}
+
+impl Context {
+    /// Starts recording a new frame and returns its index, to pass to
+    /// [`Context::complete_frame`] once the GPU has finished executing it.
+    ///
+    /// Buffers, images, acceleration structures and pipelines dropped from then on are only
+    /// destroyed once the frame completes, so they can be replaced while earlier frames are
+    /// still in flight.
+    pub fn begin_frame(&self) -> u64 {
+        self.device.deletion_queue.begin_frame()
+    }
+
+    /// Destroys the resources dropped during frame `frame_index` and earlier frames, e.g. after
+    /// waiting for the fence of the frame. Frames must complete in order.
+    pub fn complete_frame(&self, frame_index: u64) {
+        self.device
+            .deletion_queue
+            .complete_frame(&self.device, frame_index);
+    }
+
+    /// Number of dropped resources waiting for their frame to complete.
+    pub fn pending_deletion_count(&self) -> usize {
+        self.device.deletion_queue.pending_count()
+    }
+}
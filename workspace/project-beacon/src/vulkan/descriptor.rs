@@ -3,7 +3,9 @@ use std::sync::Arc;
 use anyhow::Result;
 use ash::vk;
 
-use crate::vulkan::{device::Device, AccelerationStructure, Buffer, Context, ImageView, Sampler};
+use crate::vulkan::{
+    device::Device, AccelerationStructure, Buffer, BufferView, Context, ImageView, Sampler,
+};
 
 pub struct DescriptorSetLayout {
     device: Arc<Device>,
@@ -20,15 +22,39 @@ impl DescriptorSetLayout {
 
         Ok(Self { device, inner })
     }
+
+    pub(crate) fn new_with_binding_flags(
+        device: Arc<Device>,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        binding_flags: &[vk::DescriptorBindingFlags],
+        flags: vk::DescriptorSetLayoutCreateFlags,
+    ) -> Result<Self> {
+        assert_eq!(
+            bindings.len(),
+            binding_flags.len(),
+            "There must be one vk::DescriptorBindingFlags per binding"
+        );
+
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(binding_flags);
+        let dsl_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(flags)
+            .bindings(bindings)
+            .push_next(&mut binding_flags_info);
+        let inner = unsafe { device.inner.create_descriptor_set_layout(&dsl_info, None)? };
+
+        Ok(Self { device, inner })
+    }
 }
 
 impl Drop for DescriptorSetLayout {
     fn drop(&mut self) {
-        unsafe {
-            self.device
-                .inner
-                .destroy_descriptor_set_layout(self.inner, None);
-        }
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_descriptor_set_layout(inner, None)
+            });
     }
 }
 
@@ -42,8 +68,23 @@ impl DescriptorPool {
         device: Arc<Device>,
         max_sets: u32,
         pool_sizes: &[vk::DescriptorPoolSize],
+    ) -> Result<Self> {
+        Self::new_with_flags(
+            device,
+            vk::DescriptorPoolCreateFlags::empty(),
+            max_sets,
+            pool_sizes,
+        )
+    }
+
+    pub(crate) fn new_with_flags(
+        device: Arc<Device>,
+        flags: vk::DescriptorPoolCreateFlags,
+        max_sets: u32,
+        pool_sizes: &[vk::DescriptorPoolSize],
     ) -> Result<Self> {
         let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(flags)
             .max_sets(max_sets)
             .pool_sizes(pool_sizes);
         let inner = unsafe { device.inner.create_descriptor_pool(&pool_info, None)? };
@@ -79,11 +120,139 @@ impl DescriptorPool {
     pub fn allocate_set(&self, layout: &DescriptorSetLayout) -> Result<DescriptorSet> {
         Ok(self.allocate_sets(layout, 1)?.into_iter().next().unwrap())
     }
+
+    /// Returns all sets allocated from this pool to the pool. Those sets must not be used afterwards.
+    pub fn reset(&self) -> Result<()> {
+        unsafe {
+            self.device
+                .inner
+                .reset_descriptor_pool(self.inner, vk::DescriptorPoolResetFlags::empty())?
+        };
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DescriptorPoolSizeRatio {
+    pub ty: vk::DescriptorType,
+    /// Number of descriptors of this type reserved per set
+    pub ratio: f32,
+}
+
+/// Descriptor allocator that chains new pools whenever the current one runs out of space.
+///
+/// Meant to be used once per frame in flight: allocate the frame's sets, then call
+/// [`DescriptorAllocator::reset`] once the frame's fence has been waited on.
+pub struct DescriptorAllocator {
+    device: Arc<Device>,
+    ratios: Vec<DescriptorPoolSizeRatio>,
+    sets_per_pool: u32,
+    ready_pools: Vec<DescriptorPool>,
+    full_pools: Vec<DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    const MAX_SETS_PER_POOL: u32 = 4092;
+
+    pub(crate) fn new(
+        device: Arc<Device>,
+        initial_sets: u32,
+        ratios: &[DescriptorPoolSizeRatio],
+    ) -> Result<Self> {
+        let mut allocator = Self {
+            device,
+            ratios: ratios.to_vec(),
+            sets_per_pool: initial_sets.max(1),
+            ready_pools: vec![],
+            full_pools: vec![],
+        };
+
+        let pool = allocator.create_pool()?;
+        allocator.ready_pools.push(pool);
+
+        Ok(allocator)
+    }
+
+    /// Allocates from the last used pool. If it is exhausted, retries once in another ready pool
+    /// or a new one, then returns the error.
+    pub fn allocate(&mut self, layout: &DescriptorSetLayout) -> Result<DescriptorSet> {
+        let pool = self.take_pool()?;
+
+        match self.allocate_from(pool, layout) {
+            Err(err) if is_pool_exhausted(&err) => {
+                let pool = self.take_pool()?;
+                self.allocate_from(pool, layout)
+            }
+            result => result,
+        }
+    }
+
+    /// Resets every pool. All sets allocated so far become invalid.
+    pub fn reset(&mut self) -> Result<()> {
+        for pool in self.ready_pools.iter().chain(self.full_pools.iter()) {
+            pool.reset()?;
+        }
+        self.ready_pools.append(&mut self.full_pools);
+
+        Ok(())
+    }
+
+    pub fn pool_count(&self) -> usize {
+        self.ready_pools.len() + self.full_pools.len()
+    }
+
+    /// Puts `pool` back with the ready or the full pools, depending on the result.
+    fn allocate_from(
+        &mut self,
+        pool: DescriptorPool,
+        layout: &DescriptorSetLayout,
+    ) -> Result<DescriptorSet> {
+        let result = pool.allocate_set(layout);
+        match &result {
+            Err(err) if is_pool_exhausted(err) => self.full_pools.push(pool),
+            _ => self.ready_pools.push(pool),
+        }
+
+        result
+    }
+
+    fn take_pool(&mut self) -> Result<DescriptorPool> {
+        match self.ready_pools.pop() {
+            Some(pool) => Ok(pool),
+            None => {
+                // The ready pools ran out, the new one is bigger
+                self.sets_per_pool =
+                    (self.sets_per_pool + self.sets_per_pool / 2).min(Self::MAX_SETS_PER_POOL);
+
+                self.create_pool()
+            }
+        }
+    }
+
+    fn create_pool(&self) -> Result<DescriptorPool> {
+        let pool_sizes = self
+            .ratios
+            .iter()
+            .map(|r| vk::DescriptorPoolSize {
+                ty: r.ty,
+                descriptor_count: ((r.ratio * self.sets_per_pool as f32).ceil() as u32).max(1),
+            })
+            .collect::<Vec<_>>();
+
+        DescriptorPool::new(self.device.clone(), self.sets_per_pool, &pool_sizes)
+    }
 }
 
 impl Drop for DescriptorPool {
     fn drop(&mut self) {
-        unsafe { self.device.inner.destroy_descriptor_pool(self.inner, None) };
+        // Frees the sets of the pool, frames in flight may still use them
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_descriptor_pool(inner, None)
+            });
     }
 }
 
@@ -96,94 +265,248 @@ impl DescriptorSet {
     pub fn update(&self, writes: &[WriteDescriptorSet]) {
         use WriteDescriptorSetKind::*;
 
-        // these Vec are here to keep structure internal to WriteDescriptorSet (DescriptorImageInfo, DescriptorBufferInfo, ...) alive
+        // these Vec are here to keep structure internal to WriteDescriptorSet (DescriptorImageInfo, DescriptorBufferInfo, ...) alive.
+        // They are all filled before any vk::WriteDescriptorSet is built so pushing to them never invalidates a pointer.
         let mut img_infos = vec![];
         let mut buffer_infos = vec![];
-        let mut as_infos = vec![];
+        let mut texel_buffer_views = vec![];
+        let mut as_handles = vec![];
+
+        let image_info = |view: &ImageView, layout| {
+            vk::DescriptorImageInfo::builder()
+                .image_view(view.inner)
+                .image_layout(layout)
+                .build()
+        };
+        let buffer_info = |buffer: &Buffer, offset, range| {
+            vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.inner)
+                .offset(offset)
+                .range(range)
+                .build()
+        };
 
-        let descriptor_writes = writes
+        let payloads = writes
             .iter()
-            .map(|write| match write.kind {
-                StorageImage { view, layout } => {
-                    let img_info = vk::DescriptorImageInfo::builder()
-                        .image_view(view.inner)
-                        .image_layout(layout);
-
-                    img_infos.push(img_info);
-
-                    vk::WriteDescriptorSet::builder()
-                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                        .dst_binding(write.binding)
-                        .dst_set(self.inner)
-                        .image_info(std::slice::from_ref(img_infos.last().unwrap()))
-                        .build()
-                }
-                AccelerationStructure {
-                    acceleration_structure,
-                } => {
-                    let write_set_as = vk::WriteDescriptorSetAccelerationStructureKHR::builder()
-                        .acceleration_structures(std::slice::from_ref(
-                            &acceleration_structure.inner,
-                        ));
-
-                    as_infos.push(write_set_as);
-
-                    let mut write = vk::WriteDescriptorSet::builder()
-                        .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                        .dst_binding(write.binding)
-                        .dst_set(self.inner)
-                        .push_next(as_infos.last_mut().unwrap())
-                        .build();
-                    write.descriptor_count = 1;
-
-                    write
-                }
-                UniformBuffer { buffer } => {
-                    let buffer_info = vk::DescriptorBufferInfo::builder()
-                        .buffer(buffer.inner)
-                        .range(vk::WHOLE_SIZE);
-
-                    buffer_infos.push(buffer_info);
-
-                    vk::WriteDescriptorSet::builder()
-                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                        .dst_binding(write.binding)
-                        .dst_set(self.inner)
-                        .buffer_info(std::slice::from_ref(buffer_infos.last().unwrap()))
-                        .build()
-                }
-                StorageBuffer { buffer } => {
-                    let buffer_info = vk::DescriptorBufferInfo::builder()
-                        .buffer(buffer.inner)
-                        .range(vk::WHOLE_SIZE);
-
-                    buffer_infos.push(buffer_info);
-
-                    vk::WriteDescriptorSet::builder()
-                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                        .dst_binding(write.binding)
-                        .dst_set(self.inner)
-                        .buffer_info(std::slice::from_ref(buffer_infos.last().unwrap()))
-                        .build()
-                }
-                CombinedImageSampler {
-                    view,
-                    sampler,
-                    layout,
-                } => {
-                    let img_info = vk::DescriptorImageInfo::builder()
-                        .image_view(view.inner)
-                        .sampler(sampler.inner)
-                        .image_layout(layout);
-
-                    img_infos.push(img_info);
-
-                    vk::WriteDescriptorSet::builder()
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .dst_binding(write.binding)
-                        .dst_set(self.inner)
-                        .image_info(std::slice::from_ref(img_infos.last().unwrap()))
-                        .build()
+            .map(|write| {
+                let (descriptor_type, payload) =
+                    match write.kind {
+                        StorageImage { view, layout } => {
+                            img_infos.push(image_info(view, layout));
+                            (vk::DescriptorType::STORAGE_IMAGE, Payload::Image(1))
+                        }
+                        StorageImageArray { views, layout } => {
+                            img_infos.extend(views.iter().map(|v| image_info(v, layout)));
+                            (
+                                vk::DescriptorType::STORAGE_IMAGE,
+                                Payload::Image(views.len()),
+                            )
+                        }
+                        AccelerationStructure {
+                            acceleration_structure,
+                        } => {
+                            as_handles.push(acceleration_structure.inner);
+                            (
+                                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                                Payload::AccelerationStructure(1),
+                            )
+                        }
+                        AccelerationStructureArray {
+                            acceleration_structures,
+                        } => {
+                            as_handles.extend(acceleration_structures.iter().map(|a| a.inner));
+                            (
+                                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                                Payload::AccelerationStructure(acceleration_structures.len()),
+                            )
+                        }
+                        UniformBuffer { buffer } => {
+                            buffer_infos.push(buffer_info(buffer, 0, vk::WHOLE_SIZE));
+                            (vk::DescriptorType::UNIFORM_BUFFER, Payload::Buffer(1))
+                        }
+                        UniformBufferRange {
+                            buffer,
+                            offset,
+                            range,
+                        } => {
+                            buffer_infos.push(buffer_info(buffer, offset, range));
+                            (vk::DescriptorType::UNIFORM_BUFFER, Payload::Buffer(1))
+                        }
+                        UniformBufferDynamic { buffer, range } => {
+                            buffer_infos.push(buffer_info(buffer, 0, range));
+                            (
+                                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                                Payload::Buffer(1),
+                            )
+                        }
+                        UniformBufferArray { buffers } => {
+                            buffer_infos
+                                .extend(buffers.iter().map(|b| buffer_info(b, 0, vk::WHOLE_SIZE)));
+                            (
+                                vk::DescriptorType::UNIFORM_BUFFER,
+                                Payload::Buffer(buffers.len()),
+                            )
+                        }
+                        StorageBuffer { buffer } => {
+                            buffer_infos.push(buffer_info(buffer, 0, vk::WHOLE_SIZE));
+                            (vk::DescriptorType::STORAGE_BUFFER, Payload::Buffer(1))
+                        }
+                        StorageBufferRange {
+                            buffer,
+                            offset,
+                            range,
+                        } => {
+                            buffer_infos.push(buffer_info(buffer, offset, range));
+                            (vk::DescriptorType::STORAGE_BUFFER, Payload::Buffer(1))
+                        }
+                        StorageBufferDynamic { buffer, range } => {
+                            buffer_infos.push(buffer_info(buffer, 0, range));
+                            (
+                                vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                                Payload::Buffer(1),
+                            )
+                        }
+                        StorageBufferArray { buffers } => {
+                            buffer_infos
+                                .extend(buffers.iter().map(|b| buffer_info(b, 0, vk::WHOLE_SIZE)));
+                            (
+                                vk::DescriptorType::STORAGE_BUFFER,
+                                Payload::Buffer(buffers.len()),
+                            )
+                        }
+                        UniformTexelBuffer { view } => {
+                            texel_buffer_views.push(view.inner);
+                            (
+                                vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                                Payload::TexelBuffer(1),
+                            )
+                        }
+                        StorageTexelBuffer { view } => {
+                            texel_buffer_views.push(view.inner);
+                            (
+                                vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                                Payload::TexelBuffer(1),
+                            )
+                        }
+                        SampledImage { view, layout } => {
+                            img_infos.push(image_info(view, layout));
+                            (vk::DescriptorType::SAMPLED_IMAGE, Payload::Image(1))
+                        }
+                        SampledImageArray { views, layout } => {
+                            img_infos.extend(views.iter().map(|v| image_info(v, layout)));
+                            (
+                                vk::DescriptorType::SAMPLED_IMAGE,
+                                Payload::Image(views.len()),
+                            )
+                        }
+                        Sampler { sampler } => {
+                            img_infos.push(
+                                vk::DescriptorImageInfo::builder()
+                                    .sampler(sampler.inner)
+                                    .build(),
+                            );
+                            (vk::DescriptorType::SAMPLER, Payload::Image(1))
+                        }
+                        SamplerArray { samplers } => {
+                            img_infos.extend(samplers.iter().map(|s| {
+                                vk::DescriptorImageInfo::builder().sampler(s.inner).build()
+                            }));
+                            (vk::DescriptorType::SAMPLER, Payload::Image(samplers.len()))
+                        }
+                        CombinedImageSampler {
+                            view,
+                            sampler,
+                            layout,
+                        } => {
+                            img_infos.push(vk::DescriptorImageInfo {
+                                sampler: sampler.inner,
+                                ..image_info(view, layout)
+                            });
+                            (
+                                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                                Payload::Image(1),
+                            )
+                        }
+                        CombinedImageSamplerArray { images, layout } => {
+                            img_infos.extend(images.iter().map(|(view, sampler)| {
+                                vk::DescriptorImageInfo {
+                                    sampler: sampler.inner,
+                                    ..image_info(view, layout)
+                                }
+                            }));
+                            (
+                                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                                Payload::Image(images.len()),
+                            )
+                        }
+                        InputAttachment { view, layout } => {
+                            img_infos.push(image_info(view, layout));
+                            (vk::DescriptorType::INPUT_ATTACHMENT, Payload::Image(1))
+                        }
+                    };
+
+                (write, descriptor_type, payload)
+            })
+            .collect::<Vec<_>>();
+
+        let mut as_infos = payloads
+            .iter()
+            .scan(0, |as_offset, (_, _, payload)| {
+                Some(match *payload {
+                    Payload::AccelerationStructure(count) => {
+                        let handles = &as_handles[*as_offset..*as_offset + count];
+                        *as_offset += count;
+                        Some(
+                            vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+                                .acceleration_structures(handles)
+                                .build(),
+                        )
+                    }
+                    _ => None,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut img_offset = 0;
+        let mut buffer_offset = 0;
+        let mut texel_offset = 0;
+        let descriptor_writes = payloads
+            .iter()
+            .zip(as_infos.iter_mut())
+            .map(|((write, descriptor_type, payload), as_info)| {
+                let builder = vk::WriteDescriptorSet::builder()
+                    .descriptor_type(*descriptor_type)
+                    .dst_binding(write.binding)
+                    .dst_array_element(write.array_element)
+                    .dst_set(self.inner);
+
+                match *payload {
+                    Payload::Image(count) => {
+                        img_offset += count;
+                        builder
+                            .image_info(&img_infos[img_offset - count..img_offset])
+                            .build()
+                    }
+                    Payload::Buffer(count) => {
+                        buffer_offset += count;
+                        builder
+                            .buffer_info(&buffer_infos[buffer_offset - count..buffer_offset])
+                            .build()
+                    }
+                    Payload::TexelBuffer(count) => {
+                        texel_offset += count;
+                        builder
+                            .texel_buffer_view(
+                                &texel_buffer_views[texel_offset - count..texel_offset],
+                            )
+                            .build()
+                    }
+                    Payload::AccelerationStructure(count) => {
+                        let mut write = builder.push_next(as_info.as_mut().unwrap()).build();
+                        write.descriptor_count = count as _;
+
+                        write
+                    }
                 }
             })
             .collect::<Vec<_>>();
@@ -196,6 +519,22 @@ impl DescriptorSet {
     }
 }
 
+// Number of descriptor infos a single write consumes from each storage Vec
+#[derive(Clone, Copy)]
+enum Payload {
+    Image(usize),
+    Buffer(usize),
+    TexelBuffer(usize),
+    AccelerationStructure(usize),
+}
+
+fn is_pool_exhausted(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<vk::Result>(),
+        Some(&vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Some(&vk::Result::ERROR_FRAGMENTED_POOL)
+    )
+}
+
 impl Context {
     pub fn create_descriptor_set_layout(
         &self,
@@ -204,6 +543,20 @@ impl Context {
         DescriptorSetLayout::new(self.device.clone(), bindings)
     }
 
+    pub fn create_descriptor_set_layout_with_binding_flags(
+        &self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        binding_flags: &[vk::DescriptorBindingFlags],
+        flags: vk::DescriptorSetLayoutCreateFlags,
+    ) -> Result<DescriptorSetLayout> {
+        DescriptorSetLayout::new_with_binding_flags(
+            self.device.clone(),
+            bindings,
+            binding_flags,
+            flags,
+        )
+    }
+
     pub fn create_descriptor_pool(
         &self,
         max_sets: u32,
@@ -211,11 +564,29 @@ impl Context {
     ) -> Result<DescriptorPool> {
         DescriptorPool::new(self.device.clone(), max_sets, pool_sizes)
     }
+
+    pub fn create_descriptor_allocator(
+        &self,
+        initial_sets: u32,
+        ratios: &[DescriptorPoolSizeRatio],
+    ) -> Result<DescriptorAllocator> {
+        DescriptorAllocator::new(self.device.clone(), initial_sets, ratios)
+    }
+
+    pub fn create_descriptor_pool_with_flags(
+        &self,
+        flags: vk::DescriptorPoolCreateFlags,
+        max_sets: u32,
+        pool_sizes: &[vk::DescriptorPoolSize],
+    ) -> Result<DescriptorPool> {
+        DescriptorPool::new_with_flags(self.device.clone(), flags, max_sets, pool_sizes)
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct WriteDescriptorSet<'a> {
     pub binding: u32,
+    pub array_element: u32,
     pub kind: WriteDescriptorSetKind<'a>,
 }
 
@@ -225,18 +596,77 @@ pub enum WriteDescriptorSetKind<'a> {
         view: &'a ImageView,
         layout: vk::ImageLayout,
     },
+    StorageImageArray {
+        views: &'a [&'a ImageView],
+        layout: vk::ImageLayout,
+    },
     AccelerationStructure {
         acceleration_structure: &'a AccelerationStructure,
     },
+    AccelerationStructureArray {
+        acceleration_structures: &'a [&'a AccelerationStructure],
+    },
     UniformBuffer {
         buffer: &'a Buffer,
     },
+    UniformBufferRange {
+        buffer: &'a Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    },
+    UniformBufferDynamic {
+        buffer: &'a Buffer,
+        range: vk::DeviceSize,
+    },
+    UniformBufferArray {
+        buffers: &'a [&'a Buffer],
+    },
     StorageBuffer {
         buffer: &'a Buffer,
     },
+    StorageBufferRange {
+        buffer: &'a Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    },
+    StorageBufferDynamic {
+        buffer: &'a Buffer,
+        range: vk::DeviceSize,
+    },
+    StorageBufferArray {
+        buffers: &'a [&'a Buffer],
+    },
+    UniformTexelBuffer {
+        view: &'a BufferView,
+    },
+    StorageTexelBuffer {
+        view: &'a BufferView,
+    },
+    SampledImage {
+        view: &'a ImageView,
+        layout: vk::ImageLayout,
+    },
+    SampledImageArray {
+        views: &'a [&'a ImageView],
+        layout: vk::ImageLayout,
+    },
+    Sampler {
+        sampler: &'a Sampler,
+    },
+    SamplerArray {
+        samplers: &'a [&'a Sampler],
+    },
     CombinedImageSampler {
         view: &'a ImageView,
         sampler: &'a Sampler,
         layout: vk::ImageLayout,
     },
+    CombinedImageSamplerArray {
+        images: &'a [(&'a ImageView, &'a Sampler)],
+        layout: vk::ImageLayout,
+    },
+    InputAttachment {
+        view: &'a ImageView,
+        layout: vk::ImageLayout,
+    },
 }
@@ -1,16 +1,27 @@
-use std::{ffi::CString, sync::Arc};
+use std::{ffi::CString, mem, sync::Arc};
 
 use anyhow::Result;
 use ash::{vk, Device as AshDevice};
 
 use crate::vulkan::{
+    deletion::DeletionQueue,
+    diagnostics::{Checkpoints, DEVICE_FAULT_EXTENSION, DIAGNOSTIC_CHECKPOINTS_EXTENSION},
     instance::Instance,
+    memory::MemoryTracker,
     physical_device::PhysicalDevice,
     queue::{Queue, QueueFamily},
 };
 
 pub struct Device {
     pub inner: AshDevice,
+    pub(crate) features: DeviceFeatures,
+    pub(crate) extensions: Vec<String>,
+    pub(crate) memory: MemoryTracker,
+    pub(crate) deletion_queue: DeletionQueue,
+    /// Loaded if the `VK_EXT_device_fault` extension is enabled.
+    pub(crate) fault_fn: Option<vk::ExtDeviceFaultFn>,
+    /// Loaded if the `VK_NV_device_diagnostic_checkpoints` extension is enabled.
+    pub(crate) checkpoints: Option<Checkpoints>,
 }
 
 impl Device {
@@ -18,7 +29,7 @@ impl Device {
         instance: &Instance,
         physical_device: &PhysicalDevice,
         queue_families: &[QueueFamily],
-        required_extensions: &[&str],
+        extensions: &[&str],
         device_features: &DeviceFeatures,
     ) -> Result<Self> {
         let queue_priorities = [1.0f32];
@@ -38,7 +49,7 @@ impl Device {
                 .collect::<Vec<_>>()
         };
 
-        let device_extensions_ptrs = required_extensions
+        let device_extensions_ptrs = extensions
             .iter()
             .map(|e| CString::new(*e))
             .collect::<Result<Vec<_>, _>>()?;
@@ -52,19 +63,45 @@ impl Device {
         let mut acceleration_struct_feature =
             vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
                 .acceleration_structure(device_features.acceleration_structure);
+        let mut ray_query_feature =
+            vk::PhysicalDeviceRayQueryFeaturesKHR::builder().ray_query(device_features.ray_query);
+        let descriptor_indexing = device_features.descriptor_indexing;
         let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
-            .runtime_descriptor_array(device_features.runtime_descriptor_array)
-            .buffer_device_address(device_features.buffer_device_address);
+            .runtime_descriptor_array(
+                device_features.runtime_descriptor_array || descriptor_indexing,
+            )
+            .buffer_device_address(device_features.buffer_device_address)
+            .descriptor_indexing(descriptor_indexing)
+            .shader_sampled_image_array_non_uniform_indexing(descriptor_indexing)
+            .shader_storage_buffer_array_non_uniform_indexing(descriptor_indexing)
+            .descriptor_binding_sampled_image_update_after_bind(descriptor_indexing)
+            .descriptor_binding_storage_buffer_update_after_bind(descriptor_indexing)
+            .descriptor_binding_update_unused_while_pending(descriptor_indexing)
+            .descriptor_binding_partially_bound(descriptor_indexing);
         let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::builder()
             .dynamic_rendering(device_features.dynamic_rendering)
             .synchronization2(device_features.synchronization2);
 
+        let core_features = vk::PhysicalDeviceFeatures::builder()
+            .occlusion_query_precise(device_features.occlusion_query_precise)
+            .pipeline_statistics_query(device_features.pipeline_statistics_query)
+            .sampler_anisotropy(device_features.sampler_anisotropy)
+            .build();
+
+        let has_device_fault = extensions.contains(&DEVICE_FAULT_EXTENSION);
+        // Required to be supported with the extension
+        let mut fault_feature = vk::PhysicalDeviceFaultFeaturesEXT::builder().device_fault(true);
+
         let mut features = vk::PhysicalDeviceFeatures2::builder()
-            .features(vk::PhysicalDeviceFeatures::default())
+            .features(core_features)
             .push_next(&mut acceleration_struct_feature)
             .push_next(&mut ray_tracing_feature)
+            .push_next(&mut ray_query_feature)
             .push_next(&mut vulkan_12_features)
             .push_next(&mut vulkan_13_features);
+        if has_device_fault {
+            features = features.push_next(&mut fault_feature);
+        }
 
         let device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
@@ -77,7 +114,34 @@ impl Device {
                 .create_device(physical_device.inner, &device_create_info, None)?
         };
 
-        Ok(Self { inner })
+        let memory_properties = unsafe {
+            instance
+                .inner
+                .get_physical_device_memory_properties(physical_device.inner)
+        };
+
+        let fault_fn = has_device_fault.then(|| {
+            vk::ExtDeviceFaultFn::load(|name| unsafe {
+                mem::transmute(
+                    instance
+                        .inner
+                        .get_device_proc_addr(inner.handle(), name.as_ptr()),
+                )
+            })
+        });
+        let checkpoints = extensions
+            .contains(&DIAGNOSTIC_CHECKPOINTS_EXTENSION)
+            .then(|| Checkpoints::new(&instance.inner, &inner));
+
+        Ok(Self {
+            inner,
+            features: *device_features,
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            memory: MemoryTracker::new(memory_properties),
+            deletion_queue: DeletionQueue::new(),
+            fault_fn,
+            checkpoints,
+        })
     }
 
     pub fn get_queue(self: &Arc<Self>, queue_family: QueueFamily, queue_index: u32) -> Queue {
@@ -88,29 +152,119 @@ impl Device {
 
 impl Drop for Device {
     fn drop(&mut self) {
+        self.deletion_queue.flush(self);
         unsafe {
             self.inner.destroy_device(None);
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct DeviceFeatures {
     pub ray_tracing_pipeline: bool,
     pub acceleration_structure: bool,
+    /// `rayQueryEXT` in any shader stage, requires the `VK_KHR_ray_query` extension.
+    pub ray_query: bool,
     pub runtime_descriptor_array: bool,
+    pub descriptor_indexing: bool,
     pub buffer_device_address: bool,
     pub dynamic_rendering: bool,
     pub synchronization2: bool,
+    /// Sample counts from occlusion queries, instead of only whether any sample passed.
+    pub occlusion_query_precise: bool,
+    pub pipeline_statistics_query: bool,
+    /// Anisotropic filtering, see [`SamplerDesc::anisotropy`](crate::vulkan::SamplerDesc::anisotropy).
+    pub sampler_anisotropy: bool,
 }
 
 impl DeviceFeatures {
+    /// Features enabled in either `self` or `other`.
+    pub fn union(&self, other: &Self) -> Self {
+        self.zip_with(other, |a, b| a || b)
+    }
+
+    /// Features enabled in both `self` and `other`.
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.zip_with(other, |a, b| a && b)
+    }
+
+    /// Disables the features of extensions missing from `extensions`.
+    pub(crate) fn with_extensions(&self, extensions: &[&str]) -> Self {
+        let has_extension = |name| extensions.contains(&name);
+        Self {
+            ray_tracing_pipeline: self.ray_tracing_pipeline
+                && has_extension("VK_KHR_ray_tracing_pipeline"),
+            acceleration_structure: self.acceleration_structure
+                && has_extension("VK_KHR_acceleration_structure"),
+            ray_query: self.ray_query && has_extension("VK_KHR_ray_query"),
+            ..*self
+        }
+    }
+
+    fn zip_with(&self, other: &Self, f: impl Fn(bool, bool) -> bool) -> Self {
+        Self {
+            ray_tracing_pipeline: f(self.ray_tracing_pipeline, other.ray_tracing_pipeline),
+            acceleration_structure: f(self.acceleration_structure, other.acceleration_structure),
+            ray_query: f(self.ray_query, other.ray_query),
+            runtime_descriptor_array: f(
+                self.runtime_descriptor_array,
+                other.runtime_descriptor_array,
+            ),
+            descriptor_indexing: f(self.descriptor_indexing, other.descriptor_indexing),
+            buffer_device_address: f(self.buffer_device_address, other.buffer_device_address),
+            dynamic_rendering: f(self.dynamic_rendering, other.dynamic_rendering),
+            synchronization2: f(self.synchronization2, other.synchronization2),
+            occlusion_query_precise: f(self.occlusion_query_precise, other.occlusion_query_precise),
+            pipeline_statistics_query: f(
+                self.pipeline_statistics_query,
+                other.pipeline_statistics_query,
+            ),
+            sampler_anisotropy: f(self.sampler_anisotropy, other.sampler_anisotropy),
+        }
+    }
+
     pub fn is_compatible_with(&self, requirements: &Self) -> bool {
         (!requirements.ray_tracing_pipeline || self.ray_tracing_pipeline)
             && (!requirements.acceleration_structure || self.acceleration_structure)
+            && (!requirements.ray_query || self.ray_query)
             && (!requirements.runtime_descriptor_array || self.runtime_descriptor_array)
+            && (!requirements.descriptor_indexing || self.descriptor_indexing)
             && (!requirements.buffer_device_address || self.buffer_device_address)
             && (!requirements.dynamic_rendering || self.dynamic_rendering)
             && (!requirements.synchronization2 || self.synchronization2)
+            && (!requirements.occlusion_query_precise || self.occlusion_query_precise)
+            && (!requirements.pipeline_statistics_query || self.pipeline_statistics_query)
+            && (!requirements.sampler_anisotropy || self.sampler_anisotropy)
     }
 }
+
+#[test]
+fn test_optional_device_features() {
+    let supported = DeviceFeatures {
+        descriptor_indexing: true,
+        ray_query: true,
+        synchronization2: true,
+        ..Default::default()
+    };
+    let required = DeviceFeatures {
+        synchronization2: true,
+        ..Default::default()
+    };
+    let optional = DeviceFeatures {
+        descriptor_indexing: true,
+        ray_query: true,
+        sampler_anisotropy: true,
+        ..Default::default()
+    };
+
+    let enabled = required.union(&optional.intersection(&supported).with_extensions(&[]));
+    assert_eq!(
+        enabled,
+        DeviceFeatures {
+            descriptor_indexing: true,
+            synchronization2: true,
+            ..Default::default()
+        }
+    );
+    assert!(supported.is_compatible_with(&enabled));
+}
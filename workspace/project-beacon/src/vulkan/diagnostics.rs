@@ -0,0 +1,291 @@
+use std::ffi::{c_char, c_void, CStr};
+use std::fmt;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use ash::{extensions::nv::DeviceDiagnosticCheckpoints, vk, Device as AshDevice, Instance};
+
+use crate::vulkan::{Context, Queue};
+
+pub(crate) const DEVICE_FAULT_EXTENSION: &str = "VK_EXT_device_fault";
+pub(crate) const DIAGNOSTIC_CHECKPOINTS_EXTENSION: &str = "VK_NV_device_diagnostic_checkpoints";
+
+/// Whether `error` is a `vk::Result::ERROR_DEVICE_LOST`, e.g. after a GPU hang or a driver
+/// reset. Objects of the lost device can only be destroyed, a new context must be created.
+pub fn is_device_lost(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<vk::Result>() == Some(&vk::Result::ERROR_DEVICE_LOST)
+}
+
+/// Named markers written in command buffers, with the `VK_NV_device_diagnostic_checkpoints`
+/// extension.
+pub(crate) struct Checkpoints {
+    inner: DeviceDiagnosticCheckpoints,
+    names: CheckpointNames,
+}
+
+impl Checkpoints {
+    pub(crate) fn new(instance: &Instance, device: &AshDevice) -> Self {
+        Self {
+            inner: DeviceDiagnosticCheckpoints::new(instance, device),
+            names: CheckpointNames::default(),
+        }
+    }
+
+    pub(crate) fn set_checkpoint(&self, cmd_buffer: vk::CommandBuffer, name: &str) {
+        let marker = self.names.marker(name);
+        unsafe { self.inner.cmd_set_checkpoint(cmd_buffer, marker) };
+    }
+}
+
+/// Markers are indices in the names, offset by one so that none is null.
+#[derive(Default)]
+struct CheckpointNames(Mutex<Vec<String>>);
+
+impl CheckpointNames {
+    fn marker(&self, name: &str) -> *const c_void {
+        let mut names = self.0.lock().unwrap();
+        let index = names.iter().position(|n| n == name).unwrap_or_else(|| {
+            names.push(name.to_owned());
+            names.len() - 1
+        });
+
+        (index + 1) as *const c_void
+    }
+
+    fn name(&self, marker: *mut c_void) -> String {
+        (marker as usize)
+            .checked_sub(1)
+            .and_then(|index| self.0.lock().unwrap().get(index).cloned())
+            .unwrap_or_else(|| "unknown".to_owned())
+    }
+}
+
+/// What is known about a device loss. Empty if no diagnostic extension is enabled.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceLostReport {
+    /// From `VK_EXT_device_fault`.
+    pub fault: Option<DeviceFaultInfo>,
+    /// Last checkpoints reached by the graphics queue, from
+    /// `VK_NV_device_diagnostic_checkpoints`.
+    pub checkpoints: Vec<QueueCheckpoint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceFaultInfo {
+    pub description: String,
+    pub addresses: Vec<DeviceFaultAddress>,
+    pub vendor_infos: Vec<DeviceFaultVendorInfo>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceFaultAddress {
+    pub address_type: vk::DeviceFaultAddressTypeEXT,
+    pub address: vk::DeviceAddress,
+    /// The fault is within `address` rounded down and up to this power of two.
+    pub precision: vk::DeviceSize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceFaultVendorInfo {
+    pub description: String,
+    pub code: u64,
+    pub data: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueCheckpoint {
+    pub name: String,
+    /// Stage of the commands after the checkpoint the queue had reached.
+    pub stage: vk::PipelineStageFlags,
+}
+
+impl Context {
+    /// Fault information of the lost device, `None` without the `VK_EXT_device_fault`
+    /// extension.
+    pub fn device_fault_info(&self) -> Result<Option<DeviceFaultInfo>> {
+        let Some(fault_fn) = &self.device.fault_fn else {
+            return Ok(None);
+        };
+        let device = self.device.inner.handle();
+
+        let mut counts = vk::DeviceFaultCountsEXT::default();
+        unsafe {
+            (fault_fn.get_device_fault_info_ext)(device, &mut counts, std::ptr::null_mut())
+                .result()?
+        };
+
+        let mut addresses =
+            vec![vk::DeviceFaultAddressInfoEXT::default(); counts.address_info_count as _];
+        let mut vendor_infos =
+            vec![vk::DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as _];
+        // The vendor binary is not read
+        counts.vendor_binary_size = 0;
+        let mut info = vk::DeviceFaultInfoEXT {
+            p_address_infos: addresses.as_mut_ptr(),
+            p_vendor_infos: vendor_infos.as_mut_ptr(),
+            ..Default::default()
+        };
+        let result =
+            unsafe { (fault_fn.get_device_fault_info_ext)(device, &mut counts, &mut info) };
+        if result != vk::Result::INCOMPLETE {
+            result.result()?;
+        }
+
+        addresses.truncate(counts.address_info_count as _);
+        vendor_infos.truncate(counts.vendor_info_count as _);
+
+        Ok(Some(DeviceFaultInfo {
+            description: c_string(&info.description),
+            addresses: addresses
+                .iter()
+                .map(|address| DeviceFaultAddress {
+                    address_type: address.address_type,
+                    address: address.reported_address,
+                    precision: address.address_precision,
+                })
+                .collect(),
+            vendor_infos: vendor_infos
+                .iter()
+                .map(|vendor_info| DeviceFaultVendorInfo {
+                    description: c_string(&vendor_info.description),
+                    code: vendor_info.vendor_fault_code,
+                    data: vendor_info.vendor_fault_data,
+                })
+                .collect(),
+        }))
+    }
+
+    /// Last checkpoints reached by `queue`, empty without the
+    /// `VK_NV_device_diagnostic_checkpoints` extension.
+    pub fn queue_checkpoints(&self, queue: &Queue) -> Vec<QueueCheckpoint> {
+        let Some(checkpoints) = &self.device.checkpoints else {
+            return vec![];
+        };
+
+        let mut data = unsafe {
+            vec![
+                vk::CheckpointDataNV::default();
+                checkpoints.inner.get_queue_checkpoint_data_len(queue.inner)
+            ]
+        };
+        unsafe {
+            checkpoints
+                .inner
+                .get_queue_checkpoint_data(queue.inner, &mut data)
+        };
+
+        data.iter()
+            .map(|checkpoint| QueueCheckpoint {
+                name: checkpoints.names.name(checkpoint.p_checkpoint_marker),
+                stage: checkpoint.stage,
+            })
+            .collect()
+    }
+
+    /// Gathers the diagnostics of the enabled extensions, after the device was lost.
+    pub fn device_lost_report(&self) -> DeviceLostReport {
+        let fault = self.device_fault_info().unwrap_or_else(|err| {
+            println!("Failed to get device fault info: {err}");
+            None
+        });
+
+        DeviceLostReport {
+            fault,
+            checkpoints: self.queue_checkpoints(&self.graphics_queue),
+        }
+    }
+}
+
+impl fmt::Display for DeviceLostReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fault.is_none() && self.checkpoints.is_empty() {
+            return writeln!(f, "No device lost diagnostics available");
+        }
+
+        if let Some(fault) = &self.fault {
+            writeln!(f, "Device fault: {}", fault.description)?;
+            for address in &fault.addresses {
+                writeln!(
+                    f,
+                    "  {:?} at {:#x} (precision {:#x})",
+                    address.address_type, address.address, address.precision
+                )?;
+            }
+            for vendor_info in &fault.vendor_infos {
+                writeln!(
+                    f,
+                    "  {} (code {:#x}, data {:#x})",
+                    vendor_info.description, vendor_info.code, vendor_info.data
+                )?;
+            }
+        }
+
+        for checkpoint in &self.checkpoints {
+            writeln!(
+                f,
+                "Checkpoint {} reached {:?}",
+                checkpoint.name, checkpoint.stage
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn c_string(chars: &[c_char]) -> String {
+    unsafe { CStr::from_ptr(chars.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn test_checkpoint_names() {
+    let names = CheckpointNames::default();
+    let gbuffer = names.marker("gbuffer");
+    let lighting = names.marker("lighting");
+
+    assert!(!gbuffer.is_null());
+    assert_ne!(gbuffer, lighting);
+    assert_eq!(names.marker("gbuffer"), gbuffer);
+    assert_eq!(names.name(lighting as *mut c_void), "lighting");
+    assert_eq!(names.name(gbuffer as *mut c_void), "gbuffer");
+    assert_eq!(names.name(std::ptr::null_mut()), "unknown");
+    assert_eq!(names.name(16 as *mut c_void), "unknown");
+}
+
+#[test]
+fn test_device_lost_report_display() {
+    assert_eq!(
+        DeviceLostReport::default().to_string(),
+        "No device lost diagnostics available\n"
+    );
+
+    let report = DeviceLostReport {
+        fault: Some(DeviceFaultInfo {
+            description: "Page fault".to_owned(),
+            addresses: vec![DeviceFaultAddress {
+                address_type: vk::DeviceFaultAddressTypeEXT::READ_INVALID,
+                address: 0x1000,
+                precision: 0x100,
+            }],
+            vendor_infos: vec![DeviceFaultVendorInfo {
+                description: "MMU fault".to_owned(),
+                code: 0x2a,
+                data: 0xff,
+            }],
+        }),
+        checkpoints: vec![QueueCheckpoint {
+            name: "lighting".to_owned(),
+            stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        }],
+    };
+    assert_eq!(
+        report.to_string(),
+        concat!(
+            "Device fault: Page fault\n",
+            "  READ_INVALID at 0x1000 (precision 0x100)\n",
+            "  MMU fault (code 0x2a, data 0xff)\n",
+            "Checkpoint lighting reached COMPUTE_SHADER\n",
+        )
+    );
+}
@@ -14,8 +14,11 @@ pub struct Image {
     allocator: Arc<Mutex<Allocator>>,
     pub(crate) inner: vk::Image,
     allocation: Option<Allocation>,
+    /// Id of the allocation in the memory tracker of the device.
+    allocation_id: Option<u64>,
     pub format: vk::Format,
     pub extent: vk::Extent3D,
+    pub samples: vk::SampleCountFlags,
     is_swapchain: bool, // if set, image should not be destroyed
 }
 
@@ -31,12 +34,12 @@ impl Image {
         usage: vk::ImageUsageFlags,
         memory_location: MemoryLocation,
         format: vk::Format,
-        width: u32,
-        height: u32,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
     ) -> Result<Self> {
         let extent = vk::Extent3D {
-            width,
-            height,
+            width: extent.width,
+            height: extent.height,
             depth: 1,
         };
 
@@ -46,7 +49,7 @@ impl Image {
             .extent(extent)
             .mip_levels(1)
             .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(usage)
             .initial_layout(vk::ImageLayout::UNDEFINED);
@@ -67,14 +70,20 @@ impl Image {
                 .inner
                 .bind_image_memory(inner, allocation.memory(), allocation.offset())?
         };
+        let allocation_id =
+            device
+                .memory
+                .track("image", &allocation, requirements.memory_type_bits);
 
         Ok(Self {
             device,
             allocator,
             inner,
             allocation: Some(allocation),
+            allocation_id: Some(allocation_id),
             format,
             extent,
+            samples,
             is_swapchain: false,
         })
     }
@@ -97,19 +106,52 @@ impl Image {
             allocator,
             inner: swapchain_image,
             allocation: None,
+            allocation_id: None,
             format,
             extent,
+            samples: vk::SampleCountFlags::TYPE_1,
             is_swapchain: true,
         }
     }
 
+    pub fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        match self.format {
+            vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+                vk::ImageAspectFlags::DEPTH
+            }
+            vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+            vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT => {
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            }
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    /// Names the allocation of the image, in memory reports and leak messages. Does nothing
+    /// for swapchain images.
+    pub fn set_name(&mut self, name: &str) -> Result<()> {
+        let (Some(allocation), Some(id)) = (self.allocation.as_mut(), self.allocation_id) else {
+            return Ok(());
+        };
+
+        self.allocator
+            .lock()
+            .unwrap()
+            .rename_allocation(allocation, name)?;
+        self.device.memory.rename(id, name);
+
+        Ok(())
+    }
+
     pub fn create_image_view(&self) -> Result<ImageView> {
         let view_info = vk::ImageViewCreateInfo::builder()
             .image(self.inner)
             .view_type(vk::ImageViewType::TYPE_2D)
             .format(self.format)
             .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask: self.aspect_mask(),
                 base_mip_level: 0,
                 level_count: 1,
                 base_array_layer: 0,
@@ -140,8 +182,28 @@ impl Context {
             usage,
             memory_location,
             format,
-            width,
-            height,
+            vk::Extent2D { width, height },
+            vk::SampleCountFlags::TYPE_1,
+        )
+    }
+
+    pub fn create_multisampled_image(
+        &self,
+        usage: vk::ImageUsageFlags,
+        memory_location: MemoryLocation,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Image> {
+        Image::new_2d(
+            self.device.clone(),
+            self.allocator.clone(),
+            usage,
+            memory_location,
+            format,
+            vk::Extent2D { width, height },
+            samples,
         )
     }
 }
@@ -152,17 +214,30 @@ impl Drop for Image {
             return;
         }
 
-        unsafe { self.device.inner.destroy_image(self.inner, None) };
-        self.allocator
-            .lock()
-            .unwrap()
-            .free(self.allocation.take().unwrap())
-            .unwrap();
+        let inner = self.inner;
+        let allocator = self.allocator.clone();
+        let allocation = self.allocation.take().unwrap();
+        let allocation_id = self.allocation_id;
+
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| {
+                unsafe { device.inner.destroy_image(inner, None) };
+                allocator.lock().unwrap().free(allocation).unwrap();
+                if let Some(id) = allocation_id {
+                    device.memory.untrack(id);
+                }
+            });
     }
 }
 
 impl Drop for ImageView {
     fn drop(&mut self) {
-        unsafe { self.device.inner.destroy_image_view(self.inner, None) };
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_image_view(inner, None)
+            });
     }
 }
@@ -16,7 +16,7 @@ pub struct Instance {
 impl Instance {
     pub(crate) fn new(
         entry: &Entry,
-        display_handle: &dyn HasRawDisplayHandle,
+        display_handle: Option<&dyn HasRawDisplayHandle>,
         api_version: Version,
         app_name: &str,
     ) -> Result<Self> {
@@ -27,11 +27,25 @@ impl Instance {
             .application_name(app_name.as_c_str())
             .api_version(api_version.make_api_version());
 
-        let mut extension_names =
-            ash_window::enumerate_required_extensions(display_handle.raw_display_handle())?
-                .to_vec();
+        let mut extension_names = match display_handle {
+            Some(display_handle) => {
+                ash_window::enumerate_required_extensions(display_handle.raw_display_handle())?
+                    .to_vec()
+            }
+            None => vec![],
+        };
         extension_names.push(DebugUtils::name().as_ptr());
 
+        // Needed for HDR swapchain color spaces, when available
+        let swapchain_colorspace = vk::ExtSwapchainColorspaceFn::name();
+        let supported_extensions = entry.enumerate_instance_extension_properties(None)?;
+        let supports_swapchain_colorspace = supported_extensions
+            .iter()
+            .any(|e| unsafe { CStr::from_ptr(e.extension_name.as_ptr()) } == swapchain_colorspace);
+        if display_handle.is_some() && supports_swapchain_colorspace {
+            extension_names.push(swapchain_colorspace.as_ptr());
+        }
+
         let instance_create_info = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
             .enabled_extension_names(&extension_names);
@@ -66,7 +80,7 @@ impl Instance {
 
     pub(crate) fn enumerate_physical_devices(
         &mut self,
-        surface: &Surface,
+        surface: Option<&Surface>,
     ) -> Result<&[PhysicalDevice]> {
         if self.physical_devices.is_empty() {
             let physical_devices = unsafe { self.inner.enumerate_physical_devices()? };
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use ash::vk;
+use gpu_allocator::vulkan::Allocation;
+
+use crate::vulkan::Context;
+
+/// Live allocations of a device, to report memory usage and leaks.
+pub(crate) struct MemoryTracker {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    allocations: Mutex<HashMap<u64, TrackedAllocation>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+struct TrackedAllocation {
+    name: String,
+    size: u64,
+    memory_type_index: u32,
+}
+
+/// Memory allocated through a [`Context`], see [`Context::memory_report`].
+///
+/// Sizes are those of the allocations. The memory blocks gpu-allocator reserves to
+/// sub-allocate from are only accounted for in the heap usage reported by the driver.
+///
+/// The memory type of an allocation is inferred from its property flags, as the first type
+/// allowed for the resource with these flags. It matches the choice of gpu-allocator but may
+/// misattribute allocations if that changes, so per type and per heap numbers are estimates.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub allocated_bytes: u64,
+    pub allocation_count: usize,
+    pub heaps: Vec<MemoryHeapReport>,
+    pub types: Vec<MemoryTypeReport>,
+    /// Largest first.
+    pub names: Vec<NamedAllocationsReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryHeapReport {
+    pub index: u32,
+    pub flags: vk::MemoryHeapFlags,
+    pub size: u64,
+    pub allocated_bytes: u64,
+    pub allocation_count: usize,
+    /// How much the process can allocate from the heap, from `VK_EXT_memory_budget`.
+    pub budget: Option<u64>,
+    /// How much the process uses from the heap, from `VK_EXT_memory_budget`.
+    pub usage: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryTypeReport {
+    pub index: u32,
+    pub heap_index: u32,
+    pub flags: vk::MemoryPropertyFlags,
+    pub allocated_bytes: u64,
+    pub allocation_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedAllocationsReport {
+    pub name: String,
+    pub allocated_bytes: u64,
+    pub allocation_count: usize,
+}
+
+/// Budget and usage of each heap.
+type HeapBudgets = (
+    [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+    [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+);
+
+impl MemoryTracker {
+    pub(crate) fn new(memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
+        Self {
+            memory_properties,
+            allocations: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the id to untrack the allocation with.
+    pub(crate) fn track(&self, name: &str, allocation: &Allocation, memory_type_bits: u32) -> u64 {
+        // gpu-allocator doesn't expose the memory type it picked, only its flags. It takes the
+        // first allowed type containing the flags wanted for the memory location, so no earlier
+        // allowed type has the exact same flags. This relies on its selection order.
+        let properties = allocation.memory_properties();
+        let memory_type_index = (0..self.memory_properties.memory_type_count)
+            .find(|&index| {
+                memory_type_bits & (1 << index) != 0
+                    && self.memory_properties.memory_types[index as usize].property_flags
+                        == properties
+            })
+            .unwrap_or_default();
+
+        self.insert(name, allocation.size(), memory_type_index)
+    }
+
+    fn insert(&self, name: &str, size: u64, memory_type_index: u32) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.allocations.lock().unwrap().insert(
+            id,
+            TrackedAllocation {
+                name: name.to_owned(),
+                size,
+                memory_type_index,
+            },
+        );
+
+        id
+    }
+
+    pub(crate) fn rename(&self, id: u64, name: &str) {
+        if let Some(allocation) = self.allocations.lock().unwrap().get_mut(&id) {
+            allocation.name = name.to_owned();
+        }
+    }
+
+    pub(crate) fn untrack(&self, id: u64) {
+        self.allocations.lock().unwrap().remove(&id);
+    }
+
+    fn report(&self, budgets: Option<HeapBudgets>) -> MemoryReport {
+        let properties = &self.memory_properties;
+        let allocations = self.allocations.lock().unwrap();
+
+        let mut types = (0..properties.memory_type_count)
+            .map(|index| {
+                let memory_type = properties.memory_types[index as usize];
+                MemoryTypeReport {
+                    index,
+                    heap_index: memory_type.heap_index,
+                    flags: memory_type.property_flags,
+                    allocated_bytes: 0,
+                    allocation_count: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut names = HashMap::<&str, NamedAllocationsReport>::new();
+
+        for allocation in allocations.values() {
+            if let Some(memory_type) = types.get_mut(allocation.memory_type_index as usize) {
+                memory_type.allocated_bytes += allocation.size;
+                memory_type.allocation_count += 1;
+            }
+
+            let named = names
+                .entry(&allocation.name)
+                .or_insert_with(|| NamedAllocationsReport {
+                    name: allocation.name.clone(),
+                    allocated_bytes: 0,
+                    allocation_count: 0,
+                });
+            named.allocated_bytes += allocation.size;
+            named.allocation_count += 1;
+        }
+
+        let heaps = (0..properties.memory_heap_count)
+            .map(|index| {
+                let heap = properties.memory_heaps[index as usize];
+                let heap_types = types.iter().filter(|t| t.heap_index == index);
+                MemoryHeapReport {
+                    index,
+                    flags: heap.flags,
+                    size: heap.size,
+                    allocated_bytes: heap_types.clone().map(|t| t.allocated_bytes).sum(),
+                    allocation_count: heap_types.map(|t| t.allocation_count).sum(),
+                    budget: budgets.map(|(budget, _)| budget[index as usize]),
+                    usage: budgets.map(|(_, usage)| usage[index as usize]),
+                }
+            })
+            .collect();
+
+        // Only keep the types that are used
+        types.retain(|t| t.allocation_count > 0);
+
+        let mut names = names.into_values().collect::<Vec<_>>();
+        names.sort_by(|a, b| {
+            b.allocated_bytes
+                .cmp(&a.allocated_bytes)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        MemoryReport {
+            allocated_bytes: allocations.values().map(|a| a.size).sum(),
+            allocation_count: allocations.len(),
+            heaps,
+            types,
+            names,
+        }
+    }
+
+    /// Name and size of the allocations still alive, largest first.
+    pub(crate) fn live_allocations(&self) -> Vec<(String, u64)> {
+        let mut allocations = self
+            .allocations
+            .lock()
+            .unwrap()
+            .values()
+            .map(|allocation| (allocation.name.clone(), allocation.size))
+            .collect::<Vec<_>>();
+        allocations.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+        allocations
+    }
+}
+
+impl Context {
+    /// Memory allocated through the context, by heap, memory type and allocation name. Heap
+    /// budgets and usage are queried if the `VK_EXT_memory_budget` extension is enabled.
+    pub fn memory_report(&self) -> MemoryReport {
+        let budgets = self.is_extension_enabled("VK_EXT_memory_budget").then(|| {
+            let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+            let mut properties =
+                vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+            unsafe {
+                self.instance.inner.get_physical_device_memory_properties2(
+                    self.physical_device.inner,
+                    &mut properties,
+                )
+            };
+
+            (budget.heap_budget, budget.heap_usage)
+        });
+
+        self.device.memory.report(budgets)
+    }
+
+    /// Prints the allocations still alive, e.g. buffers or images outliving the context.
+    pub(crate) fn report_memory_leaks(&self) {
+        let allocations = self.device.memory.live_allocations();
+        if allocations.is_empty() {
+            return;
+        }
+
+        let total = allocations.iter().map(|(_, size)| size).sum();
+        println!(
+            "Memory leak: {} allocations ({}) are still alive",
+            allocations.len(),
+            format_bytes(total)
+        );
+        for (name, size) in allocations {
+            println!("  {name}: {}", format_bytes(size));
+        }
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} in {} allocations",
+            format_bytes(self.allocated_bytes),
+            self.allocation_count
+        )?;
+
+        for heap in &self.heaps {
+            write!(
+                f,
+                "Heap {} ({:?}, {}): {} in {} allocations",
+                heap.index,
+                heap.flags,
+                format_bytes(heap.size),
+                format_bytes(heap.allocated_bytes),
+                heap.allocation_count
+            )?;
+            if let (Some(usage), Some(budget)) = (heap.usage, heap.budget) {
+                write!(
+                    f,
+                    ", usage {} of {} budget",
+                    format_bytes(usage),
+                    format_bytes(budget)
+                )?;
+            }
+            writeln!(f)?;
+
+            for memory_type in self.types.iter().filter(|t| t.heap_index == heap.index) {
+                writeln!(
+                    f,
+                    "  Type {} ({:?}): {} in {} allocations",
+                    memory_type.index,
+                    memory_type.flags,
+                    format_bytes(memory_type.allocated_bytes),
+                    memory_type.allocation_count
+                )?;
+            }
+        }
+
+        for named in &self.names {
+            writeln!(
+                f,
+                "{}: {} in {} allocations",
+                named.name,
+                format_bytes(named.allocated_bytes),
+                named.allocation_count
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next_unit in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+
+    format!("{value:.2} {unit}")
+}
+
+#[test]
+fn test_memory_report() {
+    let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+        memory_type_count: 2,
+        memory_heap_count: 2,
+        ..Default::default()
+    };
+    memory_properties.memory_types[0] = vk::MemoryType {
+        property_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        heap_index: 0,
+    };
+    memory_properties.memory_types[1] = vk::MemoryType {
+        property_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+        heap_index: 1,
+    };
+    memory_properties.memory_heaps[0].size = 4 << 30;
+    memory_properties.memory_heaps[1].size = 16 << 30;
+
+    let tracker = MemoryTracker::new(memory_properties);
+    tracker.insert("image", 3 << 20, 0);
+    tracker.insert("image", 1 << 20, 0);
+    let staging = tracker.insert("staging", 8 << 20, 1);
+    tracker.insert("vertices", 2 << 20, 1);
+    tracker.untrack(staging);
+
+    let report = tracker.report(None);
+    assert_eq!(report.allocated_bytes, 6 << 20);
+    assert_eq!(report.allocation_count, 3);
+    assert_eq!(report.heaps[0].allocated_bytes, 4 << 20);
+    assert_eq!(report.heaps[1].allocated_bytes, 2 << 20);
+    assert_eq!(report.heaps[1].budget, None);
+    assert_eq!(report.types.len(), 2);
+    assert_eq!(
+        report.names,
+        vec![
+            NamedAllocationsReport {
+                name: "image".to_owned(),
+                allocated_bytes: 4 << 20,
+                allocation_count: 2,
+            },
+            NamedAllocationsReport {
+                name: "vertices".to_owned(),
+                allocated_bytes: 2 << 20,
+                allocation_count: 1,
+            },
+        ]
+    );
+
+    assert_eq!(format_bytes(512), "512 B");
+    assert_eq!(format_bytes(3 << 29), "1.50 GiB");
+}
@@ -2,15 +2,21 @@ pub extern crate ash;
 pub extern crate ash_window;
 pub extern crate gpu_allocator;
 
+mod bindless;
 mod buffer;
 mod command;
 mod context;
+mod deletion;
 mod descriptor;
 mod device;
+mod diagnostics;
 mod image;
 mod instance;
+mod memory;
 mod physical_device;
 mod pipeline;
+mod pod;
+mod profiler;
 mod query;
 mod queue;
 mod ray_tracing;
@@ -21,13 +27,18 @@ mod sync;
 
 pub mod utils;
 
+pub use bindless::*;
 pub use buffer::*;
 pub use command::*;
 pub use context::*;
 pub use descriptor::*;
 pub use device::*;
+pub use diagnostics::*;
 pub use image::*;
+pub use memory::*;
 pub use pipeline::*;
+pub use pod::*;
+pub use profiler::*;
 pub use query::*;
 pub use queue::*;
 pub use ray_tracing::*;
@@ -11,6 +11,8 @@ pub struct PhysicalDevice {
     pub(crate) name: String,
     pub(crate) device_type: vk::PhysicalDeviceType,
     pub(crate) limits: vk::PhysicalDeviceLimits,
+    /// Update-after-bind descriptor limits, see [`crate::vulkan::BindlessDescriptorHeap`].
+    pub(crate) descriptor_indexing_properties: vk::PhysicalDeviceDescriptorIndexingProperties,
     pub(crate) queue_families: Vec<QueueFamily>,
     pub(crate) supported_extensions: Vec<String>,
     pub(crate) supported_surface_formats: Vec<vk::SurfaceFormatKHR>,
@@ -21,7 +23,7 @@ pub struct PhysicalDevice {
 impl PhysicalDevice {
     pub(crate) fn new(
         instance: &Instance,
-        surface: &Surface,
+        surface: Option<&Surface>,
         inner: vk::PhysicalDevice,
     ) -> Result<Self> {
         let props = unsafe { instance.get_physical_device_properties(inner) };
@@ -36,18 +38,27 @@ impl PhysicalDevice {
         let device_type = props.device_type;
         let limits = props.limits;
 
+        let mut descriptor_indexing_properties =
+            vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut props2 =
+            vk::PhysicalDeviceProperties2::builder().push_next(&mut descriptor_indexing_properties);
+        unsafe { instance.get_physical_device_properties2(inner, &mut props2) };
+
         let queue_family_properties =
             unsafe { instance.get_physical_device_queue_family_properties(inner) };
         let queue_families = queue_family_properties
             .into_iter()
             .enumerate()
             .map(|(index, p)| {
-                let present_support = unsafe {
-                    surface.inner.get_physical_device_surface_support(
-                        inner,
-                        index as _,
-                        surface.surface_khr,
-                    )?
+                let present_support = match surface {
+                    Some(surface) => unsafe {
+                        surface.inner.get_physical_device_surface_support(
+                            inner,
+                            index as _,
+                            surface.surface_khr,
+                        )?
+                    },
+                    None => false,
                 };
 
                 Ok(QueueFamily::new(index as _, p, present_support))
@@ -64,21 +75,24 @@ impl PhysicalDevice {
             })
             .collect();
 
-        let supported_surface_formats = unsafe {
-            surface
-                .inner
-                .get_physical_device_surface_formats(inner, surface.surface_khr)?
-        };
-
-        let supported_present_modes = unsafe {
-            surface
-                .inner
-                .get_physical_device_surface_present_modes(inner, surface.surface_khr)?
+        let (supported_surface_formats, supported_present_modes) = match surface {
+            Some(surface) => unsafe {
+                (
+                    surface
+                        .inner
+                        .get_physical_device_surface_formats(inner, surface.surface_khr)?,
+                    surface
+                        .inner
+                        .get_physical_device_surface_present_modes(inner, surface.surface_khr)?,
+                )
+            },
+            None => (vec![], vec![]),
         };
 
         let mut ray_tracing_feature = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
         let mut acceleration_struct_feature =
             vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut ray_query_feature = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
         let mut features12 = vk::PhysicalDeviceVulkan12Features::builder()
             .runtime_descriptor_array(true)
             .buffer_device_address(true);
@@ -86,17 +100,30 @@ impl PhysicalDevice {
         let mut features = vk::PhysicalDeviceFeatures2::builder()
             .push_next(&mut ray_tracing_feature)
             .push_next(&mut acceleration_struct_feature)
+            .push_next(&mut ray_query_feature)
             .push_next(&mut features12)
             .push_next(&mut features13);
         unsafe { instance.get_physical_device_features2(inner, &mut features) };
+        let core_features = features.features;
 
         let supported_device_features = DeviceFeatures {
             ray_tracing_pipeline: ray_tracing_feature.ray_tracing_pipeline == vk::TRUE,
             acceleration_structure: acceleration_struct_feature.acceleration_structure == vk::TRUE,
+            ray_query: ray_query_feature.ray_query == vk::TRUE,
             runtime_descriptor_array: features12.runtime_descriptor_array == vk::TRUE,
+            descriptor_indexing: features12.descriptor_indexing == vk::TRUE
+                && features12.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+                && features12.shader_storage_buffer_array_non_uniform_indexing == vk::TRUE
+                && features12.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+                && features12.descriptor_binding_storage_buffer_update_after_bind == vk::TRUE
+                && features12.descriptor_binding_update_unused_while_pending == vk::TRUE
+                && features12.descriptor_binding_partially_bound == vk::TRUE,
             buffer_device_address: features12.buffer_device_address == vk::TRUE,
             dynamic_rendering: features13.dynamic_rendering == vk::TRUE,
             synchronization2: features13.synchronization2 == vk::TRUE,
+            occlusion_query_precise: core_features.occlusion_query_precise == vk::TRUE,
+            pipeline_statistics_query: core_features.pipeline_statistics_query == vk::TRUE,
+            sampler_anisotropy: core_features.sampler_anisotropy == vk::TRUE,
         };
 
         Ok(Self {
@@ -104,6 +131,7 @@ impl PhysicalDevice {
             name,
             device_type,
             limits,
+            descriptor_indexing_properties,
             queue_families,
             supported_extensions,
             supported_surface_formats,
@@ -60,6 +60,11 @@ impl Context {
 
 impl Drop for ComputePipeline {
     fn drop(&mut self) {
-        unsafe { self.device.inner.destroy_pipeline(self.inner, None) };
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_pipeline(inner, None)
+            });
     }
 }
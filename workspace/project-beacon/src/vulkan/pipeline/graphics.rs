@@ -3,7 +3,7 @@ use std::{ffi::CString, sync::Arc};
 use anyhow::Result;
 use ash::vk;
 
-use crate::vulkan::{device::Device, Context, PipelineLayout, ShaderModule};
+use crate::vulkan::{device::Device, Context, PipelineLayout, ShaderModule, Vertex};
 
 pub struct GraphicsPipeline {
     device: Arc<Device>,
@@ -15,16 +15,15 @@ pub struct GraphicsPipelineCreateInfo<'a> {
     pub shaders: &'a [GraphicsShaderCreateInfo<'a>],
     pub primitive_topology: vk::PrimitiveTopology,
     pub extent: Option<vk::Extent2D>,
-    pub color_attachment_format: vk::Format,
+    pub color_attachment_formats: &'a [vk::Format],
     pub color_attachment_blend: Option<vk::PipelineColorBlendAttachmentState>,
+    pub depth_attachment_format: Option<vk::Format>,
+    pub stencil_attachment_format: Option<vk::Format>,
+    pub depth_stencil_state: Option<vk::PipelineDepthStencilStateCreateInfo>,
+    pub samples: Option<vk::SampleCountFlags>,
     pub dynamic_states: Option<&'a [vk::DynamicState]>,
 }
 
-pub trait Vertex {
-    fn bindings() -> Vec<vk::VertexInputBindingDescription>;
-    fn attributes() -> Vec<vk::VertexInputAttributeDescription>;
-}
-
 #[derive(Debug, Clone, Copy)]
 pub struct GraphicsShaderCreateInfo<'a> {
     pub source: &'a [u8],
@@ -113,7 +112,7 @@ impl GraphicsPipeline {
         // msaa
         let multisampling_info = vk::PipelineMultisampleStateCreateInfo::builder()
             .sample_shading_enable(false)
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+            .rasterization_samples(create_info.samples.unwrap_or(vk::SampleCountFlags::TYPE_1))
             .min_sample_shading(1.0)
             .alpha_to_coverage_enable(false)
             .alpha_to_one_enable(false);
@@ -126,21 +125,43 @@ impl GraphicsPipeline {
                     color_write_mask: vk::ColorComponentFlags::RGBA,
                     ..Default::default()
                 });
-        let color_blend_attachments = [color_blend_attachment];
+        let color_blend_attachments =
+            vec![color_blend_attachment; create_info.color_attachment_formats.len()];
         let color_blending_info = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
             .logic_op(vk::LogicOp::COPY)
             .attachments(&color_blend_attachments)
             .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
+        // depth/stencil
+        let depth_stencil_info = create_info.depth_stencil_state.unwrap_or_else(|| {
+            let depth_enabled = create_info.depth_attachment_format.is_some();
+            vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(depth_enabled)
+                .depth_write_enable(depth_enabled)
+                .depth_compare_op(vk::CompareOp::LESS)
+                .depth_bounds_test_enable(false)
+                .stencil_test_enable(false)
+                .build()
+        });
+
         // dynamic states
         let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::builder()
             .dynamic_states(create_info.dynamic_states.unwrap_or(&[]));
 
         // dynamic rendering
-        let color_attachment_formats = [create_info.color_attachment_format];
         let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
-            .color_attachment_formats(&color_attachment_formats);
+            .color_attachment_formats(create_info.color_attachment_formats)
+            .depth_attachment_format(
+                create_info
+                    .depth_attachment_format
+                    .unwrap_or(vk::Format::UNDEFINED),
+            )
+            .stencil_attachment_format(
+                create_info
+                    .stencil_attachment_format
+                    .unwrap_or(vk::Format::UNDEFINED),
+            );
 
         let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stages_infos)
@@ -149,6 +170,7 @@ impl GraphicsPipeline {
             .viewport_state(&viewport_info)
             .rasterization_state(&rasterizer_info)
             .multisample_state(&multisampling_info)
+            .depth_stencil_state(&depth_stencil_info)
             .color_blend_state(&color_blending_info)
             .dynamic_state(&dynamic_state_info)
             .layout(layout.inner)
@@ -181,6 +203,11 @@ impl Context {
 
 impl Drop for GraphicsPipeline {
     fn drop(&mut self) {
-        unsafe { self.device.inner.destroy_pipeline(self.inner, None) };
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_pipeline(inner, None)
+            });
     }
 }
@@ -14,13 +14,16 @@ impl PipelineLayout {
     pub(crate) fn new(
         device: Arc<Device>,
         descriptor_set_layouts: &[&DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
     ) -> Result<Self> {
         let layouts = descriptor_set_layouts
             .iter()
             .map(|l| l.inner)
             .collect::<Vec<_>>();
 
-        let pipe_layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&layouts);
+        let pipe_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&layouts)
+            .push_constant_ranges(push_constant_ranges);
         let inner = unsafe {
             device
                 .inner
@@ -36,7 +39,19 @@ impl Context {
         &self,
         descriptor_set_layouts: &[&DescriptorSetLayout],
     ) -> Result<PipelineLayout> {
-        PipelineLayout::new(self.device.clone(), descriptor_set_layouts)
+        PipelineLayout::new(self.device.clone(), descriptor_set_layouts, &[])
+    }
+
+    pub fn create_pipeline_layout_with_push_constants(
+        &self,
+        descriptor_set_layouts: &[&DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Result<PipelineLayout> {
+        PipelineLayout::new(
+            self.device.clone(),
+            descriptor_set_layouts,
+            push_constant_ranges,
+        )
     }
 }
 
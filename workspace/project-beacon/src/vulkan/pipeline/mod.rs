@@ -2,8 +2,10 @@ mod compute;
 mod graphics;
 mod layout;
 mod shader;
+mod vertex;
 
 pub use compute::*;
 pub use graphics::*;
 pub use layout::*;
 pub use shader::*;
+pub use vertex::*;
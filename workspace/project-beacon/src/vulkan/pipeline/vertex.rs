@@ -0,0 +1,123 @@
+use ash::vk;
+use glam::{IVec2, IVec3, IVec4, Mat4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
+use half::f16;
+
+pub use project_beacon_derive::Vertex;
+
+pub trait Vertex {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription>;
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription>;
+}
+
+/// A type that can be used as a field of a `#[derive(Vertex)]` struct.
+///
+/// Types larger than a single location (e.g. matrices) take `LOCATION_COUNT` consecutive
+/// locations, each `size_of::<Self>() / LOCATION_COUNT` bytes apart.
+pub trait VertexAttribute {
+    const FORMAT: vk::Format;
+    const LOCATION_COUNT: u32 = 1;
+}
+
+/// Four `u8` normalized to `[0.0, 1.0]` in the shader, typically a packed RGBA color.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct Unorm8x4(pub [u8; 4]);
+
+/// Four `i8` normalized to `[-1.0, 1.0]` in the shader, typically a packed normal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct Snorm8x4(pub [i8; 4]);
+
+impl Unorm8x4 {
+    pub fn from_rgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+        let pack = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Self([pack(r), pack(g), pack(b), pack(a)])
+    }
+}
+
+impl Snorm8x4 {
+    pub fn from_xyzw(x: f32, y: f32, z: f32, w: f32) -> Self {
+        let pack = |v: f32| (v.clamp(-1.0, 1.0) * 127.0).round() as i8;
+        Self([pack(x), pack(y), pack(z), pack(w)])
+    }
+}
+
+macro_rules! impl_vertex_attribute {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(
+            impl VertexAttribute for $ty {
+                const FORMAT: vk::Format = vk::Format::$format;
+            }
+        )*
+    };
+}
+
+impl_vertex_attribute!(
+    f32 => R32_SFLOAT,
+    [f32; 1] => R32_SFLOAT,
+    [f32; 2] => R32G32_SFLOAT,
+    [f32; 3] => R32G32B32_SFLOAT,
+    [f32; 4] => R32G32B32A32_SFLOAT,
+    u32 => R32_UINT,
+    [u32; 1] => R32_UINT,
+    [u32; 2] => R32G32_UINT,
+    [u32; 3] => R32G32B32_UINT,
+    [u32; 4] => R32G32B32A32_UINT,
+    i32 => R32_SINT,
+    [i32; 1] => R32_SINT,
+    [i32; 2] => R32G32_SINT,
+    [i32; 3] => R32G32B32_SINT,
+    [i32; 4] => R32G32B32A32_SINT,
+    f16 => R16_SFLOAT,
+    [f16; 1] => R16_SFLOAT,
+    [f16; 2] => R16G16_SFLOAT,
+    [f16; 3] => R16G16B16_SFLOAT,
+    [f16; 4] => R16G16B16A16_SFLOAT,
+    [u8; 4] => R8G8B8A8_UINT,
+    [i8; 4] => R8G8B8A8_SINT,
+    [u16; 2] => R16G16_UINT,
+    [u16; 4] => R16G16B16A16_UINT,
+    Unorm8x4 => R8G8B8A8_UNORM,
+    Snorm8x4 => R8G8B8A8_SNORM,
+    Vec2 => R32G32_SFLOAT,
+    Vec3 => R32G32B32_SFLOAT,
+    Vec4 => R32G32B32A32_SFLOAT,
+    IVec2 => R32G32_SINT,
+    IVec3 => R32G32B32_SINT,
+    IVec4 => R32G32B32A32_SINT,
+    UVec2 => R32G32_UINT,
+    UVec3 => R32G32B32_UINT,
+    UVec4 => R32G32B32A32_UINT,
+);
+
+impl VertexAttribute for Mat4 {
+    const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+    const LOCATION_COUNT: u32 = 4;
+}
+
+/// No vertex input, for pipelines generating their vertices from `gl_VertexIndex`.
+impl Vertex for () {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        vec![]
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![]
+    }
+}
+
+/// Combines a per-vertex and a per-instance layout (or any two layouts on distinct bindings
+/// and locations) for a single pipeline.
+impl<A: Vertex, B: Vertex> Vertex for (A, B) {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        let mut bindings = A::bindings();
+        bindings.extend(B::bindings());
+        bindings
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        let mut attributes = A::attributes();
+        attributes.extend(B::attributes());
+        attributes
+    }
+}
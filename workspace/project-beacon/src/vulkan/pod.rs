@@ -0,0 +1,41 @@
+use std::mem::size_of;
+
+/// Plain old data that can be viewed as bytes, e.g. push constants or shader record data, and
+/// read back from bytes written by the GPU.
+///
+/// # Safety
+///
+/// The type must be `#[repr(C)]` (or a primitive) and have no padding bytes, i.e. the size of
+/// its fields adds up to its size, and every field must be `Pod` too. Any bit pattern must be
+/// a valid value, which rules out `bool`, enums and references.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+impl_pod!(half::f16);
+impl_pod!(glam::Vec2, glam::Vec3, glam::Vec4, glam::Mat4);
+impl_pod!(
+    glam::UVec2,
+    glam::UVec3,
+    glam::UVec4,
+    glam::IVec2,
+    glam::IVec3,
+    glam::IVec4
+);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+pub(crate) fn bytes_of<T: Pod>(data: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data as *const T as *const u8, size_of::<T>()) }
+}
+
+#[test]
+fn test_bytes_of() {
+    assert_eq!(bytes_of(&0x0403_0201u32), &[1, 2, 3, 4]);
+    assert_eq!(bytes_of(&[1u16, 2]), &[1, 0, 2, 0]);
+}
@@ -0,0 +1,424 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use ash::vk;
+
+use crate::vulkan::{CommandBuffer, Context, QueryPool};
+
+/// Measures the GPU time of named, nested scopes recorded in command buffers.
+///
+/// Each frame uses its own query pool, read back without waiting once the GPU is done with it,
+/// so results are available a few frames after they are recorded.
+pub struct GpuProfiler {
+    timestamp_period: f64,
+    max_scopes: u32,
+    max_frame_history: usize,
+    frames: Vec<ProfilerFrame>,
+    current_frame: Cell<usize>,
+    frame_index: Cell<u64>,
+    history: RefCell<VecDeque<FrameTimings>>,
+}
+
+struct ProfilerFrame {
+    pool: QueryPool,
+    /// Index of the frame whose results are still to be read.
+    pending_frame_index: Cell<Option<u64>>,
+    scopes: RefCell<Vec<RecordedScope>>,
+    open_scopes: RefCell<Vec<usize>>,
+}
+
+/// Scope `i` writes timestamps `2 * i` and `2 * i + 1`.
+struct RecordedScope {
+    name: String,
+    depth: u32,
+    is_ended: bool,
+}
+
+/// GPU timings of the scopes of a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameTimings {
+    pub frame_index: u64,
+    /// In the order they were opened, parents before their children.
+    pub scopes: Vec<ScopeTiming>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeTiming {
+    pub name: String,
+    /// 0 for top level scopes.
+    pub depth: u32,
+    /// On the GPU clock, in nanoseconds.
+    pub start_ns: u64,
+    pub duration_ns: u64,
+}
+
+/// Closes its profiler scope when dropped. See [`CommandBuffer::profile_scope`].
+#[must_use = "The scope ends when the guard is dropped"]
+pub struct ProfileScope<'a> {
+    profiler: &'a GpuProfiler,
+    cmd_buffer: &'a CommandBuffer,
+    index: Option<usize>,
+}
+
+impl GpuProfiler {
+    /// Number of recent frames kept by default.
+    pub const DEFAULT_FRAME_HISTORY: usize = 300;
+
+    pub(crate) fn new(
+        context: &Context,
+        frame_count: u32,
+        max_scopes: u32,
+        max_frame_history: usize,
+    ) -> Result<Self> {
+        ensure!(frame_count > 0, "The profiler needs at least one frame");
+        ensure!(max_scopes > 0, "The profiler needs at least one scope");
+
+        let frames = (0..frame_count)
+            .map(|_| {
+                Ok(ProfilerFrame {
+                    pool: context.create_query_pool(vk::QueryType::TIMESTAMP, 2 * max_scopes)?,
+                    pending_frame_index: Cell::new(None),
+                    scopes: RefCell::new(vec![]),
+                    open_scopes: RefCell::new(vec![]),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            timestamp_period: context.physical_device.limits.timestamp_period as _,
+            max_scopes,
+            max_frame_history,
+            frames,
+            current_frame: Cell::new(0),
+            frame_index: Cell::new(0),
+            history: RefCell::new(VecDeque::with_capacity(max_frame_history)),
+        })
+    }
+
+    /// Reads back finished frames and starts a new one. Must be recorded outside of a render
+    /// pass, before any scope of the frame.
+    ///
+    /// The frame reuses the query pool of the frame recorded `frame_count` frames ago, which
+    /// the GPU must be done with.
+    pub fn begin_frame(&self, cmd_buffer: &CommandBuffer) -> Result<()> {
+        self.collect()?;
+
+        let current_frame = (self.current_frame.get() + 1) % self.frames.len();
+        self.current_frame.set(current_frame);
+        let frame_index = self.frame_index.get() + 1;
+        self.frame_index.set(frame_index);
+
+        // Results that are still not available are dropped
+        let frame = &self.frames[current_frame];
+        frame.pending_frame_index.set(Some(frame_index));
+        frame.scopes.borrow_mut().clear();
+        frame.open_scopes.borrow_mut().clear();
+
+        cmd_buffer.reset_query_pool(&frame.pool);
+
+        Ok(())
+    }
+
+    /// Index of the frame last begun, see [`FrameTimings::frame_index`].
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index.get()
+    }
+
+    /// Timings of the last frames whose results were read back, oldest first.
+    pub fn history(&self) -> Ref<'_, VecDeque<FrameTimings>> {
+        self.history.borrow()
+    }
+
+    pub fn latest_frame(&self) -> Option<FrameTimings> {
+        self.history.borrow().back().cloned()
+    }
+
+    /// Writes the frames of the history as Chrome trace events, to open in `chrome://tracing`
+    /// or Perfetto.
+    pub fn write_chrome_trace(&self, writer: impl Write) -> Result<()> {
+        write_chrome_trace(self.history.borrow().iter(), writer)
+    }
+
+    fn begin_scope(&self, cmd_buffer: &CommandBuffer, name: &str) -> Option<usize> {
+        // The query pools are only reset by begin_frame
+        if self.frame_index.get() == 0 {
+            return None;
+        }
+
+        let frame = &self.frames[self.current_frame.get()];
+        let mut scopes = frame.scopes.borrow_mut();
+        let mut open_scopes = frame.open_scopes.borrow_mut();
+
+        // Scopes past the capacity of the frame are not measured
+        let index = scopes.len();
+        if index as u32 >= self.max_scopes {
+            return None;
+        }
+
+        scopes.push(RecordedScope {
+            name: name.to_owned(),
+            depth: open_scopes.len() as _,
+            is_ended: false,
+        });
+        open_scopes.push(index);
+
+        cmd_buffer.write_query_pool_timestamp(
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            &frame.pool,
+            2 * index as u32,
+        );
+
+        Some(index)
+    }
+
+    fn end_scope(&self, cmd_buffer: &CommandBuffer, index: usize) {
+        let frame = &self.frames[self.current_frame.get()];
+        let mut scopes = frame.scopes.borrow_mut();
+        let mut open_scopes = frame.open_scopes.borrow_mut();
+
+        // The frame may have been restarted while the scope was open
+        let Some(position) = open_scopes.iter().rposition(|&open| open == index) else {
+            return;
+        };
+        open_scopes.truncate(position);
+        scopes[index].is_ended = true;
+
+        cmd_buffer.write_query_pool_timestamp(
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            &frame.pool,
+            2 * index as u32 + 1,
+        );
+    }
+
+    /// Moves the results of finished frames to the history, oldest frames first.
+    fn collect(&self) -> Result<()> {
+        let mut frames = self
+            .frames
+            .iter()
+            .filter_map(|frame| Some((frame.pending_frame_index.get()?, frame)))
+            .collect::<Vec<_>>();
+        frames.sort_by_key(|(frame_index, _)| *frame_index);
+
+        for (frame_index, frame) in frames {
+            let scopes = frame.scopes.borrow();
+            let query_count = 2 * scopes.len() as u32;
+            let results = if query_count > 0 {
+                frame.pool.available_results(0, query_count)?
+            } else {
+                vec![]
+            };
+
+            let Some(scopes) = scope_timings(&scopes, &results, self.timestamp_period) else {
+                // Later frames can't be done either
+                break;
+            };
+
+            let mut history = self.history.borrow_mut();
+            if history.len() == self.max_frame_history {
+                history.pop_front();
+            }
+            history.push_back(FrameTimings {
+                frame_index,
+                scopes,
+            });
+
+            frame.pending_frame_index.set(None);
+        }
+
+        Ok(())
+    }
+}
+
+/// Timings of the ended scopes, `None` until all their timestamps are available. Unended
+/// scopes have no end timestamp and are skipped.
+fn scope_timings(
+    scopes: &[RecordedScope],
+    results: &[Option<u64>],
+    timestamp_period: f64,
+) -> Option<Vec<ScopeTiming>> {
+    let to_ns = |timestamp: u64| (timestamp as f64 * timestamp_period) as u64;
+
+    scopes
+        .iter()
+        .enumerate()
+        .filter(|(_, scope)| scope.is_ended)
+        .map(|(index, scope)| {
+            let start = to_ns(results[2 * index]?);
+            let end = to_ns(results[2 * index + 1]?);
+            Some(ScopeTiming {
+                name: scope.name.clone(),
+                depth: scope.depth,
+                start_ns: start,
+                duration_ns: end.saturating_sub(start),
+            })
+        })
+        .collect()
+}
+
+impl FrameTimings {
+    /// First scope named `name`.
+    pub fn scope(&self, name: &str) -> Option<&ScopeTiming> {
+        self.scopes.iter().find(|scope| scope.name == name)
+    }
+}
+
+impl ScopeTiming {
+    pub fn duration(&self) -> Duration {
+        Duration::from_nanos(self.duration_ns)
+    }
+}
+
+impl CommandBuffer {
+    /// Opens a scope measured by `profiler`, closed when the returned guard is dropped.
+    pub fn profile_scope<'a>(&'a self, profiler: &'a GpuProfiler, name: &str) -> ProfileScope<'a> {
+        ProfileScope {
+            profiler,
+            cmd_buffer: self,
+            index: profiler.begin_scope(self, name),
+        }
+    }
+}
+
+impl Drop for ProfileScope<'_> {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            self.profiler.end_scope(self.cmd_buffer, index);
+        }
+    }
+}
+
+impl Context {
+    /// Creates a profiler cycling through `frame_count` frames, each measuring up to
+    /// `max_scopes` scopes. `frame_count` must be greater than the number of frames in flight.
+    pub fn create_gpu_profiler(&self, frame_count: u32, max_scopes: u32) -> Result<GpuProfiler> {
+        GpuProfiler::new(
+            self,
+            frame_count,
+            max_scopes,
+            GpuProfiler::DEFAULT_FRAME_HISTORY,
+        )
+    }
+}
+
+/// Complete events in microseconds, relative to the first scope. Frames are laid out on the
+/// GPU timeline, nested scopes stack under their parent.
+fn write_chrome_trace<'a>(
+    frames: impl Iterator<Item = &'a FrameTimings> + Clone,
+    mut writer: impl Write,
+) -> Result<()> {
+    let origin = frames
+        .clone()
+        .flat_map(|frame| frame.scopes.iter().map(|scope| scope.start_ns))
+        .min()
+        .unwrap_or_default();
+
+    write!(writer, r#"{{"displayTimeUnit":"ms","traceEvents":["#)?;
+    let mut is_first = true;
+    for frame in frames {
+        for scope in &frame.scopes {
+            if !is_first {
+                write!(writer, ",")?;
+            }
+            is_first = false;
+
+            write!(
+                writer,
+                r#"{{"name":"{}","cat":"gpu","ph":"X","ts":{},"dur":{},"pid":0,"tid":0,"args":{{"frame":{}}}}}"#,
+                escape_json(&scope.name),
+                (scope.start_ns - origin) as f64 / 1000.0,
+                scope.duration_ns as f64 / 1000.0,
+                frame.frame_index,
+            )?;
+        }
+    }
+    writeln!(writer, "]}}")?;
+
+    Ok(())
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn test_write_chrome_trace() {
+    let frames = [FrameTimings {
+        frame_index: 7,
+        scopes: vec![
+            ScopeTiming {
+                name: "frame".to_owned(),
+                depth: 0,
+                start_ns: 1_000_000,
+                duration_ns: 2_500,
+            },
+            ScopeTiming {
+                name: "\"shadows\"".to_owned(),
+                depth: 1,
+                start_ns: 1_001_000,
+                duration_ns: 500,
+            },
+        ],
+    }];
+
+    let mut trace = vec![];
+    write_chrome_trace(frames.iter(), &mut trace).unwrap();
+
+    assert_eq!(
+        String::from_utf8(trace).unwrap(),
+        concat!(
+            r#"{"displayTimeUnit":"ms","traceEvents":["#,
+            r#"{"name":"frame","cat":"gpu","ph":"X","ts":0,"dur":2.5,"pid":0,"tid":0,"args":{"frame":7}},"#,
+            r#"{"name":"\"shadows\"","cat":"gpu","ph":"X","ts":1,"dur":0.5,"pid":0,"tid":0,"args":{"frame":7}}"#,
+            "]}\n"
+        )
+    );
+}
+
+#[test]
+fn test_scope_timings() {
+    let scope = |name: &str, depth, is_ended| RecordedScope {
+        name: name.to_owned(),
+        depth,
+        is_ended,
+    };
+    let scopes = [
+        scope("frame", 0, true),
+        scope("shadows", 1, true),
+        scope("unended", 1, false),
+    ];
+
+    let results = [Some(100), Some(300), Some(150), Some(200), Some(250), None];
+    assert_eq!(
+        scope_timings(&scopes, &results, 2.0),
+        Some(vec![
+            ScopeTiming {
+                name: "frame".to_owned(),
+                depth: 0,
+                start_ns: 200,
+                duration_ns: 400,
+            },
+            ScopeTiming {
+                name: "shadows".to_owned(),
+                depth: 1,
+                start_ns: 300,
+                duration_ns: 100,
+            },
+        ])
+    );
+
+    // A frame is only read back once every ended scope is
+    let results = [Some(100), Some(300), Some(150), None, None, None];
+    assert_eq!(scope_timings(&scopes, &results, 2.0), None);
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use ash::vk;
 
 use crate::vulkan::{Context, Device};
@@ -38,9 +38,12 @@ impl Context {
 
 impl<const C: usize> Drop for TimestampQueryPool<C> {
     fn drop(&mut self) {
-        unsafe {
-            self.device.inner.destroy_query_pool(self.inner, None);
-        }
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_query_pool(inner, None)
+            });
     }
 }
 
@@ -72,3 +75,329 @@ impl<const C: usize> TimestampQueryPool<C> {
         Ok(result)
     }
 }
+
+/// Query pool of any type with a count known at runtime.
+pub struct QueryPool {
+    device: Arc<Device>,
+    pub(crate) inner: vk::QueryPool,
+    pub ty: vk::QueryType,
+    pub count: u32,
+}
+
+impl QueryPool {
+    pub(crate) fn new(
+        device: Arc<Device>,
+        ty: vk::QueryType,
+        count: u32,
+        pipeline_statistics: vk::QueryPipelineStatisticFlags,
+    ) -> Result<Self> {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(ty)
+            .query_count(count)
+            .pipeline_statistics(pipeline_statistics);
+
+        let inner = unsafe { device.inner.create_query_pool(&create_info, None)? };
+
+        Ok(Self {
+            device,
+            inner,
+            ty,
+            count,
+        })
+    }
+
+    pub fn wait_for_results(&self, first_query: u32, query_count: u32) -> Result<Vec<u64>> {
+        let mut data = vec![0u64; query_count as _];
+
+        unsafe {
+            self.device.inner.get_query_pool_results(
+                self.inner,
+                first_query,
+                query_count,
+                &mut data,
+                vk::QueryResultFlags::WAIT | vk::QueryResultFlags::TYPE_64,
+            )?;
+        }
+
+        Ok(data)
+    }
+
+    /// Results of the queries that are available, without waiting for the others.
+    pub fn available_results(
+        &self,
+        first_query: u32,
+        query_count: u32,
+    ) -> Result<Vec<Option<u64>>> {
+        Ok(self
+            .available_values(first_query, query_count, 1)?
+            .into_iter()
+            .map(|values| values.map(|values| values[0]))
+            .collect())
+    }
+
+    /// `value_count` values per query, for the queries that are available.
+    fn available_values(
+        &self,
+        first_query: u32,
+        query_count: u32,
+        value_count: usize,
+    ) -> Result<Vec<Option<Vec<u64>>>> {
+        // Values then availability of each query
+        let stride = value_count + 1;
+        let mut data = vec![0u64; query_count as usize * stride];
+
+        // Not through ash, which expects a single value per query
+        let result = unsafe {
+            (self.device.inner.fp_v1_0().get_query_pool_results)(
+                self.device.inner.handle(),
+                self.inner,
+                first_query,
+                query_count,
+                std::mem::size_of_val(data.as_slice()),
+                data.as_mut_ptr().cast(),
+                (stride * std::mem::size_of::<u64>()) as _,
+                vk::QueryResultFlags::WITH_AVAILABILITY | vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        match result {
+            vk::Result::SUCCESS | vk::Result::NOT_READY => {}
+            err => return Err(err.into()),
+        }
+
+        Ok(data
+            .chunks_exact(stride)
+            .map(|query| (query[value_count] != 0).then(|| query[..value_count].to_vec()))
+            .collect())
+    }
+}
+
+/// Query pools whose queries cover the commands recorded between
+/// [`CommandBuffer::begin_query`](crate::vulkan::CommandBuffer::begin_query) and
+/// [`CommandBuffer::end_query`](crate::vulkan::CommandBuffer::end_query).
+pub trait ScopedQueryPool {
+    fn query_pool(&self) -> &QueryPool;
+
+    fn control_flags(&self) -> vk::QueryControlFlags {
+        vk::QueryControlFlags::empty()
+    }
+}
+
+/// Counts the samples passing the depth and stencil tests.
+pub struct OcclusionQueryPool {
+    pool: QueryPool,
+    precise: bool,
+}
+
+impl OcclusionQueryPool {
+    pub fn count(&self) -> u32 {
+        self.pool.count
+    }
+
+    /// Whether results are sample counts. Otherwise they are only zero or non-zero.
+    pub fn is_precise(&self) -> bool {
+        self.precise
+    }
+
+    /// Passing sample counts of the available queries.
+    pub fn available_results(
+        &self,
+        first_query: u32,
+        query_count: u32,
+    ) -> Result<Vec<Option<u64>>> {
+        self.pool.available_results(first_query, query_count)
+    }
+
+    /// Whether any sample passed, for the available queries.
+    pub fn available_visibility(
+        &self,
+        first_query: u32,
+        query_count: u32,
+    ) -> Result<Vec<Option<bool>>> {
+        Ok(self
+            .available_results(first_query, query_count)?
+            .into_iter()
+            .map(|samples| samples.map(|samples| samples > 0))
+            .collect())
+    }
+}
+
+impl ScopedQueryPool for OcclusionQueryPool {
+    fn query_pool(&self) -> &QueryPool {
+        &self.pool
+    }
+
+    fn control_flags(&self) -> vk::QueryControlFlags {
+        if self.precise {
+            vk::QueryControlFlags::PRECISE
+        } else {
+            vk::QueryControlFlags::empty()
+        }
+    }
+}
+
+/// Counts pipeline events, e.g. shader invocations, selected when creating the pool.
+pub struct PipelineStatisticsQueryPool {
+    pool: QueryPool,
+    statistics: vk::QueryPipelineStatisticFlags,
+}
+
+/// Counters of a pipeline statistics query. Counters not selected for the pool are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: Option<u64>,
+    pub input_assembly_primitives: Option<u64>,
+    pub vertex_shader_invocations: Option<u64>,
+    pub geometry_shader_invocations: Option<u64>,
+    pub geometry_shader_primitives: Option<u64>,
+    pub clipping_invocations: Option<u64>,
+    pub clipping_primitives: Option<u64>,
+    pub fragment_shader_invocations: Option<u64>,
+    pub tessellation_control_shader_patches: Option<u64>,
+    pub tessellation_evaluation_shader_invocations: Option<u64>,
+    pub compute_shader_invocations: Option<u64>,
+}
+
+impl PipelineStatisticsQueryPool {
+    pub fn count(&self) -> u32 {
+        self.pool.count
+    }
+
+    pub fn statistics(&self) -> vk::QueryPipelineStatisticFlags {
+        self.statistics
+    }
+
+    /// Counters of the available queries.
+    pub fn available_results(
+        &self,
+        first_query: u32,
+        query_count: u32,
+    ) -> Result<Vec<Option<PipelineStatistics>>> {
+        let value_count = self.statistics.as_raw().count_ones() as usize;
+
+        Ok(self
+            .pool
+            .available_values(first_query, query_count, value_count)?
+            .into_iter()
+            .map(|values| {
+                values.map(|values| PipelineStatistics::from_values(self.statistics, &values))
+            })
+            .collect())
+    }
+}
+
+impl ScopedQueryPool for PipelineStatisticsQueryPool {
+    fn query_pool(&self) -> &QueryPool {
+        &self.pool
+    }
+}
+
+impl PipelineStatistics {
+    /// `values` holds a counter per flag set in `statistics`, in the order of the flag bits.
+    fn from_values(statistics: vk::QueryPipelineStatisticFlags, values: &[u64]) -> Self {
+        type Flags = vk::QueryPipelineStatisticFlags;
+
+        let mut values = values.iter().copied();
+        let mut value = |flag: Flags| statistics.contains(flag).then(|| values.next()).flatten();
+
+        // In bit order
+        Self {
+            input_assembly_vertices: value(Flags::INPUT_ASSEMBLY_VERTICES),
+            input_assembly_primitives: value(Flags::INPUT_ASSEMBLY_PRIMITIVES),
+            vertex_shader_invocations: value(Flags::VERTEX_SHADER_INVOCATIONS),
+            geometry_shader_invocations: value(Flags::GEOMETRY_SHADER_INVOCATIONS),
+            geometry_shader_primitives: value(Flags::GEOMETRY_SHADER_PRIMITIVES),
+            clipping_invocations: value(Flags::CLIPPING_INVOCATIONS),
+            clipping_primitives: value(Flags::CLIPPING_PRIMITIVES),
+            fragment_shader_invocations: value(Flags::FRAGMENT_SHADER_INVOCATIONS),
+            tessellation_control_shader_patches: value(Flags::TESSELLATION_CONTROL_SHADER_PATCHES),
+            tessellation_evaluation_shader_invocations: value(
+                Flags::TESSELLATION_EVALUATION_SHADER_INVOCATIONS,
+            ),
+            compute_shader_invocations: value(Flags::COMPUTE_SHADER_INVOCATIONS),
+        }
+    }
+}
+
+impl Context {
+    pub fn create_query_pool(&self, ty: vk::QueryType, count: u32) -> Result<QueryPool> {
+        QueryPool::new(
+            self.device.clone(),
+            ty,
+            count,
+            vk::QueryPipelineStatisticFlags::empty(),
+        )
+    }
+
+    /// Precise queries count samples and require the `occlusion_query_precise` feature.
+    pub fn create_occlusion_query_pool(
+        &self,
+        count: u32,
+        precise: bool,
+    ) -> Result<OcclusionQueryPool> {
+        ensure!(
+            !precise || self.device.features.occlusion_query_precise,
+            "Precise occlusion queries require the occlusion_query_precise feature"
+        );
+
+        Ok(OcclusionQueryPool {
+            pool: self.create_query_pool(vk::QueryType::OCCLUSION, count)?,
+            precise,
+        })
+    }
+
+    /// Requires the `pipeline_statistics_query` feature.
+    pub fn create_pipeline_statistics_query_pool(
+        &self,
+        count: u32,
+        statistics: vk::QueryPipelineStatisticFlags,
+    ) -> Result<PipelineStatisticsQueryPool> {
+        ensure!(
+            self.device.features.pipeline_statistics_query,
+            "Pipeline statistics queries require the pipeline_statistics_query feature"
+        );
+        ensure!(
+            !statistics.is_empty(),
+            "At least one pipeline statistic must be selected"
+        );
+
+        Ok(PipelineStatisticsQueryPool {
+            pool: QueryPool::new(
+                self.device.clone(),
+                vk::QueryType::PIPELINE_STATISTICS,
+                count,
+                statistics,
+            )?,
+            statistics,
+        })
+    }
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_query_pool(inner, None)
+            });
+    }
+}
+
+#[test]
+fn test_pipeline_statistics_from_values() {
+    let statistics = vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES;
+
+    let stats = PipelineStatistics::from_values(statistics, &[3, 2, 1]);
+
+    assert_eq!(
+        stats,
+        PipelineStatistics {
+            vertex_shader_invocations: Some(3),
+            clipping_primitives: Some(2),
+            compute_shader_invocations: Some(1),
+            ..Default::default()
+        }
+    );
+}
@@ -1,16 +1,78 @@
-use std::sync::Arc;
+use std::{ops::Range, sync::Arc};
 
 use anyhow::Result;
 use ash::vk;
 use gpu_allocator::MemoryLocation;
 
-use crate::vulkan::{Buffer, Context, RayTracingContext};
+use crate::vulkan::{
+    device::Device, Buffer, CommandBuffer, Context, MemoryBarrier, RayTracingContext,
+};
+
+/// Scratch memory used by a single batch of builds. Larger batches are split and the scratch
+/// buffer is reused between the parts.
+const MAX_BATCH_SCRATCH_SIZE: vk::DeviceSize = 128 * 1024 * 1024;
 
 pub struct AccelerationStructure {
+    device: Arc<Device>,
     ray_tracing: Arc<RayTracingContext>,
     pub(crate) inner: vk::AccelerationStructureKHR,
     _buffer: Buffer,
     pub address: u64,
+    pub level: vk::AccelerationStructureTypeKHR,
+    pub flags: vk::BuildAccelerationStructureFlagsKHR,
+    pub size: vk::DeviceSize,
+    pub build_scratch_size: vk::DeviceSize,
+    pub update_scratch_size: vk::DeviceSize,
+    /// Reused by every refit, only for structures built with `ALLOW_UPDATE`.
+    update_scratch_buffer: Option<ScratchBuffer>,
+}
+
+#[derive(Clone, Copy)]
+pub struct AccelerationStructureCreateInfo<'a> {
+    pub level: vk::AccelerationStructureTypeKHR,
+    pub flags: vk::BuildAccelerationStructureFlagsKHR,
+    pub geometries: &'a [vk::AccelerationStructureGeometryKHR],
+    pub ranges: &'a [vk::AccelerationStructureBuildRangeInfoKHR],
+    pub max_primitive_counts: &'a [u32],
+}
+
+/// Device memory used by acceleration structure builds and updates.
+///
+/// The start address is aligned to `min_acceleration_structure_scratch_offset_alignment`,
+/// which offsets used for sub-allocations must respect as well.
+pub struct ScratchBuffer {
+    buffer: Buffer,
+    address: u64,
+    pub size: vk::DeviceSize,
+    pub alignment: vk::DeviceSize,
+}
+
+impl ScratchBuffer {
+    pub(crate) fn new(context: &Context, size: vk::DeviceSize) -> Result<Self> {
+        let alignment = scratch_alignment(context);
+
+        let buffer = context.create_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryLocation::GpuOnly,
+            size + alignment,
+        )?;
+        let address = align_up(buffer.get_device_address(), alignment);
+
+        Ok(Self {
+            buffer,
+            address,
+            size,
+            alignment,
+        })
+    }
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
 }
 
 impl AccelerationStructure {
@@ -21,11 +83,101 @@ impl AccelerationStructure {
         as_geometry: &[vk::AccelerationStructureGeometryKHR],
         as_ranges: &[vk::AccelerationStructureBuildRangeInfoKHR],
         max_primitive_counts: &[u32],
+    ) -> Result<Self> {
+        let mut structures = Self::new_batch(
+            context,
+            ray_tracing,
+            &[AccelerationStructureCreateInfo {
+                level,
+                flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+                geometries: as_geometry,
+                ranges: as_ranges,
+                max_primitive_counts,
+            }],
+        )?;
+
+        Ok(structures.remove(0))
+    }
+
+    pub(crate) fn new_batch(
+        context: &Context,
+        ray_tracing: Arc<RayTracingContext>,
+        create_infos: &[AccelerationStructureCreateInfo],
+    ) -> Result<Vec<Self>> {
+        if create_infos.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let structures = create_infos
+            .iter()
+            .map(|info| {
+                Self::with_capacity(
+                    context,
+                    ray_tracing.clone(),
+                    info.level,
+                    info.flags,
+                    info.geometries,
+                    info.max_primitive_counts,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let scratch_sizes = structures
+            .iter()
+            .map(|s| s.build_scratch_size)
+            .collect::<Vec<_>>();
+        let alignment = scratch_alignment(context);
+        let batches = plan_scratch_batches(&scratch_sizes, alignment, MAX_BATCH_SCRATCH_SIZE);
+
+        let scratch_size = batches.iter().map(|b| b.size).max().unwrap_or_default();
+        let scratch_buffer = ScratchBuffer::new(context, scratch_size)?;
+
+        context.execute_one_time_commands(|cmd_buffer| {
+            for (index, batch) in batches.iter().enumerate() {
+                if index > 0 {
+                    // The scratch buffer is reused by the next batch
+                    cmd_buffer.pipeline_memory_barriers(&[build_barrier()]);
+                }
+
+                let build_geo_infos = batch
+                    .structures
+                    .clone()
+                    .zip(&batch.offsets)
+                    .map(|(i, offset)| {
+                        structures[i].build_info(
+                            create_infos[i].geometries,
+                            vk::BuildAccelerationStructureModeKHR::BUILD,
+                            scratch_buffer.address + offset,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let ranges = batch
+                    .structures
+                    .clone()
+                    .map(|i| create_infos[i].ranges)
+                    .collect::<Vec<_>>();
+
+                cmd_buffer.build_multiple_acceleration_structures(&build_geo_infos, &ranges);
+            }
+        })?;
+
+        Ok(structures)
+    }
+
+    /// Creates a structure large enough to be built from `geometries` with up to
+    /// `max_primitive_counts` primitives each. It still has to be built.
+    pub(crate) fn with_capacity(
+        context: &Context,
+        ray_tracing: Arc<RayTracingContext>,
+        level: vk::AccelerationStructureTypeKHR,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+        geometries: &[vk::AccelerationStructureGeometryKHR],
+        max_primitive_counts: &[u32],
     ) -> Result<Self> {
         let build_geo_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
             .ty(level)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
-            .geometries(as_geometry);
+            .flags(flags)
+            .geometries(geometries);
 
         let build_size = unsafe {
             ray_tracing
@@ -37,16 +189,36 @@ impl AccelerationStructure {
                 )
         };
 
+        Self::allocate(
+            context,
+            ray_tracing,
+            level,
+            flags,
+            build_size.acceleration_structure_size,
+            build_size.build_scratch_size,
+            build_size.update_scratch_size,
+        )
+    }
+
+    fn allocate(
+        context: &Context,
+        ray_tracing: Arc<RayTracingContext>,
+        level: vk::AccelerationStructureTypeKHR,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+        size: vk::DeviceSize,
+        build_scratch_size: vk::DeviceSize,
+        update_scratch_size: vk::DeviceSize,
+    ) -> Result<Self> {
         let buffer = context.create_buffer(
             vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
                 | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
             MemoryLocation::GpuOnly,
-            build_size.acceleration_structure_size,
+            size,
         )?;
 
         let create_info = vk::AccelerationStructureCreateInfoKHR::builder()
             .buffer(buffer.inner)
-            .size(build_size.acceleration_structure_size)
+            .size(size)
             .ty(level);
         let inner = unsafe {
             ray_tracing
@@ -54,27 +226,6 @@ impl AccelerationStructure {
                 .create_acceleration_structure(&create_info, None)?
         };
 
-        let scratch_buffer = context.create_buffer(
-            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-            MemoryLocation::GpuOnly,
-            build_size.build_scratch_size,
-        )?;
-        let scratch_buffer_address = scratch_buffer.get_device_address();
-
-        let build_geo_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
-            .ty(level)
-            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
-            .geometries(as_geometry)
-            .dst_acceleration_structure(inner)
-            .scratch_data(vk::DeviceOrHostAddressKHR {
-                device_address: scratch_buffer_address,
-            });
-
-        context.execute_one_time_commands(|cmd_buffer| {
-            cmd_buffer.build_acceleration_structures(&build_geo_info, as_ranges);
-        })?;
-
         let address_info =
             vk::AccelerationStructureDeviceAddressInfoKHR::builder().acceleration_structure(inner);
         let address = unsafe {
@@ -83,13 +234,128 @@ impl AccelerationStructure {
                 .get_acceleration_structure_device_address(&address_info)
         };
 
+        let update_scratch_buffer = flags
+            .contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE)
+            .then(|| ScratchBuffer::new(context, update_scratch_size.max(1)))
+            .transpose()?;
+
         Ok(Self {
+            device: context.device.clone(),
             ray_tracing,
             inner,
             _buffer: buffer,
             address,
+            level,
+            flags,
+            size,
+            build_scratch_size,
+            update_scratch_size,
+            update_scratch_buffer,
+        })
+    }
+
+    pub(crate) fn build_info(
+        &self,
+        geometries: &[vk::AccelerationStructureGeometryKHR],
+        mode: vk::BuildAccelerationStructureModeKHR,
+        scratch_address: u64,
+    ) -> vk::AccelerationStructureBuildGeometryInfoKHR {
+        let mut build_geo_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(self.level)
+            .mode(mode)
+            .flags(self.flags)
+            .geometries(geometries)
+            .dst_acceleration_structure(self.inner)
+            .scratch_data(vk::DeviceOrHostAddressKHR {
+                device_address: scratch_address,
+            });
+
+        if mode == vk::BuildAccelerationStructureModeKHR::UPDATE {
+            build_geo_info = build_geo_info.src_acceleration_structure(self.inner);
+        }
+
+        build_geo_info.build()
+    }
+
+    /// Records an in-place refit with new vertex positions or instance transforms, using the
+    /// scratch buffer of the structure. The topology must be the same as the one it was built
+    /// with. Waits for the previous refit, which used the same scratch memory, and for shaders
+    /// of earlier frames tracing against the structure.
+    pub fn record_update(
+        &self,
+        cmd_buffer: &CommandBuffer,
+        geometries: &[vk::AccelerationStructureGeometryKHR],
+        ranges: &[vk::AccelerationStructureBuildRangeInfoKHR],
+    ) {
+        let scratch_buffer = self
+            .update_scratch_buffer
+            .as_ref()
+            .expect("Acceleration structure was not built with ALLOW_UPDATE");
+
+        cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+            src_stage_mask: build_barrier().src_stage_mask | TRACE_STAGES,
+            ..build_barrier()
+        }]);
+        cmd_buffer.update_acceleration_structure(self, geometries, ranges, scratch_buffer, 0);
+    }
+
+    /// Refits the structure and waits for completion, a convenience for one-off refits. It
+    /// stalls the queue, per-frame refits should use [`AccelerationStructure::record_update`]
+    /// or [`CommandBuffer::update_acceleration_structure`] instead.
+    pub fn update(
+        &self,
+        context: &Context,
+        geometries: &[vk::AccelerationStructureGeometryKHR],
+        ranges: &[vk::AccelerationStructureBuildRangeInfoKHR],
+    ) -> Result<()> {
+        context.execute_one_time_commands(|cmd_buffer| {
+            self.record_update(cmd_buffer, geometries, ranges);
         })
     }
+
+    /// Creates a compacted copy of the structure. Prefer
+    /// [`Context::compact_acceleration_structures`] for many structures.
+    pub fn compact(&self, context: &Context) -> Result<AccelerationStructure> {
+        let mut compacted = context.compact_acceleration_structures(&[self])?;
+
+        Ok(compacted.remove(0))
+    }
+}
+
+impl CommandBuffer {
+    /// Records an in-place refit of `acceleration_structure`, which must have been built with
+    /// `ALLOW_UPDATE`. `scratch_buffer` needs `update_scratch_size` bytes from
+    /// `scratch_offset` and must not be in use by another build.
+    ///
+    /// The way to refit every frame, sharing scratch memory between structures. See
+    /// [`AccelerationStructure::record_update`] to use the scratch buffer of the structure.
+    pub fn update_acceleration_structure(
+        &self,
+        acceleration_structure: &AccelerationStructure,
+        geometries: &[vk::AccelerationStructureGeometryKHR],
+        ranges: &[vk::AccelerationStructureBuildRangeInfoKHR],
+        scratch_buffer: &ScratchBuffer,
+        scratch_offset: vk::DeviceSize,
+    ) {
+        assert!(
+            acceleration_structure
+                .flags
+                .contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE),
+            "Acceleration structure was not built with ALLOW_UPDATE"
+        );
+        assert!(
+            scratch_offset + acceleration_structure.update_scratch_size <= scratch_buffer.size,
+            "Scratch buffer is too small to update the acceleration structure"
+        );
+
+        let build_geo_info = acceleration_structure.build_info(
+            geometries,
+            vk::BuildAccelerationStructureModeKHR::UPDATE,
+            scratch_buffer.address + scratch_offset,
+        );
+
+        self.build_acceleration_structures(&build_geo_info, ranges);
+    }
 }
 
 impl Context {
@@ -132,14 +398,203 @@ impl Context {
             max_primitive_counts,
         )
     }
+
+    pub fn create_acceleration_structure(
+        &self,
+        create_info: AccelerationStructureCreateInfo,
+    ) -> Result<AccelerationStructure> {
+        let mut structures = self.create_acceleration_structures(&[create_info])?;
+
+        Ok(structures.remove(0))
+    }
+
+    /// Builds all structures with a single submit, sharing one scratch buffer. The structures
+    /// must not depend on each other (e.g. a TLAS on one of the BLASes of the batch).
+    pub fn create_acceleration_structures(
+        &self,
+        create_infos: &[AccelerationStructureCreateInfo],
+    ) -> Result<Vec<AccelerationStructure>> {
+        let ray_tracing = self.ray_tracing.clone().expect(
+            "Cannot call Context::create_acceleration_structures when ray tracing is not enabled",
+        );
+
+        AccelerationStructure::new_batch(self, ray_tracing, create_infos)
+    }
+
+    /// Creates compacted copies of structures built with `ALLOW_COMPACTION`. The originals
+    /// can be dropped afterwards.
+    pub fn compact_acceleration_structures(
+        &self,
+        structures: &[&AccelerationStructure],
+    ) -> Result<Vec<AccelerationStructure>> {
+        let ray_tracing = self.ray_tracing.clone().expect(
+            "Cannot call Context::compact_acceleration_structures when ray tracing is not enabled",
+        );
+        assert!(
+            structures.iter().all(|s| s
+                .flags
+                .contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION)),
+            "Acceleration structures must be built with ALLOW_COMPACTION to be compacted"
+        );
+
+        let query_pool = self.create_query_pool(
+            vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+            structures.len() as _,
+        )?;
+
+        self.execute_one_time_commands(|cmd_buffer| {
+            cmd_buffer.reset_query_pool(&query_pool);
+            cmd_buffer.write_acceleration_structures_properties(structures, &query_pool, 0);
+        })?;
+        let compacted_sizes = query_pool.wait_for_results(0, structures.len() as _)?;
+
+        let compacted = structures
+            .iter()
+            .zip(compacted_sizes)
+            .map(|(structure, size)| {
+                AccelerationStructure::allocate(
+                    self,
+                    ray_tracing.clone(),
+                    structure.level,
+                    structure.flags,
+                    size,
+                    structure.build_scratch_size,
+                    structure.update_scratch_size,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.execute_one_time_commands(|cmd_buffer| {
+            for (src, dst) in structures.iter().zip(&compacted) {
+                cmd_buffer.copy_acceleration_structure(
+                    src,
+                    dst,
+                    vk::CopyAccelerationStructureModeKHR::COMPACT,
+                );
+            }
+        })?;
+
+        Ok(compacted)
+    }
+
+    pub fn create_scratch_buffer(&self, size: vk::DeviceSize) -> Result<ScratchBuffer> {
+        ScratchBuffer::new(self, size)
+    }
 }
 
 impl Drop for AccelerationStructure {
     fn drop(&mut self) {
-        unsafe {
-            self.ray_tracing
-                .acceleration_structure_fn
-                .destroy_acceleration_structure(self.inner, None);
+        // The buffer is dropped after and deferred to the same frame, so it outlives the structure
+        let inner = self.inner;
+        let acceleration_structure_fn = self.ray_tracing.acceleration_structure_fn.clone();
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |_| unsafe {
+                acceleration_structure_fn.destroy_acceleration_structure(inner, None)
+            });
+    }
+}
+
+fn scratch_alignment(context: &Context) -> vk::DeviceSize {
+    context
+        .ray_tracing
+        .as_ref()
+        .map(|rt| {
+            rt.acceleration_structure_properties
+                .min_acceleration_structure_scratch_offset_alignment as _
+        })
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Stages reading acceleration structures, with ray tracing pipelines or ray queries.
+pub(crate) const TRACE_STAGES: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::from_raw(
+    vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR.as_raw()
+        | vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+        | vk::PipelineStageFlags2::COMPUTE_SHADER.as_raw(),
+);
+
+fn build_barrier() -> MemoryBarrier {
+    MemoryBarrier {
+        src_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+        dst_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR
+            | vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+        src_stage_mask: vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+        dst_stage_mask: vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+    }
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ScratchBatch {
+    pub(crate) structures: Range<usize>,
+    pub(crate) offsets: Vec<vk::DeviceSize>,
+    pub(crate) size: vk::DeviceSize,
+}
+
+/// Splits consecutive builds into batches whose aligned scratch memory fits in `budget`.
+/// A build larger than the budget gets a batch of its own.
+pub(crate) fn plan_scratch_batches(
+    scratch_sizes: &[vk::DeviceSize],
+    alignment: vk::DeviceSize,
+    budget: vk::DeviceSize,
+) -> Vec<ScratchBatch> {
+    let mut batches = vec![];
+    let mut current = ScratchBatch {
+        structures: 0..0,
+        offsets: vec![],
+        size: 0,
+    };
+
+    for (index, &scratch_size) in scratch_sizes.iter().enumerate() {
+        let mut offset = align_up(current.size, alignment);
+        if !current.offsets.is_empty() && offset + scratch_size > budget {
+            let next = ScratchBatch {
+                structures: index..index,
+                offsets: vec![],
+                size: 0,
+            };
+            batches.push(std::mem::replace(&mut current, next));
+            offset = 0;
         }
+
+        current.offsets.push(offset);
+        current.structures.end = index + 1;
+        current.size = offset + scratch_size;
     }
+
+    if !current.offsets.is_empty() {
+        batches.push(current);
+    }
+
+    batches
+}
+
+#[test]
+fn test_plan_scratch_batches() {
+    let batches = plan_scratch_batches(&[100, 50, 200, 10], 128, 512);
+
+    assert_eq!(
+        batches,
+        [
+            ScratchBatch {
+                structures: 0..3,
+                offsets: vec![0, 128, 256],
+                size: 456,
+            },
+            ScratchBatch {
+                structures: 3..4,
+                offsets: vec![0],
+                size: 10,
+            },
+        ]
+    );
+
+    // Builds larger than the budget are still built, alone
+    let batches = plan_scratch_batches(&[1000, 1000], 128, 512);
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].size, 1000);
 }
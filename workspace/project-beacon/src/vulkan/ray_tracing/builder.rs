@@ -0,0 +1,626 @@
+use std::{marker::PhantomData, mem::size_of};
+
+use anyhow::Result;
+use ash::vk;
+use glam::Mat4;
+use gpu_allocator::MemoryLocation;
+
+use crate::vulkan::{
+    AccelerationStructure, AccelerationStructureCreateInfo, Buffer, CommandBuffer, Context,
+    MemoryBarrier, ScratchBuffer, SwapchainConfig, TRACE_STAGES,
+};
+
+/// Bottom level acceleration structure geometry built from vertex and index buffers, or from
+/// [`vk::AabbPositionsKHR`] buffers for procedural geometry. A BLAS cannot mix both.
+///
+/// Buffers must be created with `SHADER_DEVICE_ADDRESS` and
+/// `ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR` usages, indices are `u32`.
+pub struct BlasBuilder<'a> {
+    geometries: Vec<vk::AccelerationStructureGeometryKHR>,
+    ranges: Vec<vk::AccelerationStructureBuildRangeInfoKHR>,
+    max_primitive_counts: Vec<u32>,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    _buffers: PhantomData<&'a Buffer>,
+}
+
+impl<'a> Default for BlasBuilder<'a> {
+    fn default() -> Self {
+        Self {
+            geometries: vec![],
+            ranges: vec![],
+            max_primitive_counts: vec![],
+            flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+            _buffers: PhantomData,
+        }
+    }
+}
+
+impl<'a> BlasBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_triangles(
+        vertex_buffer: &'a Buffer,
+        index_buffer: &'a Buffer,
+        format: vk::Format,
+        stride: vk::DeviceSize,
+        opaque: bool,
+    ) -> Self {
+        Self::new().triangles(vertex_buffer, index_buffer, format, stride, opaque)
+    }
+
+    /// Adds a triangle geometry to the structure.
+    pub fn triangles(
+        self,
+        vertex_buffer: &'a Buffer,
+        index_buffer: &'a Buffer,
+        format: vk::Format,
+        stride: vk::DeviceSize,
+        opaque: bool,
+    ) -> Self {
+        let vertex_count = (vertex_buffer.size / stride) as u32;
+        let primitive_count = (index_buffer.size / (3 * size_of::<u32>() as u64)) as u32;
+
+        let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+            .vertex_format(format)
+            .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: vertex_buffer.get_device_address(),
+            })
+            .vertex_stride(stride)
+            .max_vertex(vertex_count.saturating_sub(1))
+            .index_type(vk::IndexType::UINT32)
+            .index_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: index_buffer.get_device_address(),
+            })
+            .build();
+
+        self.geometry(
+            vk::GeometryTypeKHR::TRIANGLES,
+            vk::AccelerationStructureGeometryDataKHR { triangles },
+            primitive_count,
+            opaque,
+        )
+    }
+
+    pub fn from_aabbs(aabb_buffer: &'a Buffer, opaque: bool) -> Self {
+        Self::new().aabbs(aabb_buffer, opaque)
+    }
+
+    /// Adds a procedural geometry made of tightly packed [`vk::AabbPositionsKHR`]. Hits are
+    /// reported by the intersection shader of a procedural hit group.
+    pub fn aabbs(self, aabb_buffer: &'a Buffer, opaque: bool) -> Self {
+        let stride = size_of::<vk::AabbPositionsKHR>() as vk::DeviceSize;
+        let primitive_count = (aabb_buffer.size / stride) as u32;
+
+        let aabbs = vk::AccelerationStructureGeometryAabbsDataKHR::builder()
+            .data(vk::DeviceOrHostAddressConstKHR {
+                device_address: aabb_buffer.get_device_address(),
+            })
+            .stride(stride)
+            .build();
+
+        self.geometry(
+            vk::GeometryTypeKHR::AABBS,
+            vk::AccelerationStructureGeometryDataKHR { aabbs },
+            primitive_count,
+            opaque,
+        )
+    }
+
+    fn geometry(
+        mut self,
+        ty: vk::GeometryTypeKHR,
+        geometry: vk::AccelerationStructureGeometryDataKHR,
+        primitive_count: u32,
+        opaque: bool,
+    ) -> Self {
+        debug_assert!(
+            self.geometries.iter().all(|g| g.geometry_type == ty),
+            "A BLAS cannot contain both triangles and AABBs"
+        );
+
+        let flags = if opaque {
+            vk::GeometryFlagsKHR::OPAQUE
+        } else {
+            vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION
+        };
+
+        self.geometries.push(
+            vk::AccelerationStructureGeometryKHR::builder()
+                .geometry_type(ty)
+                .flags(flags)
+                .geometry(geometry)
+                .build(),
+        );
+        self.ranges.push(
+            vk::AccelerationStructureBuildRangeInfoKHR::builder()
+                .primitive_count(primitive_count)
+                .build(),
+        );
+        self.max_primitive_counts.push(primitive_count);
+
+        self
+    }
+
+    pub fn flags(self, flags: vk::BuildAccelerationStructureFlagsKHR) -> Self {
+        Self { flags, ..self }
+    }
+
+    /// Can be passed to [`Context::create_acceleration_structures`] to build many BLASes at
+    /// once.
+    pub fn create_info(&self) -> AccelerationStructureCreateInfo<'_> {
+        AccelerationStructureCreateInfo {
+            level: vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+            flags: self.flags,
+            geometries: &self.geometries,
+            ranges: &self.ranges,
+            max_primitive_counts: &self.max_primitive_counts,
+        }
+    }
+
+    pub fn build(&self, context: &Context) -> Result<AccelerationStructure> {
+        context.create_acceleration_structure(self.create_info())
+    }
+
+    /// Refits `blas` with the current content of the buffers and waits for completion. See
+    /// [`BlasBuilder::record_update`] for per-frame refits, e.g. after skinning.
+    pub fn update(&self, context: &Context, blas: &AccelerationStructure) -> Result<()> {
+        blas.update(context, &self.geometries, &self.ranges)
+    }
+
+    /// Records a refit of `blas` with the current content of the buffers, using its own
+    /// scratch buffer.
+    pub fn record_update(&self, cmd_buffer: &CommandBuffer, blas: &AccelerationStructure) {
+        blas.record_update(cmd_buffer, &self.geometries, &self.ranges);
+    }
+
+    /// Records a refit of `blas` using scratch memory shared with other refits.
+    pub fn record_update_with_scratch(
+        &self,
+        cmd_buffer: &CommandBuffer,
+        blas: &AccelerationStructure,
+        scratch_buffer: &ScratchBuffer,
+        scratch_offset: vk::DeviceSize,
+    ) {
+        cmd_buffer.update_acceleration_structure(
+            blas,
+            &self.geometries,
+            &self.ranges,
+            scratch_buffer,
+            scratch_offset,
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TlasInstance {
+    pub transform: Mat4,
+    /// `gl_InstanceCustomIndexEXT`, 24 bits.
+    pub custom_index: u32,
+    pub mask: u8,
+    /// Offset of the instance's hit group records in the shader binding table, 24 bits.
+    pub sbt_record_offset: u32,
+    pub flags: vk::GeometryInstanceFlagsKHR,
+    pub blas_address: u64,
+}
+
+impl TlasInstance {
+    pub fn new(blas: &AccelerationStructure, transform: Mat4) -> Self {
+        Self {
+            transform,
+            custom_index: 0,
+            mask: 0xFF,
+            sbt_record_offset: 0,
+            flags: vk::GeometryInstanceFlagsKHR::empty(),
+            blas_address: blas.address,
+        }
+    }
+
+    pub fn custom_index(self, custom_index: u32) -> Self {
+        Self {
+            custom_index,
+            ..self
+        }
+    }
+
+    pub fn mask(self, mask: u8) -> Self {
+        Self { mask, ..self }
+    }
+
+    pub fn sbt_record_offset(self, sbt_record_offset: u32) -> Self {
+        Self {
+            sbt_record_offset,
+            ..self
+        }
+    }
+
+    pub fn flags(self, flags: vk::GeometryInstanceFlagsKHR) -> Self {
+        Self { flags, ..self }
+    }
+
+    pub(crate) fn to_vk(self) -> vk::AccelerationStructureInstanceKHR {
+        debug_assert!(
+            self.custom_index < 1 << 24,
+            "Custom index must fit in 24 bits"
+        );
+        debug_assert!(
+            self.sbt_record_offset < 1 << 24,
+            "SBT record offset must fit in 24 bits"
+        );
+
+        // glam is column major, Vulkan expects the first three rows
+        let rows = self.transform.transpose().to_cols_array();
+        let mut matrix = [0.0; 12];
+        matrix.copy_from_slice(&rows[..12]);
+
+        vk::AccelerationStructureInstanceKHR {
+            transform: vk::TransformMatrixKHR { matrix },
+            instance_custom_index_and_mask: vk::Packed24_8::new(self.custom_index, self.mask),
+            instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
+                self.sbt_record_offset,
+                self.flags.as_raw() as u8,
+            ),
+            acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                device_handle: self.blas_address,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlasBuildMode {
+    Build,
+    Update,
+}
+
+pub struct TlasBuilder {
+    instances: Vec<TlasInstance>,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    frames_in_flight: u32,
+}
+
+impl Default for TlasBuilder {
+    fn default() -> Self {
+        Self {
+            instances: vec![],
+            flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+                | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE,
+            frames_in_flight: SwapchainConfig::DEFAULT_FRAMES_IN_FLIGHT,
+        }
+    }
+}
+
+impl TlasBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn instance(mut self, instance: TlasInstance) -> Self {
+        self.instances.push(instance);
+        self
+    }
+
+    pub fn instances(mut self, instances: impl IntoIterator<Item = TlasInstance>) -> Self {
+        self.instances.extend(instances);
+        self
+    }
+
+    /// Defaults to `PREFER_FAST_TRACE | ALLOW_UPDATE`. Without `ALLOW_UPDATE` the TLAS is
+    /// always rebuilt.
+    pub fn flags(self, flags: vk::BuildAccelerationStructureFlagsKHR) -> Self {
+        Self { flags, ..self }
+    }
+
+    /// Number of instance buffers, cycled by each update. Must be at least the
+    /// [`SwapchainConfig::frames_in_flight`] of the app when using [`Tlas::record_update`],
+    /// defaults to [`SwapchainConfig::DEFAULT_FRAMES_IN_FLIGHT`].
+    pub fn frames_in_flight(self, frames_in_flight: u32) -> Self {
+        Self {
+            frames_in_flight,
+            ..self
+        }
+    }
+
+    pub fn build(self, context: &Context) -> Result<Tlas> {
+        Tlas::new(
+            context,
+            &self.instances,
+            self.flags,
+            self.frames_in_flight.max(1),
+        )
+    }
+}
+
+/// Top level acceleration structure owning its instance buffers.
+///
+/// Setting new instances refits the structure when only transforms (or masks, custom indices,
+/// ...) changed, and rebuilds it otherwise, in place. Recorded updates wait for the traces and
+/// builds recorded before them, so frames still in flight can use the structure.
+///
+/// Instances are written from the CPU, each update uses the next of one instance buffer per
+/// frame in flight so that builds of previous frames still read theirs. Growing past the
+/// instance capacity recreates the structure, see [`Tlas::generation`].
+pub struct Tlas {
+    pub acceleration_structure: AccelerationStructure,
+    instance_buffers: Vec<Buffer>,
+    instance_buffer_index: usize,
+    scratch_buffer: ScratchBuffer,
+    capacity: u32,
+    blas_addresses: Vec<u64>,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    generation: u64,
+}
+
+impl Tlas {
+    fn new(
+        context: &Context,
+        instances: &[TlasInstance],
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+        frames_in_flight: u32,
+    ) -> Result<Self> {
+        let mut tlas = Self::with_capacity(
+            context,
+            instances.len().max(1) as _,
+            flags,
+            frames_in_flight,
+        )?;
+
+        context.execute_one_time_commands(|cmd_buffer| {
+            tlas.record(cmd_buffer, instances, TlasBuildMode::Build)
+        })??;
+
+        Ok(tlas)
+    }
+
+    fn with_capacity(
+        context: &Context,
+        capacity: u32,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+        frames_in_flight: u32,
+    ) -> Result<Self> {
+        let ray_tracing = context
+            .ray_tracing
+            .clone()
+            .expect("Cannot create a Tlas when ray tracing is not enabled");
+
+        let instance_buffers = (0..frames_in_flight)
+            .map(|_| {
+                context.create_buffer(
+                    vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                    MemoryLocation::CpuToGpu,
+                    (capacity as usize * size_of::<vk::AccelerationStructureInstanceKHR>()) as _,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let acceleration_structure = AccelerationStructure::with_capacity(
+            context,
+            ray_tracing,
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            flags,
+            &[instances_geometry(&instance_buffers[0])],
+            &[capacity],
+        )?;
+
+        let scratch_buffer = context.create_scratch_buffer(
+            acceleration_structure
+                .build_scratch_size
+                .max(acceleration_structure.update_scratch_size),
+        )?;
+
+        Ok(Self {
+            acceleration_structure,
+            instance_buffers,
+            instance_buffer_index: 0,
+            scratch_buffer,
+            capacity,
+            blas_addresses: vec![],
+            flags,
+            generation: 0,
+        })
+    }
+
+    /// Sets the instances and waits for the TLAS to be built or refitted.
+    pub fn update(
+        &mut self,
+        context: &Context,
+        instances: &[TlasInstance],
+    ) -> Result<TlasBuildMode> {
+        self.reserve(context, instances.len())?;
+
+        let mode = self.build_mode(instances);
+        context
+            .execute_one_time_commands(|cmd_buffer| self.record(cmd_buffer, instances, mode))??;
+
+        Ok(mode)
+    }
+
+    /// Sets the instances and records the TLAS build or refit into `cmd_buffer`, followed by
+    /// a barrier making it visible to ray tracing, fragment and compute shaders. At most
+    /// [`TlasBuilder::frames_in_flight`] recorded updates can be pending on the GPU.
+    pub fn record_update(
+        &mut self,
+        context: &Context,
+        cmd_buffer: &CommandBuffer,
+        instances: &[TlasInstance],
+    ) -> Result<TlasBuildMode> {
+        self.reserve(context, instances.len())?;
+
+        let mode = self.build_mode(instances);
+        self.record(cmd_buffer, instances, mode)?;
+
+        Ok(mode)
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.blas_addresses.len()
+    }
+
+    /// Incremented when an update recreates the structure with a larger capacity. Descriptor
+    /// sets referencing [`Tlas::acceleration_structure`] must then be written again.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The previous structure is destroyed once the frames in flight are done with it.
+    fn reserve(&mut self, context: &Context, instance_count: usize) -> Result<()> {
+        if instance_count as u32 > self.capacity {
+            let capacity = (instance_count as u32).next_power_of_two();
+            let frames_in_flight = self.instance_buffers.len() as _;
+            let generation = self.generation + 1;
+            *self = Self::with_capacity(context, capacity, self.flags, frames_in_flight)?;
+            self.generation = generation;
+        }
+
+        Ok(())
+    }
+
+    fn build_mode(&self, instances: &[TlasInstance]) -> TlasBuildMode {
+        choose_build_mode(
+            self.flags,
+            &self.blas_addresses,
+            instances.iter().map(|i| i.blas_address),
+        )
+    }
+
+    fn record(
+        &mut self,
+        cmd_buffer: &CommandBuffer,
+        instances: &[TlasInstance],
+        mode: TlasBuildMode,
+    ) -> Result<()> {
+        // Buffers of the previous updates may still be read by the builds of frames in flight
+        self.instance_buffer_index = (self.instance_buffer_index + 1) % self.instance_buffers.len();
+        let instance_buffer = &mut self.instance_buffers[self.instance_buffer_index];
+
+        let vk_instances = instances.iter().map(|i| i.to_vk()).collect::<Vec<_>>();
+        instance_buffer.copy_data_to_buffer(&vk_instances)?;
+        self.blas_addresses = instances.iter().map(|i| i.blas_address).collect();
+
+        let geometries = [instances_geometry(instance_buffer)];
+        let ranges = [vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(instances.len() as _)
+            .build()];
+
+        let mode = match mode {
+            TlasBuildMode::Build => vk::BuildAccelerationStructureModeKHR::BUILD,
+            TlasBuildMode::Update => vk::BuildAccelerationStructureModeKHR::UPDATE,
+        };
+        let build_geo_info = self.acceleration_structure.build_info(
+            &geometries,
+            mode,
+            self.scratch_buffer.address(),
+        );
+
+        // Previous traces and builds must be done before the structure and the scratch buffer
+        // are overwritten
+        cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+            dst_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR
+                | vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+            src_stage_mask: TRACE_STAGES
+                | vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+            dst_stage_mask: vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+        }]);
+
+        cmd_buffer.build_acceleration_structures(&build_geo_info, &ranges);
+
+        cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+            src_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+            dst_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR,
+            src_stage_mask: vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+            dst_stage_mask: TRACE_STAGES,
+        }]);
+
+        Ok(())
+    }
+}
+
+fn instances_geometry(instance_buffer: &Buffer) -> vk::AccelerationStructureGeometryKHR {
+    let instances = vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+        .array_of_pointers(false)
+        .data(vk::DeviceOrHostAddressConstKHR {
+            device_address: instance_buffer.get_device_address(),
+        })
+        .build();
+
+    vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+        .geometry(vk::AccelerationStructureGeometryDataKHR { instances })
+        .build()
+}
+
+/// Refitting is only possible when the TLAS allows it and has been built once with the same
+/// number of instances. Instances pointing to different BLASes would degrade the quality of
+/// a refitted TLAS too much so those trigger a rebuild as well.
+fn choose_build_mode(
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    built_blas_addresses: &[u64],
+    blas_addresses: impl ExactSizeIterator<Item = u64>,
+) -> TlasBuildMode {
+    let can_update = flags.contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE)
+        && !built_blas_addresses.is_empty()
+        && built_blas_addresses.len() == blas_addresses.len();
+
+    if can_update && built_blas_addresses.iter().copied().eq(blas_addresses) {
+        TlasBuildMode::Update
+    } else {
+        TlasBuildMode::Build
+    }
+}
+
+#[test]
+fn test_choose_build_mode() {
+    use vk::BuildAccelerationStructureFlagsKHR as F;
+
+    let allow_update = F::PREFER_FAST_TRACE | F::ALLOW_UPDATE;
+
+    assert_eq!(
+        choose_build_mode(allow_update, &[], [1, 2].into_iter()),
+        TlasBuildMode::Build
+    );
+    assert_eq!(
+        choose_build_mode(allow_update, &[1, 2], [1, 2].into_iter()),
+        TlasBuildMode::Update
+    );
+    assert_eq!(
+        choose_build_mode(allow_update, &[1, 2], [1, 2, 3].into_iter()),
+        TlasBuildMode::Build
+    );
+    assert_eq!(
+        choose_build_mode(allow_update, &[1, 2], [1, 3].into_iter()),
+        TlasBuildMode::Build
+    );
+    assert_eq!(
+        choose_build_mode(F::PREFER_FAST_TRACE, &[1, 2], [1, 2].into_iter()),
+        TlasBuildMode::Build
+    );
+}
+
+#[test]
+fn test_instance_transform_is_row_major() {
+    let instance = TlasInstance {
+        transform: Mat4::from_translation(glam::vec3(1.0, 2.0, 3.0)),
+        custom_index: 7,
+        mask: 0xAB,
+        sbt_record_offset: 2,
+        flags: vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+        blas_address: 42,
+    }
+    .to_vk();
+
+    assert_eq!(
+        instance.transform.matrix,
+        [1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 2.0, 0.0, 0.0, 1.0, 3.0]
+    );
+    assert_eq!(instance.instance_custom_index_and_mask.low_24(), 7);
+    assert_eq!(instance.instance_custom_index_and_mask.high_8(), 0xAB);
+    assert_eq!(
+        instance
+            .instance_shader_binding_table_record_offset_and_flags
+            .high_8(),
+        vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE.as_raw() as u8
+    );
+}
@@ -1,8 +1,10 @@
 mod acceleration_structure;
+mod builder;
 mod pipeline;
 mod shader_binding_table;
 
 pub use acceleration_structure::*;
+pub use builder::*;
 pub use pipeline::*;
 pub use shader_binding_table::*;
 
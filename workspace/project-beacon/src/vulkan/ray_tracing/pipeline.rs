@@ -1,6 +1,6 @@
 use std::{ffi::CString, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use ash::vk;
 
 use crate::vulkan::{device::Device, Context};
@@ -10,6 +10,10 @@ use crate::vulkan::{PipelineLayout, RayTracingContext, ShaderModule};
 #[derive(Debug, Clone, Copy)]
 pub struct RayTracingPipelineCreateInfo<'a> {
     pub shaders: &'a [RayTracingShaderCreateInfo<'a>],
+    /// Hit groups with any-hit or intersection shaders. They are placed in the hit region of
+    /// the shader binding table after the `ClosestHit` shaders of `shaders`.
+    pub hit_groups: &'a [RayTracingHitGroup<'a>],
+    /// At most the device's `maxRayRecursionDepth`.
     pub max_ray_recursion_depth: u32,
 }
 
@@ -24,7 +28,85 @@ pub struct RayTracingShaderCreateInfo<'a> {
 pub enum RayTracingShaderGroup {
     RayGen,
     Miss,
+    /// Triangles hit group with only a closest hit shader.
     ClosestHit,
+    Callable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayTracingHitGroupType {
+    Triangles,
+    Procedural,
+}
+
+/// Closest hit, any hit and intersection shaders invoked together on a ray hit.
+///
+/// Triangles groups can use an any hit shader for alpha testing but no intersection shader.
+/// Procedural groups are used with AABB geometries and require an intersection shader.
+#[derive(Debug, Clone, Copy)]
+pub struct RayTracingHitGroup<'a> {
+    pub ty: RayTracingHitGroupType,
+    pub closest_hit_source: Option<&'a [u8]>,
+    pub any_hit_source: Option<&'a [u8]>,
+    pub intersection_source: Option<&'a [u8]>,
+}
+
+impl<'a> RayTracingHitGroup<'a> {
+    pub fn triangles() -> Self {
+        Self {
+            ty: RayTracingHitGroupType::Triangles,
+            closest_hit_source: None,
+            any_hit_source: None,
+            intersection_source: None,
+        }
+    }
+
+    pub fn procedural(intersection_source: &'a [u8]) -> Self {
+        Self {
+            ty: RayTracingHitGroupType::Procedural,
+            intersection_source: Some(intersection_source),
+            ..Self::triangles()
+        }
+    }
+
+    pub fn closest_hit(self, source: &'a [u8]) -> Self {
+        Self {
+            closest_hit_source: Some(source),
+            ..self
+        }
+    }
+
+    pub fn any_hit(self, source: &'a [u8]) -> Self {
+        Self {
+            any_hit_source: Some(source),
+            ..self
+        }
+    }
+}
+
+impl RayTracingPipelineCreateInfo<'_> {
+    fn validate(&self, device_max_ray_recursion_depth: u32) -> Result<()> {
+        ensure!(
+            self.max_ray_recursion_depth <= device_max_ray_recursion_depth,
+            "Ray recursion depth {} exceeds the device limit of {device_max_ray_recursion_depth}",
+            self.max_ray_recursion_depth
+        );
+
+        for hit_group in self.hit_groups {
+            match hit_group.ty {
+                RayTracingHitGroupType::Triangles => ensure!(
+                    hit_group.intersection_source.is_none(),
+                    "Triangles hit groups can't have an intersection shader"
+                ),
+                RayTracingHitGroupType::Procedural => ensure!(
+                    hit_group.intersection_source.is_some(),
+                    "Procedural hit groups require an intersection shader"
+                ),
+            }
+        }
+
+        Ok(())
+    }
 }
 
 pub struct RayTracingPipeline {
@@ -33,12 +115,14 @@ pub struct RayTracingPipeline {
     pub(crate) shader_group_info: RayTracingShaderGroupInfo,
 }
 
+/// Groups are ordered by type: raygen, miss, hit then callable.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct RayTracingShaderGroupInfo {
     pub group_count: u32,
     pub raygen_shader_count: u32,
     pub miss_shader_count: u32,
     pub hit_shader_count: u32,
+    pub callable_shader_count: u32,
 }
 
 impl RayTracingPipeline {
@@ -48,57 +132,113 @@ impl RayTracingPipeline {
         layout: &PipelineLayout,
         create_info: RayTracingPipelineCreateInfo,
     ) -> Result<Self> {
-        let mut shader_group_info = RayTracingShaderGroupInfo {
-            group_count: create_info.shaders.len() as _,
-            ..Default::default()
-        };
+        create_info.validate(ray_tracing.pipeline_properties.max_ray_recursion_depth)?;
 
         let mut modules = vec![];
         let mut stages = vec![];
-        let mut groups = vec![];
+
+        let mut raygen_groups = vec![];
+        let mut miss_groups = vec![];
+        let mut hit_groups = vec![];
+        let mut callable_groups = vec![];
 
         let entry_point_name = CString::new("main").unwrap();
 
-        for (shader_index, shader) in create_info.shaders.iter().enumerate() {
-            let module = ShaderModule::from_bytes(device.clone(), shader.source)?;
+        let mut add_stage = |source: &[u8], stage: vk::ShaderStageFlags| -> Result<u32> {
+            let module = ShaderModule::from_bytes(device.clone(), source)?;
 
-            let stage = vk::PipelineShaderStageCreateInfo::builder()
-                .stage(shader.stage)
-                .module(module.inner)
-                .name(&entry_point_name)
-                .build();
+            stages.push(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(stage)
+                    .module(module.inner)
+                    .name(&entry_point_name)
+                    .build(),
+            );
+            modules.push(module);
 
-            match shader.group {
-                RayTracingShaderGroup::RayGen => shader_group_info.raygen_shader_count += 1,
-                RayTracingShaderGroup::Miss => shader_group_info.miss_shader_count += 1,
-                RayTracingShaderGroup::ClosestHit => shader_group_info.hit_shader_count += 1,
-            };
+            Ok(stages.len() as u32 - 1)
+        };
 
-            let mut group = vk::RayTracingShaderGroupCreateInfoKHR::builder()
+        let unused_group = || {
+            vk::RayTracingShaderGroupCreateInfoKHR::builder()
                 .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
                 .general_shader(vk::SHADER_UNUSED_KHR)
                 .closest_hit_shader(vk::SHADER_UNUSED_KHR)
                 .any_hit_shader(vk::SHADER_UNUSED_KHR)
-                .intersection_shader(vk::SHADER_UNUSED_KHR);
-            group = match shader.group {
-                RayTracingShaderGroup::RayGen | RayTracingShaderGroup::Miss => {
-                    group.general_shader(shader_index as _)
+                .intersection_shader(vk::SHADER_UNUSED_KHR)
+        };
+
+        for shader in create_info.shaders {
+            let shader_index = add_stage(shader.source, shader.stage)?;
+
+            match shader.group {
+                RayTracingShaderGroup::RayGen => {
+                    raygen_groups.push(unused_group().general_shader(shader_index).build())
+                }
+                RayTracingShaderGroup::Miss => {
+                    miss_groups.push(unused_group().general_shader(shader_index).build())
+                }
+                RayTracingShaderGroup::ClosestHit => hit_groups.push(
+                    unused_group()
+                        .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                        .closest_hit_shader(shader_index)
+                        .build(),
+                ),
+                RayTracingShaderGroup::Callable => {
+                    callable_groups.push(unused_group().general_shader(shader_index).build())
                 }
-                RayTracingShaderGroup::ClosestHit => group
-                    .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
-                    .closest_hit_shader(shader_index as _),
             };
+        }
 
-            modules.push(module);
-            stages.push(stage);
-            groups.push(group.build());
+        for hit_group in create_info.hit_groups {
+            let mut group = unused_group();
+
+            group = group.ty(match hit_group.ty {
+                RayTracingHitGroupType::Triangles => {
+                    vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP
+                }
+                RayTracingHitGroupType::Procedural => {
+                    vk::RayTracingShaderGroupTypeKHR::PROCEDURAL_HIT_GROUP
+                }
+            });
+
+            // Only set for procedural groups, see `validate`
+            if let Some(source) = hit_group.intersection_source {
+                group = group.intersection_shader(add_stage(
+                    source,
+                    vk::ShaderStageFlags::INTERSECTION_KHR,
+                )?);
+            }
+
+            if let Some(source) = hit_group.closest_hit_source {
+                group = group
+                    .closest_hit_shader(add_stage(source, vk::ShaderStageFlags::CLOSEST_HIT_KHR)?);
+            }
+            if let Some(source) = hit_group.any_hit_source {
+                group = group.any_hit_shader(add_stage(source, vk::ShaderStageFlags::ANY_HIT_KHR)?);
+            }
+
+            hit_groups.push(group.build());
         }
 
+        let shader_group_info = RayTracingShaderGroupInfo {
+            group_count: (raygen_groups.len()
+                + miss_groups.len()
+                + hit_groups.len()
+                + callable_groups.len()) as _,
+            raygen_shader_count: raygen_groups.len() as _,
+            miss_shader_count: miss_groups.len() as _,
+            hit_shader_count: hit_groups.len() as _,
+            callable_shader_count: callable_groups.len() as _,
+        };
+
+        let groups = [raygen_groups, miss_groups, hit_groups, callable_groups].concat();
+
         let pipe_info = vk::RayTracingPipelineCreateInfoKHR::builder()
             .layout(layout.inner)
             .stages(&stages)
             .groups(&groups)
-            .max_pipeline_ray_recursion_depth(2);
+            .max_pipeline_ray_recursion_depth(create_info.max_ray_recursion_depth);
 
         let inner = unsafe {
             ray_tracing.pipeline_fn.create_ray_tracing_pipelines(
@@ -133,6 +273,43 @@ impl Context {
 
 impl Drop for RayTracingPipeline {
     fn drop(&mut self) {
-        unsafe { self.device.inner.destroy_pipeline(self.inner, None) };
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_pipeline(inner, None)
+            });
     }
 }
+
+#[test]
+fn test_validate_create_info() {
+    let source = &[0u8; 4][..];
+    let validate = |max_ray_recursion_depth, hit_groups| {
+        RayTracingPipelineCreateInfo {
+            shaders: &[],
+            hit_groups,
+            max_ray_recursion_depth,
+        }
+        .validate(1)
+    };
+
+    let hit_groups = [
+        RayTracingHitGroup::triangles().any_hit(source),
+        RayTracingHitGroup::procedural(source),
+    ];
+    assert!(validate(1, &hit_groups).is_ok());
+    assert!(validate(2, &hit_groups).is_err());
+
+    let triangles_with_intersection = [RayTracingHitGroup {
+        intersection_source: Some(source),
+        ..RayTracingHitGroup::triangles()
+    }];
+    assert!(validate(1, &triangles_with_intersection).is_err());
+
+    let procedural_without_intersection = [RayTracingHitGroup {
+        intersection_source: None,
+        ..RayTracingHitGroup::procedural(source)
+    }];
+    assert!(validate(1, &procedural_without_intersection).is_err());
+}
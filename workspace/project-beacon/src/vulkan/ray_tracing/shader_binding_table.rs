@@ -1,144 +1,325 @@
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use ash::vk;
 use gpu_allocator::MemoryLocation;
 
-use crate::vulkan::{utils::compute_aligned_size, Buffer, Context, RayTracingContext, RayTracingPipeline};
+use crate::vulkan::{bytes_of, Buffer, Context, Pod, RayTracingContext, RayTracingPipeline};
 
 pub struct ShaderBindingTable {
     _buffer: Buffer,
     pub(crate) raygen_region: vk::StridedDeviceAddressRegionKHR,
     pub(crate) miss_region: vk::StridedDeviceAddressRegionKHR,
     pub(crate) hit_region: vk::StridedDeviceAddressRegionKHR,
+    pub(crate) callable_region: vk::StridedDeviceAddressRegionKHR,
+}
+
+/// A shader group handle followed by optional inline data, read in shaders through a
+/// `shaderRecordEXT` buffer block.
+#[derive(Debug, Clone)]
+pub struct ShaderRecord {
+    /// Index of the group among the pipeline's groups of the same type, e.g. 1 for the second
+    /// miss shader.
+    pub group: u32,
+    pub data: Vec<u8>,
+}
+
+impl ShaderRecord {
+    pub fn new(group: u32) -> Self {
+        Self {
+            group,
+            data: vec![],
+        }
+    }
+
+    pub fn data<T: Pod>(self, data: &T) -> Self {
+        Self {
+            data: bytes_of(data).to_vec(),
+            ..self
+        }
+    }
+}
+
+impl From<u32> for ShaderRecord {
+    fn from(group: u32) -> Self {
+        Self::new(group)
+    }
+}
+
+/// Lays out the records of a shader binding table.
+///
+/// Records are placed in their region in the order they are added. When tracing, the hit
+/// record used is `instanceShaderBindingTableRecordOffset + sbtRecordOffset +
+/// geometryIndex * sbtRecordStride` and the miss record is `missIndex`, so with two ray
+/// types (e.g. primary and shadow) hit groups are added in pairs and traced with a stride of 2.
+pub struct ShaderBindingTableBuilder<'a> {
+    pipeline: &'a RayTracingPipeline,
+    raygen: Option<ShaderRecord>,
+    miss: Vec<ShaderRecord>,
+    hit: Vec<ShaderRecord>,
+    callable: Vec<ShaderRecord>,
+}
+
+impl<'a> ShaderBindingTableBuilder<'a> {
+    pub fn new(pipeline: &'a RayTracingPipeline) -> Self {
+        Self {
+            pipeline,
+            raygen: None,
+            miss: vec![],
+            hit: vec![],
+            callable: vec![],
+        }
+    }
+
+    /// Sets the ray generation record, there is exactly one per table.
+    pub fn raygen(mut self, record: impl Into<ShaderRecord>) -> Self {
+        self.raygen = Some(record.into());
+        self
+    }
+
+    pub fn miss(mut self, record: impl Into<ShaderRecord>) -> Self {
+        self.miss.push(record.into());
+        self
+    }
+
+    pub fn hit_group(mut self, record: impl Into<ShaderRecord>) -> Self {
+        self.hit.push(record.into());
+        self
+    }
+
+    pub fn callable(mut self, record: impl Into<ShaderRecord>) -> Self {
+        self.callable.push(record.into());
+        self
+    }
+
+    /// Fails if no raygen record was set.
+    pub fn build(self, context: &Context) -> Result<ShaderBindingTable> {
+        ensure!(
+            self.raygen.is_some(),
+            "A shader binding table needs a raygen record"
+        );
+
+        let ray_tracing = context
+            .ray_tracing
+            .as_ref()
+            .expect("Cannot create a shader binding table when ray tracing is not enabled");
+
+        ShaderBindingTable::new(context, ray_tracing, self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RegionLayout {
+    pub(crate) offset: u64,
+    pub(crate) stride: u64,
+    pub(crate) size: u64,
+}
+
+/// Computes the raygen, miss, hit and callable regions from their `(record count, max
+/// record data size)`. Regions start at `base_alignment` and records are
+/// `handle_alignment` aligned.
+pub(crate) fn layout_regions(
+    handle_size: u32,
+    handle_alignment: u32,
+    base_alignment: u32,
+    regions: [(usize, usize); 4],
+) -> [RegionLayout; 4] {
+    let mut offset = 0;
+
+    regions.map(|(count, data_size)| {
+        if count == 0 {
+            return RegionLayout::default();
+        }
+
+        let stride = align_up(
+            handle_size as u64 + data_size as u64,
+            handle_alignment as u64,
+        );
+        let layout = RegionLayout {
+            offset,
+            stride,
+            size: stride * count as u64,
+        };
+        offset = align_up(offset + layout.size, base_alignment as u64);
+
+        layout
+    })
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
 }
 
 impl ShaderBindingTable {
-    pub(crate) fn new(
+    fn new(
         context: &Context,
         ray_tracing: &RayTracingContext,
-        pipeline: &RayTracingPipeline,
+        builder: ShaderBindingTableBuilder,
     ) -> Result<Self> {
-        let desc = pipeline.shader_group_info;
-
-        // Handle size & aligment
-        let handle_size = ray_tracing.pipeline_properties.shader_group_handle_size;
-        let handle_alignment = ray_tracing
-            .pipeline_properties
-            .shader_group_handle_alignment;
-        let aligned_handle_size = compute_aligned_size(handle_size, handle_alignment);
-        let handle_pad = aligned_handle_size - handle_size;
+        let desc = builder.pipeline.shader_group_info;
+        let properties = &ray_tracing.pipeline_properties;
+        let handle_size = properties.shader_group_handle_size as usize;
 
-        let group_alignment = ray_tracing.pipeline_properties.shader_group_base_alignment;
-
-        // Get Handles
-        let data_size = desc.group_count * handle_size;
         let handles = unsafe {
             ray_tracing
                 .pipeline_fn
                 .get_ray_tracing_shader_group_handles(
-                    pipeline.inner,
+                    builder.pipeline.inner,
                     0,
                     desc.group_count,
-                    data_size as _,
+                    desc.group_count as usize * handle_size,
                 )?
         };
 
-        // Region sizes
-        let raygen_region_size = compute_aligned_size(
-            desc.raygen_shader_count * aligned_handle_size,
-            group_alignment,
-        );
-
-        let miss_region_size = compute_aligned_size(
-            desc.miss_shader_count * aligned_handle_size,
-            group_alignment,
-        );
-        let hit_region_size =
-            compute_aligned_size(desc.hit_shader_count * aligned_handle_size, group_alignment);
-
-        // Create sbt data
-        let buffer_size = raygen_region_size + miss_region_size + hit_region_size;
-        let mut stb_data = Vec::<u8>::with_capacity(buffer_size as _);
-        let groups_shader_count = [
-            desc.raygen_shader_count,
-            desc.miss_shader_count,
-            desc.hit_shader_count,
+        // Groups are ordered by type in the pipeline
+        let regions = [
+            (
+                builder.raygen.into_iter().collect::<Vec<_>>(),
+                0,
+                desc.raygen_shader_count,
+            ),
+            (
+                builder.miss,
+                desc.raygen_shader_count,
+                desc.miss_shader_count,
+            ),
+            (
+                builder.hit,
+                desc.raygen_shader_count + desc.miss_shader_count,
+                desc.hit_shader_count,
+            ),
+            (
+                builder.callable,
+                desc.raygen_shader_count + desc.miss_shader_count + desc.hit_shader_count,
+                desc.callable_shader_count,
+            ),
         ];
 
-        let mut offset = 0;
-        // for each groups
-        for group_shader_count in groups_shader_count {
-            let group_size = group_shader_count * aligned_handle_size;
-            let aligned_group_size = compute_aligned_size(group_size, group_alignment);
-            let group_pad = aligned_group_size - group_size;
-
-            // for each handle
-            for _ in 0..group_shader_count {
-                //copy handle
-                for _ in 0..handle_size as usize {
-                    stb_data.push(handles[offset]);
-                    offset += 1;
-                }
-
-                // pad handle to alignment
-                for _ in 0..handle_pad {
-                    stb_data.push(0);
-                }
-            }
+        let layouts = layout_regions(
+            properties.shader_group_handle_size,
+            properties.shader_group_handle_alignment,
+            properties.shader_group_base_alignment,
+            regions
+                .each_ref()
+                .map(|(records, ..)| (records.len(), max_data_size(records))),
+        );
 
-            // pad group to alignment
-            for _ in 0..group_pad {
-                stb_data.push(0);
-            }
+        for layout in &layouts {
+            assert!(
+                layout.stride <= properties.max_shader_group_stride as u64,
+                "Shader record stride {} exceeds the device limit of {}",
+                layout.stride,
+                properties.max_shader_group_stride
+            );
         }
 
-        // Create buffer
-        let buffer_usage = vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
-            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
-        let memory_location = MemoryLocation::CpuToGpu;
+        // Buffer addresses are not guaranteed to be aligned to the region base alignment
+        let base_alignment = properties.shader_group_base_alignment as u64;
+        let table_size = layouts.iter().map(|l| l.offset + l.size).max().unwrap_or(0);
+        let buffer = context.create_buffer(
+            vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryLocation::CpuToGpu,
+            table_size.max(1) + base_alignment,
+        )?;
+        let buffer_address = buffer.get_device_address();
+        let start = align_up(buffer_address, base_alignment) - buffer_address;
 
-        let buffer = context.create_buffer(buffer_usage, memory_location, buffer_size as _)?;
+        let mut data = vec![0u8; (start + table_size) as usize];
+        for ((records, first_group, group_count), layout) in regions.iter().zip(&layouts) {
+            for (index, record) in records.iter().enumerate() {
+                assert!(
+                    record.group < *group_count,
+                    "Shader record references group {} but the pipeline only has {} of this type",
+                    record.group,
+                    group_count
+                );
 
-        buffer.copy_data_to_buffer(&stb_data)?;
+                let handle_offset = (first_group + record.group) as usize * handle_size;
+                let record_offset = (start + layout.offset + layout.stride * index as u64) as usize;
 
-        let address = buffer.get_device_address();
+                data[record_offset..record_offset + handle_size]
+                    .copy_from_slice(&handles[handle_offset..handle_offset + handle_size]);
+                data[record_offset + handle_size..record_offset + handle_size + record.data.len()]
+                    .copy_from_slice(&record.data);
+            }
+        }
+
+        buffer.copy_data_to_buffer(&data)?;
 
         // see https://nvpro-samples.github.io/vk_raytracing_tutorial_KHR/Images/sbt_0.png
-        let raygen_region = vk::StridedDeviceAddressRegionKHR::builder()
-            .device_address(address)
-            .size(raygen_region_size as _)
-            .stride(raygen_region_size as _)
-            .build();
-
-        let miss_region = vk::StridedDeviceAddressRegionKHR::builder()
-            .device_address(address + raygen_region.size)
-            .size(miss_region_size as _)
-            .stride(aligned_handle_size as _)
-            .build();
-
-        let hit_region = vk::StridedDeviceAddressRegionKHR::builder()
-            .device_address(address + raygen_region.size + miss_region.size)
-            .size(hit_region_size as _)
-            .stride(aligned_handle_size as _)
-            .build();
+        // Empty regions must have a null address
+        let [raygen_region, miss_region, hit_region, callable_region] = layouts.map(|layout| {
+            if layout.size == 0 {
+                return vk::StridedDeviceAddressRegionKHR::default();
+            }
+
+            vk::StridedDeviceAddressRegionKHR::builder()
+                .device_address(buffer_address + start + layout.offset)
+                .size(layout.size)
+                .stride(layout.stride)
+                .build()
+        });
 
         Ok(Self {
             _buffer: buffer,
             raygen_region,
             miss_region,
             hit_region,
+            callable_region,
         })
     }
 }
 
+fn max_data_size(records: &[ShaderRecord]) -> usize {
+    records.iter().map(|r| r.data.len()).max().unwrap_or(0)
+}
+
 impl Context {
+    /// Shader binding table with the first raygen group and all other groups of the pipeline
+    /// in order, without record data.
     pub fn create_shader_binding_table(
         &self,
         pipeline: &RayTracingPipeline,
     ) -> Result<ShaderBindingTable> {
-        let ray_tracing = self.ray_tracing.as_ref().expect(
-            "Cannot call Context::create_shader_binding_table when ray tracing is not enabled",
-        );
+        let desc = pipeline.shader_group_info;
+
+        let mut builder = ShaderBindingTableBuilder::new(pipeline).raygen(0);
+        for index in 0..desc.miss_shader_count {
+            builder = builder.miss(index);
+        }
+        for index in 0..desc.hit_shader_count {
+            builder = builder.hit_group(index);
+        }
+        for index in 0..desc.callable_shader_count {
+            builder = builder.callable(index);
+        }
 
-        ShaderBindingTable::new(self, ray_tracing, pipeline)
+        builder.build(self)
     }
 }
+
+#[test]
+fn test_layout_regions() {
+    let layouts = layout_regions(32, 32, 64, [(1, 0), (2, 0), (3, 16), (0, 0)]);
+
+    assert_eq!(
+        layouts,
+        [
+            RegionLayout {
+                offset: 0,
+                stride: 32,
+                size: 32
+            },
+            RegionLayout {
+                offset: 64,
+                stride: 32,
+                size: 64
+            },
+            RegionLayout {
+                offset: 128,
+                stride: 64,
+                size: 192
+            },
+            RegionLayout::default(),
+        ]
+    );
+}
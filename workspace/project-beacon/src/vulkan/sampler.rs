@@ -1,4 +1,6 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use ash::vk;
@@ -10,6 +12,45 @@ pub struct Sampler {
     pub(crate) inner: vk::Sampler,
 }
 
+/// Sampler state, built from one of the presets. Also the key of the sampler cache, see
+/// [`Context::get_sampler`].
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// U, V and W.
+    pub address_modes: [vk::SamplerAddressMode; 3],
+    pub mip_lod_bias: f32,
+    /// Clamped to `max_sampler_anisotropy`. Ignored without the `sampler_anisotropy` feature.
+    pub max_anisotropy: Option<f32>,
+    /// For depth comparison samplers, e.g. `sampler2DShadow`.
+    pub compare_op: Option<vk::CompareOp>,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub border_color: vk::BorderColor,
+}
+
+/// Fields of a [`SamplerDesc`], with floats as bits so that descs can be hashed.
+type SamplerKey = (
+    vk::Filter,
+    vk::Filter,
+    vk::SamplerMipmapMode,
+    [vk::SamplerAddressMode; 3],
+    u32,
+    Option<u32>,
+    Option<vk::CompareOp>,
+    u32,
+    u32,
+    vk::BorderColor,
+);
+
+/// Samplers created from descs, shared by all their users.
+#[derive(Default)]
+pub(crate) struct SamplerCache {
+    samplers: Mutex<HashMap<SamplerDesc, Arc<Sampler>>>,
+}
+
 impl Sampler {
     pub(crate) fn new(device: Arc<Device>, create_info: &vk::SamplerCreateInfo) -> Result<Self> {
         let inner = unsafe { device.inner.create_sampler(create_info, None)? };
@@ -18,16 +59,227 @@ impl Sampler {
     }
 }
 
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self::linear_repeat()
+    }
+}
+
+impl SamplerDesc {
+    /// Trilinear filtering, repeating texture coordinates.
+    pub fn linear_repeat() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_modes: [vk::SamplerAddressMode::REPEAT; 3],
+            mip_lod_bias: 0.0,
+            max_anisotropy: None,
+            compare_op: None,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+        }
+    }
+
+    /// Linear filtering, clamping texture coordinates to the edge.
+    pub fn linear_clamp() -> Self {
+        Self::linear_repeat().address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+    }
+
+    /// Nearest filtering, clamping texture coordinates to the edge.
+    pub fn nearest_clamp() -> Self {
+        Self::linear_clamp()
+            .filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+    }
+
+    /// Linear filtered depth comparison, outside of the shadow map is lit.
+    pub fn shadow_comparison() -> Self {
+        Self::linear_repeat()
+            .address_mode(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+    }
+
+    /// Trilinear filtering with up to `max_anisotropy` samples, repeating texture coordinates.
+    pub fn anisotropic(max_anisotropy: f32) -> Self {
+        Self::linear_repeat().anisotropy(max_anisotropy)
+    }
+
+    pub fn filter(self, filter: vk::Filter) -> Self {
+        Self {
+            mag_filter: filter,
+            min_filter: filter,
+            ..self
+        }
+    }
+
+    pub fn mipmap_mode(self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+        Self {
+            mipmap_mode,
+            ..self
+        }
+    }
+
+    /// Same mode for U, V and W.
+    pub fn address_mode(self, address_mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            address_modes: [address_mode; 3],
+            ..self
+        }
+    }
+
+    pub fn border_color(self, border_color: vk::BorderColor) -> Self {
+        Self {
+            border_color,
+            ..self
+        }
+    }
+
+    pub fn lod_bias(self, mip_lod_bias: f32) -> Self {
+        Self {
+            mip_lod_bias,
+            ..self
+        }
+    }
+
+    pub fn lod_range(self, min_lod: f32, max_lod: f32) -> Self {
+        Self {
+            min_lod,
+            max_lod,
+            ..self
+        }
+    }
+
+    /// Requires the `sampler_anisotropy` device feature, anisotropic filtering is disabled
+    /// otherwise.
+    pub fn anisotropy(self, max_anisotropy: f32) -> Self {
+        Self {
+            max_anisotropy: Some(max_anisotropy),
+            ..self
+        }
+    }
+
+    pub fn compare_op(self, compare_op: vk::CompareOp) -> Self {
+        Self {
+            compare_op: Some(compare_op),
+            ..self
+        }
+    }
+
+    /// `device_max_anisotropy` is `None` if anisotropic filtering is not enabled.
+    fn create_info(&self, device_max_anisotropy: Option<f32>) -> vk::SamplerCreateInfo {
+        let max_anisotropy = self
+            .max_anisotropy
+            .zip(device_max_anisotropy)
+            .map(|(max, device_max)| max.min(device_max))
+            .filter(|&max| max > 1.0);
+
+        vk::SamplerCreateInfo::builder()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_modes[0])
+            .address_mode_v(self.address_modes[1])
+            .address_mode_w(self.address_modes[2])
+            .mip_lod_bias(self.mip_lod_bias)
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::NEVER))
+            .min_lod(self.min_lod)
+            .max_lod(self.max_lod)
+            .border_color(self.border_color)
+            .build()
+    }
+
+    fn key(&self) -> SamplerKey {
+        (
+            self.mag_filter,
+            self.min_filter,
+            self.mipmap_mode,
+            self.address_modes,
+            self.mip_lod_bias.to_bits(),
+            self.max_anisotropy.map(f32::to_bits),
+            self.compare_op,
+            self.min_lod.to_bits(),
+            self.max_lod.to_bits(),
+            self.border_color,
+        )
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
 impl Context {
     pub fn create_sampler(&self, create_info: &vk::SamplerCreateInfo) -> Result<Sampler> {
         Sampler::new(self.device.clone(), create_info)
     }
+
+    /// Sampler for `desc`, created on first use and shared after. Drivers limit the number
+    /// of samplers, so prefer it to creating one per material.
+    pub fn get_sampler(&self, desc: &SamplerDesc) -> Result<Arc<Sampler>> {
+        let mut samplers = self.samplers.samplers.lock().unwrap();
+        if let Some(sampler) = samplers.get(desc) {
+            return Ok(sampler.clone());
+        }
+
+        let device_max_anisotropy = self
+            .device
+            .features
+            .sampler_anisotropy
+            .then_some(self.physical_device.limits.max_sampler_anisotropy);
+        let sampler = Arc::new(self.create_sampler(&desc.create_info(device_max_anisotropy))?);
+        samplers.insert(*desc, sampler.clone());
+
+        Ok(sampler)
+    }
+
+    /// Number of samplers in the cache of [`Context::get_sampler`].
+    pub fn cached_sampler_count(&self) -> usize {
+        self.samplers.samplers.lock().unwrap().len()
+    }
 }
 
 impl Drop for Sampler {
     fn drop(&mut self) {
-        unsafe {
-            self.device.inner.destroy_sampler(self.inner, None);
-        }
+        let inner = self.inner;
+        self.device
+            .deletion_queue
+            .defer(&self.device, move |device| unsafe {
+                device.inner.destroy_sampler(inner, None)
+            });
     }
 }
+
+#[test]
+fn test_sampler_desc() {
+    let anisotropic = SamplerDesc::anisotropic(16.0);
+    assert_eq!(anisotropic.create_info(Some(8.0)).max_anisotropy, 8.0);
+    assert_eq!(anisotropic.create_info(None).anisotropy_enable, vk::FALSE);
+
+    let shadow = SamplerDesc::shadow_comparison().create_info(None);
+    assert_eq!(shadow.compare_enable, vk::TRUE);
+    assert_eq!(shadow.compare_op, vk::CompareOp::LESS_OR_EQUAL);
+
+    let mut samplers = HashMap::new();
+    samplers.insert(SamplerDesc::linear_repeat(), 0);
+    samplers.insert(SamplerDesc::default(), 1);
+    samplers.insert(SamplerDesc::nearest_clamp(), 2);
+    assert_eq!(samplers.len(), 2);
+    assert_eq!(samplers[&SamplerDesc::linear_repeat()], 1);
+}
@@ -5,6 +5,47 @@ use ash::{extensions::khr::Swapchain as AshSwapchain, vk};
 
 use crate::vulkan::{device::Device, Context, Image, ImageView, Queue, Semaphore};
 
+const HDR_METADATA_EXTENSION: &str = "VK_EXT_hdr_metadata";
+
+/// How the presentation engine interprets swapchain values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapchainEncoding {
+    /// SDR, sRGB transfer function and BT.709 primaries.
+    Srgb,
+    /// PQ (ST 2084) transfer function and BT.2020 primaries, in a 10 bit format.
+    Hdr10,
+    /// Linear BT.709 primaries in a float format, 1.0 is 80 nits and values can exceed 1.
+    ScRgb,
+}
+
+/// HDR mastering metadata, in nits. The primaries are those of the swapchain encoding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HdrMetadata {
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    pub max_content_light_level: f32,
+    pub max_frame_average_light_level: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapchainConfig {
+    /// Encodings to try in order. The first surface format is used if none are supported.
+    /// Only used when the swapchain is created, the format is kept when reconfiguring.
+    pub preferred_encodings: Vec<SwapchainEncoding>,
+    /// Sent to the display when an HDR encoding is chosen. Requires the `VK_EXT_hdr_metadata`
+    /// extension, ignored if it is not enabled.
+    pub hdr_metadata: Option<HdrMetadata>,
+    /// Present modes to try in order, `FIFO` is used if none are supported.
+    pub preferred_present_modes: Vec<vk::PresentModeKHR>,
+    /// Requested number of images, clamped to the surface limits. Defaults to one more than
+    /// the minimum.
+    pub image_count: Option<u32>,
+    /// Number of frames the CPU can record ahead of the GPU.
+    pub frames_in_flight: u32,
+    /// Frames per second `app::run` paces rendering to, independently of the present mode.
+    pub frame_rate_limit: Option<u32>,
+}
+
 pub struct AcquiredImage {
     pub index: u32,
     pub is_suboptimal: bool,
@@ -17,171 +58,364 @@ pub struct Swapchain {
     pub extent: vk::Extent2D,
     pub format: vk::Format,
     pub color_space: vk::ColorSpaceKHR,
+    pub encoding: SwapchainEncoding,
     pub present_mode: vk::PresentModeKHR,
     pub images: Vec<Image>,
     pub views: Vec<ImageView>,
+    config: SwapchainConfig,
+    /// Replaced by the last recreation, destroyed by the next one.
+    retired: Option<RetiredSwapchain>,
+    /// Loaded if the `VK_EXT_hdr_metadata` extension is enabled.
+    hdr_metadata_fn: Option<vk::ExtHdrMetadataFn>,
 }
 
-impl Swapchain {
-    pub fn new(context: &Context, width: u32, height: u32) -> Result<Self> {
-        println!("Creating vulkan swapchain");
-
-        let device = context.device.clone();
+struct RetiredSwapchain {
+    swapchain_khr: vk::SwapchainKHR,
+    views: Vec<ImageView>,
+    images: Vec<Image>,
+}
 
-        // Swapchain format
-        let format = {
-            let formats = unsafe {
-                context.surface.inner.get_physical_device_surface_formats(
-                    context.physical_device.inner,
-                    context.surface.surface_khr,
-                )?
-            };
-            if formats.len() == 1 && formats[0].format == vk::Format::UNDEFINED {
-                vk::SurfaceFormatKHR {
-                    format: vk::Format::B8G8R8A8_UNORM,
-                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-                }
-            } else {
-                *formats
-                    .iter()
-                    .find(|format| {
-                        format.format == vk::Format::B8G8R8A8_UNORM
-                            && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-                    })
-                    .unwrap_or(&formats[0])
+impl SwapchainEncoding {
+    /// Supported surface formats, in order of preference.
+    pub fn surface_formats(self) -> &'static [vk::SurfaceFormatKHR] {
+        const fn format(
+            format: vk::Format,
+            color_space: vk::ColorSpaceKHR,
+        ) -> vk::SurfaceFormatKHR {
+            vk::SurfaceFormatKHR {
+                format,
+                color_space,
             }
-        };
-        println!("Swapchain format: {format:?}");
+        }
 
-        // Swapchain present mode
-        let present_mode = {
-            let present_modes = unsafe {
-                context
-                    .surface
-                    .inner
-                    .get_physical_device_surface_present_modes(
-                        context.physical_device.inner,
-                        context.surface.surface_khr,
-                    )?
-            };
-            if present_modes.contains(&vk::PresentModeKHR::IMMEDIATE) {
-                vk::PresentModeKHR::IMMEDIATE
-            } else {
-                vk::PresentModeKHR::FIFO
-            }
-        };
-        println!("Swapchain present mode: {present_mode:?}");
+        const SRGB: &[vk::SurfaceFormatKHR] = &[
+            format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            format(vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            format(
+                vk::Format::B8G8R8A8_UNORM,
+                vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            ),
+            format(
+                vk::Format::R8G8B8A8_UNORM,
+                vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            ),
+        ];
+        const HDR10: &[vk::SurfaceFormatKHR] = &[
+            format(
+                vk::Format::A2B10G10R10_UNORM_PACK32,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            ),
+            format(
+                vk::Format::A2R10G10B10_UNORM_PACK32,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            ),
+        ];
+        const SCRGB: &[vk::SurfaceFormatKHR] = &[format(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        )];
+
+        match self {
+            Self::Srgb => SRGB,
+            Self::Hdr10 => HDR10,
+            Self::ScRgb => SCRGB,
+        }
+    }
 
-        let capabilities = unsafe {
-            context
-                .surface
-                .inner
-                .get_physical_device_surface_capabilities(
-                    context.physical_device.inner,
-                    context.surface.surface_khr,
-                )?
-        };
+    pub fn from_color_space(color_space: vk::ColorSpaceKHR) -> Option<Self> {
+        match color_space {
+            vk::ColorSpaceKHR::SRGB_NONLINEAR => Some(Self::Srgb),
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Some(Self::Hdr10),
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Some(Self::ScRgb),
+            _ => None,
+        }
+    }
 
-        // Swapchain extent
-        let extent = {
-            if capabilities.current_extent.width != std::u32::MAX {
-                capabilities.current_extent
-            } else {
-                let min = capabilities.min_image_extent;
-                let max = capabilities.max_image_extent;
-                let width = width.min(max.width).max(min.width);
-                let height = height.min(max.height).max(min.height);
-                vk::Extent2D { width, height }
-            }
-        };
-        println!("Swapchain extent: {extent:?}");
+    pub fn is_hdr(self) -> bool {
+        self != Self::Srgb
+    }
 
-        // Swapchain image count
-        let image_count = capabilities.min_image_count + 1;
-        println!("Swapchain image count: {image_count:?}");
+    /// xy chromaticities of the red, green and blue primaries and of the white point.
+    fn primaries(self) -> [[f32; 2]; 4] {
+        const D65: [f32; 2] = [0.3127, 0.3290];
+        match self {
+            Self::Hdr10 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046], D65],
+            Self::Srgb | Self::ScRgb => [[0.640, 0.330], [0.300, 0.600], [0.150, 0.060], D65],
+        }
+    }
+}
 
-        // Swapchain
-        let families_indices = [
-            context.graphics_queue_family.index,
-            context.present_queue_family.index,
-        ];
+impl Default for HdrMetadata {
+    fn default() -> Self {
+        Self {
+            min_luminance: 0.001,
+            max_luminance: 1000.0,
+            max_content_light_level: 1000.0,
+            max_frame_average_light_level: 400.0,
+        }
+    }
+}
 
-        let create_info = {
-            let mut builder = vk::SwapchainCreateInfoKHR::builder()
-                .surface(context.surface.surface_khr)
-                .min_image_count(image_count)
-                .image_format(format.format)
-                .image_color_space(format.color_space)
-                .image_extent(extent)
-                .image_array_layers(1)
-                .image_usage(
-                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST,
-                );
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        Self {
+            preferred_encodings: vec![SwapchainEncoding::Srgb],
+            hdr_metadata: None,
+            preferred_present_modes: vec![vk::PresentModeKHR::FIFO],
+            image_count: None,
+            frames_in_flight: Self::DEFAULT_FRAMES_IN_FLIGHT,
+            frame_rate_limit: None,
+        }
+    }
+}
 
-            builder = if context.graphics_queue_family.index != context.present_queue_family.index {
-                builder
-                    .image_sharing_mode(vk::SharingMode::CONCURRENT)
-                    .queue_family_indices(&families_indices)
-            } else {
-                builder.image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            };
+impl SwapchainConfig {
+    pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
 
-            builder
-                .pre_transform(capabilities.current_transform)
-                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                .present_mode(present_mode)
-                .clipped(true)
-        };
+    pub fn preferred_encodings(self, preferred_encodings: &[SwapchainEncoding]) -> Self {
+        Self {
+            preferred_encodings: preferred_encodings.to_vec(),
+            ..self
+        }
+    }
 
-        let inner = AshSwapchain::new(&context.instance.inner, &context.device.inner);
-        let swapchain_khr = unsafe { inner.create_swapchain(&create_info, None)? };
+    pub fn hdr_metadata(self, hdr_metadata: HdrMetadata) -> Self {
+        Self {
+            hdr_metadata: Some(hdr_metadata),
+            ..self
+        }
+    }
 
-        // Swapchain images and image views
-        let images = unsafe { inner.get_swapchain_images(swapchain_khr)? };
-        let images = images
-            .into_iter()
-            .map(|i| {
-                Image::from_swapchain_image(
-                    device.clone(),
-                    context.allocator.clone(),
-                    i,
-                    format.format,
-                    extent,
-                )
-            })
-            .collect::<Vec<_>>();
+    pub fn preferred_present_modes(self, preferred_present_modes: &[vk::PresentModeKHR]) -> Self {
+        Self {
+            preferred_present_modes: preferred_present_modes.to_vec(),
+            ..self
+        }
+    }
 
-        let views = images
+    /// Waits for vertical blanks with `FIFO`, or prefers `MAILBOX` then `IMMEDIATE`.
+    pub fn vsync(self, vsync: bool) -> Self {
+        if vsync {
+            self.preferred_present_modes(&[vk::PresentModeKHR::FIFO])
+        } else {
+            self.preferred_present_modes(&[
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::IMMEDIATE,
+            ])
+        }
+    }
+
+    /// Whether the preferred present mode waits for vertical blanks.
+    pub fn is_vsync(&self) -> bool {
+        matches!(
+            self.preferred_present_modes.first(),
+            None | Some(&vk::PresentModeKHR::FIFO) | Some(&vk::PresentModeKHR::FIFO_RELAXED)
+        )
+    }
+
+    pub fn image_count(self, image_count: u32) -> Self {
+        Self {
+            image_count: Some(image_count),
+            ..self
+        }
+    }
+
+    pub fn frames_in_flight(self, frames_in_flight: u32) -> Self {
+        Self {
+            frames_in_flight,
+            ..self
+        }
+    }
+
+    pub fn frame_rate_limit(self, frame_rate_limit: Option<u32>) -> Self {
+        Self {
+            frame_rate_limit,
+            ..self
+        }
+    }
+
+    /// Prefers HDR10, then scRGB, then sRGB.
+    pub fn hdr() -> Self {
+        Self::default()
+            .preferred_encodings(&[
+                SwapchainEncoding::Hdr10,
+                SwapchainEncoding::ScRgb,
+                SwapchainEncoding::Srgb,
+            ])
+            .hdr_metadata(HdrMetadata::default())
+    }
+}
+
+pub fn select_present_mode(
+    available: &[vk::PresentModeKHR],
+    preferred_present_modes: &[vk::PresentModeKHR],
+) -> vk::PresentModeKHR {
+    preferred_present_modes
+        .iter()
+        .copied()
+        .find(|mode| available.contains(mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
+/// Picks the requested image count, or one more than the minimum, within the surface limits.
+pub fn select_image_count(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    requested_image_count: Option<u32>,
+) -> u32 {
+    let image_count = requested_image_count
+        .unwrap_or(capabilities.min_image_count + 1)
+        .max(capabilities.min_image_count);
+
+    // 0 means there is no maximum
+    match capabilities.max_image_count {
+        0 => image_count,
+        max => image_count.min(max),
+    }
+}
+
+/// Picks the first supported format of the preferred encodings.
+pub fn select_surface_format(
+    available: &[vk::SurfaceFormatKHR],
+    preferred_encodings: &[SwapchainEncoding],
+) -> (vk::SurfaceFormatKHR, SwapchainEncoding) {
+    // A single undefined format means that any format can be used
+    if available.len() == 1 && available[0].format == vk::Format::UNDEFINED {
+        let encoding = preferred_encodings
             .iter()
-            .map(Image::create_image_view)
-            .collect::<Result<Vec<_>, _>>()?;
+            .copied()
+            .find(|e| !e.is_hdr())
+            .unwrap_or(SwapchainEncoding::Srgb);
+        return (encoding.surface_formats()[0], encoding);
+    }
+
+    preferred_encodings
+        .iter()
+        .find_map(|&encoding| {
+            encoding
+                .surface_formats()
+                .iter()
+                .find(|format| available.contains(format))
+                .map(|&format| (format, encoding))
+        })
+        .unwrap_or_else(|| {
+            let format = available[0];
+            let encoding = SwapchainEncoding::from_color_space(format.color_space)
+                .unwrap_or(SwapchainEncoding::Srgb);
+            (format, encoding)
+        })
+}
+
+impl Swapchain {
+    pub fn new(context: &Context, width: u32, height: u32) -> Result<Self> {
+        Self::new_with_config(context, width, height, &SwapchainConfig::default())
+    }
+
+    pub fn new_with_config(
+        context: &Context,
+        width: u32,
+        height: u32,
+        config: &SwapchainConfig,
+    ) -> Result<Self> {
+        println!("Creating vulkan swapchain");
+
+        let device = context.device.clone();
+        let surface = context
+            .surface
+            .as_ref()
+            .expect("Cannot create a swapchain with a headless Context");
+
+        // Swapchain format
+        let (format, encoding) = {
+            let formats = unsafe {
+                surface.inner.get_physical_device_surface_formats(
+                    context.physical_device.inner,
+                    surface.surface_khr,
+                )?
+            };
+            select_surface_format(&formats, &config.preferred_encodings)
+        };
+        println!("Swapchain format: {format:?}, encoding: {encoding:?}");
+
+        let hdr_metadata_fn = context
+            .is_extension_enabled(HDR_METADATA_EXTENSION)
+            .then(|| {
+                vk::ExtHdrMetadataFn::load(|name| unsafe {
+                    std::mem::transmute(
+                        context
+                            .instance
+                            .inner
+                            .get_device_proc_addr(device.inner.handle(), name.as_ptr()),
+                    )
+                })
+            });
 
-        Ok(Self {
+        let inner = AshSwapchain::new(&context.instance.inner, &context.device.inner);
+
+        let mut swapchain = Self {
             device,
             inner,
-            swapchain_khr,
-            extent,
+            swapchain_khr: vk::SwapchainKHR::null(),
+            extent: vk::Extent2D::default(),
             format: format.format,
             color_space: format.color_space,
-            present_mode,
-            images,
-            views,
-        })
+            encoding,
+            present_mode: vk::PresentModeKHR::FIFO,
+            images: vec![],
+            views: vec![],
+            config: config.clone(),
+            retired: None,
+            hdr_metadata_fn,
+        };
+        swapchain.create(context, width, height)?;
+
+        Ok(swapchain)
     }
 
+    /// Recreates the swapchain, passing the current one as `old_swapchain` so the presentation
+    /// engine can reuse its resources. The replaced swapchain is destroyed on the next
+    /// recreation, so the work using its images must be done by then.
     pub fn resize(&mut self, context: &Context, width: u32, height: u32) -> Result<()> {
         println!("Resizing vulkan swapchain to {width}x{height}");
 
-        self.destroy();
+        self.create(context, width, height)
+    }
 
-        let capabilities = unsafe {
-            context
-                .surface
-                .inner
-                .get_physical_device_surface_capabilities(
+    pub fn config(&self) -> &SwapchainConfig {
+        &self.config
+    }
+
+    /// Recreates the swapchain with a new present mode, image count or HDR metadata. The format
+    /// chosen at creation is kept. The GPU must not be using the swapchain images.
+    pub fn set_config(&mut self, context: &Context, config: &SwapchainConfig) -> Result<()> {
+        self.config = config.clone();
+
+        let extent = self.extent;
+        self.resize(context, extent.width, extent.height)
+    }
+
+    fn create(&mut self, context: &Context, width: u32, height: u32) -> Result<()> {
+        let surface = context
+            .surface
+            .as_ref()
+            .expect("Cannot create a swapchain with a headless Context");
+
+        // Swapchain present mode
+        let present_mode = {
+            let present_modes = unsafe {
+                surface.inner.get_physical_device_surface_present_modes(
                     context.physical_device.inner,
-                    context.surface.surface_khr,
+                    surface.surface_khr,
                 )?
+            };
+            select_present_mode(&present_modes, &self.config.preferred_present_modes)
+        };
+        println!("Swapchain present mode: {present_mode:?}");
+
+        let capabilities = unsafe {
+            surface.inner.get_physical_device_surface_capabilities(
+                context.physical_device.inner,
+                surface.surface_khr,
+            )?
         };
 
         // Swapchain extent
@@ -199,7 +433,8 @@ impl Swapchain {
         println!("Swapchain extent: {extent:?}");
 
         // Swapchain image count
-        let image_count = capabilities.min_image_count;
+        let image_count = select_image_count(&capabilities, self.config.image_count);
+        println!("Swapchain image count: {image_count:?}");
 
         // Swapchain
         let families_indices = [
@@ -209,7 +444,7 @@ impl Swapchain {
 
         let create_info = {
             let mut builder = vk::SwapchainCreateInfoKHR::builder()
-                .surface(context.surface.surface_khr)
+                .surface(surface.surface_khr)
                 .min_image_count(image_count)
                 .image_format(self.format)
                 .image_color_space(self.color_space)
@@ -230,8 +465,9 @@ impl Swapchain {
             builder
                 .pre_transform(capabilities.current_transform)
                 .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                .present_mode(self.present_mode)
+                .present_mode(present_mode)
                 .clipped(true)
+                .old_swapchain(self.swapchain_khr)
         };
 
         let swapchain_khr = unsafe { self.inner.create_swapchain(&create_info, None)? };
@@ -256,14 +492,63 @@ impl Swapchain {
             .map(Image::create_image_view)
             .collect::<Result<Vec<_>, _>>()?;
 
+        self.destroy_retired();
+        if self.swapchain_khr != vk::SwapchainKHR::null() {
+            self.retired = Some(RetiredSwapchain {
+                swapchain_khr: self.swapchain_khr,
+                views: std::mem::replace(&mut self.views, views),
+                images: std::mem::replace(&mut self.images, images),
+            });
+        } else {
+            self.views = views;
+            self.images = images;
+        }
+
         self.swapchain_khr = swapchain_khr;
         self.extent = extent;
-        self.images = images;
-        self.views = views;
+        self.present_mode = present_mode;
+        self.set_hdr_metadata();
 
         Ok(())
     }
 
+    /// Metadata sent to the display, if any.
+    pub fn hdr_metadata(&self) -> Option<HdrMetadata> {
+        self.config
+            .hdr_metadata
+            .filter(|_| self.encoding.is_hdr() && self.hdr_metadata_fn.is_some())
+    }
+
+    fn set_hdr_metadata(&self) {
+        let (Some(hdr_metadata_fn), Some(metadata)) = (&self.hdr_metadata_fn, self.hdr_metadata())
+        else {
+            return;
+        };
+
+        let [red, green, blue, white] = self
+            .encoding
+            .primaries()
+            .map(|[x, y]| vk::XYColorEXT { x, y });
+        let metadata = vk::HdrMetadataEXT::builder()
+            .display_primary_red(red)
+            .display_primary_green(green)
+            .display_primary_blue(blue)
+            .white_point(white)
+            .min_luminance(metadata.min_luminance)
+            .max_luminance(metadata.max_luminance)
+            .max_content_light_level(metadata.max_content_light_level)
+            .max_frame_average_light_level(metadata.max_frame_average_light_level);
+
+        unsafe {
+            (hdr_metadata_fn.set_hdr_metadata_ext)(
+                self.device.inner.handle(),
+                1,
+                &self.swapchain_khr,
+                &*metadata,
+            )
+        };
+    }
+
     pub fn acquire_next_image(&self, timeout: u64, semaphore: &Semaphore) -> Result<AcquiredImage> {
         let (index, is_suboptimal) = unsafe {
             self.inner.acquire_next_image(
@@ -300,12 +585,30 @@ impl Swapchain {
         Ok(result)
     }
 
-    fn destroy(&mut self) {
+    fn destroy_retired(&mut self) {
+        if let Some(RetiredSwapchain {
+            swapchain_khr,
+            views,
+            images,
+        }) = self.retired.take()
+        {
+            // Views before the swapchain owning their images
+            drop(views);
+            drop(images);
+            unsafe { self.inner.destroy_swapchain(swapchain_khr, None) };
+        }
+    }
+
+    /// Releases the window of the swapchain, which can't be used after. Destroying the
+    /// swapchain again does nothing.
+    pub(crate) fn destroy(&mut self) {
+        self.destroy_retired();
         unsafe {
             self.views.clear();
             self.images.clear();
             self.inner.destroy_swapchain(self.swapchain_khr, None);
         }
+        self.swapchain_khr = vk::SwapchainKHR::null();
     }
 }
 
@@ -314,3 +617,38 @@ impl Drop for Swapchain {
         self.destroy();
     }
 }
+
+#[test]
+fn test_select_surface_format() {
+    use SwapchainEncoding::*;
+
+    let srgb = Srgb.surface_formats()[2];
+    let hdr10 = Hdr10.surface_formats()[0];
+    let available = [srgb, hdr10];
+
+    assert_eq!(select_surface_format(&available, &[Srgb]), (srgb, Srgb));
+    assert_eq!(
+        select_surface_format(&available, &[ScRgb, Hdr10, Srgb]),
+        (hdr10, Hdr10)
+    );
+    assert_eq!(select_surface_format(&[srgb], &[Hdr10]), (srgb, Srgb));
+}
+
+#[test]
+fn test_select_image_count() {
+    let capabilities = vk::SurfaceCapabilitiesKHR {
+        min_image_count: 2,
+        max_image_count: 4,
+        ..Default::default()
+    };
+
+    assert_eq!(select_image_count(&capabilities, None), 3);
+    assert_eq!(select_image_count(&capabilities, Some(1)), 2);
+    assert_eq!(select_image_count(&capabilities, Some(8)), 4);
+
+    let unbounded = vk::SurfaceCapabilitiesKHR {
+        max_image_count: 0,
+        ..capabilities
+    };
+    assert_eq!(select_image_count(&unbounded, Some(8)), 8);
+}
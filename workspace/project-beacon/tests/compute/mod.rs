@@ -0,0 +1,202 @@
+//! Compared against CPU results. Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use project_beacon::{
+    compute::ReduceOp,
+    vulkan::{
+        ash::vk, gpu_allocator::MemoryLocation, Buffer, CommandBuffer, Context, ContextBuilder,
+        DeviceFeatures, MemoryBarrier, VERSION_1_3,
+    },
+};
+
+use crate::gpu_tests_enabled;
+
+// Several levels of scan and a partial last workgroup
+const COUNT: u32 = 70_000;
+
+fn create_context() -> Option<Context> {
+    gpu_tests_enabled().then(|| {
+        ContextBuilder::headless()
+            .vulkan_version(VERSION_1_3)
+            .required_device_features(DeviceFeatures {
+                synchronization2: true,
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
+    })
+}
+
+fn create_buffer(context: &Context, data: &[u32]) -> Buffer {
+    let buffer = context
+        .create_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::CpuToGpu,
+            std::mem::size_of_val(data) as _,
+        )
+        .unwrap();
+    buffer.copy_data_to_buffer(data).unwrap();
+    buffer
+}
+
+fn random_data(count: u32, seed: u32) -> Vec<u32> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            state
+        })
+        .collect()
+}
+
+fn host_read_barrier(cmd_buffer: &CommandBuffer) {
+    cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+        src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE | vk::AccessFlags2::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags2::HOST_READ,
+        src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER
+            | vk::PipelineStageFlags2::ALL_TRANSFER,
+        dst_stage_mask: vk::PipelineStageFlags2::HOST,
+    }]);
+}
+
+#[test]
+fn test_reduction() {
+    let Some(context) = create_context() else {
+        return;
+    };
+    let data = random_data(COUNT, 1)
+        .iter()
+        .map(|value| value >> 12)
+        .collect::<Vec<_>>();
+    let input = create_buffer(&context, &data);
+    let result = create_buffer(&context, &[0]);
+    let reduction = context.create_reduction(&input, COUNT).unwrap();
+
+    for (op, expected) in [
+        (
+            ReduceOp::Sum,
+            data.iter().fold(0u32, |a, &b| a.wrapping_add(b)),
+        ),
+        (ReduceOp::Min, *data.iter().min().unwrap()),
+        (ReduceOp::Max, *data.iter().max().unwrap()),
+    ] {
+        context
+            .execute_one_time_commands(|cmd_buffer| {
+                reduction.record(cmd_buffer, COUNT, op).unwrap();
+                cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+                    src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    dst_access_mask: vk::AccessFlags2::TRANSFER_READ,
+                    src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    dst_stage_mask: vk::PipelineStageFlags2::ALL_TRANSFER,
+                }]);
+                cmd_buffer.copy_buffer(reduction.output(), &result);
+                host_read_barrier(cmd_buffer);
+            })
+            .unwrap();
+
+        assert_eq!(result.read_data_from_buffer::<u32>().unwrap(), [expected]);
+    }
+}
+
+#[test]
+fn test_prefix_scan() {
+    let Some(context) = create_context() else {
+        return;
+    };
+    let data = random_data(COUNT, 2)
+        .iter()
+        .map(|value| value % 100)
+        .collect::<Vec<_>>();
+    let input = create_buffer(&context, &data);
+    let output = create_buffer(&context, &vec![0; COUNT as usize]);
+    let scan = context.create_prefix_scan(&input, &output, COUNT).unwrap();
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            scan.record(cmd_buffer, COUNT).unwrap();
+            host_read_barrier(cmd_buffer);
+        })
+        .unwrap();
+
+    let expected = data
+        .iter()
+        .scan(0, |sum, &value| {
+            let prefix = *sum;
+            *sum += value;
+            Some(prefix)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(output.read_data_from_buffer::<u32>().unwrap(), expected);
+}
+
+#[test]
+fn test_radix_sort() {
+    let Some(context) = create_context() else {
+        return;
+    };
+    // Few distinct keys, to check that the sort is stable
+    let keys = random_data(COUNT, 3)
+        .iter()
+        .map(|value| value % 1000 * 4_000_000)
+        .collect::<Vec<_>>();
+    let values = (0..COUNT).collect::<Vec<_>>();
+    let key_buffer = create_buffer(&context, &keys);
+    let value_buffer = create_buffer(&context, &values);
+    let sort = context
+        .create_radix_sort(&key_buffer, &value_buffer, COUNT)
+        .unwrap();
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            sort.record(cmd_buffer, COUNT).unwrap();
+            host_read_barrier(cmd_buffer);
+        })
+        .unwrap();
+
+    let mut expected = keys.iter().copied().zip(values).collect::<Vec<_>>();
+    expected.sort_by_key(|(key, _)| *key);
+    let (expected_keys, expected_values): (Vec<_>, Vec<_>) = expected.into_iter().unzip();
+    assert_eq!(
+        key_buffer.read_data_from_buffer::<u32>().unwrap(),
+        expected_keys
+    );
+    assert_eq!(
+        value_buffer.read_data_from_buffer::<u32>().unwrap(),
+        expected_values
+    );
+}
+
+#[test]
+fn test_stream_compaction() {
+    let Some(context) = create_context() else {
+        return;
+    };
+    let data = random_data(COUNT, 4);
+    let flags = data.iter().map(|value| value >> 31).collect::<Vec<_>>();
+    let input = create_buffer(&context, &data);
+    let flag_buffer = create_buffer(&context, &flags);
+    let output = create_buffer(&context, &vec![0; COUNT as usize]);
+    let count = create_buffer(&context, &[u32::MAX]);
+    let compaction = context
+        .create_stream_compaction(&input, &flag_buffer, &output, &count, COUNT)
+        .unwrap();
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            compaction.record(cmd_buffer, COUNT).unwrap();
+            host_read_barrier(cmd_buffer);
+        })
+        .unwrap();
+
+    let expected = data
+        .iter()
+        .zip(&flags)
+        .filter(|(_, &flag)| flag == 1)
+        .map(|(&value, _)| value)
+        .collect::<Vec<_>>();
+    let compacted_count = count.read_data_from_buffer::<u32>().unwrap()[0];
+    assert_eq!(compacted_count as usize, expected.len());
+    assert_eq!(
+        output.read_data_from_buffer::<u32>().unwrap()[..expected.len()],
+        expected
+    );
+}
@@ -0,0 +1,22 @@
+use project_beacon::render_graph::Access;
+use project_beacon::vulkan::ash::vk::PipelineStageFlags2;
+
+fn uses_ray_tracing_stage(access: Access) -> bool {
+    access
+        .info()
+        .stage_mask
+        .contains(PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+}
+
+#[test]
+fn test_ray_tracing_stage_only_in_ray_tracing_accesses() {
+    // Invalid in barriers without the ray tracing pipeline feature
+    assert!(!uses_ray_tracing_stage(Access::UniformRead));
+    assert!(!uses_ray_tracing_stage(Access::ComputeShaderStorageWrite));
+    assert!(!uses_ray_tracing_stage(
+        Access::RayQueryAccelerationStructureRead
+    ));
+
+    assert!(uses_ray_tracing_stage(Access::RayTracingShaderUniformRead));
+    assert!(Access::RayTracingShaderUniformRead.is_read());
+}
@@ -0,0 +1,245 @@
+use project_beacon::render_graph::{Access, ImageDesc, RenderGraph, ResourceHandle};
+use project_beacon::vulkan::ash::vk;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 64,
+    height: 64,
+};
+
+fn color_desc() -> ImageDesc {
+    ImageDesc::new_2d(vk::Format::R8G8B8A8_UNORM, EXTENT)
+}
+
+#[test]
+fn test_unused_passes_are_culled() {
+    let mut graph = RenderGraph::new();
+    let gbuffer = graph.create_image("gbuffer", color_desc());
+    let unused = graph.create_image("unused", color_desc());
+    let output = graph.create_image("output", color_desc());
+    graph.mark_output(output);
+
+    graph
+        .add_pass("gbuffer")
+        .write(gbuffer, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("unused")
+        .write(unused, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("lighting")
+        .read(gbuffer, Access::FragmentShaderSampledRead)
+        .write(output, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+
+    let compiled = graph.compile();
+    let names = compiled
+        .passes
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect::<Vec<_>>();
+
+    assert_eq!(names, ["gbuffer", "lighting"]);
+    assert_eq!(compiled.culled_passes, [1]);
+    assert_eq!(compiled.image_slot(unused), None);
+}
+
+#[test]
+fn test_side_effects_are_not_culled() {
+    let mut graph = RenderGraph::new();
+    let image = graph.create_image("image", color_desc());
+
+    graph
+        .add_pass("debug")
+        .write(image, Access::ColorAttachmentWrite)
+        .side_effects()
+        .execute(|_, _| Ok(()));
+
+    assert!(graph.compile().culled_passes.is_empty());
+}
+
+#[test]
+fn test_layout_transitions_between_passes() {
+    let mut graph = RenderGraph::new();
+    let gbuffer = graph.create_image("gbuffer", color_desc());
+    let output = graph.create_image("output", color_desc());
+    graph.mark_output(output);
+
+    graph
+        .add_pass("gbuffer")
+        .write(gbuffer, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("lighting")
+        .read(gbuffer, Access::FragmentShaderSampledRead)
+        .write(output, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+
+    let compiled = graph.compile();
+
+    let first = &compiled.passes[0].barriers;
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].old_layout, vk::ImageLayout::UNDEFINED);
+    assert_eq!(
+        first[0].new_layout,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    );
+
+    let barrier = compiled.passes[1]
+        .barriers
+        .iter()
+        .find(|b| b.resource == ResourceHandle::Image(gbuffer))
+        .unwrap();
+    assert_eq!(
+        barrier.old_layout,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    );
+    assert_eq!(
+        barrier.new_layout,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    );
+    assert_eq!(
+        barrier.src_stage_mask,
+        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+    );
+    assert_eq!(
+        barrier.src_access_mask,
+        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+    );
+    assert_eq!(
+        barrier.dst_stage_mask,
+        vk::PipelineStageFlags2::FRAGMENT_SHADER
+    );
+}
+
+#[test]
+fn test_consecutive_reads_need_no_barrier() {
+    let mut graph = RenderGraph::new();
+    let source = graph.create_image("source", color_desc());
+    let a = graph.create_image("a", color_desc());
+    let b = graph.create_image("b", color_desc());
+    graph.mark_output(a);
+    graph.mark_output(b);
+
+    graph
+        .add_pass("source")
+        .write(source, Access::ComputeShaderStorageWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("a")
+        .read(source, Access::ComputeShaderStorageRead)
+        .write(a, Access::ComputeShaderStorageWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("b")
+        .read(source, Access::ComputeShaderStorageRead)
+        .write(b, Access::ComputeShaderStorageWrite)
+        .execute(|_, _| Ok(()));
+
+    let compiled = graph.compile();
+
+    let source_barriers = |pass: usize| {
+        compiled.passes[pass]
+            .barriers
+            .iter()
+            .filter(|b| b.resource == ResourceHandle::Image(source))
+            .count()
+    };
+    assert_eq!(source_barriers(1), 1);
+    assert_eq!(source_barriers(2), 0);
+}
+
+#[test]
+fn test_transient_images_are_aliased() {
+    let mut graph = RenderGraph::new();
+    let a = graph.create_image("a", color_desc());
+    let b = graph.create_image("b", color_desc());
+    let c = graph.create_image("c", color_desc());
+    let d = graph.create_image("d", color_desc());
+    graph.mark_output(d);
+
+    graph
+        .add_pass("a")
+        .write(a, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("b")
+        .read(a, Access::FragmentShaderSampledRead)
+        .write(b, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("c")
+        .read(b, Access::FragmentShaderSampledRead)
+        .write(c, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("d")
+        .read(c, Access::FragmentShaderSampledRead)
+        .write(d, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+
+    let compiled = graph.compile();
+
+    // a is dead once c is written, c then d reuse the physical images of a and b
+    assert_eq!(compiled.physical_image_count(), 2);
+    assert_eq!(compiled.image_slot(a), compiled.image_slot(c));
+    assert_eq!(compiled.image_slot(b), compiled.image_slot(d));
+    assert_ne!(compiled.image_slot(a), compiled.image_slot(b));
+
+    // The aliased image waits for the last use of the previous one
+    let barrier = compiled.passes[2]
+        .barriers
+        .iter()
+        .find(|barrier| barrier.resource == ResourceHandle::Image(c))
+        .unwrap();
+    assert_eq!(barrier.old_layout, vk::ImageLayout::UNDEFINED);
+    assert_eq!(
+        barrier.src_stage_mask,
+        vk::PipelineStageFlags2::FRAGMENT_SHADER
+    );
+}
+
+#[test]
+fn test_different_descriptions_are_not_aliased() {
+    let mut graph = RenderGraph::new();
+    let a = graph.create_image("a", color_desc());
+    let depth = graph.create_image("depth", ImageDesc::new_2d(vk::Format::D32_SFLOAT, EXTENT));
+    graph.mark_output(depth);
+
+    graph
+        .add_pass("a")
+        .write(a, Access::ColorAttachmentWrite)
+        .side_effects()
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("depth")
+        .write(depth, Access::DepthStencilAttachmentWrite)
+        .execute(|_, _| Ok(()));
+
+    assert_eq!(graph.compile().physical_image_count(), 2);
+}
+
+#[test]
+fn test_dot_export() {
+    let mut graph = RenderGraph::new();
+    let used = graph.create_image("used", color_desc());
+    let unused = graph.create_image("unused \"image\"", color_desc());
+    graph.mark_output(used);
+
+    graph
+        .add_pass("draw")
+        .write(used, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+    graph
+        .add_pass("culled")
+        .write(unused, Access::ColorAttachmentWrite)
+        .execute(|_, _| Ok(()));
+
+    let dot = graph.to_dot();
+
+    assert!(dot.starts_with("digraph render_graph {"));
+    assert!(dot.contains("pass_0 [shape=box, label=\"draw\"];"));
+    assert!(dot.contains("pass_1 [shape=box, label=\"culled\", style=dashed"));
+    assert!(dot.contains("unused \\\"image\\\""));
+    assert!(dot.contains("pass_0 -> resource_0 [label=\"ColorAttachmentWrite\"];"));
+}
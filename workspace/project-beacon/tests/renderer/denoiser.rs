@@ -0,0 +1,149 @@
+//! Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use half::f16;
+use project_beacon::renderer::DenoiserSettings;
+use project_beacon::vulkan::{
+    ash::vk, gpu_allocator::MemoryLocation, Buffer, Context, ContextBuilder, DeviceFeatures,
+    MemoryBarrier, VERSION_1_3,
+};
+
+use crate::gpu_tests_enabled;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 16,
+    height: 16,
+};
+// Rows below are sky
+const GEOMETRY_ROWS: u32 = 12;
+const ALBEDO: f32 = 0.5;
+const SKY: [f32; 4] = [0.2, 0.4, 0.8, 1.0];
+
+#[test]
+fn test_denoiser() {
+    if !gpu_tests_enabled() {
+        return;
+    }
+
+    let context = ContextBuilder::headless()
+        .vulkan_version(VERSION_1_3)
+        .required_device_features(DeviceFeatures {
+            synchronization2: true,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let denoiser = context
+        .create_denoiser(EXTENT, DenoiserSettings::default())
+        .unwrap();
+
+    // Checkerboard of irradiance 0.25 and 0.75 on a flat, grey wall facing the camera
+    let pixels = (0..EXTENT.height).flat_map(|y| (0..EXTENT.width).map(move |x| (x, y)));
+    let mut color = vec![];
+    let mut albedo = vec![];
+    let mut normal_depth = vec![];
+    for (x, y) in pixels {
+        if y < GEOMETRY_ROWS {
+            let irradiance = if (x + y) % 2 == 0 { 0.25 } else { 0.75 };
+            let radiance = ALBEDO * irradiance;
+            color.push([radiance, radiance, radiance, 1.0]);
+            albedo.push([ALBEDO, ALBEDO, ALBEDO, 1.0]);
+            normal_depth.push([0.0, 0.0, 1.0, 1.0]);
+        } else {
+            color.push(SKY);
+            albedo.push([1.0; 4]);
+            normal_depth.push([0.0; 4]);
+        }
+    }
+    let motion = vec![[0.0f32; 4]; color.len()];
+
+    let uploads = [
+        (denoiser.color_input(), to_f16(&color)),
+        (denoiser.albedo_input(), to_f16(&albedo)),
+        (denoiser.motion_input(), to_f16(&motion)),
+    ]
+    .into_iter()
+    .map(|(image, data)| (image, staging_buffer(&context, &data)))
+    .chain([(
+        denoiser.normal_depth_input(),
+        staging_buffer(&context, &normal_depth),
+    )])
+    .collect::<Vec<_>>();
+    let readback = context
+        .create_buffer(
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+            (color.len() * 4 * 2) as _,
+        )
+        .unwrap();
+
+    // The second frame reprojects the history of the first one
+    for _ in 0..2 {
+        context
+            .execute_one_time_commands(|cmd_buffer| {
+                for (image, buffer) in &uploads {
+                    cmd_buffer.copy_buffer_to_image(buffer, &image.image, vk::ImageLayout::GENERAL);
+                }
+                cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+                    src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                    dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ,
+                    src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
+                    dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                }]);
+
+                denoiser.record(cmd_buffer).unwrap();
+
+                cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+                    src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    dst_access_mask: vk::AccessFlags2::TRANSFER_READ,
+                    src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
+                }]);
+                cmd_buffer.copy_image_to_buffer(
+                    &denoiser.output().image,
+                    vk::ImageLayout::GENERAL,
+                    &readback,
+                );
+            })
+            .unwrap();
+    }
+
+    let output = readback
+        .read_data_from_buffer::<[f16; 4]>()
+        .unwrap()
+        .into_iter()
+        .map(|pixel| pixel.map(f16::to_f32))
+        .collect::<Vec<_>>();
+    let (geometry, sky) = output.split_at((EXTENT.width * GEOMETRY_ROWS) as usize);
+
+    for pixel in sky {
+        for (value, expected) in pixel.iter().zip(SKY) {
+            assert!((value - expected).abs() < 1e-3, "{pixel:?}");
+        }
+    }
+
+    // Filtered towards the mean irradiance of 0.5, the input is 50% off
+    let expected = ALBEDO * 0.5;
+    for pixel in geometry {
+        assert!((pixel[0] - expected).abs() < 0.1 * expected, "{pixel:?}");
+        assert_eq!(pixel[3], 1.0);
+    }
+    let mean = geometry.iter().map(|pixel| pixel[0]).sum::<f32>() / geometry.len() as f32;
+    assert!((mean - expected).abs() < 0.02 * expected, "{mean}");
+}
+
+fn to_f16(data: &[[f32; 4]]) -> Vec<[f16; 4]> {
+    data.iter().map(|pixel| pixel.map(f16::from_f32)).collect()
+}
+
+fn staging_buffer<T: Copy>(context: &Context, data: &[T]) -> Buffer {
+    let buffer = context
+        .create_buffer(
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+            std::mem::size_of_val(data) as _,
+        )
+        .unwrap();
+    buffer.copy_data_to_buffer(data).unwrap();
+
+    buffer
+}
@@ -0,0 +1,3 @@
+mod denoiser;
+mod path_tracer;
+mod tonemap;
@@ -0,0 +1,124 @@
+//! Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use project_beacon::{
+    renderer::{
+        reference::{CpuImage, ReferenceRenderer, ReferenceSettings},
+        PathTracerSettings,
+    },
+    scene::Scene,
+    vulkan::{ContextBuilder, DeviceFeatures, MemoryBarrier, VERSION_1_3},
+};
+
+use crate::gpu_tests_enabled;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+const FRAMES: u32 = 16;
+
+#[test]
+fn test_cornell_box() {
+    if !gpu_tests_enabled() {
+        return;
+    }
+
+    let context = ContextBuilder::headless()
+        .vulkan_version(VERSION_1_3)
+        .optional_extensions(&[
+            "VK_KHR_ray_tracing_pipeline",
+            "VK_KHR_acceleration_structure",
+            "VK_KHR_deferred_host_operations",
+        ])
+        .required_device_features(DeviceFeatures {
+            runtime_descriptor_array: true,
+            buffer_device_address: true,
+            synchronization2: true,
+            ..Default::default()
+        })
+        .optional_device_features(DeviceFeatures {
+            ray_tracing_pipeline: true,
+            acceleration_structure: true,
+            ..Default::default()
+        })
+        .with_raytracing_context(true)
+        .build()
+        .unwrap();
+    let features = context.device_features();
+    if !(features.ray_tracing_pipeline && features.acceleration_structure) {
+        println!("Skipped, the device does not support ray tracing pipelines");
+        return;
+    }
+
+    let scene = Scene::cornell_box();
+    let camera = Scene::cornell_box_camera(WIDTH as f32 / HEIGHT as f32);
+    let gpu_scene = context.create_gpu_scene(&scene).unwrap();
+    let path_tracer = context
+        .create_path_tracer(
+            &gpu_scene,
+            vk::Extent2D {
+                width: WIDTH,
+                height: HEIGHT,
+            },
+            PathTracerSettings {
+                max_frames: FRAMES,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let readback = context
+        .create_buffer(
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+            (WIDTH * HEIGHT * 16) as _,
+        )
+        .unwrap();
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            // One more than max_frames, which must not be traced
+            for _ in 0..=FRAMES {
+                path_tracer.record(cmd_buffer, &gpu_scene, &camera);
+                cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+                    src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                        | vk::AccessFlags2::SHADER_STORAGE_WRITE
+                        | vk::AccessFlags2::TRANSFER_READ,
+                    src_stage_mask: vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR,
+                    dst_stage_mask: vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR
+                        | vk::PipelineStageFlags2::TRANSFER,
+                }]);
+            }
+
+            cmd_buffer.copy_image_to_buffer(
+                path_tracer.accumulation_image(),
+                vk::ImageLayout::GENERAL,
+                &readback,
+            );
+        })
+        .unwrap();
+    assert_eq!(path_tracer.frame_count(), FRAMES);
+
+    let pixels = readback.read_data_from_buffer::<[f32; 4]>().unwrap();
+    assert!(pixels.iter().flatten().all(|c| c.is_finite()));
+
+    let image = CpuImage::from_pixels(WIDTH, HEIGHT, pixels).unwrap();
+    assert!(image.mean_luminance() > 0.01, "Image is black");
+
+    // Same scene traced on the CPU, the two noisy estimates must agree on average
+    let reference = ReferenceRenderer::new(&scene).render(
+        &camera,
+        WIDTH,
+        HEIGHT,
+        &ReferenceSettings::default(),
+    );
+    // A flipped, mirrored or channel swapped image has a mean absolute error above 0.09
+    let difference = image.compare(&reference).unwrap();
+    assert!(
+        (0.85..1.15).contains(&difference.mean_luminance_ratio),
+        "{difference:?}"
+    );
+    assert!(difference.mean_absolute_error < 0.07, "{difference:?}");
+    assert!(difference.root_mean_squared_error < 0.3, "{difference:?}");
+}
@@ -0,0 +1,195 @@
+//! Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use std::time::Duration;
+
+use half::f16;
+use project_beacon::app::ImageAndView;
+use project_beacon::renderer::{TonemapOperator, TonemapSettings, Tonemapper, HDR_FORMAT};
+use project_beacon::vulkan::{
+    ash::vk, gpu_allocator::MemoryLocation, Context, ContextBuilder, DeviceFeatures, ImageBarrier,
+    MemoryBarrier, SwapchainEncoding, VERSION_1_3,
+};
+
+use crate::gpu_tests_enabled;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 16,
+    height: 16,
+};
+// The target format encodes to sRGB, the output stays linear
+const TARGET_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+#[test]
+fn test_tonemapper() {
+    if !gpu_tests_enabled() {
+        return;
+    }
+
+    let context = ContextBuilder::headless()
+        .vulkan_version(VERSION_1_3)
+        .required_device_features(DeviceFeatures {
+            synchronization2: true,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let input = create_input(&context);
+    let mut tonemapper = context
+        .create_tonemapper(
+            std::slice::from_ref(&input),
+            TARGET_FORMAT,
+            SwapchainEncoding::Srgb,
+            TonemapSettings {
+                operator: TonemapOperator::Reinhard,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    let reinhard = |x: f32| x / (1.0 + x);
+    let pixel_count = (EXTENT.width * EXTENT.height) as usize;
+    let grey = |value| vec![[value, value, value, 1.0]; pixel_count];
+    // Every other pixel is black
+    let half_black = |value| {
+        (0..pixel_count)
+            .map(|i| {
+                if i % 2 == 0 {
+                    [value, value, value, 1.0]
+                } else {
+                    [0.0, 0.0, 0.0, 1.0]
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The first frame snaps the exposure to middle grey, within a histogram bin
+    let output = tonemap(&context, &tonemapper, &input, &grey(2.0), Duration::ZERO);
+    assert_all_near(&output, reinhard(0.18), 0.01);
+
+    // Nothing is adapted in no time, the exposure of the previous frame is kept
+    let image = half_black(0.5);
+    let output = tonemap(&context, &tonemapper, &input, &image, Duration::ZERO);
+    let (lit, black) = split_lit(&output);
+    assert_all_near(&lit, reinhard(0.18 / 4.0), 0.005);
+    assert_all_near(&black, 0.0, 0.0);
+
+    // Black pixels are left out of the average
+    tonemapper.reset();
+    let output = tonemap(&context, &tonemapper, &input, &image, Duration::ZERO);
+    assert_all_near(&split_lit(&output).0, reinhard(0.18), 0.01);
+
+    tonemapper.settings.auto_exposure = false;
+    tonemapper.settings.exposure_compensation = 1.0;
+    let output = tonemap(&context, &tonemapper, &input, &grey(0.25), Duration::ZERO);
+    assert_all_near(&output, reinhard(0.5), 0.002);
+}
+
+fn create_input(context: &Context) -> ImageAndView {
+    let image = context
+        .create_image(
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+            HDR_FORMAT,
+            EXTENT.width,
+            EXTENT.height,
+        )
+        .unwrap();
+    let view = image.create_image_view().unwrap();
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            cmd_buffer.pipeline_image_barriers(&[ImageBarrier {
+                image: &image,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::GENERAL,
+                src_access_mask: vk::AccessFlags2::NONE,
+                dst_access_mask: vk::AccessFlags2::NONE,
+                src_stage_mask: vk::PipelineStageFlags2::NONE,
+                dst_stage_mask: vk::PipelineStageFlags2::ALL_COMMANDS,
+            }]);
+        })
+        .unwrap();
+
+    ImageAndView { view, image }
+}
+
+/// Uploads `pixels` to `input`, tonemaps it and reads back the output.
+fn tonemap(
+    context: &Context,
+    tonemapper: &Tonemapper,
+    input: &ImageAndView,
+    pixels: &[[f32; 4]],
+    delta_time: Duration,
+) -> Vec<[f32; 4]> {
+    let pixels = pixels
+        .iter()
+        .map(|pixel| pixel.map(f16::from_f32))
+        .collect::<Vec<_>>();
+    let staging = context
+        .create_buffer(
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+            std::mem::size_of_val(pixels.as_slice()) as _,
+        )
+        .unwrap();
+    staging.copy_data_to_buffer(&pixels).unwrap();
+    let readback = context
+        .create_buffer(
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+            std::mem::size_of_val(pixels.as_slice()) as _,
+        )
+        .unwrap();
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            cmd_buffer.copy_buffer_to_image(&staging, &input.image, vk::ImageLayout::GENERAL);
+            cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+                src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ,
+                src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
+                dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            }]);
+
+            tonemapper.record(cmd_buffer, 0, delta_time);
+
+            cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+                src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                dst_access_mask: vk::AccessFlags2::TRANSFER_READ,
+                src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
+            }]);
+            cmd_buffer.copy_image_to_buffer(
+                &tonemapper.output().image,
+                vk::ImageLayout::GENERAL,
+                &readback,
+            );
+        })
+        .unwrap();
+
+    readback
+        .read_data_from_buffer::<[f16; 4]>()
+        .unwrap()
+        .into_iter()
+        .map(|pixel| pixel.map(f16::to_f32))
+        .collect()
+}
+
+/// Even and odd pixels of a half black image.
+fn split_lit(pixels: &[[f32; 4]]) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+    (
+        pixels.iter().step_by(2).copied().collect(),
+        pixels.iter().skip(1).step_by(2).copied().collect(),
+    )
+}
+
+fn assert_all_near(pixels: &[[f32; 4]], expected: f32, tolerance: f32) {
+    for pixel in pixels {
+        for value in &pixel[..3] {
+            assert!(
+                (value - expected).abs() <= tolerance,
+                "{pixel:?}, expected {expected}"
+            );
+        }
+        assert_eq!(pixel[3], 1.0);
+    }
+}
@@ -1 +1,15 @@
+mod compute;
+mod render_graph;
+mod renderer;
 mod vulkan;
+
+/// Tests needing a Vulkan device, lavapipe works, only run with `BEACON_GPU_TESTS=1`. CI sets it
+/// where lavapipe is installed.
+fn gpu_tests_enabled() -> bool {
+    let enabled = std::env::var("BEACON_GPU_TESTS").is_ok_and(|value| value == "1");
+    if !enabled {
+        println!("Skipped, set BEACON_GPU_TESTS=1 to run tests on a Vulkan device");
+    }
+
+    enabled
+}
@@ -0,0 +1,45 @@
+//! Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use project_beacon::vulkan::{ash::vk, gpu_allocator::MemoryLocation, ContextBuilder, VERSION_1_3};
+
+use crate::gpu_tests_enabled;
+
+#[test]
+fn test_deferred_deletion() {
+    if !gpu_tests_enabled() {
+        return;
+    }
+
+    let context = ContextBuilder::headless()
+        .vulkan_version(VERSION_1_3)
+        .build()
+        .unwrap();
+    let create_buffer = || {
+        context
+            .create_buffer(
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                MemoryLocation::GpuOnly,
+                1024,
+            )
+            .unwrap()
+    };
+
+    // Destroyed immediately before the first frame
+    drop(create_buffer());
+    assert_eq!(context.pending_deletion_count(), 0);
+    assert_eq!(context.memory_report().allocation_count, 0);
+
+    let first_frame = context.begin_frame();
+    let buffer = create_buffer();
+    let second_frame = context.begin_frame();
+    drop(buffer);
+    assert_eq!(context.pending_deletion_count(), 1);
+    assert_eq!(context.memory_report().allocation_count, 1);
+
+    context.complete_frame(first_frame);
+    assert_eq!(context.pending_deletion_count(), 1);
+
+    context.complete_frame(second_frame);
+    assert_eq!(context.pending_deletion_count(), 0);
+    assert_eq!(context.memory_report().allocation_count, 0);
+}
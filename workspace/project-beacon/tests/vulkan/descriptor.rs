@@ -0,0 +1,140 @@
+//! Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use project_beacon::vulkan::{
+    ash::vk, gpu_allocator::MemoryLocation, Buffer, ComputePipelineCreateInfo, Context,
+    ContextBuilder, DescriptorPoolSizeRatio, DeviceFeatures, MemoryBarrier, WriteDescriptorSet,
+    WriteDescriptorSetKind, VERSION_1_3,
+};
+
+use crate::gpu_tests_enabled;
+
+const SET_COUNT: u32 = 5;
+/// Largest `minStorageBufferOffsetAlignment` allowed by the spec.
+const OUTPUT_STRIDE: u64 = 256;
+
+fn create_buffer(context: &Context, size: u64) -> Buffer {
+    context
+        .create_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+            size,
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_descriptor_allocator_growth() {
+    if !gpu_tests_enabled() {
+        return;
+    }
+
+    let context = ContextBuilder::headless()
+        .vulkan_version(VERSION_1_3)
+        .required_device_features(DeviceFeatures {
+            synchronization2: true,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    let binding = |binding, descriptor_count| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_count(descriptor_count)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+    };
+    let layout = context
+        .create_descriptor_set_layout(&[binding(0, 2), binding(1, 1)])
+        .unwrap();
+    let pipeline_layout = context.create_pipeline_layout(&[&layout]).unwrap();
+    let pipeline = context
+        .create_compute_pipeline(
+            &pipeline_layout,
+            ComputePipelineCreateInfo {
+                shader_source: &include_bytes!("./shaders/descriptor_allocator.comp.spv")[..],
+            },
+        )
+        .unwrap();
+
+    // Room for 2 sets in the first pool
+    let mut allocator = context
+        .create_descriptor_allocator(
+            2,
+            &[DescriptorPoolSizeRatio {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                ratio: 3.0,
+            }],
+        )
+        .unwrap();
+    let sets = (0..SET_COUNT)
+        .map(|_| allocator.allocate(&layout).unwrap())
+        .collect::<Vec<_>>();
+    let pool_count = allocator.pool_count();
+    assert!(pool_count > 1, "No pool was added past the first one");
+
+    let inputs = (0..SET_COUNT)
+        .map(|i| {
+            let buffers = [create_buffer(&context, 4), create_buffer(&context, 4)];
+            buffers[0].copy_data_to_buffer(&[i]).unwrap();
+            buffers[1].copy_data_to_buffer(&[10 * i]).unwrap();
+            buffers
+        })
+        .collect::<Vec<_>>();
+    let output = create_buffer(&context, SET_COUNT as u64 * OUTPUT_STRIDE);
+
+    // Sets of every pool are written and used after the allocator grew
+    for (i, (set, [a, b])) in sets.iter().zip(&inputs).enumerate() {
+        set.update(&[
+            WriteDescriptorSet {
+                binding: 0,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBufferArray { buffers: &[a, b] },
+            },
+            WriteDescriptorSet {
+                binding: 1,
+                array_element: 0,
+                kind: WriteDescriptorSetKind::StorageBufferRange {
+                    buffer: &output,
+                    offset: i as u64 * OUTPUT_STRIDE,
+                    range: 4,
+                },
+            },
+        ]);
+    }
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            cmd_buffer.bind_compute_pipeline(&pipeline);
+            for set in &sets {
+                cmd_buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::COMPUTE,
+                    &pipeline_layout,
+                    0,
+                    &[set],
+                );
+                cmd_buffer.dispatch(1, 1, 1);
+            }
+            cmd_buffer.pipeline_memory_barriers(&[MemoryBarrier {
+                src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                dst_access_mask: vk::AccessFlags2::HOST_READ,
+                src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                dst_stage_mask: vk::PipelineStageFlags2::HOST,
+            }]);
+        })
+        .unwrap();
+
+    let results = output.read_data_from_buffer::<u32>().unwrap();
+    for i in 0..SET_COUNT {
+        assert_eq!(results[(i as u64 * OUTPUT_STRIDE / 4) as usize], 11 * i);
+    }
+
+    // Reset pools are reused
+    drop(sets);
+    allocator.reset().unwrap();
+    for _ in 0..SET_COUNT {
+        allocator.allocate(&layout).unwrap();
+    }
+    assert_eq!(allocator.pool_count(), pool_count);
+}
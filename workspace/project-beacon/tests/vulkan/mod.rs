@@ -1 +1,6 @@
+mod deletion;
+mod descriptor;
+mod query;
+mod sampler;
 mod version;
+mod vertex;
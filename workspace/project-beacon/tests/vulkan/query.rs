@@ -0,0 +1,148 @@
+//! Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use project_beacon::vulkan::{
+    ash::vk, gpu_allocator::MemoryLocation, ContextBuilder, DeviceFeatures,
+    GraphicsPipelineCreateInfo, GraphicsShaderCreateInfo, ImageBarrier, RenderingAttachment,
+    RenderingInfo, ScopedQueryPool, VERSION_1_3,
+};
+
+use crate::gpu_tests_enabled;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 64,
+    height: 64,
+};
+const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+#[test]
+fn test_occlusion_and_pipeline_statistics_queries() {
+    if !gpu_tests_enabled() {
+        return;
+    }
+
+    let context = ContextBuilder::headless()
+        .vulkan_version(VERSION_1_3)
+        .required_device_features(DeviceFeatures {
+            dynamic_rendering: true,
+            synchronization2: true,
+            ..Default::default()
+        })
+        .optional_device_features(DeviceFeatures {
+            occlusion_query_precise: true,
+            pipeline_statistics_query: true,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let features = context.device_features();
+
+    let image = context
+        .create_image(
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            MemoryLocation::GpuOnly,
+            FORMAT,
+            EXTENT.width,
+            EXTENT.height,
+        )
+        .unwrap();
+    let view = image.create_image_view().unwrap();
+
+    let pipeline_layout = context.create_pipeline_layout(&[]).unwrap();
+    let pipeline = context
+        .create_graphics_pipeline::<()>(
+            &pipeline_layout,
+            GraphicsPipelineCreateInfo {
+                shaders: &[
+                    GraphicsShaderCreateInfo {
+                        source: &include_bytes!("./shaders/fullscreen.vert.spv")[..],
+                        stage: vk::ShaderStageFlags::VERTEX,
+                    },
+                    GraphicsShaderCreateInfo {
+                        source: &include_bytes!("./shaders/white.frag.spv")[..],
+                        stage: vk::ShaderStageFlags::FRAGMENT,
+                    },
+                ],
+                primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                extent: Some(EXTENT),
+                color_attachment_formats: &[FORMAT],
+                color_attachment_blend: None,
+                depth_attachment_format: None,
+                stencil_attachment_format: None,
+                depth_stencil_state: None,
+                samples: None,
+                dynamic_states: None,
+            },
+        )
+        .unwrap();
+
+    // Query 0 covers a full screen triangle, query 1 covers nothing
+    let occlusion = context
+        .create_occlusion_query_pool(2, features.occlusion_query_precise)
+        .unwrap();
+    let statistics = features.pipeline_statistics_query.then(|| {
+        context
+            .create_pipeline_statistics_query_pool(
+                1,
+                vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES
+                    | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES,
+            )
+            .unwrap()
+    });
+
+    context
+        .execute_one_time_commands(|cmd_buffer| {
+            cmd_buffer.reset_query_pool(occlusion.query_pool());
+            if let Some(statistics) = &statistics {
+                cmd_buffer.reset_query_pool(statistics.query_pool());
+            }
+
+            cmd_buffer.pipeline_image_barriers(&[ImageBarrier {
+                image: &image,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                src_access_mask: vk::AccessFlags2::NONE,
+                dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                src_stage_mask: vk::PipelineStageFlags2::NONE,
+                dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            }]);
+
+            cmd_buffer.begin_rendering_with_info(
+                &RenderingInfo::new(EXTENT).color_attachment(RenderingAttachment::color(&view)),
+            );
+            cmd_buffer.bind_graphics_pipeline(&pipeline);
+
+            cmd_buffer.begin_query(&occlusion, 0);
+            if let Some(statistics) = &statistics {
+                cmd_buffer.begin_query(statistics, 0);
+            }
+            cmd_buffer.draw(3);
+            if let Some(statistics) = &statistics {
+                cmd_buffer.end_query(statistics, 0);
+            }
+            cmd_buffer.end_query(&occlusion, 0);
+
+            cmd_buffer.begin_query(&occlusion, 1);
+            cmd_buffer.end_query(&occlusion, 1);
+
+            cmd_buffer.end_rendering();
+        })
+        .unwrap();
+
+    let samples = occlusion.available_results(0, 2).unwrap();
+    if occlusion.is_precise() {
+        let pixel_count = (EXTENT.width * EXTENT.height) as u64;
+        assert_eq!(samples, [Some(pixel_count), Some(0)]);
+    }
+    assert_eq!(
+        occlusion.available_visibility(0, 2).unwrap(),
+        [Some(true), Some(false)]
+    );
+
+    if let Some(statistics) = &statistics {
+        let results = statistics.available_results(0, 1).unwrap();
+        let results = results[0].expect("Pipeline statistics query is not available");
+        assert_eq!(results.input_assembly_vertices, Some(3));
+        assert_eq!(results.input_assembly_primitives, Some(1));
+        assert_eq!(results.vertex_shader_invocations, None);
+    }
+}
@@ -0,0 +1,41 @@
+//! Needs a Vulkan device, see [`crate::gpu_tests_enabled`].
+
+use std::sync::Arc;
+
+use project_beacon::vulkan::{ContextBuilder, DeviceFeatures, SamplerDesc, VERSION_1_3};
+
+use crate::gpu_tests_enabled;
+
+#[test]
+fn test_sampler_cache() {
+    if !gpu_tests_enabled() {
+        return;
+    }
+
+    let context = ContextBuilder::headless()
+        .vulkan_version(VERSION_1_3)
+        .optional_device_features(DeviceFeatures {
+            sampler_anisotropy: true,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    let linear = context.get_sampler(&SamplerDesc::linear_repeat()).unwrap();
+    let same_linear = context.get_sampler(&SamplerDesc::default()).unwrap();
+    assert!(Arc::ptr_eq(&linear, &same_linear));
+
+    let nearest = context.get_sampler(&SamplerDesc::nearest_clamp()).unwrap();
+    assert!(!Arc::ptr_eq(&linear, &nearest));
+
+    // Created with or without the feature, clamped to the device limit
+    let anisotropic = context
+        .get_sampler(&SamplerDesc::anisotropic(16.0))
+        .unwrap();
+    let same_anisotropic = context
+        .get_sampler(&SamplerDesc::anisotropic(16.0))
+        .unwrap();
+    assert!(Arc::ptr_eq(&anisotropic, &same_anisotropic));
+
+    assert_eq!(context.cached_sampler_count(), 3);
+}
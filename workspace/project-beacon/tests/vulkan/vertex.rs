@@ -0,0 +1,81 @@
+use glam::{Mat4, Vec2, Vec3};
+use half::f16;
+use project_beacon::vulkan::{self, ash::vk, Unorm8x4, Vertex};
+
+#[derive(vulkan::Vertex)]
+#[repr(C)]
+#[allow(dead_code)]
+struct MeshVertex {
+    position: Vec3,
+    uv: Vec2,
+    color: Unorm8x4,
+    tangent: [f16; 4],
+    #[vertex(format = R8G8B8A8_SNORM)]
+    normal: [i8; 4],
+}
+
+#[derive(vulkan::Vertex)]
+#[vertex(binding = 1, instance, location = 5)]
+#[repr(C)]
+#[allow(dead_code)]
+struct InstanceData {
+    transform: Mat4,
+    #[vertex(skip)]
+    _padding: [u32; 3],
+    #[vertex(location = 10)]
+    id: u32,
+}
+
+#[test]
+fn test_derive_offsets_and_formats() {
+    let bindings = MeshVertex::bindings();
+    assert_eq!(bindings.len(), 1);
+    assert_eq!(bindings[0].binding, 0);
+    assert_eq!(bindings[0].stride, 36);
+    assert_eq!(bindings[0].input_rate, vk::VertexInputRate::VERTEX);
+
+    let attributes = MeshVertex::attributes()
+        .iter()
+        .map(|a| (a.binding, a.location, a.format, a.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        attributes,
+        [
+            (0, 0, vk::Format::R32G32B32_SFLOAT, 0),
+            (0, 1, vk::Format::R32G32_SFLOAT, 12),
+            (0, 2, vk::Format::R8G8B8A8_UNORM, 20),
+            (0, 3, vk::Format::R16G16B16A16_SFLOAT, 24),
+            (0, 4, vk::Format::R8G8B8A8_SNORM, 32),
+        ]
+    );
+}
+
+#[test]
+fn test_derive_instance_binding() {
+    let bindings = InstanceData::bindings();
+    assert_eq!(bindings[0].binding, 1);
+    assert_eq!(bindings[0].stride, 80);
+    assert_eq!(bindings[0].input_rate, vk::VertexInputRate::INSTANCE);
+
+    let attributes = InstanceData::attributes()
+        .iter()
+        .map(|a| (a.binding, a.location, a.format, a.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        attributes,
+        [
+            (1, 5, vk::Format::R32G32B32A32_SFLOAT, 0),
+            (1, 6, vk::Format::R32G32B32A32_SFLOAT, 16),
+            (1, 7, vk::Format::R32G32B32A32_SFLOAT, 32),
+            (1, 8, vk::Format::R32G32B32A32_SFLOAT, 48),
+            (1, 10, vk::Format::R32_UINT, 76),
+        ]
+    );
+}
+
+#[test]
+fn test_tuple_combines_layouts() {
+    let bindings = <(MeshVertex, InstanceData)>::bindings();
+    assert_eq!(bindings.len(), 2);
+    assert_eq!(<(MeshVertex, InstanceData)>::attributes().len(), 10);
+}